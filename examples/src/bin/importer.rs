@@ -9,6 +9,10 @@ use iroh_car::CarReader;
 use iroh_rpc_client::{Client, Config as RpcClientConfig};
 use par_stream::prelude::*;
 
+/// Number of blocks stored by a single `put_many`, 64 blocks of the default 256KiB chunk
+/// size make up the 16MiB the unixfs adder sends at most.
+const PUT_MANY_BLOCKS: usize = 64;
+
 #[derive(Parser, Debug, Clone)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
@@ -59,21 +63,24 @@ async fn main() -> Result<()> {
                 Ok((cid, data, links))
             }
         })
-        .try_par_then_unordered(None, move |(cid, data, links)| {
+        .try_chunks(PUT_MANY_BLOCKS)
+        .map_err(|err| err.1)
+        .try_par_then_unordered(None, move |blocks| {
             let rpc = rpc.clone();
             let pb = pb_clone.clone();
             async move {
-                let l = data.len();
-                rpc.try_store()?.put(cid, data, links).await?;
+                let count = blocks.len();
+                let l: usize = blocks.iter().map(|(_, data, _)| data.len()).sum();
+                rpc.try_store()?.put_many(blocks).await?;
                 pb.inc(l as _);
-                Ok(l)
+                Ok((count, l))
             }
         })
         .try_collect()
         .await?;
 
-    let count = res.len();
-    let bytes: usize = res.into_iter().sum();
+    let count = res.iter().map(|(count, _)| count).sum::<usize>();
+    let bytes: usize = res.into_iter().map(|(_, bytes)| bytes).sum();
     pb.finish();
 
     println!(
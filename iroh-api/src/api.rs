@@ -7,11 +7,14 @@ use crate::p2p::MockP2p;
 use crate::p2p::{ClientP2p, P2p};
 use crate::{AddEvent, IpfsPath};
 use anyhow::Result;
+use cid::multihash::Code;
 use cid::Cid;
 use futures::future::{BoxFuture, LocalBoxFuture};
 use futures::stream::LocalBoxStream;
 use futures::FutureExt;
 use futures::StreamExt;
+use iroh_resolver::chunker::Chunker;
+use iroh_resolver::resolver::OutMetrics;
 use iroh_resolver::unixfs_builder;
 use iroh_rpc_client::Client;
 use iroh_rpc_client::StatusTable;
@@ -55,16 +58,21 @@ pub trait Api {
         &self,
         path: &Path,
         wrap: bool,
+        chunker: Chunker,
+        hash: Code,
     ) -> LocalBoxFuture<'_, Result<LocalBoxStream<'static, Result<AddEvent>>>>;
     fn add_dir(
         &self,
         path: &Path,
         wrap: bool,
+        chunker: Chunker,
+        hash: Code,
     ) -> LocalBoxFuture<'_, Result<LocalBoxStream<'static, Result<AddEvent>>>>;
     fn add_symlink(
         &self,
         path: &Path,
         wrap: bool,
+        hash: Code,
     ) -> LocalBoxFuture<'_, Result<LocalBoxStream<'static, Result<AddEvent>>>>;
 
     fn check(&self) -> BoxFuture<'_, StatusTable>;
@@ -136,13 +144,13 @@ impl Api for Iroh {
                 if out.is_dir() {
                     yield (relative_path, OutType::Dir);
                 } else if out.is_symlink() {
-                    let mut reader = out.pretty(resolver.clone(), Default::default(), iroh_resolver::resolver::ResponseClip::NoClip)?;
+                    let mut reader = out.pretty(resolver.clone(), OutMetrics::disabled(), iroh_resolver::resolver::ResponseClip::NoClip)?;
                     let mut target = String::new();
                     reader.read_to_string(&mut target).await?;
                     let target = PathBuf::from(target);
                     yield (relative_path, OutType::Symlink(target));
                 } else {
-                    let reader = out.pretty(resolver.clone(), Default::default(), iroh_resolver::resolver::ResponseClip::NoClip)?;
+                    let reader = out.pretty(resolver.clone(), OutMetrics::disabled(), iroh_resolver::resolver::ResponseClip::NoClip)?;
                     yield (relative_path, OutType::Reader(Box::new(reader)));
                 }
             }
@@ -154,13 +162,15 @@ impl Api for Iroh {
         &self,
         path: &Path,
         wrap: bool,
+        chunker: Chunker,
+        hash: Code,
     ) -> LocalBoxFuture<'_, Result<LocalBoxStream<'static, Result<AddEvent>>>> {
         let providing_client = iroh_resolver::unixfs_builder::StoreAndProvideClient {
             client: self.client.clone(),
         };
         let path = path.to_path_buf();
         async move {
-            unixfs_builder::add_file(Some(providing_client), &path, wrap)
+            unixfs_builder::add_file(Some(providing_client), &path, wrap, chunker, hash)
                 .await
                 .map(|s| s.boxed_local())
         }
@@ -171,13 +181,15 @@ impl Api for Iroh {
         &self,
         path: &Path,
         wrap: bool,
+        chunker: Chunker,
+        hash: Code,
     ) -> LocalBoxFuture<'_, Result<LocalBoxStream<'static, Result<AddEvent>>>> {
         let providing_client = iroh_resolver::unixfs_builder::StoreAndProvideClient {
             client: self.client.clone(),
         };
         let path = path.to_path_buf();
         async move {
-            unixfs_builder::add_dir(Some(providing_client), &path, wrap)
+            unixfs_builder::add_dir(Some(providing_client), &path, wrap, chunker, hash)
                 .await
                 .map(|s| s.boxed_local())
         }
@@ -188,13 +200,14 @@ impl Api for Iroh {
         &self,
         path: &Path,
         wrap: bool,
+        hash: Code,
     ) -> LocalBoxFuture<'_, Result<LocalBoxStream<'static, Result<AddEvent>>>> {
         let providing_client = iroh_resolver::unixfs_builder::StoreAndProvideClient {
             client: self.client.clone(),
         };
         let path = path.to_path_buf();
         async move {
-            unixfs_builder::add_symlink(Some(providing_client), &path, wrap)
+            unixfs_builder::add_symlink(Some(providing_client), &path, wrap, hash)
                 .await
                 .map(|s| s.boxed_local())
         }
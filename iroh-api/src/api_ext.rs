@@ -3,10 +3,12 @@ use std::path::{Path, PathBuf};
 use crate::{AddEvent, Api, Cid, IpfsPath, OutType};
 use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
+use cid::multihash::Code;
 use futures::stream::LocalBoxStream;
 use futures::Stream;
 use futures::StreamExt;
 use futures::TryStreamExt;
+use iroh_resolver::chunker::Chunker;
 use relative_path::RelativePathBuf;
 
 #[async_trait(?Send)]
@@ -32,24 +34,28 @@ pub trait ApiExt: Api {
         Ok(root_path)
     }
 
+    /// Adds the file, directory or symlink at `path`, splitting files with `chunker` and
+    /// deriving the cids of its blocks with `hash`.
     async fn add_stream(
         &self,
         path: &Path,
         wrap: bool,
+        chunker: Chunker,
+        hash: Code,
     ) -> Result<LocalBoxStream<'static, Result<AddEvent>>> {
         if path.is_dir() {
-            self.add_dir(path, wrap).await
+            self.add_dir(path, wrap, chunker, hash).await
         } else if path.is_symlink() {
-            self.add_symlink(path, wrap).await
+            self.add_symlink(path, wrap, hash).await
         } else if path.is_file() {
-            self.add_file(path, wrap).await
+            self.add_file(path, wrap, chunker, hash).await
         } else {
             anyhow::bail!("can only add files or directories")
         }
     }
 
-    async fn add(&self, path: &Path, wrap: bool) -> Result<Cid> {
-        let add_events = self.add_stream(path, wrap).await?;
+    async fn add(&self, path: &Path, wrap: bool, chunker: Chunker, hash: Code) -> Result<Cid> {
+        let add_events = self.add_stream(path, wrap, chunker, hash).await?;
 
         add_events
             .try_fold(None, |_acc, add_event| async move {
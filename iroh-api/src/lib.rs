@@ -14,8 +14,11 @@ pub use crate::p2p::MockP2p;
 pub use crate::p2p::P2p as P2pApi;
 pub use crate::p2p::PeerIdOrAddr;
 pub use bytes::Bytes;
+pub use cid::multihash::Code;
 pub use cid::Cid;
+pub use iroh_resolver::chunker::Chunker;
 pub use iroh_resolver::resolver::Path as IpfsPath;
+pub use iroh_resolver::unixfs::hash_from_name;
 pub use iroh_resolver::unixfs_builder::AddEvent;
 pub use iroh_rpc_client::{Lookup, ServiceStatus, StatusRow, StatusTable};
 pub use libp2p::gossipsub::MessageId;
@@ -11,7 +11,12 @@ use futures::future::BoxFuture;
 use libp2p::PeerId;
 use tracing::{debug, error, info, warn};
 
-use crate::{block::Block, message::BitswapMessage, network::Network, Store};
+use crate::{
+    block::Block,
+    message::{BitswapMessage, Priority, WantType},
+    network::Network,
+    Store,
+};
 
 use self::session::BlockReceiver;
 use self::{peer_manager::PeerManager, session::Session, session_manager::SessionManager};
@@ -54,6 +59,17 @@ pub struct Stat {
     pub messages_received: u64,
 }
 
+/// An outstanding want, as queued or sent to a single peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WantlistEntry {
+    pub cid: Cid,
+    pub peer: PeerId,
+    pub priority: Priority,
+    pub want_type: WantType,
+    /// Time since the want was sent, `None` if it is still queued.
+    pub age: Option<Duration>,
+}
+
 #[derive(Derivative)]
 #[derivative(Debug)]
 #[derive(Clone)]
@@ -140,6 +156,17 @@ impl<S: Store> Client<S> {
         session.get_block(key).await
     }
 
+    /// Fetches a block, asking the given providers for it before searching for others.
+    pub async fn get_block_with_providers(
+        &self,
+        session_id: u64,
+        key: &Cid,
+        providers: &[PeerId],
+    ) -> Result<Block> {
+        let session = self.get_or_create_session(session_id).await;
+        session.get_block_with_providers(key, providers).await
+    }
+
     pub async fn get_blocks_with_session_id(
         &self,
         session_id: u64,
@@ -275,6 +302,11 @@ impl<S: Store> Client<S> {
         self.peer_manager().current_wants().await
     }
 
+    /// Returns the outstanding wants for every peer, sorted by cid and peer.
+    pub async fn get_wantlist_entries(&self) -> Vec<WantlistEntry> {
+        self.peer_manager().wantlist().await
+    }
+
     /// Returns the current list of want-blocks.
     pub async fn get_want_blocks(&self) -> AHashSet<Cid> {
         self.peer_manager().current_want_blocks().await
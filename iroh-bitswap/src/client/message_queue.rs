@@ -40,8 +40,6 @@ enum WantsUpdate {
         want_haves: Vec<Cid>,
     },
     Cancels(AHashSet<Cid>),
-    #[cfg(test)]
-    #[allow(dead_code)]
     GetWants(tokio::sync::oneshot::Sender<Wants>),
 }
 
@@ -128,8 +126,7 @@ impl MessageQueue {
         }
     }
 
-    #[cfg(test)]
-    #[allow(dead_code)]
+    /// Returns a snapshot of the pending and sent wants for this peer.
     pub(crate) async fn wants(&self) -> Result<Wants> {
         let (s, r) = tokio::sync::oneshot::channel();
         self.send_wants_update(WantsUpdate::GetWants(s)).await;
@@ -344,11 +341,11 @@ impl MessageQueueActor {
 
         let dh_timeout_manager = DontHaveTimeoutManager::new(peer, on_dont_have_timeout).await;
 
-        let msg_sender_config = MessageSenderConfig {
-            max_retries: config.max_retries,
-            send_timeout: config.send_timeout,
-            send_error_backoff: config.send_error_backof,
-        };
+        let msg_sender_config = MessageSenderConfig::builder()
+            .max_retries(config.max_retries)
+            .send_timeout(config.send_timeout)
+            .send_error_backoff(config.send_error_backof)
+            .build();
         Self {
             config,
             wants,
@@ -435,8 +432,9 @@ impl MessageQueueActor {
                     self.signal_work();
                 }
             }
-            #[cfg(test)]
-            WantsUpdate::GetWants(r) => r.send(self.wants.clone()).unwrap(),
+            WantsUpdate::GetWants(r) => {
+                let _ = r.send(self.wants.clone());
+            }
         }
     }
 
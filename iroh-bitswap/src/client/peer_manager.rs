@@ -1,4 +1,4 @@
-use std::{fmt::Debug, sync::Arc};
+use std::{fmt::Debug, sync::Arc, time::Instant};
 
 use ahash::{AHashMap, AHashSet};
 use anyhow::Result;
@@ -12,7 +12,10 @@ use tracing::{debug, error, trace, warn};
 
 use crate::network::Network;
 
-use super::{message_queue::MessageQueue, peer_want_manager::PeerWantManager, session::Signaler};
+use super::{
+    message_queue::MessageQueue, peer_want_manager::PeerWantManager, session::Signaler,
+    WantlistEntry,
+};
 
 #[derive(Debug, Clone)]
 pub struct PeerManager {
@@ -26,6 +29,7 @@ enum Message {
     GetCurrentWants(oneshot::Sender<AHashSet<Cid>>),
     GetCurrentWantBlocks(oneshot::Sender<AHashSet<Cid>>),
     GetCurrentWantHaves(oneshot::Sender<AHashSet<Cid>>),
+    GetWantlist(oneshot::Sender<Vec<WantlistEntry>>),
     Connected(PeerId),
     Disconnected(PeerId),
     ResponseReceived(PeerId, Vec<Cid>),
@@ -187,6 +191,13 @@ impl PeerManager {
         r.await.unwrap_or_default()
     }
 
+    /// Returns the pending wants of all peers, including their priority and age.
+    pub async fn wantlist(&self) -> Vec<WantlistEntry> {
+        let (s, r) = oneshot::channel();
+        self.send(Message::GetWantlist(s)).await;
+        r.await.unwrap_or_default()
+    }
+
     /// Informs the `PeerManager` that the given session is interested in events about the given peer.
     pub async fn register_session(&self, peer: &PeerId, signaler: Signaler) -> bool {
         let (s, r) = oneshot::channel();
@@ -320,6 +331,9 @@ async fn run(mut actor: PeerManagerActor) {
                     Some(Message::GetCurrentWantHaves(r)) => {
                         let _ = r.send(actor.current_want_haves());
                     },
+                    Some(Message::GetWantlist(r)) => {
+                        let _ = r.send(actor.wantlist().await);
+                    },
                     Some(Message::Connected(peer)) => {
                         actor.connected(peer).await;
                     },
@@ -554,6 +568,36 @@ impl PeerManagerActor {
         self.peer_want_manager.get_want_haves()
     }
 
+    async fn wantlist(&self) -> Vec<WantlistEntry> {
+        let now = Instant::now();
+        let mut entries = Vec::new();
+        for (peer, state) in &self.peers {
+            let wants = match state.message_queue.wants().await {
+                Ok(wants) => wants,
+                Err(err) => {
+                    warn!("failed to read the wants for {}: {:?}", peer, err);
+                    continue;
+                }
+            };
+
+            for mut list in [wants.bcst_wants, wants.peer_wants] {
+                let sent_at = &list.sent_at;
+                for entry in list.pending.entries().chain(list.sent.entries()) {
+                    entries.push(WantlistEntry {
+                        cid: entry.cid,
+                        peer: *peer,
+                        priority: entry.priority,
+                        want_type: entry.want_type,
+                        age: sent_at.get(&entry.cid).map(|at| now.duration_since(*at)),
+                    });
+                }
+            }
+        }
+        entries.sort_by(|a, b| a.cid.cmp(&b.cid).then_with(|| a.peer.cmp(&b.peer)));
+
+        entries
+    }
+
     /// Returns true if the peer is new..
     async fn register_session(&mut self, peer: PeerId, signaler: Signaler) -> bool {
         debug!("register session {}: {}", peer, signaler.id());
@@ -721,6 +765,7 @@ mod tests {
     use std::time::Duration;
 
     use crate::block::tests::create_random_block_v1;
+    use crate::message::WantType;
 
     use super::*;
 
@@ -910,6 +955,37 @@ mod tests {
         peer_manager.stop().await.unwrap();
     }
 
+    #[tokio::test]
+    async fn test_wantlist() {
+        let this = PeerId::random();
+        let peer1 = PeerId::random();
+        let network = Network::new(this);
+
+        let peer_manager = PeerManager::new(this, network).await;
+        let cids = gen_cids(2);
+
+        peer_manager.connected(&peer1).await;
+        assert!(peer_manager.wantlist().await.is_empty());
+
+        peer_manager
+            .send_wants(&peer1, &[cids[0]][..], &[cids[1]][..])
+            .await;
+
+        let wantlist = peer_manager.wantlist().await;
+        assert_eq!(wantlist.len(), 2);
+        for entry in &wantlist {
+            assert_eq!(entry.peer, peer1);
+            if entry.cid == cids[0] {
+                assert_eq!(entry.want_type, WantType::Block);
+            } else {
+                assert_eq!(entry.cid, cids[1]);
+                assert_eq!(entry.want_type, WantType::Have);
+            }
+        }
+
+        peer_manager.stop().await.unwrap();
+    }
+
     #[tokio::test]
     async fn test_send_cancels() {
         let this = PeerId::random();
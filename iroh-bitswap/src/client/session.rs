@@ -39,7 +39,8 @@ const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
 #[derive(Debug, Clone, PartialEq, Eq)]
 enum Op {
     Receive(Vec<Cid>),
-    Want(Vec<Cid>),
+    /// Wanted keys, with providers hinted to have them.
+    Want(Vec<Cid>, Vec<PeerId>),
     Cancel(Vec<Cid>),
     Broadcast(AHashSet<Cid>),
     WantsSent(Vec<Cid>),
@@ -127,8 +128,8 @@ impl Session {
                             Ok(Op::Receive(keys)) => {
                                 loop_state.handle_receive(keys).await;
                             },
-                            Ok(Op::Want(keys)) => {
-                                loop_state.want_blocks(keys).await;
+                            Ok(Op::Want(keys, providers)) => {
+                                loop_state.want_blocks(keys, providers).await;
                             },
                             Ok(Op::Cancel(keys)) => {
                                 record!(BitswapMetrics::CancelBlocks, keys.len() as u64);
@@ -274,7 +275,14 @@ impl Session {
 
     /// Fetches a single block.
     pub async fn get_block(&self, key: &Cid) -> Result<Block> {
-        let r = self.get_blocks(&[*key][..]).await?;
+        self.get_block_with_providers(key, &[]).await
+    }
+
+    /// Fetches a single block, asking the given providers for it first.
+    pub async fn get_block_with_providers(&self, key: &Cid, providers: &[PeerId]) -> Result<Block> {
+        let r = self
+            .get_blocks_with_providers(&[*key][..], providers)
+            .await?;
         let block = r.recv().await?;
         Ok(block)
     }
@@ -283,6 +291,17 @@ impl Session {
     /// returns a channel that found blocks will be returned on. No order is
     /// guaranteed on the returned blocks.
     pub async fn get_blocks(&self, keys: &[Cid]) -> Result<BlockReceiver> {
+        self.get_blocks_with_providers(keys, &[]).await
+    }
+
+    /// Like [`Session::get_blocks`], but the blocks are requested from the hinted
+    /// providers directly. Only if none of them can be reached does the session fall
+    /// back to broadcasting and searching for providers.
+    pub async fn get_blocks_with_providers(
+        &self,
+        keys: &[Cid],
+        providers: &[PeerId],
+    ) -> Result<BlockReceiver> {
         ensure!(!keys.is_empty(), "missing keys");
         debug!("get blocks: {:?}", keys);
 
@@ -345,7 +364,10 @@ impl Session {
             }
         });
 
-        self.inner.incoming.send(Op::Want(keys.to_vec())).await?;
+        self.inner
+            .incoming
+            .send(Op::Want(keys.to_vec(), providers.to_vec()))
+            .await?;
 
         Ok(BlockReceiver {
             receiver: r,
@@ -371,6 +393,8 @@ struct LoopState {
     workers: Vec<JoinHandle<Option<()>>>,
     task_controller: tokio_context::task::TaskController,
     provider_search_queue: Arc<deadqueue::limited::Queue<Cid>>,
+    network: Network,
+    incoming: async_channel::Sender<Op>,
 }
 
 impl LoopState {
@@ -399,22 +423,24 @@ impl LoopState {
             workers.push(task_controller.spawn(async move {
                 loop {
                     let cid = queue.pop().await;
-                    if let Ok(chan) = network.find_providers(cid, MAX_PROVIDERS).await {
-                        let stream = tokio_stream::wrappers::ReceiverStream::new(chan);
-                        stream
+                    if let Ok(query) = network.find_provider_records(cid, MAX_PROVIDERS).await {
+                        query
                             // Remove intermitten failures.
                             .filter_map(|providers_result| future::ready(providers_result.ok()))
                             // Flatten.
                             .flat_map_unordered(None, stream::iter)
-                            // Attempt to dial the provider.
+                            // Attempt to dial the provider, unless we are already connected.
                             .filter_map(|provider| {
                                 let network = network.clone();
                                 async move {
+                                    if provider.connected {
+                                        return Some(provider.peer);
+                                    }
                                     network
-                                        .dial(provider, DEFAULT_TIMEOUT)
+                                        .dial(provider.peer, DEFAULT_TIMEOUT)
                                         .await
                                         .ok()
-                                        .map(|_| provider)
+                                        .map(|_| provider.peer)
                                 }
                             })
                             // Notify the session about successfull ones.
@@ -455,6 +481,8 @@ impl LoopState {
             workers,
             task_controller,
             provider_search_queue: queue,
+            network,
+            incoming,
         }
     }
 
@@ -560,7 +588,7 @@ impl LoopState {
     }
 
     /// Called when blocks are requested by the client.
-    async fn want_blocks(&mut self, new_keys: Vec<Cid>) {
+    async fn want_blocks(&mut self, new_keys: Vec<Cid>, providers: Vec<PeerId>) {
         record!(BitswapMetrics::WantedBlocks, new_keys.len() as u64);
         if !new_keys.is_empty() {
             // Inform the SessionInterestManager that this session is interested in the keys.
@@ -570,7 +598,13 @@ impl LoopState {
             // Tell the SessionWants tracker that that the wants have been requested.
             self.session_wants.blocks_requested(&new_keys);
             // Tell the SessionWantSender that the blocks have been requested.
-            self.session_want_sender.add(new_keys).await;
+            self.session_want_sender.add(new_keys.clone()).await;
+
+            // Hinted providers are asked directly, without broadcasting or searching.
+            if !providers.is_empty() {
+                self.try_provider_hints(new_keys, providers);
+                return;
+            }
         }
 
         // If we have discovered peers already, the sessionWantSender will
@@ -596,6 +630,57 @@ impl LoopState {
         }
     }
 
+    /// Dials the hinted providers, treating each reachable one as if it sent a HAVE for
+    /// the keys. If none can be reached the keys are broadcast, searching for providers.
+    fn try_provider_hints(&mut self, keys: Vec<Cid>, providers: Vec<PeerId>) {
+        debug!(
+            "session:{}: trying {} provider hints",
+            self.id,
+            providers.len()
+        );
+        let network = self.network.clone();
+        let incoming = self.incoming.clone();
+        self.task_controller.spawn(async move {
+            let reachable = stream::iter(providers)
+                .map(|provider| {
+                    let network = network.clone();
+                    async move {
+                        network
+                            .dial(provider, DEFAULT_TIMEOUT)
+                            .await
+                            .ok()
+                            .map(|_| provider)
+                    }
+                })
+                .buffer_unordered(MAX_PROVIDERS)
+                .filter_map(future::ready)
+                .then(|provider| {
+                    inc!(BitswapMetrics::ProvidersTotal);
+                    let incoming = incoming.clone();
+                    let keys = keys.clone();
+                    async move {
+                        let _ = incoming
+                            .send(Op::UpdateWantSender {
+                                from: provider,
+                                keys: Vec::new(),
+                                haves: keys,
+                                dont_haves: Vec::new(),
+                            })
+                            .await;
+                    }
+                })
+                .count()
+                .await;
+
+            if reachable == 0 {
+                debug!("no hinted provider reachable, falling back to broadcast");
+                let _ = incoming
+                    .send(Op::Broadcast(keys.into_iter().collect()))
+                    .await;
+            }
+        });
+    }
+
     /// Send want-haves to all connected peers
     async fn broadcast_want_haves(&self, wants: &AHashSet<Cid>) {
         debug!(
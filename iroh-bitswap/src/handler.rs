@@ -6,6 +6,7 @@ use std::{
     time::{Duration, Instant},
 };
 
+use ahash::AHashMap;
 use asynchronous_codec::Framed;
 use futures::prelude::*;
 use futures::StreamExt;
@@ -25,7 +26,7 @@ use crate::{
     error::Error,
     message::BitswapMessage,
     network,
-    protocol::{BitswapCodec, ProtocolConfig, ProtocolId},
+    protocol::{BitswapCodec, ProtocolConfig, ProtocolId, SubstreamCounts},
 };
 
 /// The initial time (in seconds) we set the keep alive for protocol negotiations to occur.
@@ -83,6 +84,9 @@ pub enum BitswapHandlerIn {
     // TODO: do we need a close?
     Protect,
     Unprotect,
+    /// Keeps the connection open until the tag is removed again.
+    Tag(String, usize),
+    Untag(String),
 }
 
 /// The maximum number of substreams we accept or create before disconnecting from the peer.
@@ -147,6 +151,14 @@ pub struct BitswapHandler {
 
     /// Flag determining whether to maintain the connection to the peer.
     keep_alive: KeepAlive,
+
+    /// Whether the connection is protected from closing.
+    protected: bool,
+    /// Tags set on the peer with their values, the connection is kept open while there are any.
+    tags: AHashMap<String, usize>,
+
+    /// Open substream counts, shared with the behaviour.
+    substreams: SubstreamCounts,
 }
 
 /// State of the inbound substream, opened either by us or by the remote.
@@ -179,7 +191,11 @@ enum OutboundSubstreamState {
 
 impl BitswapHandler {
     /// Builds a new [`BitswapHandler`].
-    pub fn new(protocol_config: ProtocolConfig, idle_timeout: Duration) -> Self {
+    pub fn new(
+        protocol_config: ProtocolConfig,
+        idle_timeout: Duration,
+        substreams: SubstreamCounts,
+    ) -> Self {
         Self {
             listen_protocol: SubstreamProtocol::new(protocol_config, ()),
             inbound_substream: None,
@@ -194,11 +210,34 @@ impl BitswapHandler {
             idle_timeout,
             upgrade_errors: VecDeque::new(),
             keep_alive: KeepAlive::Until(Instant::now() + Duration::from_secs(INITIAL_KEEP_ALIVE)),
+            protected: false,
+            tags: Default::default(),
             events: Default::default(),
+            substreams,
         }
     }
 }
 
+impl BitswapHandler {
+    fn is_kept_open(&self) -> bool {
+        self.protected || !self.tags.is_empty()
+    }
+
+    /// Sets `keep_alive`, unless the connection is protected or tagged, which keeps it open.
+    fn set_keep_alive(&mut self, keep_alive: KeepAlive) {
+        self.keep_alive = if self.is_kept_open() {
+            KeepAlive::Yes
+        } else {
+            keep_alive
+        };
+    }
+
+    /// Restarts the idle timeout.
+    fn update_keep_alive(&mut self) {
+        self.set_keep_alive(KeepAlive::Until(Instant::now() + self.idle_timeout));
+    }
+}
+
 impl ConnectionHandler for BitswapHandler {
     type InEvent = BitswapHandlerIn;
     type OutEvent = HandlerEvent;
@@ -217,13 +256,14 @@ impl ConnectionHandler for BitswapHandler {
         protocol: <Self::InboundProtocol as InboundUpgrade<NegotiatedSubstream>>::Output,
         _info: Self::InboundOpenInfo,
     ) {
-        let substream = protocol;
+        let mut substream = protocol;
 
         // If the peer doesn't support the protocol, reject all substreams
         if self.protocol_unsupported {
             return;
         }
         let protocol_id = substream.codec().protocol;
+        substream.codec_mut().open = Some(self.substreams.open(protocol_id));
         if self.protocol.is_none() {
             self.protocol = Some(protocol_id);
         }
@@ -240,7 +280,7 @@ impl ConnectionHandler for BitswapHandler {
         protocol: <Self::OutboundProtocol as OutboundUpgrade<NegotiatedSubstream>>::Output,
         message: Self::OutboundOpenInfo,
     ) {
-        let substream = protocol;
+        let mut substream = protocol;
 
         // If the peer doesn't support the protocol, reject all substreams
         if self.protocol_unsupported {
@@ -263,6 +303,7 @@ impl ConnectionHandler for BitswapHandler {
             self.send_queue.push(message);
         } else {
             trace!("New outbound substream: {:?}", protocol_id);
+            substream.codec_mut().open = Some(self.substreams.open(protocol_id));
             self.outbound_substream = Some(OutboundSubstreamState::PendingSend(substream, message));
         }
     }
@@ -280,15 +321,24 @@ impl ConnectionHandler for BitswapHandler {
                     self.send_queue.push((m, response));
                     // received a message, reset keepalive
                     // TODO: should we permanently keep this open instead, until we remove from all sessions?
-                    self.keep_alive = KeepAlive::Until(Instant::now() + self.idle_timeout);
+                    self.update_keep_alive();
                 }
             }
             BitswapHandlerIn::Protect => {
-                self.keep_alive = KeepAlive::Yes;
+                self.protected = true;
+                self.update_keep_alive();
             }
             BitswapHandlerIn::Unprotect => {
-                self.keep_alive =
-                    KeepAlive::Until(Instant::now() + Duration::from_secs(INITIAL_KEEP_ALIVE));
+                self.protected = false;
+                self.update_keep_alive();
+            }
+            BitswapHandlerIn::Tag(tag, value) => {
+                self.tags.insert(tag, value);
+                self.update_keep_alive();
+            }
+            BitswapHandlerIn::Untag(tag) => {
+                self.tags.remove(&tag);
+                self.update_keep_alive();
             }
         }
     }
@@ -336,7 +386,7 @@ impl ConnectionHandler for BitswapHandler {
                                 // clear all substreams so the keep alive returns false
                                 self.inbound_substream = None;
                                 self.outbound_substream = None;
-                                self.keep_alive = KeepAlive::No;
+                                self.set_keep_alive(KeepAlive::No);
                                 return Poll::Ready(ConnectionHandlerEvent::Custom(
                                     HandlerEvent::ProtocolNotSuppported,
                                 ));
@@ -405,7 +455,7 @@ impl ConnectionHandler for BitswapHandler {
                     match substream.poll_next_unpin(cx) {
                         Poll::Ready(Some(Ok(message))) => {
                             // reset keep alive idle timeout
-                            self.keep_alive = KeepAlive::Until(Instant::now() + self.idle_timeout);
+                            self.update_keep_alive();
 
                             self.inbound_substream =
                                 Some(InboundSubstreamState::WaitingInput(substream));
@@ -451,7 +501,7 @@ impl ConnectionHandler for BitswapHandler {
                             }
                             self.inbound_substream = None;
                             if self.outbound_substream.is_none() {
-                                self.keep_alive = KeepAlive::No;
+                                self.set_keep_alive(KeepAlive::No);
                             }
                             break;
                         }
@@ -539,7 +589,7 @@ impl ConnectionHandler for BitswapHandler {
                     match Sink::poll_flush(Pin::new(&mut substream), cx) {
                         Poll::Ready(Ok(())) => {
                             // reset the idle timeout
-                            self.keep_alive = KeepAlive::Until(Instant::now() + self.idle_timeout);
+                            self.update_keep_alive();
 
                             self.outbound_substream =
                                 Some(OutboundSubstreamState::WaitingOutput(substream))
@@ -561,7 +611,7 @@ impl ConnectionHandler for BitswapHandler {
                         Poll::Ready(Ok(())) => {
                             self.outbound_substream = None;
                             if self.inbound_substream.is_none() {
-                                self.keep_alive = KeepAlive::No;
+                                self.set_keep_alive(KeepAlive::No);
                             }
                             break;
                         }
@@ -576,7 +626,7 @@ impl ConnectionHandler for BitswapHandler {
                             ));
                         }
                         Poll::Pending => {
-                            self.keep_alive = KeepAlive::No;
+                            self.set_keep_alive(KeepAlive::No);
                             self.outbound_substream =
                                 Some(OutboundSubstreamState::_Closing(substream));
                             break;
@@ -2,7 +2,7 @@
 //!
 //! Supports the versions `1.0.0`, `1.1.0` and `1.2.0`.
 
-use std::collections::HashSet;
+use std::collections::VecDeque;
 use std::fmt::Debug;
 use std::pin::Pin;
 use std::sync::{Arc, Mutex};
@@ -13,7 +13,7 @@ use ahash::AHashMap;
 use anyhow::Result;
 use async_trait::async_trait;
 use cid::Cid;
-use handler::{BitswapHandler, HandlerEvent};
+use handler::{BitswapHandler, BitswapHandlerIn, HandlerEvent};
 use iroh_metrics::record;
 use iroh_metrics::{bitswap::BitswapMetrics, core::MRecorder, inc};
 use libp2p::core::connection::ConnectionId;
@@ -32,7 +32,7 @@ use self::client::{Client, Config as ClientConfig};
 use self::message::BitswapMessage;
 use self::network::Network;
 use self::network::OutEvent;
-use self::protocol::ProtocolConfig;
+use self::protocol::{ProtocolConfig, SubstreamCounts};
 use self::server::{Config as ServerConfig, Server};
 
 mod block;
@@ -48,9 +48,13 @@ pub mod message;
 pub mod peer_task_queue;
 
 pub use self::block::{tests::*, Block};
-pub use self::protocol::ProtocolId;
+pub use self::client::WantlistEntry;
+pub use self::network::{NetworkConfig, TimeoutPolicy};
+pub use self::protocol::{ProtocolId, SubstreamCounts};
 
 const DIAL_BACK_OFF: Duration = Duration::from_secs(10 * 60);
+/// How long stopping waits for messages that are being sent.
+const NETWORK_STOP_TIMEOUT: Duration = Duration::from_secs(5);
 
 type DialMap = AHashMap<
     PeerId,
@@ -67,6 +71,8 @@ pub struct Bitswap<S: Store> {
     idle_timeout: Duration,
     peers: Arc<Mutex<AHashMap<PeerId, PeerState>>>,
     dials: Arc<Mutex<DialMap>>,
+    /// Events for the handlers of new connections, replaying the protection and tags set before.
+    handler_events: Arc<Mutex<VecDeque<(PeerId, ConnectionId, BitswapHandlerIn)>>>,
     /// Set to true when dialing should be disabled because we have reached the conn limit.
     pause_dialing: bool,
     client: Client<S>,
@@ -74,6 +80,7 @@ pub struct Bitswap<S: Store> {
     incoming_messages: mpsc::Sender<(PeerId, BitswapMessage)>,
     peers_connected: mpsc::Sender<PeerId>,
     peers_disconnected: mpsc::Sender<PeerId>,
+    substreams: SubstreamCounts,
     _workers: Arc<Vec<JoinHandle<()>>>,
 }
 
@@ -103,6 +110,7 @@ pub struct Config {
     pub client: ClientConfig,
     pub server: ServerConfig,
     pub protocol: ProtocolConfig,
+    pub network: NetworkConfig,
     pub idle_timeout: Duration,
 }
 
@@ -112,6 +120,7 @@ impl Default for Config {
             client: ClientConfig::default(),
             server: ServerConfig::default(),
             protocol: ProtocolConfig::default(),
+            network: NetworkConfig::default(),
             idle_timeout: Duration::from_secs(30),
         }
     }
@@ -126,7 +135,7 @@ pub trait Store: Debug + Clone + Send + Sync + 'static {
 
 impl<S: Store> Bitswap<S> {
     pub async fn new(self_id: PeerId, store: S, config: Config) -> Self {
-        let network = Network::new(self_id);
+        let network = Network::new(self_id).with_config(config.network);
         let server = Server::new(network.clone(), store.clone(), config.server).await;
         let client = Client::new(
             network.clone(),
@@ -196,12 +205,14 @@ impl<S: Store> Bitswap<S> {
             idle_timeout: config.idle_timeout,
             peers: Default::default(),
             dials: Default::default(),
+            handler_events: Default::default(),
             pause_dialing: false,
             server,
             client,
             incoming_messages: sender_msg,
             peers_connected: sender_con,
             peers_disconnected: sender_dis,
+            substreams: Default::default(),
             _workers: Arc::new(workers),
         }
     }
@@ -214,8 +225,13 @@ impl<S: Store> Bitswap<S> {
         &self.client
     }
 
+    /// Currently open bitswap substreams across all connections.
+    pub fn substream_counts(&self) -> &SubstreamCounts {
+        &self.substreams
+    }
+
     pub async fn stop(self) -> Result<()> {
-        self.network.stop();
+        self.network.stop(NETWORK_STOP_TIMEOUT).await;
         let (a, b) = futures::future::join(self.client.stop(), self.server.stop()).await;
         a?;
         b?;
@@ -298,6 +314,89 @@ impl<S: Store> Bitswap<S> {
         }
     }
 
+    /// Dials `peer` unless the dial can be answered right away, using the given addresses if
+    /// there are any.
+    ///
+    /// The swarm dials the addresses concurrently, and cancels the remaining dials once one of
+    /// them succeeds.
+    fn start_dial(
+        &mut self,
+        peer: PeerId,
+        addrs: Vec<Multiaddr>,
+        response: oneshot::Sender<std::result::Result<(ConnectionId, Option<ProtocolId>), String>>,
+        id: usize,
+    ) -> Option<NetworkBehaviourAction<BitswapEvent, BitswapHandler>> {
+        match self.get_peer_state(&peer) {
+            Some(PeerState::Responsive(conn, protocol_id)) => {
+                // already connected
+                if let Err(err) = response.send(Ok((conn, Some(protocol_id)))) {
+                    debug!("dial:{}: failed to send dial response {:?}", id, err)
+                }
+                None
+            }
+            Some(PeerState::Connected(conn)) => {
+                // already connected
+                if let Err(err) = response.send(Ok((conn, None))) {
+                    debug!("dial:{}: failed to send dial response {:?}", id, err)
+                }
+                None
+            }
+            Some(PeerState::DialFailure(dialed)) if dialed.elapsed() < DIAL_BACK_OFF => {
+                // Do not bother trying to dial these for now.
+                if let Err(err) = response.send(Err(format!("dial:{}: undialable peer", id))) {
+                    debug!("dial:{}: failed to send dial response {:?}", id, err)
+                }
+                None
+            }
+            _ => {
+                if self.pause_dialing {
+                    // already connected
+                    if let Err(err) = response.send(Err(format!("dial:{}: dialing paused", id))) {
+                        debug!("dial:{}: failed to send dial response {:?}", id, err)
+                    }
+                    return None;
+                }
+
+                self.dials
+                    .lock()
+                    .unwrap()
+                    .entry(peer)
+                    .or_default()
+                    .push((id, response));
+
+                let opts = DialOpts::peer_id(peer)
+                    .condition(libp2p::swarm::dial_opts::PeerCondition::Always);
+                let opts = if addrs.is_empty() {
+                    opts.build()
+                } else {
+                    opts.addresses(addrs).build()
+                };
+                Some(NetworkBehaviourAction::Dial {
+                    opts,
+                    handler: self.new_handler(),
+                })
+            }
+        }
+    }
+
+    /// Passes the event to the connection of the peer, if there is one. Connections established
+    /// later are told the protection and tags of the peer when they are established.
+    fn notify_peer(
+        &self,
+        peer: PeerId,
+        event: BitswapHandlerIn,
+    ) -> Option<NetworkBehaviourAction<BitswapEvent, BitswapHandler>> {
+        let connection = match self.get_peer_state(&peer)? {
+            PeerState::Connected(conn_id) | PeerState::Responsive(conn_id, _) => conn_id,
+            _ => return None,
+        };
+        Some(NetworkBehaviourAction::NotifyHandler {
+            peer_id: peer,
+            handler: NotifyHandler::One(connection),
+            event,
+        })
+    }
+
     fn get_peer_state(&self, peer: &PeerId) -> Option<PeerState> {
         self.peers.lock().unwrap().get(peer).copied()
     }
@@ -385,13 +484,23 @@ pub struct Stat {
     pub provide_buf_len: usize,
 }
 
+/// A provider found for a key, with what the swarm knew about it at the time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProviderRecord {
+    pub peer: PeerId,
+    /// Known addresses of the provider.
+    pub addrs: Vec<Multiaddr>,
+    /// Whether we were connected to the provider when it was found.
+    pub connected: bool,
+}
+
 #[derive(Debug)]
 pub enum BitswapEvent {
     /// We have this content, and want it to be provided.
     Provide { key: Cid },
     FindProviders {
         key: Cid,
-        response: tokio::sync::mpsc::Sender<std::result::Result<HashSet<PeerId>, String>>,
+        response: tokio::sync::mpsc::Sender<std::result::Result<Vec<ProviderRecord>, String>>,
         limit: usize,
     },
     Ping {
@@ -406,7 +515,7 @@ impl<S: Store> NetworkBehaviour for Bitswap<S> {
 
     fn new_handler(&mut self) -> Self::ConnectionHandler {
         let protocol_config = self.protocol_config.clone();
-        BitswapHandler::new(protocol_config, self.idle_timeout)
+        BitswapHandler::new(protocol_config, self.idle_timeout, self.substreams.clone())
     }
 
     fn addresses_of_peer(&mut self, _peer_id: &PeerId) -> Vec<Multiaddr> {
@@ -424,6 +533,15 @@ impl<S: Store> NetworkBehaviour for Bitswap<S> {
         trace!("connection established {} ({})", peer_id, other_established);
         self.set_peer_state(peer_id, PeerState::Connected(*connection));
         self.pause_dialing = false;
+
+        // the handler starts out unprotected and untagged
+        let mut handler_events = self.handler_events.lock().unwrap();
+        if self.network.is_protected(peer_id) {
+            handler_events.push_back((*peer_id, *connection, BitswapHandlerIn::Protect));
+        }
+        for (tag, value) in self.network.peer_tags(peer_id) {
+            handler_events.push_back((*peer_id, *connection, BitswapHandlerIn::Tag(tag, value)));
+        }
     }
 
     fn inject_connection_closed(
@@ -513,6 +631,14 @@ impl<S: Store> NetworkBehaviour for Bitswap<S> {
         _: &mut impl PollParameters,
     ) -> Poll<NetworkBehaviourAction<Self::OutEvent, Self::ConnectionHandler>> {
         inc!(BitswapMetrics::NetworkBehaviourActionPollTick);
+        if let Some((peer_id, connection, event)) = self.handler_events.lock().unwrap().pop_front()
+        {
+            return Poll::Ready(NetworkBehaviourAction::NotifyHandler {
+                peer_id,
+                handler: NotifyHandler::One(connection),
+                event,
+            });
+        }
         // limit work
         for _ in 0..50 {
             match Pin::new(&mut self.network).poll(cx) {
@@ -528,60 +654,18 @@ impl<S: Store> NetworkBehaviour for Bitswap<S> {
                         });
                     }
                     OutEvent::Dial { peer, response, id } => {
-                        match self.get_peer_state(&peer) {
-                            Some(PeerState::Responsive(conn, protocol_id)) => {
-                                // already connected
-                                if let Err(err) = response.send(Ok((conn, Some(protocol_id)))) {
-                                    debug!("dial:{}: failed to send dial response {:?}", id, err)
-                                }
-                                continue;
-                            }
-                            Some(PeerState::Connected(conn)) => {
-                                // already connected
-                                if let Err(err) = response.send(Ok((conn, None))) {
-                                    debug!("dial:{}: failed to send dial response {:?}", id, err)
-                                }
-                                continue;
-                            }
-                            Some(PeerState::DialFailure(dialed))
-                                if dialed.elapsed() < DIAL_BACK_OFF =>
-                            {
-                                // Do not bother trying to dial these for now.
-                                if let Err(err) =
-                                    response.send(Err(format!("dial:{}: undialable peer", id)))
-                                {
-                                    debug!("dial:{}: failed to send dial response {:?}", id, err)
-                                }
-                                continue;
-                            }
-                            _ => {
-                                if self.pause_dialing {
-                                    // already connected
-                                    if let Err(err) =
-                                        response.send(Err(format!("dial:{}: dialing paused", id)))
-                                    {
-                                        debug!(
-                                            "dial:{}: failed to send dial response {:?}",
-                                            id, err
-                                        )
-                                    }
-                                    continue;
-                                }
-
-                                self.dials
-                                    .lock()
-                                    .unwrap()
-                                    .entry(peer)
-                                    .or_default()
-                                    .push((id, response));
-
-                                return Poll::Ready(NetworkBehaviourAction::Dial {
-                                    opts: DialOpts::peer_id(peer)
-                                        .condition(libp2p::swarm::dial_opts::PeerCondition::Always)
-                                        .build(),
-                                    handler: self.new_handler(),
-                                });
-                            }
+                        if let Some(action) = self.start_dial(peer, Vec::new(), response, id) {
+                            return Poll::Ready(action);
+                        }
+                    }
+                    OutEvent::DialAddrs {
+                        peer,
+                        addrs,
+                        response,
+                        id,
+                    } => {
+                        if let Some(action) = self.start_dial(peer, addrs, response, id) {
+                            return Poll::Ready(action);
                         }
                     }
                     OutEvent::GenerateEvent(ev) => {
@@ -597,30 +681,30 @@ impl<S: Store> NetworkBehaviour for Bitswap<S> {
                         return Poll::Ready(NetworkBehaviourAction::NotifyHandler {
                             peer_id: peer,
                             handler: NotifyHandler::One(connection_id),
-                            event: handler::BitswapHandlerIn::Message(message, response),
+                            event: BitswapHandlerIn::Message(message, response),
                         });
                     }
                     OutEvent::ProtectPeer { peer } => {
-                        if let Some(PeerState::Responsive(conn_id, _)) = self.get_peer_state(&peer)
-                        {
-                            return Poll::Ready(NetworkBehaviourAction::NotifyHandler {
-                                peer_id: peer,
-                                handler: NotifyHandler::One(conn_id),
-                                event: handler::BitswapHandlerIn::Protect,
-                            });
+                        if let Some(action) = self.notify_peer(peer, BitswapHandlerIn::Protect) {
+                            return Poll::Ready(action);
+                        }
+                    }
+                    OutEvent::UnprotectPeer { peer } => {
+                        if let Some(action) = self.notify_peer(peer, BitswapHandlerIn::Unprotect) {
+                            return Poll::Ready(action);
+                        }
+                    }
+                    OutEvent::TagPeer { peer, tag, value } => {
+                        trace!("tag {}: {} - {}", peer, tag, value);
+                        let event = BitswapHandlerIn::Tag(tag, value);
+                        if let Some(action) = self.notify_peer(peer, event) {
+                            return Poll::Ready(action);
                         }
                     }
-                    OutEvent::UnprotectPeer { peer, response } => {
-                        if let Some(PeerState::Responsive(conn_id, _)) = self.get_peer_state(&peer)
-                        {
-                            let _ = response.send(true);
-                            return Poll::Ready(NetworkBehaviourAction::NotifyHandler {
-                                peer_id: peer,
-                                handler: NotifyHandler::One(conn_id),
-                                event: handler::BitswapHandlerIn::Unprotect,
-                            });
+                    OutEvent::UntagPeer { peer, tag } => {
+                        if let Some(action) = self.notify_peer(peer, BitswapHandlerIn::Untag(tag)) {
+                            return Poll::Ready(action);
                         }
-                        let _ = response.send(false);
                     }
                 },
             }
@@ -633,6 +717,7 @@ impl<S: Store> NetworkBehaviour for Bitswap<S> {
 #[cfg(test)]
 mod tests {
     use std::io::{Error, ErrorKind};
+    use std::sync::atomic::{AtomicUsize, Ordering};
     use std::sync::Arc;
     use std::time::Duration;
 
@@ -775,6 +860,179 @@ mod tests {
         get_block::<1024>().await;
     }
 
+    #[tokio::test]
+    async fn test_protected_connection_stays_open() {
+        let idle_timeout = Duration::from_millis(200);
+        let config = || Config {
+            idle_timeout,
+            ..Default::default()
+        };
+        let (peer1_id, trans1) = mk_transport();
+        let (peer2_id, trans2) = mk_transport();
+
+        let store1 = TestStore::default();
+        let block = create_random_block_v1();
+        store1
+            .store
+            .write()
+            .await
+            .insert(*block.cid(), block.clone());
+        let bs1 = Bitswap::new(peer1_id, store1, config()).await;
+        let bs2 = Bitswap::new(peer2_id, TestStore::default(), config()).await;
+        // protected before connecting, so the protection is replayed to the new connections
+        bs1.network.protect_peer(peer2_id).await;
+        bs2.network.tag_peer(&peer1_id, "test", 1).await;
+
+        let mut swarm1 = SwarmBuilder::new(trans1, bs1, peer1_id)
+            .executor(Box::new(|fut| {
+                tokio::task::spawn(fut);
+            }))
+            .build();
+        let mut swarm2 = SwarmBuilder::new(trans2, bs2, peer2_id)
+            .executor(Box::new(|fut| {
+                tokio::task::spawn(fut);
+            }))
+            .build();
+        Swarm::listen_on(&mut swarm1, "/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+        let addr = loop {
+            if let Some(SwarmEvent::NewListenAddr { address, .. }) = swarm1.next().await {
+                break address;
+            }
+        };
+
+        let (closed_s, mut closed_r) = mpsc::channel(2);
+        let peer1 = tokio::task::spawn({
+            let closed_s = closed_s.clone();
+            async move {
+                loop {
+                    if let Some(SwarmEvent::ConnectionClosed { .. }) = swarm1.next().await {
+                        closed_s.send(1).await.ok();
+                    }
+                }
+            }
+        });
+        let swarm2_bs = swarm2.behaviour().clone();
+        Swarm::dial(&mut swarm2, addr).unwrap();
+        let peer2 = tokio::task::spawn(async move {
+            loop {
+                match swarm2.next().await {
+                    Some(SwarmEvent::ConnectionEstablished { peer_id, .. }) => {
+                        swarm2
+                            .behaviour()
+                            .on_identify(&peer_id, &["/ipfs/bitswap/1.2.0".to_string()]);
+                    }
+                    Some(SwarmEvent::ConnectionClosed { .. }) => {
+                        closed_s.send(2).await.ok();
+                    }
+                    _ => {}
+                }
+            }
+        });
+
+        let received = swarm2_bs.client().get_block(block.cid()).await.unwrap();
+        assert_eq!(received, block);
+
+        // the exchange doesn't bring back the idle timeout
+        tokio::time::sleep(idle_timeout * 5).await;
+        assert!(closed_r.try_recv().is_err());
+
+        peer1.abort();
+        peer2.abort();
+    }
+
+    #[tokio::test]
+    async fn test_get_block_with_providers() {
+        let (peer1_id, trans) = mk_transport();
+        let store1 = TestStore::default();
+        let bs1 = Bitswap::new(peer1_id, store1.clone(), Config::default()).await;
+        let mut swarm1 = SwarmBuilder::new(trans, bs1, peer1_id)
+            .executor(Box::new(|fut| {
+                tokio::task::spawn(fut);
+            }))
+            .build();
+
+        let block = create_random_block_v1();
+        store1
+            .store
+            .write()
+            .await
+            .insert(*block.cid(), block.clone());
+
+        let (tx, mut rx) = mpsc::channel::<Multiaddr>(1);
+
+        Swarm::listen_on(&mut swarm1, "/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+
+        let peer1 = tokio::task::spawn(async move {
+            while swarm1.next().now_or_never().is_some() {}
+            let listeners: Vec<_> = Swarm::listeners(&swarm1).collect();
+            for l in listeners {
+                tx.send(l.clone()).await.unwrap();
+            }
+
+            loop {
+                let ev = swarm1.next().await;
+                trace!("peer1: {:?}", ev);
+            }
+        });
+
+        let (peer2_id, trans) = mk_transport();
+        let store2 = TestStore::default();
+        let bs2 = Bitswap::new(peer2_id, store2.clone(), Config::default()).await;
+
+        let mut swarm2 = SwarmBuilder::new(trans, bs2, peer2_id)
+            .executor(Box::new(|fut| {
+                tokio::task::spawn(fut);
+            }))
+            .build();
+
+        let swarm2_bs = swarm2.behaviour().clone();
+        let (connected_s, connected_r) = oneshot::channel();
+        let provider_searches = Arc::new(AtomicUsize::new(0));
+        let searches = provider_searches.clone();
+        let peer2 = tokio::task::spawn(async move {
+            let addr = rx.recv().await.unwrap();
+            Swarm::dial(&mut swarm2, addr).unwrap();
+
+            let mut connected_s = Some(connected_s);
+            loop {
+                match swarm2.next().await {
+                    Some(SwarmEvent::ConnectionEstablished { peer_id, .. }) => {
+                        swarm2.behaviour().on_identify(
+                            &peer_id,
+                            &[
+                                "/ipfs/bitswap/1.2.0".to_string(),
+                                "/ipfs/bitswap/1.1.0".to_string(),
+                            ],
+                        );
+                        if let Some(s) = connected_s.take() {
+                            s.send(()).ok();
+                        }
+                    }
+                    Some(SwarmEvent::Behaviour(BitswapEvent::FindProviders { .. })) => {
+                        searches.fetch_add(1, Ordering::SeqCst);
+                    }
+                    ev => trace!("peer2: {:?}", ev),
+                }
+            }
+        });
+
+        connected_r.await.unwrap();
+
+        let received_block = swarm2_bs
+            .client()
+            .get_block_with_providers(1, block.cid(), &[peer1_id])
+            .await
+            .unwrap();
+        assert_eq!(block, received_block);
+        // the hinted provider had the block, so nobody else was searched for
+        assert_eq!(provider_searches.load(Ordering::SeqCst), 0);
+
+        peer1.abort();
+        peer1.await.ok();
+        peer2.abort();
+        peer2.await.ok();
+    }
+
     async fn get_block<const N: usize>() {
         let (peer1_id, trans) = mk_transport();
         let store1 = TestStore::default();
@@ -867,6 +1125,10 @@ mod tests {
                 let received_block = result.unwrap();
                 assert_eq!(block, received_block);
             }
+
+            // the transfer runs over counted substreams
+            assert!(swarm2_bs.substream_counts().total() > 0);
+            assert!(swarm2_bs.substream_counts().get(ProtocolId::Bitswap120) > 0);
         }
 
         {
@@ -1,21 +1,26 @@
 use std::{
     collections::HashSet,
     pin::Pin,
-    sync::{atomic::AtomicUsize, Arc},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
     task::{Context, Poll},
     time::Duration,
 };
 
+use ahash::{AHashMap, AHashSet};
 use anyhow::{anyhow, bail, Result};
 use cid::Cid;
 use futures::Stream;
 use iroh_metrics::{bitswap::BitswapMetrics, inc};
 use iroh_metrics::{core::MRecorder, record};
-use libp2p::{core::connection::ConnectionId, PeerId};
-use tokio::sync::{mpsc, oneshot};
+use libp2p::{core::connection::ConnectionId, Multiaddr, PeerId};
+use rand::Rng;
+use tokio::sync::{mpsc, oneshot, Notify, Semaphore};
 use tracing::{debug, info, trace};
 
-use crate::{message::BitswapMessage, protocol::ProtocolId, BitswapEvent};
+use crate::{message::BitswapMessage, protocol::ProtocolId, BitswapEvent, ProviderRecord};
 
 const CONNECT_TIMEOUT: Duration = Duration::from_secs(20);
 const MAX_SEND_TIMEOUT: Duration = Duration::from_secs(3 * 60 + 5);
@@ -23,6 +28,10 @@ const MIN_SEND_TIMEOUT: Duration = Duration::from_secs(2);
 const SEND_LATENCY: Duration = Duration::from_secs(2);
 // 100kbit/s
 const MIN_SEND_RATE: u64 = (100 * 1000) / 8;
+/// Default capacity of the channel carrying events to the swarm.
+pub const DEFAULT_OUT_EVENT_CAPACITY: usize = 1024;
+/// Default limit of dials that are in progress at the same time.
+pub const DEFAULT_MAX_CONCURRENT_DIALS: usize = 128;
 
 #[derive(Debug, Clone)]
 pub struct Network {
@@ -30,6 +39,35 @@ pub struct Network {
     network_out_sender: async_channel::Sender<OutEvent>,
     self_id: PeerId,
     dial_id: Arc<AtomicUsize>,
+    tags: Arc<Mutex<AHashMap<PeerId, AHashMap<String, usize>>>>,
+    protected: Arc<Mutex<AHashSet<PeerId>>>,
+    config: NetworkConfig,
+    in_flight_sends: Arc<InFlight>,
+    dial_permits: Arc<Semaphore>,
+}
+
+/// Counts the messages handed to the swarm whose outcome is still unknown.
+#[derive(Debug, Default)]
+struct InFlight {
+    count: AtomicUsize,
+    done: Notify,
+}
+
+struct InFlightGuard<'a>(&'a InFlight);
+
+impl<'a> InFlightGuard<'a> {
+    fn new(in_flight: &'a InFlight) -> Self {
+        in_flight.count.fetch_add(1, Ordering::SeqCst);
+        InFlightGuard(in_flight)
+    }
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        if self.0.count.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.done.notify_waiters();
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -39,6 +77,13 @@ pub enum OutEvent {
         response: oneshot::Sender<std::result::Result<(ConnectionId, Option<ProtocolId>), String>>,
         id: usize,
     },
+    /// Dials the peer on the given addresses, concurrently.
+    DialAddrs {
+        peer: PeerId,
+        addrs: Vec<Multiaddr>,
+        response: oneshot::Sender<std::result::Result<(ConnectionId, Option<ProtocolId>), String>>,
+        id: usize,
+    },
     Disconnect(PeerId, oneshot::Sender<()>),
     SendMessage {
         peer: PeerId,
@@ -52,7 +97,15 @@ pub enum OutEvent {
     },
     UnprotectPeer {
         peer: PeerId,
-        response: oneshot::Sender<bool>,
+    },
+    TagPeer {
+        peer: PeerId,
+        tag: String,
+        value: usize,
+    },
+    UntagPeer {
+        peer: PeerId,
+        tag: String,
     },
 }
 
@@ -60,26 +113,86 @@ pub enum OutEvent {
 pub enum SendError {
     #[error("protocol not supported")]
     ProtocolNotSupported,
+    #[error("timeout")]
+    Timeout,
+    #[error("connection closed")]
+    ConnectionClosed,
+    #[error("dial failed: {0}")]
+    DialFailed(String),
     #[error("{0}")]
     Other(String),
 }
 
+impl SendError {
+    /// Returns `true` if sending the message again might succeed.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            SendError::Timeout | SendError::ConnectionClosed | SendError::Other(_) => true,
+            SendError::ProtocolNotSupported | SendError::DialFailed(_) => false,
+        }
+    }
+}
+
 impl Network {
     pub fn new(self_id: PeerId) -> Self {
-        let (network_out_sender, network_out_receiver) = async_channel::bounded(1024);
+        Self::with_capacity(self_id, DEFAULT_OUT_EVENT_CAPACITY)
+    }
+
+    /// Creates a network whose channel to the swarm holds at most `capacity` events, at least
+    /// one.
+    ///
+    /// Once the channel is full, every operation that emits an event (sending messages,
+    /// dialing, finding providers, ...) waits until the swarm has polled enough events to make
+    /// room. Timeouts of these operations include the time spent waiting.
+    pub fn with_capacity(self_id: PeerId, capacity: usize) -> Self {
+        let (network_out_sender, network_out_receiver) = async_channel::bounded(capacity.max(1));
 
         Network {
             network_out_receiver,
             network_out_sender,
             self_id,
             dial_id: Arc::new(AtomicUsize::new(0)),
+            tags: Default::default(),
+            protected: Default::default(),
+            config: NetworkConfig::default(),
+            in_flight_sends: Default::default(),
+            dial_permits: Arc::new(Semaphore::new(DEFAULT_MAX_CONCURRENT_DIALS)),
         }
     }
 
+    /// Limits the number of dials in progress at the same time, further dials wait for one of
+    /// them to finish. The time spent waiting doesn't count towards the dial timeout.
+    pub fn with_max_concurrent_dials(mut self, max_concurrent_dials: usize) -> Self {
+        assert!(max_concurrent_dials > 0);
+        self.dial_permits = Arc::new(Semaphore::new(max_concurrent_dials));
+        self
+    }
+
+    /// Sets the dial and send timeouts.
+    pub fn with_config(mut self, config: NetworkConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Sets the policy for the timeouts of messages sent through [`Network::send_message`].
+    pub fn with_timeout_policy(mut self, timeout_policy: TimeoutPolicy) -> Self {
+        self.config.timeout_policy = timeout_policy;
+        self
+    }
+
+    pub fn config(&self) -> &NetworkConfig {
+        &self.config
+    }
+
     pub fn self_id(&self) -> &PeerId {
         &self.self_id
     }
 
+    /// Number of events waiting to be polled by the swarm.
+    pub fn pending_out_events(&self) -> usize {
+        self.network_out_receiver.len()
+    }
+
     pub async fn ping(&self, peer: &PeerId) -> Result<Duration> {
         let (s, r) = oneshot::channel();
         let res = tokio::time::timeout(Duration::from_secs(30), async {
@@ -98,28 +211,113 @@ impl Network {
         Ok(res)
     }
 
-    pub fn stop(self) {
-        // nothing to do yet
+    /// Reads the latency to the peer `count` times, waiting `interval` between reads.
+    ///
+    /// Each read is a [`Network::ping`], which sends nothing to the peer but returns the
+    /// latency last measured by the periodic pings of the swarm. Samples are only new after
+    /// the swarm pinged the peer again, reads without a latency count as unknown.
+    pub async fn sample_latency(
+        &self,
+        peer: &PeerId,
+        count: usize,
+        interval: Duration,
+    ) -> Result<LatencyStats> {
+        if count == 0 {
+            bail!("no latency samples to take");
+        }
+
+        let mut latencies = Vec::with_capacity(count);
+        for i in 0..count {
+            if i > 0 {
+                tokio::time::sleep(interval).await;
+            }
+            match self.ping(peer).await {
+                Ok(latency) => latencies.push(latency),
+                Err(err) => debug!("latency of {}: unknown: {:?}", peer, err),
+            }
+        }
+
+        let mean = if latencies.is_empty() {
+            None
+        } else {
+            Some(latencies.iter().sum::<Duration>() / latencies.len() as u32)
+        };
+        Ok(LatencyStats {
+            min: latencies.iter().min().copied(),
+            max: latencies.iter().max().copied(),
+            mean,
+            unknown: (count - latencies.len()) as f64 / count as f64,
+        })
+    }
+
+    /// Stops the network, for all of its clones.
+    ///
+    /// Events that the swarm has not picked up yet are answered with errors, and new events are
+    /// refused. Waits at most `stop_timeout` for the outcome of messages already handed to the
+    /// swarm.
+    pub async fn stop(self, stop_timeout: Duration) {
+        self.network_out_receiver.close();
+        while let Ok(event) = self.network_out_receiver.try_recv() {
+            match event {
+                OutEvent::SendMessage { response, .. } => {
+                    let _ = response.send(Err(SendError::ConnectionClosed));
+                }
+                OutEvent::Dial { response, .. } | OutEvent::DialAddrs { response, .. } => {
+                    let _ = response.send(Err("network stopped".to_string()));
+                }
+                OutEvent::Disconnect(_, response) => {
+                    let _ = response.send(());
+                }
+                // dropping the remaining responders fails their callers
+                _ => {}
+            }
+        }
+
+        let in_flight = &self.in_flight_sends;
+        let drained = tokio::time::timeout(stop_timeout, async {
+            loop {
+                let done = in_flight.done.notified();
+                if in_flight.count.load(Ordering::SeqCst) == 0 {
+                    break;
+                }
+                done.await;
+            }
+        })
+        .await;
+        if drained.is_err() {
+            debug!(
+                "stopped with {} messages in flight",
+                in_flight.count.load(Ordering::SeqCst)
+            );
+        }
     }
 
+    #[tracing::instrument(
+        skip(self, peer, message, config),
+        fields(
+            peer = %peer,
+            len = message.encoded_len(),
+            attempt = tracing::field::Empty,
+        )
+    )]
     pub async fn send_message_with_retry_and_timeout(
         &self,
         peer: PeerId,
         connection_id: ConnectionId,
         message: BitswapMessage,
-        retries: usize,
-        timeout: Duration,
-        backoff: Duration,
-    ) -> Result<()> {
+        config: &MessageSenderConfig,
+    ) -> std::result::Result<(), SendError> {
         debug!("send:{}: start: {:#?}", peer, message);
         inc!(BitswapMetrics::MessagesAttempted);
 
         let num_blocks = message.blocks().count();
         let num_block_bytes = message.blocks().map(|b| b.data.len() as u64).sum();
 
-        tokio::time::timeout(timeout, async {
-            let mut errors: Vec<anyhow::Error> = Vec::new();
+        let retries = config.max_retries;
+        let res = tokio::time::timeout(config.send_timeout, async {
+            let mut last_error = SendError::Other("no attempts made".to_string());
             for i in 1..=retries {
+                tracing::Span::current().record("attempt", i);
                 debug!("send:{}: try {}/{}", peer, i, retries);
                 let (s, r) = oneshot::channel();
                 record!(
@@ -134,42 +332,53 @@ impl Network {
                         connection_id,
                     })
                     .await
-                    .map_err(|e| anyhow!("send:{}: channel send failed: {:?}", peer, e))?;
+                    .map_err(|e| SendError::Other(format!("channel send failed: {:?}", e)))?;
+
+                let guard = InFlightGuard::new(&self.in_flight_sends);
+                let res = r.await;
+                drop(guard);
 
-                match r.await {
+                let err = match res {
                     Ok(Ok(res)) => {
                         info!("send:{}: message sent", peer);
                         return Ok(res);
                     }
                     Ok(Err(SendError::ProtocolNotSupported)) => {
                         // No point in using this peer if they don't speak our protocol.
-                        self.disconnect(peer).await?;
-                        return Err(SendError::ProtocolNotSupported.into());
-                    }
-                    Err(channel_err) => {
-                        debug!(
-                            "send:{}: try {}/{} failed with channel: {:?}",
-                            peer, i, retries, channel_err
-                        );
-                        return Err(anyhow!("send:{}: channel gone: {:?}", peer, channel_err));
-                    }
-                    Ok(Err(other)) => {
-                        debug!(
-                            "send:{}: try {}/{} failed with: {:?}",
-                            peer, i, retries, other
-                        );
-                        errors.push(other.into());
-                        if i < retries - 1 {
-                            // backoff until we retry
-                            tokio::time::sleep(backoff).await;
+                        if let Err(err) = self.disconnect(peer).await {
+                            debug!("send:{}: failed to disconnect: {:?}", peer, err);
                         }
+                        return Err(SendError::ProtocolNotSupported);
                     }
+                    // The handler dropped the response, the connection is gone.
+                    Err(_) => SendError::ConnectionClosed,
+                    Ok(Err(other)) => other,
+                };
+
+                debug!(
+                    "send:{}: try {}/{} failed with: {:?}",
+                    peer, i, retries, err
+                );
+                if !err.is_retryable() {
+                    return Err(err);
+                }
+                last_error = err;
+                if i < retries {
+                    // backoff until we retry
+                    inc!(BitswapMetrics::MessageSendRetries);
+                    tokio::time::sleep(config.backoff(i as u32 - 1)).await;
                 }
             }
-            bail!("send:{}: failed {:?}", peer, errors);
+            Err(last_error)
         })
         .await
-        .map_err(|e| anyhow!("send:{}: {:?}", peer, e))??;
+        .map_err(|_| SendError::Timeout)
+        .and_then(|res| res);
+
+        if let Err(err) = res {
+            record_send_failure(&err);
+            return Err(err);
+        }
 
         debug!("send:{}: success", peer);
         // Record successfull stats
@@ -183,11 +392,19 @@ impl Network {
         Ok(())
     }
 
-    pub async fn find_providers(
+    /// Like [`Network::find_provider_records`], only yielding the peer ids.
+    pub async fn find_providers(&self, key: Cid, limit: usize) -> Result<ProviderQuery> {
+        let records = self.find_provider_records(key, limit).await?;
+        Ok(ProviderQuery { records })
+    }
+
+    /// Searches for providers of `key`, yielding batches of newly found providers together
+    /// with their known addresses and whether they are already connected.
+    pub async fn find_provider_records(
         &self,
         key: Cid,
         limit: usize,
-    ) -> Result<mpsc::Receiver<std::result::Result<HashSet<PeerId>, String>>> {
+    ) -> Result<ProviderRecordQuery> {
         let (s, r) = mpsc::channel(limit);
         self.network_out_sender
             .send(OutEvent::GenerateEvent(BitswapEvent::FindProviders {
@@ -198,28 +415,82 @@ impl Network {
             .await
             .map_err(|e| anyhow!("channel send: {:?}", e))?;
 
-        Ok(r)
+        Ok(ProviderRecordQuery { receiver: r })
     }
 
     pub async fn dial(
         &self,
         peer: PeerId,
         timeout: Duration,
+    ) -> Result<(ConnectionId, Option<ProtocolId>)> {
+        self.dial_addrs(peer, Vec::new(), timeout).await
+    }
+
+    /// Dials the peer and returns the bitswap protocol negotiated with it, which tells whether
+    /// it understands HAVE messages (see [`ProtocolId::supports_have`]).
+    ///
+    /// Fails if the peer is connected but has not negotiated a protocol yet.
+    pub async fn probe_protocol(&self, peer: PeerId, timeout: Duration) -> Result<ProtocolId> {
+        let (_, protocol_id) = self.dial(peer, timeout).await?;
+        protocol_id.ok_or_else(|| anyhow!("no protocol negotiated with {} yet", peer))
+    }
+
+    /// Dials the peer on all of the given addresses at once, returning the first connection
+    /// that is established. The remaining dials are cancelled.
+    pub async fn dial_any(
+        &self,
+        peer: PeerId,
+        addrs: Vec<Multiaddr>,
+        timeout: Duration,
+    ) -> Result<(ConnectionId, Option<ProtocolId>)> {
+        if addrs.is_empty() {
+            bail!("no addresses to dial {}", peer);
+        }
+        self.dial_addrs(peer, addrs, timeout).await
+    }
+
+    /// Dials the peer, on the addresses known to the swarm if `addrs` is empty.
+    #[tracing::instrument(
+        name = "dial",
+        skip(self, peer, addrs, timeout),
+        fields(peer = %peer, addrs = addrs.len(), dial_id = tracing::field::Empty)
+    )]
+    async fn dial_addrs(
+        &self,
+        peer: PeerId,
+        addrs: Vec<Multiaddr>,
+        timeout: Duration,
     ) -> Result<(ConnectionId, Option<ProtocolId>)> {
         let dial_id = self
             .dial_id
             .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        tracing::Span::current().record("dial_id", dial_id);
 
+        let _permit = self
+            .dial_permits
+            .acquire()
+            .await
+            .expect("the semaphore is never closed");
         inc!(BitswapMetrics::AttemptedDials);
         debug!("dial:{}: peer {}", dial_id, peer);
-        let res = tokio::time::timeout(timeout, async move {
+        let res = match tokio::time::timeout(timeout, async move {
             let (s, r) = oneshot::channel();
-            self.network_out_sender
-                .send(OutEvent::Dial {
+            let event = if addrs.is_empty() {
+                OutEvent::Dial {
                     peer,
                     response: s,
                     id: dial_id,
-                })
+                }
+            } else {
+                OutEvent::DialAddrs {
+                    peer,
+                    addrs,
+                    response: s,
+                    id: dial_id,
+                }
+            };
+            self.network_out_sender
+                .send(event)
                 .await
                 .map_err(|e| anyhow!("dial:{}: channel send: {:?}", dial_id, e))?;
 
@@ -229,7 +500,15 @@ impl Network {
             Ok::<_, anyhow::Error>(res)
         })
         .await
-        .map_err(|e| anyhow!("dial:{} error: {:?}", dial_id, e))??;
+        .map_err(|e| anyhow!("dial:{} error: {:?}", dial_id, e))
+        .and_then(|res| res)
+        {
+            Ok(res) => res,
+            Err(err) => {
+                inc!(BitswapMetrics::DialFailures);
+                return Err(err);
+            }
+        };
 
         debug!("dial:{}: success {}", dial_id, peer);
         inc!(BitswapMetrics::Dials);
@@ -242,29 +521,80 @@ impl Network {
         to: PeerId,
         config: MessageSenderConfig,
     ) -> Result<MessageSender> {
-        let (connection_id, protocol_id) = self.dial(to, CONNECT_TIMEOUT).await?;
+        let (connection_id, protocol_id) = self.dial(to, self.config.connect_timeout).await?;
 
         Ok(MessageSender {
             to,
             config,
             network: self.clone(),
-            connection_id,
-            protocol_id,
+            connection: Mutex::new((connection_id, protocol_id)),
+            connected: AtomicBool::new(true),
         })
     }
 
-    pub async fn send_message(&self, peer: PeerId, message: BitswapMessage) -> Result<()> {
-        let (connection_id, _) = self.dial(peer, CONNECT_TIMEOUT).await?;
-        let timeout = send_timeout(message.encoded_len());
-        self.send_message_with_retry_and_timeout(
-            peer,
-            connection_id,
-            message,
-            1,
-            timeout,
-            Duration::from_millis(0),
-        )
-        .await
+    #[tracing::instrument(
+        skip(self, peer, message),
+        fields(peer = %peer, len = message.encoded_len())
+    )]
+    pub async fn send_message(
+        &self,
+        peer: PeerId,
+        message: BitswapMessage,
+    ) -> std::result::Result<(), SendError> {
+        let (connection_id, _) =
+            self.dial(peer, self.config.connect_timeout)
+                .await
+                .map_err(|e| {
+                    let err = SendError::DialFailed(e.to_string());
+                    record_send_failure(&err);
+                    err
+                })?;
+        let config = MessageSenderConfig::builder()
+            .max_retries(1)
+            .send_timeout(
+                self.config
+                    .timeout_policy
+                    .send_timeout(message.encoded_len()),
+            )
+            .build();
+        self.send_message_with_retry_and_timeout(peer, connection_id, message, &config)
+            .await
+    }
+
+    /// Sends all messages to the peer over a single connection, dialing it only once.
+    ///
+    /// The messages are queued on the connection together instead of one after the other. The
+    /// results are in the order of the messages, if the dial fails every message fails with
+    /// the dial error.
+    pub async fn send_messages(
+        &self,
+        peer: PeerId,
+        messages: Vec<BitswapMessage>,
+    ) -> Vec<std::result::Result<(), SendError>> {
+        let connection_id = match self.dial(peer, self.config.connect_timeout).await {
+            Ok((connection_id, _)) => connection_id,
+            Err(err) => {
+                let err = SendError::DialFailed(err.to_string());
+                for _ in &messages {
+                    record_send_failure(&err);
+                }
+                return vec![err; messages.len()];
+            }
+        };
+
+        let sends = messages.into_iter().map(|message| async move {
+            let config = MessageSenderConfig::builder()
+                .max_retries(1)
+                .send_timeout(
+                    self.config
+                        .timeout_policy
+                        .send_timeout(message.encoded_len()),
+                )
+                .build();
+            self.send_message_with_retry_and_timeout(peer, connection_id, message, &config)
+                .await
+        });
+        futures::future::join_all(sends).await
     }
 
     pub async fn disconnect(&self, peer: PeerId) -> Result<()> {
@@ -287,35 +617,122 @@ impl Network {
         Ok(())
     }
 
-    pub fn tag_peer(&self, peer: &PeerId, tag: &str, value: usize) {
-        // TODO: is this needed?
+    /// Provides `key` now and again every `interval`, until the returned guard is dropped.
+    ///
+    /// Keeps content discoverable while provider records expire. Fails if `interval` is zero.
+    pub fn provide_with_refresh(&self, key: Cid, interval: Duration) -> Result<ProvideGuard> {
+        if interval.is_zero() {
+            bail!("provide refresh interval must be non-zero");
+        }
+        let network = self.clone();
+        let handle = tokio::task::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                interval.tick().await;
+                if let Err(err) = network.provide(key).await {
+                    debug!("stopped providing {}: {:?}", key, err);
+                    break;
+                }
+            }
+        });
+        Ok(ProvideGuard { handle })
+    }
+
+    /// Tags the peer, the connection to it is kept open as long as it has any tags.
+    ///
+    /// Setting a tag to the value it already has is a no-op.
+    pub async fn tag_peer(&self, peer: &PeerId, tag: &str, value: usize) {
         trace!("tag {}: {} - {}", peer, tag, value);
+        let previous = self
+            .tags
+            .lock()
+            .unwrap()
+            .entry(*peer)
+            .or_default()
+            .insert(tag.to_string(), value);
+
+        if previous != Some(value) {
+            self.send_out_event(OutEvent::TagPeer {
+                peer: *peer,
+                tag: tag.to_string(),
+                value,
+            })
+            .await;
+        }
     }
 
-    pub fn untag_peer(&self, peer: &PeerId, tag: &str) {
-        // TODO: is this needed?
+    pub async fn untag_peer(&self, peer: &PeerId, tag: &str) {
         trace!("untag {}: {}", peer, tag);
+        let removed = {
+            let mut tags = self.tags.lock().unwrap();
+            match tags.get_mut(peer) {
+                Some(peer_tags) => {
+                    let removed = peer_tags.remove(tag).is_some();
+                    if peer_tags.is_empty() {
+                        tags.remove(peer);
+                    }
+                    removed
+                }
+                None => false,
+            }
+        };
+
+        if removed {
+            self.send_out_event(OutEvent::UntagPeer {
+                peer: *peer,
+                tag: tag.to_string(),
+            })
+            .await;
+        }
     }
 
+    /// Keeps the connection to the peer open until it is unprotected.
     pub async fn protect_peer(&self, peer: PeerId) {
         trace!("protect {}", peer);
-        let _ = self.network_out_sender.send(OutEvent::ProtectPeer { peer });
+        if self.protected.lock().unwrap().insert(peer) {
+            self.send_out_event(OutEvent::ProtectPeer { peer }).await;
+        }
     }
 
+    /// Returns `true` if the peer was protected.
     pub async fn unprotect_peer(&self, peer: PeerId) -> bool {
         trace!("unprotect {}", peer);
+        let was_protected = self.protected.lock().unwrap().remove(&peer);
+        if was_protected {
+            self.send_out_event(OutEvent::UnprotectPeer { peer }).await;
+        }
+        was_protected
+    }
 
-        let (s, r) = oneshot::channel();
-        let _ = self
-            .network_out_sender
-            .send(OutEvent::UnprotectPeer { peer, response: s });
+    /// Whether the peer is protected, see [`Network::protect_peer`].
+    pub fn is_protected(&self, peer: &PeerId) -> bool {
+        self.protected.lock().unwrap().contains(peer)
+    }
 
-        r.await.unwrap_or_default()
+    /// The tags set on the peer with their values.
+    pub fn peer_tags(&self, peer: &PeerId) -> Vec<(String, usize)> {
+        self.tags
+            .lock()
+            .unwrap()
+            .get(peer)
+            .map(|tags| {
+                tags.iter()
+                    .map(|(tag, value)| (tag.clone(), *value))
+                    .collect()
+            })
+            .unwrap_or_default()
     }
 
-    pub fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<OutEvent> {
-        inc!(BitswapMetrics::NetworkPollTick);
-        match Pin::new(&mut self.network_out_receiver).poll_next(cx) {
+    async fn send_out_event(&self, event: OutEvent) {
+        if let Err(err) = self.network_out_sender.send(event).await {
+            debug!("channel send: {:?}", err);
+        }
+    }
+
+    /// Like [`Stream::poll_next`], but stays pending once the network is stopped, as the
+    /// swarm has nothing left to do for it.
+    pub fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<OutEvent> {
+        match self.poll_next(cx) {
             Poll::Ready(Some(ev)) => Poll::Ready(ev),
             Poll::Ready(None) => Poll::Pending,
             Poll::Pending => Poll::Pending,
@@ -323,11 +740,105 @@ impl Network {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// The events for the swarm, in the order they were emitted.
+///
+/// Polling an empty network registers the waker, so the swarm is woken once the next event is
+/// emitted. The stream ends once the network is [stopped](Network::stop) and the events left
+/// are drained, until then the network keeps it open with a sender of its own.
+impl Stream for Network {
+    type Item = OutEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        inc!(BitswapMetrics::NetworkPollTick);
+        Pin::new(&mut self.network_out_receiver).poll_next(cx)
+    }
+}
+
+/// Latencies of a series of samples, see [`Network::sample_latency`], `None` if none of them
+/// had one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LatencyStats {
+    pub min: Option<Duration>,
+    pub max: Option<Duration>,
+    pub mean: Option<Duration>,
+    /// The fraction of samples without a known latency.
+    pub unknown: f64,
+}
+
+/// Re-announces a key while alive, see [`Network::provide_with_refresh`].
+#[derive(Debug)]
+#[must_use = "dropping the guard stops providing"]
+pub struct ProvideGuard {
+    handle: tokio::task::JoinHandle<()>,
+}
+
+impl Drop for ProvideGuard {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+/// A running provider query, yielding providers as they are found.
+///
+/// The query is stopped once it is cancelled or dropped.
+#[derive(Debug)]
+pub struct ProviderRecordQuery {
+    receiver: mpsc::Receiver<std::result::Result<Vec<ProviderRecord>, String>>,
+}
+
+impl ProviderRecordQuery {
+    /// Stops the query. Providers that were already found can still be received.
+    pub fn cancel(&mut self) {
+        self.receiver.close();
+    }
+}
+
+impl Stream for ProviderRecordQuery {
+    type Item = std::result::Result<Vec<ProviderRecord>, String>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+/// Like [`ProviderRecordQuery`], only yielding the peer ids of the providers.
+#[derive(Debug)]
+pub struct ProviderQuery {
+    records: ProviderRecordQuery,
+}
+
+impl ProviderQuery {
+    /// Stops the query. Providers that were already found can still be received.
+    pub fn cancel(&mut self) {
+        self.records.cancel();
+    }
+}
+
+impl Stream for ProviderQuery {
+    type Item = std::result::Result<HashSet<PeerId>, String>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.records).poll_next(cx).map(|item| {
+            item.map(|records| records.map(|records| records.into_iter().map(|r| r.peer).collect()))
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct MessageSenderConfig {
     pub max_retries: usize,
     pub send_timeout: Duration,
+    /// Delay before the first retry.
     pub send_error_backoff: Duration,
+    /// Factor the delay grows by with every further retry. Negative and NaN factors keep the
+    /// delay constant.
+    pub backoff_multiplier: f64,
+    pub max_backoff: Duration,
+    /// Randomizes each delay between half and all of its value, so that many senders failing
+    /// at once don't retry at once.
+    pub backoff_jitter: bool,
+    /// Whether to dial the peer again, once, when the connection turns out to be closed.
+    pub reconnect: bool,
 }
 
 impl Default for MessageSenderConfig {
@@ -336,20 +847,152 @@ impl Default for MessageSenderConfig {
             max_retries: 3,
             send_timeout: MAX_SEND_TIMEOUT,
             send_error_backoff: Duration::from_millis(100),
+            backoff_multiplier: 2.0,
+            max_backoff: Duration::from_secs(10),
+            backoff_jitter: false,
+            reconnect: true,
         }
     }
 }
 
-/// Calculates an appropriate timeout based on the message size.
-fn send_timeout(size: usize) -> Duration {
-    let mut timeout = SEND_LATENCY;
-    timeout += Duration::from_secs(size as u64 / MIN_SEND_RATE);
-    if timeout > MAX_SEND_TIMEOUT {
-        MAX_SEND_TIMEOUT
-    } else if timeout < MIN_SEND_TIMEOUT {
-        MIN_SEND_TIMEOUT
-    } else {
-        timeout
+impl MessageSenderConfig {
+    /// Starts from the default config, for overriding single fields.
+    pub fn builder() -> MessageSenderConfigBuilder {
+        MessageSenderConfigBuilder::default()
+    }
+
+    /// The delay before retrying after the given failed attempt, counting from zero.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let multiplier = if self.backoff_multiplier >= 0.0 {
+            self.backoff_multiplier
+        } else {
+            1.0
+        };
+        let backoff = self.send_error_backoff.as_secs_f64()
+            * multiplier.powi(attempt.try_into().unwrap_or(i32::MAX));
+        // compared as floats, as `Duration::from_secs_f64` panics for values too large for a
+        // duration, and for the NaN of an infinite multiplier without an initial delay
+        let backoff = if backoff >= self.max_backoff.as_secs_f64() {
+            self.max_backoff
+        } else {
+            Duration::from_secs_f64(backoff.max(0.0))
+        };
+        if self.backoff_jitter {
+            rand::thread_rng().gen_range(backoff / 2..=backoff)
+        } else {
+            backoff
+        }
+    }
+}
+
+/// Builds a [`MessageSenderConfig`], fields that aren't set keep their default.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MessageSenderConfigBuilder {
+    config: MessageSenderConfig,
+}
+
+impl MessageSenderConfigBuilder {
+    pub fn max_retries(mut self, max_retries: usize) -> Self {
+        self.config.max_retries = max_retries;
+        self
+    }
+
+    pub fn send_timeout(mut self, send_timeout: Duration) -> Self {
+        self.config.send_timeout = send_timeout;
+        self
+    }
+
+    pub fn send_error_backoff(mut self, send_error_backoff: Duration) -> Self {
+        self.config.send_error_backoff = send_error_backoff;
+        self
+    }
+
+    pub fn backoff_multiplier(mut self, backoff_multiplier: f64) -> Self {
+        self.config.backoff_multiplier = backoff_multiplier;
+        self
+    }
+
+    pub fn max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.config.max_backoff = max_backoff;
+        self
+    }
+
+    pub fn backoff_jitter(mut self, backoff_jitter: bool) -> Self {
+        self.config.backoff_jitter = backoff_jitter;
+        self
+    }
+
+    pub fn reconnect(mut self, reconnect: bool) -> Self {
+        self.config.reconnect = reconnect;
+        self
+    }
+
+    pub fn build(self) -> MessageSenderConfig {
+        self.config
+    }
+}
+
+fn record_send_failure(err: &SendError) {
+    match err {
+        SendError::Timeout => inc!(BitswapMetrics::MessageSendTimeouts),
+        SendError::ConnectionClosed => inc!(BitswapMetrics::MessageSendConnectionClosed),
+        SendError::ProtocolNotSupported => inc!(BitswapMetrics::MessageSendProtocolNotSupported),
+        SendError::DialFailed(_) => inc!(BitswapMetrics::MessageSendDialFailed),
+        SendError::Other(_) => inc!(BitswapMetrics::MessageSendOtherFailures),
+    }
+}
+
+/// Timeouts of the [`Network`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NetworkConfig {
+    /// How long dialing a peer may take.
+    pub connect_timeout: Duration,
+    pub timeout_policy: TimeoutPolicy,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        NetworkConfig {
+            connect_timeout: CONNECT_TIMEOUT,
+            timeout_policy: TimeoutPolicy::default(),
+        }
+    }
+}
+
+/// Determines how long sending a message may take, based on its size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeoutPolicy {
+    /// The slowest transfer rate we tolerate, in bytes per second.
+    pub min_rate: u64,
+    /// Time allowed on top of the transfer itself.
+    pub base_latency: Duration,
+    pub min_timeout: Duration,
+    pub max_timeout: Duration,
+}
+
+impl Default for TimeoutPolicy {
+    fn default() -> Self {
+        TimeoutPolicy {
+            min_rate: MIN_SEND_RATE,
+            base_latency: SEND_LATENCY,
+            min_timeout: MIN_SEND_TIMEOUT,
+            max_timeout: MAX_SEND_TIMEOUT,
+        }
+    }
+}
+
+impl TimeoutPolicy {
+    /// Calculates an appropriate timeout based on the message size.
+    pub fn send_timeout(&self, size: usize) -> Duration {
+        let mut timeout = self.base_latency;
+        timeout += Duration::from_secs(size as u64 / self.min_rate.max(1));
+        if timeout > self.max_timeout {
+            self.max_timeout
+        } else if timeout < self.min_timeout {
+            self.min_timeout
+        } else {
+            timeout
+        }
     }
 }
 
@@ -358,29 +1001,694 @@ pub struct MessageSender {
     to: PeerId,
     network: Network,
     config: MessageSenderConfig,
-    connection_id: ConnectionId,
-    protocol_id: Option<ProtocolId>,
+    connection: Mutex<(ConnectionId, Option<ProtocolId>)>,
+    connected: AtomicBool,
 }
 
 impl MessageSender {
     pub fn supports_have(&self) -> bool {
-        self.protocol_id.map(|p| p.supports_have()).unwrap_or(true) // optimisticallly assume haves are supported
+        let protocol_id = self.connection.lock().unwrap().1;
+        protocol_id.map(|p| p.supports_have()).unwrap_or(true) // optimisticallly assume haves are supported
+    }
+
+    /// Returns `false` if the last message could not be sent because the connection was closed.
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    pub async fn send_message(
+        &self,
+        message: BitswapMessage,
+    ) -> std::result::Result<(), SendError> {
+        let reconnect_message = self.config.reconnect.then(|| message.clone());
+        let connection_id = self.connection.lock().unwrap().0;
+        let res = self
+            .network
+            .send_message_with_retry_and_timeout(self.to, connection_id, message, &self.config)
+            .await;
+
+        let res = match (res, reconnect_message) {
+            (Err(SendError::ConnectionClosed), Some(message)) => {
+                debug!("send:{}: connection closed, reconnecting", self.to);
+                let connection = self
+                    .network
+                    .dial(self.to, self.network.config.connect_timeout)
+                    .await
+                    .map_err(|e| SendError::DialFailed(e.to_string()));
+                match connection {
+                    Ok((connection_id, protocol_id)) => {
+                        *self.connection.lock().unwrap() = (connection_id, protocol_id);
+                        self.network
+                            .send_message_with_retry_and_timeout(
+                                self.to,
+                                connection_id,
+                                message,
+                                &self.config,
+                            )
+                            .await
+                    }
+                    Err(err) => Err(err),
+                }
+            }
+            (res, _) => res,
+        };
+
+        if let Err(SendError::ConnectionClosed | SendError::DialFailed(_)) = res {
+            self.connected.store(false, Ordering::Relaxed);
+        } else {
+            self.connected.store(true, Ordering::Relaxed);
+        }
+        res
+    }
+
+    pub async fn disconnect(&self) -> Result<()> {
+        self.network.disconnect(self.to).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::tests::create_random_block_v1;
+
+    /// Polls the out events of a network, like the swarm does.
+    struct MockSwarm(Pin<Box<Network>>);
+
+    impl MockSwarm {
+        fn new(network: &Network) -> Self {
+            MockSwarm(Box::pin(network.clone()))
+        }
+
+        async fn next(&mut self) -> OutEvent {
+            futures::future::poll_fn(|cx| self.0.as_mut().poll(cx)).await
+        }
+    }
+
+    /// Runs `swarm` in the background, answering the out events of `network`.
+    fn spawn_swarm<F, Fut>(network: &Network, swarm: F) -> tokio::task::JoinHandle<Fut::Output>
+    where
+        F: FnOnce(MockSwarm) -> Fut,
+        Fut: std::future::Future + Send + 'static,
+        Fut::Output: Send + 'static,
+    {
+        tokio::task::spawn(swarm(MockSwarm::new(network)))
+    }
+
+    #[tokio::test]
+    async fn test_pending_out_events() {
+        let network = Network::with_capacity(PeerId::random(), 2);
+        assert_eq!(network.pending_out_events(), 0);
+
+        let cid = *create_random_block_v1().cid();
+        network.provide(cid).await.unwrap();
+        network.provide(cid).await.unwrap();
+        assert_eq!(network.pending_out_events(), 2);
+
+        // the channel is full, so the next event waits for the swarm
+        assert!(network.network_out_sender.is_full());
+
+        let mut swarm = MockSwarm::new(&network);
+        swarm.next().await;
+        assert_eq!(network.pending_out_events(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_zero_capacity() {
+        let network = Network::with_capacity(PeerId::random(), 0);
+        let cid = *create_random_block_v1().cid();
+        network.provide(cid).await.unwrap();
+        assert_eq!(network.pending_out_events(), 1);
+        assert!(network.network_out_sender.is_full());
+    }
+
+    #[tokio::test]
+    async fn test_out_event_stream() {
+        use futures::StreamExt;
+
+        let network = Network::new(PeerId::random());
+        let cid = *create_random_block_v1().cid();
+        network.provide(cid).await.unwrap();
+        network.provide(cid).await.unwrap();
+
+        let mut events = network.clone();
+        for _ in 0..2 {
+            assert!(matches!(
+                events.next().await,
+                Some(OutEvent::GenerateEvent(BitswapEvent::Provide { .. }))
+            ));
+        }
+        assert!(futures::poll!(events.next()).is_pending());
+
+        // the parked stream is woken by the next event
+        let next = tokio::task::spawn(async move { events.next().await });
+        tokio::task::yield_now().await;
+        network.provide(cid).await.unwrap();
+        assert!(matches!(
+            next.await.unwrap(),
+            Some(OutEvent::GenerateEvent(BitswapEvent::Provide { .. }))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_out_event_stream_ends_when_stopped() {
+        use futures::StreamExt;
+
+        let network = Network::new(PeerId::random());
+        let mut events = network.clone();
+        let parked = tokio::task::spawn(async move { events.next().await.is_none() });
+        tokio::task::yield_now().await;
+
+        network.clone().stop(Duration::from_millis(10)).await;
+        assert!(parked.await.unwrap());
+        let mut events = network;
+        assert!(events.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_sample_latency() {
+        let network = Network::new(PeerId::random());
+        let peer = PeerId::random();
+
+        // knows the latency for every other sample, with increasing latencies
+        let responder = spawn_swarm(&network, |mut swarm| async move {
+            for i in 0..4 {
+                match swarm.next().await {
+                    OutEvent::GenerateEvent(BitswapEvent::Ping { response, .. }) => {
+                        if i % 2 == 0 {
+                            let latency = Duration::from_millis(10 * (i + 1));
+                            response.send(Some(latency)).unwrap();
+                        }
+                    }
+                    ev => panic!("unexpected event: {:?}", ev),
+                }
+            }
+        });
+
+        let stats = network
+            .sample_latency(&peer, 4, Duration::ZERO)
+            .await
+            .unwrap();
+        responder.await.unwrap();
+        assert_eq!(
+            stats,
+            LatencyStats {
+                min: Some(Duration::from_millis(10)),
+                max: Some(Duration::from_millis(30)),
+                mean: Some(Duration::from_millis(20)),
+                unknown: 0.5,
+            }
+        );
     }
 
-    pub async fn send_message(&self, message: BitswapMessage) -> Result<()> {
-        self.network
+    #[tokio::test]
+    async fn test_stop() {
+        let network = Network::new(PeerId::random());
+        let peer = PeerId::random();
+
+        let send = tokio::task::spawn({
+            let network = network.clone();
+            let config = MessageSenderConfig {
+                max_retries: 1,
+                ..Default::default()
+            };
+            async move {
+                network
+                    .send_message_with_retry_and_timeout(
+                        peer,
+                        ConnectionId::new(0),
+                        BitswapMessage::default(),
+                        &config,
+                    )
+                    .await
+            }
+        });
+        let dial = tokio::task::spawn({
+            let network = network.clone();
+            async move { network.dial(peer, Duration::from_secs(30)).await }
+        });
+        while network.pending_out_events() < 2 {
+            tokio::task::yield_now().await;
+        }
+
+        network.clone().stop(Duration::from_secs(1)).await;
+        assert!(matches!(
+            send.await.unwrap(),
+            Err(SendError::ConnectionClosed)
+        ));
+        assert!(dial.await.unwrap().is_err());
+        assert!(network
+            .provide(*create_random_block_v1().cid())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_send_messages() {
+        let network = Network::new(PeerId::random());
+        let peer = PeerId::random();
+
+        // dials once, then fails the second of three messages
+        let swarm = spawn_swarm(&network, |mut swarm| async move {
+            let mut dials = 0;
+            let mut sent = 0;
+            while sent < 3 {
+                match swarm.next().await {
+                    OutEvent::Dial { response, .. } => {
+                        dials += 1;
+                        response.send(Ok((ConnectionId::new(dials), None))).unwrap();
+                    }
+                    OutEvent::SendMessage {
+                        response,
+                        connection_id,
+                        ..
+                    } => {
+                        assert_eq!(connection_id, ConnectionId::new(1));
+                        let res = if sent == 1 {
+                            Err(SendError::Other("failed".to_string()))
+                        } else {
+                            Ok(())
+                        };
+                        response.send(res).unwrap();
+                        sent += 1;
+                    }
+                    ev => panic!("unexpected event: {:?}", ev),
+                }
+            }
+            dials
+        });
+
+        let messages = vec![BitswapMessage::default(); 3];
+        let results = network.send_messages(peer, messages).await;
+        assert_eq!(swarm.await.unwrap(), 1);
+        assert!(results[0].is_ok());
+        assert!(matches!(results[1], Err(SendError::Other(_))));
+        assert!(results[2].is_ok());
+
+        // without a swarm answering dials, every message fails the same way
+        let network = Network::new(PeerId::random());
+        network.clone().stop(Duration::ZERO).await;
+        let results = network
+            .send_messages(peer, vec![BitswapMessage::default(); 2])
+            .await;
+        assert_eq!(results.len(), 2);
+        assert!(results
+            .iter()
+            .all(|res| matches!(res, Err(SendError::DialFailed(_)))));
+    }
+
+    #[tokio::test]
+    async fn test_probe_protocol() {
+        let network = Network::new(PeerId::random());
+        let peer = PeerId::random();
+
+        let swarm = spawn_swarm(&network, |mut swarm| async move {
+            for protocol_id in [Some(ProtocolId::Bitswap120), None] {
+                match swarm.next().await {
+                    OutEvent::Dial { response, .. } => {
+                        response
+                            .send(Ok((ConnectionId::new(0), protocol_id)))
+                            .unwrap();
+                    }
+                    ev => panic!("unexpected event: {:?}", ev),
+                }
+            }
+        });
+
+        let timeout = Duration::from_secs(5);
+        let protocol_id = network.probe_protocol(peer, timeout).await.unwrap();
+        assert_eq!(protocol_id, ProtocolId::Bitswap120);
+        assert!(protocol_id.supports_have());
+        assert!(network.probe_protocol(peer, timeout).await.is_err());
+        swarm.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_provide_with_refresh() {
+        let network = Network::new(PeerId::random());
+        let cid = *create_random_block_v1().cid();
+
+        assert!(network.provide_with_refresh(cid, Duration::ZERO).is_err());
+
+        let guard = network
+            .provide_with_refresh(cid, Duration::from_millis(10))
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(55)).await;
+        drop(guard);
+        tokio::task::yield_now().await;
+
+        // provided at 0, 10, 20, 30, 40 and 50ms
+        assert_eq!(network.pending_out_events(), 6);
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(network.pending_out_events(), 6);
+    }
+
+    #[tokio::test]
+    async fn test_tag_peer() {
+        let network = Network::new(PeerId::random());
+        let peer = PeerId::random();
+
+        network.tag_peer(&peer, "session", 1).await;
+        network.tag_peer(&peer, "session", 1).await;
+        assert_eq!(network.pending_out_events(), 1);
+
+        network.tag_peer(&peer, "session", 2).await;
+        assert_eq!(network.pending_out_events(), 2);
+
+        network.untag_peer(&peer, "session").await;
+        network.untag_peer(&peer, "session").await;
+        assert_eq!(network.pending_out_events(), 3);
+        assert!(network.tags.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_protect_peer() {
+        let network = Network::new(PeerId::random());
+        let peer = PeerId::random();
+
+        assert!(!network.unprotect_peer(peer).await);
+
+        network.protect_peer(peer).await;
+        network.protect_peer(peer).await;
+        assert_eq!(network.pending_out_events(), 1);
+
+        assert!(network.unprotect_peer(peer).await);
+        assert!(!network.unprotect_peer(peer).await);
+        assert_eq!(network.pending_out_events(), 2);
+    }
+
+    #[test]
+    fn test_timeout_policy() {
+        let default = TimeoutPolicy::default();
+        assert_eq!(default.send_timeout(0), Duration::from_secs(2));
+        assert_eq!(default.send_timeout(125_000), Duration::from_secs(12));
+        assert_eq!(default.send_timeout(usize::MAX), MAX_SEND_TIMEOUT);
+
+        let fast = TimeoutPolicy {
+            min_rate: 10 * 1000 * 1000,
+            base_latency: Duration::from_millis(500),
+            min_timeout: Duration::from_secs(1),
+            max_timeout: Duration::from_secs(30),
+        };
+        assert_eq!(fast.send_timeout(0), Duration::from_secs(1));
+        assert_eq!(
+            fast.send_timeout(100 * 1000 * 1000),
+            Duration::from_millis(10_500)
+        );
+        assert_eq!(fast.send_timeout(usize::MAX), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_message_sender_config_builder() {
+        let config = MessageSenderConfig::builder()
+            .max_retries(5)
+            .send_timeout(Duration::from_secs(1))
+            .backoff_jitter(true)
+            .build();
+        assert_eq!(
+            config,
+            MessageSenderConfig {
+                max_retries: 5,
+                send_timeout: Duration::from_secs(1),
+                backoff_jitter: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            MessageSenderConfig::builder().build(),
+            MessageSenderConfig::default()
+        );
+    }
+
+    #[test]
+    fn test_backoff() {
+        let config = MessageSenderConfig {
+            send_error_backoff: Duration::from_millis(100),
+            backoff_multiplier: 2.0,
+            max_backoff: Duration::from_secs(1),
+            ..Default::default()
+        };
+        let delays: Vec<_> = (0..6).map(|attempt| config.backoff(attempt)).collect();
+        assert_eq!(
+            delays,
+            [100, 200, 400, 800, 1000, 1000].map(Duration::from_millis)
+        );
+
+        let config = MessageSenderConfig {
+            backoff_jitter: true,
+            ..config
+        };
+        for (attempt, delay) in delays.into_iter().enumerate() {
+            let jittered = config.backoff(attempt as u32);
+            assert!(delay / 2 <= jittered && jittered <= delay);
+        }
+
+        let config = MessageSenderConfig {
+            send_error_backoff: Duration::ZERO,
+            backoff_multiplier: f64::INFINITY,
+            max_backoff: Duration::MAX,
+            ..Default::default()
+        };
+        assert_eq!(config.backoff(0), Duration::ZERO);
+        assert_eq!(config.backoff(1), Duration::ZERO);
+    }
+
+    /// The delays between the attempts to send a message that fails every time.
+    async fn retry_delays(config: MessageSenderConfig) -> Vec<Duration> {
+        let network = Network::new(PeerId::random());
+        let attempts = config.max_retries;
+        let swarm = spawn_swarm(&network, |mut swarm| async move {
+            let mut sent_at = Vec::new();
+            while sent_at.len() < attempts {
+                match swarm.next().await {
+                    OutEvent::SendMessage { response, .. } => {
+                        sent_at.push(tokio::time::Instant::now());
+                        response.send(Err(SendError::ConnectionClosed)).unwrap();
+                    }
+                    ev => panic!("unexpected event: {:?}", ev),
+                }
+            }
+            sent_at
+        });
+
+        let res = network
             .send_message_with_retry_and_timeout(
-                self.to,
-                self.connection_id,
-                message,
-                self.config.max_retries,
-                self.config.send_timeout,
-                self.config.send_error_backoff,
+                PeerId::random(),
+                ConnectionId::new(0),
+                BitswapMessage::default(),
+                &config,
             )
+            .await;
+        assert!(matches!(res, Err(SendError::ConnectionClosed)));
+        let sent_at = swarm.await.unwrap();
+        sent_at.windows(2).map(|w| w[1] - w[0]).collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retry_delays() {
+        let config = MessageSenderConfig {
+            max_retries: 5,
+            send_timeout: Duration::from_secs(60),
+            send_error_backoff: Duration::from_millis(100),
+            backoff_multiplier: 3.0,
+            max_backoff: Duration::from_secs(1),
+            ..Default::default()
+        };
+        assert_eq!(
+            retry_delays(config.clone()).await,
+            [100, 300, 900, 1000].map(Duration::from_millis)
+        );
+
+        for backoff_multiplier in [-2.0, f64::NAN] {
+            let config = MessageSenderConfig {
+                backoff_multiplier,
+                ..config.clone()
+            };
+            assert_eq!(
+                retry_delays(config).await,
+                [100; 4].map(Duration::from_millis)
+            );
+        }
+
+        let config = MessageSenderConfig {
+            backoff_multiplier: f64::INFINITY,
+            ..config
+        };
+        assert_eq!(
+            retry_delays(config).await,
+            [100, 1000, 1000, 1000].map(Duration::from_millis)
+        );
+    }
+
+    #[test]
+    fn test_send_error_is_retryable() {
+        assert!(SendError::Timeout.is_retryable());
+        assert!(SendError::ConnectionClosed.is_retryable());
+        assert!(!SendError::ProtocolNotSupported.is_retryable());
+        assert!(!SendError::DialFailed("unreachable".to_string()).is_retryable());
+    }
+
+    #[tokio::test]
+    async fn test_dial_any() {
+        let network = Network::new(PeerId::random());
+        let peer = PeerId::random();
+        let addrs: Vec<Multiaddr> = vec![
+            "/ip4/127.0.0.1/tcp/4001".parse().unwrap(),
+            "/ip4/127.0.0.1/udp/4001/quic".parse().unwrap(),
+        ];
+
+        assert!(network
+            .dial_any(peer, Vec::new(), Duration::from_secs(1))
             .await
+            .is_err());
+
+        let dial = tokio::task::spawn({
+            let network = network.clone();
+            let addrs = addrs.clone();
+            async move { network.dial_any(peer, addrs, Duration::from_secs(5)).await }
+        });
+
+        let mut swarm = MockSwarm::new(&network);
+        match swarm.next().await {
+            OutEvent::DialAddrs {
+                peer: dialed,
+                addrs: dialed_addrs,
+                response,
+                ..
+            } => {
+                assert_eq!(dialed, peer);
+                assert_eq!(dialed_addrs, addrs);
+                response.send(Err("unreachable".to_string())).unwrap();
+            }
+            ev => panic!("unexpected event: {:?}", ev),
+        }
+        assert!(dial.await.unwrap().is_err());
     }
 
-    pub async fn disconnect(&self) -> Result<()> {
-        self.network.disconnect(self.to).await
+    #[tokio::test]
+    async fn test_max_concurrent_dials() {
+        let network = Network::new(PeerId::random()).with_max_concurrent_dials(1);
+        let dials: Vec<_> = (0..2)
+            .map(|_| {
+                let network = network.clone();
+                tokio::task::spawn(async move {
+                    network.dial(PeerId::random(), Duration::from_secs(5)).await
+                })
+            })
+            .collect();
+
+        let mut swarm = MockSwarm::new(&network);
+        let first = match swarm.next().await {
+            OutEvent::Dial { response, .. } => response,
+            ev => panic!("unexpected event: {:?}", ev),
+        };
+        // the second dial waits for the first one to finish
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(network.pending_out_events(), 0);
+
+        first.send(Err("unreachable".to_string())).unwrap();
+        match swarm.next().await {
+            OutEvent::Dial { response, .. } => {
+                response.send(Err("unreachable".to_string())).unwrap()
+            }
+            ev => panic!("unexpected event: {:?}", ev),
+        }
+
+        for dial in dials {
+            assert!(dial.await.unwrap().is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connect_timeout_config() {
+        let config = NetworkConfig {
+            connect_timeout: Duration::from_millis(100),
+            ..Default::default()
+        };
+        let network = Network::new(PeerId::random()).with_config(config);
+        assert_eq!(network.config(), &config);
+
+        // a swarm that never answers the dial
+        let swarm = spawn_swarm(&network, |mut swarm| async move {
+            match swarm.next().await {
+                OutEvent::Dial { response, .. } => response,
+                ev => panic!("unexpected event: {:?}", ev),
+            }
+        });
+
+        let res = tokio::time::timeout(
+            Duration::from_secs(5),
+            network.send_message(PeerId::random(), BitswapMessage::default()),
+        )
+        .await
+        .expect("dial must time out after the configured timeout");
+        assert!(matches!(res, Err(SendError::DialFailed(_))));
+        drop(swarm.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_message_sender_reconnect() {
+        let network = Network::new(PeerId::random());
+        let peer = PeerId::random();
+        let config = MessageSenderConfig {
+            max_retries: 1,
+            ..Default::default()
+        };
+
+        // a swarm whose first connection is gone
+        let swarm = spawn_swarm(&network, |mut swarm| async move {
+            let mut dials = 0;
+            let mut sent = Vec::new();
+            while sent.len() < 2 {
+                match swarm.next().await {
+                    OutEvent::Dial { response, .. } => {
+                        let connection_id = ConnectionId::new(dials);
+                        dials += 1;
+                        response.send(Ok((connection_id, None))).unwrap();
+                    }
+                    OutEvent::SendMessage {
+                        response,
+                        connection_id,
+                        ..
+                    } => {
+                        if connection_id != ConnectionId::new(0) {
+                            response.send(Ok(())).unwrap();
+                        }
+                        sent.push(connection_id);
+                    }
+                    ev => panic!("unexpected event: {:?}", ev),
+                }
+            }
+            (dials, sent)
+        });
+
+        let sender = network.new_message_sender(peer, config).await.unwrap();
+        sender
+            .send_message(BitswapMessage::default())
+            .await
+            .unwrap();
+        assert!(sender.is_connected());
+
+        let (dials, sent) = swarm.await.unwrap();
+        assert_eq!(dials, 2);
+        assert_eq!(sent, vec![ConnectionId::new(0), ConnectionId::new(1)]);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_provider_query() {
+        let network = Network::new(PeerId::random());
+        let mut query = network
+            .find_providers(*create_random_block_v1().cid(), 10)
+            .await
+            .unwrap();
+
+        let mut swarm = MockSwarm::new(&network);
+        let response = match swarm.next().await {
+            OutEvent::GenerateEvent(BitswapEvent::FindProviders { response, .. }) => response,
+            ev => panic!("unexpected event: {:?}", ev),
+        };
+        assert!(!response.is_closed());
+
+        query.cancel();
+        assert!(response.is_closed());
     }
 }
@@ -1,5 +1,7 @@
 use std::future::Future;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
 use asynchronous_codec::{Decoder, Encoder, Framed};
 use bytes::{Bytes, BytesMut};
@@ -56,6 +58,54 @@ impl ProtocolId {
     pub fn supports_have(self) -> bool {
         matches!(self, ProtocolId::Bitswap120)
     }
+
+    /// All protocol versions, oldest first.
+    pub fn all() -> [ProtocolId; 4] {
+        [
+            ProtocolId::Legacy,
+            ProtocolId::Bitswap100,
+            ProtocolId::Bitswap110,
+            ProtocolId::Bitswap120,
+        ]
+    }
+}
+
+/// Number of open bitswap substreams, by protocol version.
+///
+/// Maintained as substreams open and close, so reading it is cheap.
+#[derive(Debug, Default, Clone)]
+pub struct SubstreamCounts(Arc<[AtomicUsize; 4]>);
+
+impl SubstreamCounts {
+    /// Counts a newly negotiated substream until the returned guard is dropped.
+    pub(crate) fn open(&self, protocol: ProtocolId) -> OpenSubstream {
+        self.0[protocol as usize].fetch_add(1, Ordering::Relaxed);
+        OpenSubstream {
+            counts: self.clone(),
+            protocol,
+        }
+    }
+
+    pub fn get(&self, protocol: ProtocolId) -> usize {
+        self.0[protocol as usize].load(Ordering::Relaxed)
+    }
+
+    pub fn total(&self) -> usize {
+        ProtocolId::all().into_iter().map(|p| self.get(p)).sum()
+    }
+}
+
+/// Keeps a substream counted in [`SubstreamCounts`] while it is alive.
+#[derive(Debug)]
+pub(crate) struct OpenSubstream {
+    counts: SubstreamCounts,
+    protocol: ProtocolId,
+}
+
+impl Drop for OpenSubstream {
+    fn drop(&mut self) {
+        self.counts.0[self.protocol as usize].fetch_sub(1, Ordering::Relaxed);
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -134,6 +184,8 @@ pub struct BitswapCodec {
     /// Codec to encode/decode the Unsigned varint length prefix of the frames.
     pub length_codec: codec::UviBytes,
     pub protocol: ProtocolId,
+    /// Set once the handler accepted the substream, counting it as open.
+    pub(crate) open: Option<OpenSubstream>,
 }
 
 impl BitswapCodec {
@@ -141,6 +193,7 @@ impl BitswapCodec {
         BitswapCodec {
             length_codec,
             protocol,
+            open: None,
         }
     }
 }
@@ -182,7 +182,10 @@ mod tests {
                 gateway_addr: None,
                 p2p_addr: None,
                 store_addr: None,
+                fallback_store_addrs: Vec::new(),
                 channels: Some(1),
+                max_retries: None,
+                keepalive_secs: None,
             },
         );
         config.set_default_headers();
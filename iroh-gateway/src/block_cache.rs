@@ -0,0 +1,215 @@
+//! In memory cache of recently loaded blocks, in front of the content loader of the gateway.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use bytes::Bytes;
+use cid::Cid;
+use iroh_metrics::{core::MRecorder, gateway::GatewayMetrics, inc};
+use iroh_resolver::resolver::{ContentLoader, ContextId, LoadedCid, LoaderContext, Source};
+
+use crate::client::verify_block;
+
+/// Name of the cache in [`Source::Store`], for blocks served from it.
+pub const BLOCK_CACHE: &str = "gateway-block-cache";
+
+/// Keeps blocks up to a total size in bytes, evicting the least recently used ones first.
+#[derive(Debug)]
+pub struct BlockCache {
+    max_bytes: usize,
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// Blocks with the tick they were last used at.
+    blocks: HashMap<Cid, (Bytes, u64)>,
+    /// The cids of `blocks` by the tick they were last used at, oldest first.
+    lru: BTreeMap<u64, Cid>,
+    tick: u64,
+    bytes: usize,
+}
+
+impl Inner {
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+}
+
+impl BlockCache {
+    pub fn new(max_bytes: usize) -> Self {
+        BlockCache {
+            max_bytes,
+            inner: Default::default(),
+        }
+    }
+
+    pub fn get(&self, cid: &Cid) -> Option<Bytes> {
+        let mut inner = self.inner.lock().unwrap();
+        let tick = inner.next_tick();
+        let inner = &mut *inner;
+        match inner.blocks.get_mut(cid) {
+            Some((data, used)) => {
+                inner.lru.remove(used);
+                inner.lru.insert(tick, *cid);
+                *used = tick;
+                inc!(GatewayMetrics::BlockCacheHits);
+                Some(data.clone())
+            }
+            None => {
+                inc!(GatewayMetrics::BlockCacheMisses);
+                None
+            }
+        }
+    }
+
+    pub fn contains(&self, cid: &Cid) -> bool {
+        self.inner.lock().unwrap().blocks.contains_key(cid)
+    }
+
+    /// Adds a block, evicting the least recently used blocks until it fits. Blocks larger
+    /// than the whole cache are not kept.
+    pub fn insert(&self, cid: Cid, data: Bytes) {
+        if data.len() > self.max_bytes {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        let tick = inner.next_tick();
+        inner.bytes += data.len();
+        if let Some((old, used)) = inner.blocks.insert(cid, (data, tick)) {
+            inner.lru.remove(&used);
+            inner.bytes -= old.len();
+        }
+        inner.lru.insert(tick, cid);
+
+        while inner.bytes > self.max_bytes {
+            let oldest = *inner
+                .lru
+                .keys()
+                .next()
+                .expect("blocks are left while over the limit");
+            let cid = inner.lru.remove(&oldest).unwrap();
+            if let Some((data, _)) = inner.blocks.remove(&cid) {
+                inner.bytes -= data.len();
+            }
+        }
+    }
+
+    /// Total size of the cached blocks in bytes.
+    pub fn size(&self) -> usize {
+        self.inner.lock().unwrap().bytes
+    }
+}
+
+/// Serves blocks from the [`BlockCache`] if it is set, adding the blocks loaded through
+/// `loader` to it.
+///
+/// Blocks loaded through `loader` are checked against their CID first, unless verification
+/// is turned off. A mismatch fails the load with an [`std::io::Error`] carrying
+/// `ClientError::HashMismatch`, which readers of file contents pass on as is.
+#[derive(Debug, Clone)]
+pub struct CachingLoader<T> {
+    loader: T,
+    cache: Option<Arc<BlockCache>>,
+    verify: bool,
+}
+
+impl<T: ContentLoader> CachingLoader<T> {
+    pub fn new(loader: T, cache: Option<Arc<BlockCache>>) -> Self {
+        CachingLoader {
+            loader,
+            cache,
+            verify: true,
+        }
+    }
+
+    /// Turns checking loaded blocks against their CID on or off, e.g. off for loaders that
+    /// only read from a trusted store.
+    pub fn with_verification(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    pub fn cache(&self) -> Option<&BlockCache> {
+        self.cache.as_deref()
+    }
+}
+
+#[async_trait]
+impl<T: ContentLoader> ContentLoader for CachingLoader<T> {
+    async fn load_cid(&self, cid: &Cid, ctx: &LoaderContext) -> Result<LoadedCid> {
+        // only verified blocks are cached, so hits need no check
+        if let Some(data) = self.cache().and_then(|cache| cache.get(cid)) {
+            return Ok(LoadedCid {
+                data,
+                source: Source::Store(BLOCK_CACHE),
+            });
+        }
+        let loaded = self.loader.load_cid(cid, ctx).await?;
+        if self.verify {
+            verify_block(cid, &loaded.data)
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+        }
+        if let Some(cache) = self.cache() {
+            cache.insert(*cid, loaded.data.clone());
+        }
+        Ok(loaded)
+    }
+
+    async fn stop_session(&self, ctx: ContextId) -> Result<()> {
+        self.loader.stop_session(ctx).await
+    }
+
+    async fn has_cid(&self, cid: &Cid) -> Result<bool> {
+        if self.cache().map_or(false, |cache| cache.contains(cid)) {
+            return Ok(true);
+        }
+        self.loader.has_cid(cid).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cid::multihash::{Code, MultihashDigest};
+    use libipld::IpldCodec;
+
+    use super::*;
+
+    fn block(data: &'static [u8]) -> (Cid, Bytes) {
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(data));
+        (cid, Bytes::from_static(data))
+    }
+
+    #[test]
+    fn evicts_least_recently_used_by_size() {
+        let cache = BlockCache::new(10);
+        let (a, a_data) = block(b"aaaa");
+        let (b, b_data) = block(b"bbbb");
+        let (c, c_data) = block(b"cccccc");
+        cache.insert(a, a_data.clone());
+        cache.insert(b, b_data);
+        assert_eq!(cache.size(), 8);
+
+        // using `a` makes `b` the oldest
+        assert_eq!(cache.get(&a), Some(a_data));
+        cache.insert(c, c_data);
+        assert!(cache.contains(&a));
+        assert!(!cache.contains(&b));
+        assert!(cache.contains(&c));
+        assert_eq!(cache.size(), 10);
+
+        // a block larger than the cache is not kept and evicts nothing
+        let (big, big_data) = block(b"larger than ten bytes");
+        cache.insert(big, big_data);
+        assert!(!cache.contains(&big));
+        assert_eq!(cache.size(), 10);
+
+        // replacing a block doesn't count it twice
+        let (_, c_data) = block(b"cccccc");
+        cache.insert(c, c_data);
+        assert_eq!(cache.size(), 10);
+    }
+}
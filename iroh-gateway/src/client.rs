@@ -1,11 +1,18 @@
+use std::collections::{HashMap, HashSet};
 use std::ops::Range;
 use std::pin::Pin;
+use std::sync::{Arc, Mutex};
 use std::task::Poll;
+use std::time::{Duration, Instant};
 
 use anyhow::Result;
 use bytes::Bytes;
+use cid::multihash::{Code, MultihashDigest};
 use cid::Cid;
-use futures::{StreamExt, TryStream};
+use futures::{
+    future::{BoxFuture, Shared},
+    FutureExt, StreamExt, TryStream, TryStreamExt,
+};
 use http::HeaderMap;
 use iroh_car::{CarHeader, CarWriter};
 use iroh_metrics::{
@@ -14,33 +21,117 @@ use iroh_metrics::{
     observe, record,
 };
 use iroh_resolver::resolver::{
-    CidOrDomain, ContentLoader, Metadata, Out, OutMetrics, OutPrettyReader, OutType, Resolver,
-    ResponseClip, Source,
+    record_block_source, CidOrDomain, ContentLoader, FetchPolicy, Metadata,
+    NameResolutionsExceeded, Out, OutMetrics, OutPrettyReader, OutType, PathType, Resolver,
+    ResponseClip, Source, UnixfsType,
 };
+use iroh_resolver::{codecs::Codec, unixfs::Link};
 use mime::Mime;
-use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncSeekExt, AsyncWrite};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
 use tokio_util::io::ReaderStream;
 use tracing::{info, warn};
 
+use crate::block_cache::{BlockCache, CachingLoader};
+use crate::error::ClientError;
+use crate::headers::etag_matches;
 use crate::response::ResponseFormat;
-use crate::{constants::RECURSION_LIMIT, handlers::GetParams};
+use crate::{
+    constants::{NAME_CACHE_TTL, RECURSION_LIMIT, SHARED_RESOLVE_DEADLINE, SHARED_RESOLVE_LIMIT},
+    handlers::GetParams,
+};
 
 #[derive(Debug, Clone)]
 pub struct Client<T: ContentLoader> {
-    pub(crate) resolver: Resolver<T>,
+    pub(crate) resolver: Resolver<CachingLoader<T>>,
+    resolve_timeout: Option<Duration>,
+    in_flight: Arc<InFlight>,
+    shared_resolve_deadline: Duration,
+    shared_resolve_limit: Duration,
+    names: Arc<NameCache>,
+}
+
+type SharedResolve = Shared<BoxFuture<'static, Result<Out, Arc<anyhow::Error>>>>;
+
+/// Resolves currently running, by path and fetch policy.
+#[derive(Default)]
+struct InFlight(Mutex<HashMap<(String, FetchPolicy), SharedResolve>>);
+
+impl std::fmt::Debug for InFlight {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("InFlight")
+            .field(&self.0.lock().unwrap().len())
+            .finish()
+    }
+}
+
+/// CIDs that IPNS and DNSLink names resolved to, with the time they were resolved.
+#[derive(Default)]
+struct NameCache(Mutex<HashMap<String, (Cid, Instant)>>);
+
+impl NameCache {
+    fn get(&self, name: &str) -> Option<Cid> {
+        let names = self.0.lock().unwrap();
+        match names.get(name) {
+            Some((cid, resolved)) if resolved.elapsed() < NAME_CACHE_TTL => Some(*cid),
+            _ => None,
+        }
+    }
+
+    fn insert(&self, name: String, cid: Cid) {
+        let mut names = self.0.lock().unwrap();
+        names.retain(|_, (_, resolved)| resolved.elapsed() < NAME_CACHE_TTL);
+        names.insert(name, (cid, Instant::now()));
+    }
+}
+
+impl std::fmt::Debug for NameCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("NameCache")
+            .field(&self.0.lock().unwrap().len())
+            .finish()
+    }
 }
 
 pub struct PrettyStreamBody<T: ContentLoader>(
-    ReaderStream<tokio::io::BufReader<OutPrettyReader<T>>>,
+    ReaderStream<tokio::io::BufReader<OutPrettyReader<CachingLoader<T>>>>,
     Option<u64>,
     Option<Mime>,
 );
 
+/// An entry of a UnixFS directory, see [`Client::list_dir`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub cid: Cid,
+    /// Cumulative size of the entry, as recorded in the directory.
+    pub size: Option<u64>,
+    /// Only known for raw blocks, which are always files. Telling the type of other entries
+    /// requires loading them.
+    pub typ: Option<UnixfsType>,
+}
+
+impl From<Link> for DirEntry {
+    fn from(link: Link) -> Self {
+        let typ = match Codec::try_from(link.cid.codec()) {
+            Ok(Codec::Raw) => Some(UnixfsType::File),
+            _ => None,
+        };
+        DirEntry {
+            name: link.name.unwrap_or_default(),
+            cid: link.cid,
+            size: link.tsize,
+            typ,
+        }
+    }
+}
+
 #[allow(clippy::large_enum_variant)]
 pub enum FileResult<T: ContentLoader> {
     File(PrettyStreamBody<T>),
     Directory(Out),
     Raw(PrettyStreamBody<T>),
+    /// The content matches the `If-None-Match` of the request, nothing is streamed.
+    NotModified,
 }
 
 impl<T: ContentLoader> PrettyStreamBody<T> {
@@ -88,55 +179,244 @@ impl<T: ContentLoader + std::marker::Unpin> http_body::Body for PrettyStreamBody
 
 impl<T: ContentLoader + std::marker::Unpin> Client<T> {
     pub fn new(rpc_client: &T) -> Self {
+        Self::with_loader(CachingLoader::new(rpc_client.clone(), None))
+    }
+
+    /// Like [`Client::new`], but keeps up to `max_bytes` of the blocks loaded most recently in
+    /// memory, serving them from there when they are requested again.
+    pub fn with_block_cache(rpc_client: &T, max_bytes: usize) -> Self {
+        let cache = Arc::new(BlockCache::new(max_bytes));
+        Self::with_loader(CachingLoader::new(rpc_client.clone(), Some(cache)))
+    }
+
+    /// Loads blocks through `loader`, see [`CachingLoader::with_verification`] for reading
+    /// without checking the blocks.
+    pub fn with_loader(loader: CachingLoader<T>) -> Self {
+        Self {
+            resolver: Resolver::new(loader),
+            resolve_timeout: None,
+            in_flight: Default::default(),
+            shared_resolve_deadline: SHARED_RESOLVE_DEADLINE,
+            shared_resolve_limit: SHARED_RESOLVE_LIMIT,
+            names: Default::default(),
+        }
+    }
+
+    /// Bounds the number of IPNS/DNSLink names resolved concurrently, see
+    /// [`Resolver::with_name_resolution_limit`].
+    pub fn with_name_resolution_limit(self, limit: usize, max_queued: usize) -> Self {
         Self {
-            resolver: Resolver::new(rpc_client.clone()),
+            resolver: self.resolver.with_name_resolution_limit(limit, max_queued),
+            ..self
+        }
+    }
+
+    /// Fails requests with [`ClientError::Timeout`] if their root block isn't found within
+    /// `timeout`.
+    pub fn with_resolve_timeout(self, timeout: Duration) -> Self {
+        Self {
+            resolve_timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Resolves `path`, fetching blocks from the network as set by `policy`.
+    ///
+    /// The resolve timeout bounds the whole resolve, including retries of the policy.
+    async fn resolve(
+        &self,
+        path: iroh_resolver::resolver::Path,
+        policy: FetchPolicy,
+    ) -> Result<Out, ClientError> {
+        match self.resolve_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, self.resolve_shared(path, policy))
+                .await
+                .map_err(|_| ClientError::Timeout)?,
+            None => self.resolve_shared(path, policy).await,
+        }
+    }
+
+    /// Replaces an IPNS or DNSLink name at the root of `path` by the CID it currently points
+    /// to, reusing resolutions for [`NAME_CACHE_TTL`].
+    async fn resolve_name(
+        &self,
+        path: &iroh_resolver::resolver::Path,
+    ) -> Result<iroh_resolver::resolver::Path, ClientError> {
+        if path.typ() != PathType::Ipns {
+            return Ok(path.clone());
+        }
+        let name = path.root().to_string();
+        let cid = match self.names.get(&name) {
+            Some(cid) => cid,
+            None => {
+                let cid = self.resolver.resolve_name(path).await.map_err(|e| {
+                    if e.is::<NameResolutionsExceeded>() {
+                        ClientError::TooManyNameResolutions(e.to_string())
+                    } else {
+                        ClientError::NameResolution(e.to_string())
+                    }
+                })?;
+                self.names.insert(name, cid);
+                cid
+            }
+        };
+        let mut resolved = iroh_resolver::resolver::Path::from_cid(cid);
+        for part in path.tail() {
+            resolved.push(part);
         }
+        Ok(resolved)
     }
 
+    /// Resolves `path`, sharing the result with concurrent requests for the same path and
+    /// `policy`.
+    ///
+    /// Requests joining a running resolve fall back to their own once it takes longer than
+    /// [`SHARED_RESOLVE_DEADLINE`].
+    ///
+    /// The shared resolve runs in its own task, so it finishes and leaves the in-flight map
+    /// even if the request that started it is cancelled. It gives up after the resolve timeout,
+    /// or [`SHARED_RESOLVE_LIMIT`] without one.
+    async fn resolve_shared(
+        &self,
+        path: iroh_resolver::resolver::Path,
+        policy: FetchPolicy,
+    ) -> Result<Out, ClientError> {
+        let key = (path.to_string(), policy);
+        let (shared, started) = {
+            let mut in_flight = self.in_flight.0.lock().unwrap();
+            match in_flight.get(&key) {
+                Some(shared) => (shared.clone(), false),
+                None => {
+                    let resolver = self.resolver.clone();
+                    let registry = self.in_flight.clone();
+                    let path = path.clone();
+                    let key = key.clone();
+                    let limit = self.resolve_timeout.unwrap_or(self.shared_resolve_limit);
+                    let task = tokio::spawn(async move {
+                        let res = tokio::time::timeout(
+                            limit,
+                            resolver.resolve_with_fetch_policy(path, policy),
+                        )
+                        .await
+                        .unwrap_or_else(|elapsed| Err(elapsed.into()))
+                        .map_err(Arc::new);
+                        registry.0.lock().unwrap().remove(&key);
+                        res
+                    });
+                    let shared = async move {
+                        task.await.unwrap_or_else(|err| {
+                            Err(Arc::new(anyhow::anyhow!("resolve task failed: {}", err)))
+                        })
+                    }
+                    .boxed()
+                    .shared();
+                    in_flight.insert(key, shared.clone());
+                    (shared, true)
+                }
+            }
+        };
+
+        let res = if started {
+            shared.await
+        } else {
+            match tokio::time::timeout(self.shared_resolve_deadline, shared).await {
+                Ok(res) => res,
+                Err(_) => {
+                    info!("shared resolve of {} is slow, resolving separately", key.0);
+                    return self
+                        .resolver
+                        .resolve_with_fetch_policy(path, policy)
+                        .await
+                        .map_err(|err| ClientError::from_resolve_error(&err));
+                }
+            }
+        };
+        res.map_err(|err| ClientError::from_resolve_error(&err))
+    }
+
+    /// Resolves the file at `path`, setting up a body that streams its contents.
+    ///
+    /// IPNS and DNSLink names are resolved first, the returned metadata keeps `path` and
+    /// starts its `resolved_path` with the CID the name points to.
+    ///
+    /// Unless `sniff` is set nothing beyond the root block is loaded here, and the body
+    /// carries no sniffed content-type.
+    ///
+    /// If `if_none_match` matches [`Metadata::etag`], [`FileResult::NotModified`] is returned
+    /// instead of a body.
+    ///
+    /// Blocks of the file, including the ones streamed by the body, are fetched as set by
+    /// `fetch_policy`. The resolve timeout of the client only bounds the root block.
     #[tracing::instrument(skip(self))]
     pub async fn get_file(
         &self,
         path: iroh_resolver::resolver::Path,
         start_time: std::time::Instant,
         range: Option<Range<u64>>,
-    ) -> Result<(FileResult<T>, Metadata), String> {
+        sniff: bool,
+        if_none_match: Option<String>,
+        fetch_policy: FetchPolicy,
+    ) -> Result<(FileResult<T>, Metadata), ClientError> {
         info!("get file {}", path);
         let res = self
-            .resolver
-            .resolve(path)
-            .await
-            .map_err(|e| e.to_string())?;
-        let metadata = res.metadata().clone();
+            .resolve(self.resolve_name(&path).await?, fetch_policy)
+            .await?;
+        let mut metadata = res.metadata().clone();
+        // keeps telling mutable content apart, e.g. for caching headers
+        metadata.path = path.clone();
         record_ttfb_metrics(start_time, &metadata.source);
 
+        if let (Some(inm), Some(etag)) = (if_none_match, metadata.etag()) {
+            if etag_matches(&inm, &etag) {
+                return Ok((FileResult::NotModified, metadata));
+            }
+        }
+
         if res.is_dir() {
             let body = FileResult::Directory(res);
             Ok((body, metadata))
         } else {
+            // ranges starting past the end are left for the caller to reject
+            let range = range
+                .filter(|range| metadata.size.map_or(true, |size| range.start < size))
+                .map(|range| Range {
+                    start: range.start,
+                    end: metadata.size.map_or(range.end, |size| range.end.min(size)),
+                });
             let mut clip = 0;
+            let mut body_size = metadata.size;
             if let Some(range) = &range {
-                clip = range.end as usize;
+                // open ended ranges of unknown size are read to the end
+                if range.end != u64::MAX {
+                    clip = range.end as usize;
+                    body_size = Some(range.end - range.start);
+                } else {
+                    body_size = None;
+                }
             }
             let reader = res
                 .pretty(
                     self.resolver.clone(),
-                    OutMetrics { start: start_time },
+                    OutMetrics::new(start_time),
                     ResponseClip::from(clip),
                 )
-                .map_err(|e| e.to_string())?;
+                .map_err(ClientError::Upstream)?;
 
             let mut buf_reader = tokio::io::BufReader::with_capacity(1024 * 1024, reader);
-            let body_sample = buf_reader.fill_buf().await.map_err(|e| e.to_string())?;
-            let mime = sniff_content_type(body_sample);
+            let mime = if sniff {
+                let body_sample = buf_reader.fill_buf().await?;
+                Some(sniff_content_type(body_sample))
+            } else {
+                None
+            };
             if let Some(range) = range {
                 buf_reader
                     .seek(tokio::io::SeekFrom::Start(range.start))
-                    .await
-                    .map_err(|e| e.to_string())?;
+                    .await?;
             }
             let stream = ReaderStream::new(buf_reader);
 
-            let body = PrettyStreamBody(stream, metadata.size, Some(mime));
+            let body = PrettyStreamBody(stream, body_size, mime);
 
             if metadata.typ == OutType::Raw {
                 return Ok((FileResult::Raw(body), metadata));
@@ -146,23 +426,158 @@ impl<T: ContentLoader + std::marker::Unpin> Client<T> {
         }
     }
 
+    /// Resolves `path` like [`Client::get_file`], returning only its metadata. Nothing beyond
+    /// the root block is loaded, the size of UnixFS files is the one recorded in their root.
+    #[tracing::instrument(skip(self))]
+    pub async fn head(
+        &self,
+        path: iroh_resolver::resolver::Path,
+        fetch_policy: FetchPolicy,
+    ) -> Result<Metadata, ClientError> {
+        info!("head {}", path);
+        let res = self
+            .resolve(self.resolve_name(&path).await?, fetch_policy)
+            .await?;
+        let mut metadata = res.metadata().clone();
+        metadata.path = path;
+        Ok(metadata)
+    }
+
+    /// Loads the single block `cid` as is, without interpreting it as UnixFS or following its
+    /// links. Fails with [`ClientError::HashMismatch`] if the bytes don't hash to `cid`.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_block(&self, cid: Cid) -> Result<Bytes, ClientError> {
+        info!("get block {}", cid);
+        let load = self.resolver.load_block(cid);
+        let loaded = match self.resolve_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, load)
+                .await
+                .map_err(|_| ClientError::Timeout)?,
+            None => load.await,
+        }
+        .map_err(|err| ClientError::from_resolve_error(&err))?;
+        verify_block(&cid, &loaded.data)?;
+        Ok(loaded.data)
+    }
+
+    /// Lists the entries of the UnixFS directory at `path`, including all shards of HAMT
+    /// directories, without loading the entries themselves.
+    #[tracing::instrument(skip(self))]
+    pub async fn list_dir(
+        &self,
+        path: iroh_resolver::resolver::Path,
+        fetch_policy: FetchPolicy,
+    ) -> Result<Vec<DirEntry>, ClientError> {
+        info!("list dir {}", path);
+        let res = self.resolve(path.clone(), fetch_policy).await?;
+        let links = res
+            .unixfs_read_dir(&self.resolver, OutMetrics::new(std::time::Instant::now()))
+            .map_err(ClientError::Upstream)?
+            .ok_or_else(|| ClientError::InvalidPath(format!("{} is not a directory", path)))?;
+        links
+            .map_ok(DirEntry::from)
+            .try_collect()
+            .await
+            .map_err(ClientError::Upstream)
+    }
+
     #[tracing::instrument(skip(self))]
     pub async fn get_car_recursive(
         self,
         path: iroh_resolver::resolver::Path,
         start_time: std::time::Instant,
-    ) -> Result<axum::body::StreamBody<ReaderStream<tokio::io::DuplexStream>>, String> {
+        fetch_policy: FetchPolicy,
+    ) -> Result<axum::body::StreamBody<ReaderStream<tokio::io::DuplexStream>>, ClientError> {
+        let stream = self.get_car(path, start_time, fetch_policy).await?;
+        Ok(axum::body::StreamBody::new(stream))
+    }
+
+    /// Streams the dag below `path` as a CARv1, with the resolved root as its only root and
+    /// every block once, even if several nodes link to it.
+    ///
+    /// IPNS and DNSLink names are resolved before streaming, failures after that end the
+    /// stream early. Blocks are fetched as set by `fetch_policy`.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_car(
+        &self,
+        path: iroh_resolver::resolver::Path,
+        start_time: std::time::Instant,
+        fetch_policy: FetchPolicy,
+    ) -> Result<ReaderStream<tokio::io::DuplexStream>, ClientError> {
         info!("get car {}", path);
+        let path = self.resolve_name(&path).await?;
         // TODO: Find out what a good buffer size is here.
         let (writer, reader) = tokio::io::duplex(1024 * 64);
-        let body = axum::body::StreamBody::new(ReaderStream::new(reader));
         let client = self.clone();
         tokio::task::spawn(async move {
-            if let Err(e) = fetch_car_recursive(&client.resolver, path, writer, start_time).await {
+            if let Err(e) =
+                fetch_car_recursive(&client.resolver, path, writer, start_time, fetch_policy).await
+            {
                 warn!("failed to load recursively: {:?}", e);
             }
         });
 
+        Ok(ReaderStream::new(reader))
+    }
+
+    /// Streams the given ranges of the file at `path` as a `multipart/byteranges` body.
+    ///
+    /// Each range is read through its own seekable reader, the ranges are expected to be
+    /// validated against the file size already.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_file_ranges(
+        self,
+        path: iroh_resolver::resolver::Path,
+        start_time: std::time::Instant,
+        ranges: Vec<Range<u64>>,
+        boundary: String,
+        content_type: Option<String>,
+        fetch_policy: FetchPolicy,
+    ) -> Result<axum::body::StreamBody<ReaderStream<tokio::io::DuplexStream>>, ClientError> {
+        info!("get file ranges {}", path);
+        let res = self.resolve(path, fetch_policy).await?;
+        let (writer, reader) = tokio::io::duplex(1024 * 64);
+        let body = axum::body::StreamBody::new(ReaderStream::new(reader));
+        let client = self.clone();
+        tokio::task::spawn(async move {
+            let parts = ByteRanges {
+                ranges,
+                boundary,
+                content_type,
+            };
+            if let Err(e) =
+                write_byte_ranges(&client.resolver, res, parts, writer, start_time).await
+            {
+                warn!("failed to write byte ranges: {:?}", e);
+            }
+        });
+
+        Ok(body)
+    }
+
+    /// Streams the content below `path` as a tar archive.
+    ///
+    /// Entries are named relative to `path`, the archive is written while the
+    /// DAG is being resolved, so it never has to be held in memory.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_tar(
+        self,
+        path: iroh_resolver::resolver::Path,
+        start_time: std::time::Instant,
+    ) -> Result<axum::body::StreamBody<ReaderStream<tokio::io::DuplexStream>>, ClientError> {
+        info!("get tar {}", path);
+        let (writer, reader) = tokio::io::duplex(1024 * 64);
+        let body = axum::body::StreamBody::new(ReaderStream::new(reader));
+        let client = self.clone();
+        tokio::task::spawn(async move {
+            if let Err(e) =
+                fetch_tar_recursive(&client.resolver, path, writer, start_time, RECURSION_LIMIT)
+                    .await
+            {
+                warn!("failed to load tar recursively: {:?}", e);
+            }
+        });
+
         Ok(body)
     }
 
@@ -171,43 +586,17 @@ impl<T: ContentLoader + std::marker::Unpin> Client<T> {
         self,
         path: iroh_resolver::resolver::Path,
         start_time: std::time::Instant,
-    ) -> Result<axum::body::Body, String> {
+    ) -> Result<axum::body::Body, ClientError> {
         info!("get file {}", path);
         let (mut sender, body) = axum::body::Body::channel();
 
         tokio::spawn(async move {
-            let res = self.resolver.resolve_recursive(path);
-            tokio::pin!(res);
-
-            while let Some(res) = res.next().await {
-                match res {
-                    Ok(res) => {
-                        let metadata = res.metadata().clone();
-                        record_ttfb_metrics(start_time, &metadata.source);
-                        let reader = res.pretty(
-                            self.resolver.clone(),
-                            OutMetrics { start: start_time },
-                            ResponseClip::NoClip,
-                        );
-                        match reader {
-                            Ok(mut reader) => {
-                                let mut bytes = Vec::new();
-                                reader.read_to_end(&mut bytes).await.unwrap();
-                                sender.send_data(bytes.into()).await.unwrap();
-                            }
-                            Err(e) => {
-                                warn!("failed to load recursively: {:?}", e);
-                                sender.abort();
-                                break;
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        warn!("failed to load recursively: {:?}", e);
-                        sender.abort();
-                        break;
-                    }
-                }
+            let timeout = self.resolve_timeout;
+            if let Err(e) =
+                send_file_recursive(&self.resolver, path, &mut sender, start_time, timeout).await
+            {
+                warn!("failed to load recursively: {:?}", e);
+                sender.abort();
             }
         });
 
@@ -223,6 +612,22 @@ impl<T: ContentLoader> Client<T> {
     }
 }
 
+/// Checks that `data` hashes to `cid`.
+pub(crate) fn verify_block(cid: &Cid, data: &[u8]) -> Result<(), ClientError> {
+    let code = cid.hash().code();
+    let hash = Code::try_from(code).map_err(|_| {
+        ClientError::Upstream(anyhow::anyhow!(
+            "unsupported multihash {:#x} of {}",
+            code,
+            cid
+        ))
+    })?;
+    if hash.digest(data).digest() != cid.hash().digest() {
+        return Err(ClientError::HashMismatch(*cid));
+    }
+    Ok(())
+}
+
 #[derive(Debug, Clone)]
 pub struct Request {
     pub format: ResponseFormat,
@@ -231,6 +636,102 @@ pub struct Request {
     pub query_file_name: String,
     pub download: bool,
     pub query_params: GetParams,
+    /// A `HEAD` request, answered with the headers of the `GET` response but no body.
+    pub head: bool,
+}
+
+#[derive(Debug)]
+struct ByteRanges {
+    ranges: Vec<Range<u64>>,
+    boundary: String,
+    content_type: Option<String>,
+}
+
+async fn write_byte_ranges<T, W>(
+    resolver: &Resolver<T>,
+    out: Out,
+    parts: ByteRanges,
+    mut writer: W,
+    start_time: std::time::Instant,
+) -> Result<(), anyhow::Error>
+where
+    T: ContentLoader + std::marker::Unpin,
+    W: AsyncWrite + Send + Unpin,
+{
+    let size = out
+        .metadata()
+        .size
+        .map(|size| size.to_string())
+        .unwrap_or_else(|| "*".to_string());
+    for range in parts.ranges {
+        let mut part_headers = format!("\r\n--{}\r\n", parts.boundary);
+        if let Some(content_type) = &parts.content_type {
+            part_headers.push_str(&format!("Content-Type: {}\r\n", content_type));
+        }
+        part_headers.push_str(&format!(
+            "Content-Range: bytes {}-{}/{}\r\n\r\n",
+            range.start,
+            range.end - 1,
+            size
+        ));
+        writer.write_all(part_headers.as_bytes()).await?;
+
+        let mut reader = out.clone().pretty(
+            resolver.clone(),
+            OutMetrics::new(start_time),
+            ResponseClip::from(range.end as usize),
+        )?;
+        reader.seek(tokio::io::SeekFrom::Start(range.start)).await?;
+        tokio::io::copy(&mut reader.take(range.end - range.start), &mut writer).await?;
+    }
+    writer
+        .write_all(format!("\r\n--{}--\r\n", parts.boundary).as_bytes())
+        .await?;
+    writer.shutdown().await?;
+
+    Ok(())
+}
+
+/// Sends the contents of every file below `path` to `sender`, returning early if the
+/// receiving side is dropped.
+async fn send_file_recursive<T>(
+    resolver: &Resolver<T>,
+    path: iroh_resolver::resolver::Path,
+    sender: &mut hyper::body::Sender,
+    start_time: std::time::Instant,
+    resolve_timeout: Option<Duration>,
+) -> Result<(), anyhow::Error>
+where
+    T: ContentLoader + std::marker::Unpin,
+{
+    let stream = resolver.resolve_recursive(path);
+    tokio::pin!(stream);
+
+    let mut next = match resolve_timeout {
+        Some(timeout) => tokio::time::timeout(timeout, stream.next())
+            .await
+            .map_err(|_| ClientError::Timeout)?,
+        None => stream.next().await,
+    };
+    while let Some(out) = next {
+        let out = out?;
+        record_ttfb_metrics(start_time, &out.metadata().source);
+        let reader = out.pretty(
+            resolver.clone(),
+            OutMetrics::new(start_time),
+            ResponseClip::NoClip,
+        )?;
+        let mut chunks = ReaderStream::new(reader);
+        while let Some(chunk) = chunks.next().await {
+            // the client went away, stop fetching blocks for it
+            if sender.send_data(chunk?).await.is_err() {
+                info!("request cancelled");
+                return Ok(());
+            }
+        }
+        next = stream.next().await;
+    }
+    Ok(())
 }
 
 async fn fetch_car_recursive<T, W>(
@@ -238,12 +739,17 @@ async fn fetch_car_recursive<T, W>(
     path: iroh_resolver::resolver::Path,
     writer: W,
     start_time: std::time::Instant,
+    fetch_policy: FetchPolicy,
 ) -> Result<(), anyhow::Error>
 where
     T: ContentLoader,
     W: AsyncWrite + Send + Unpin,
 {
-    let stream = resolver.resolve_recursive_raw(path, Some(RECURSION_LIMIT));
+    let stream = resolver.resolve_recursive_raw_with_fetch_policy(
+        path.clone(),
+        Some(RECURSION_LIMIT),
+        fetch_policy,
+    );
     tokio::pin!(stream);
 
     let root = stream
@@ -255,21 +761,226 @@ where
     let mut writer = CarWriter::new(header, writer);
     writer.write(*root.cid(), root.content()).await?;
 
+    // blocks linked from several nodes are yielded once per link
+    let mut written = HashSet::new();
+    written.insert(*root.cid());
     while let Some(block) = stream.next().await {
         let block = block?;
         record_ttfb_metrics(start_time, block.source());
-        writer.write(*block.cid(), block.content()).await?;
+        if written.insert(*block.cid()) {
+            writer.write(*block.cid(), block.content()).await?;
+        }
+    }
+    writer.finish().await?;
+    Ok(())
+}
+
+/// Writes the DAG at `path` into `writer` as a tar archive, failing once it has more than
+/// `max_entries` entries.
+async fn fetch_tar_recursive<T, W>(
+    resolver: &Resolver<T>,
+    path: iroh_resolver::resolver::Path,
+    mut writer: W,
+    start_time: std::time::Instant,
+    max_entries: usize,
+) -> Result<(), anyhow::Error>
+where
+    T: ContentLoader + std::marker::Unpin,
+    W: AsyncWrite + Send + Unpin,
+{
+    let root_depth = path.tail().iter().filter(|p| !p.is_empty()).count();
+    // name used when the root itself is a file
+    let root_name = path
+        .tail()
+        .iter()
+        .rev()
+        .find(|p| !p.is_empty())
+        .cloned()
+        .unwrap_or_else(|| path.root().to_string());
+
+    let stream = resolver.resolve_recursive_with_paths(path.clone());
+    tokio::pin!(stream);
+
+    let mut entries = 0;
+    while let Some(res) = stream.next().await {
+        let (entry_path, out) = res?;
+        entries += 1;
+        anyhow::ensure!(
+            entries <= max_entries,
+            "Number of links exceeds the recursion limit."
+        );
+        record_ttfb_metrics(start_time, &out.metadata().source);
+
+        let parts: Vec<&str> = entry_path
+            .tail()
+            .iter()
+            .filter(|p| !p.is_empty())
+            .skip(root_depth)
+            .map(|p| p.as_str())
+            .collect();
+        for part in &parts {
+            anyhow::ensure!(
+                *part != "." && *part != ".." && !part.contains('/'),
+                "invalid entry name {:?}",
+                part
+            );
+        }
+        let name = if parts.is_empty() {
+            if out.is_dir() {
+                // the root directory itself is implied by the archive
+                continue;
+            }
+            root_name.clone()
+        } else {
+            parts.join("/")
+        };
+
+        // UnixFS nodes carry no mode or mtime, use the usual defaults.
+        let mut header = tar::Header::new_gnu();
+        header.set_mtime(0);
+        header.set_uid(0);
+        header.set_gid(0);
+        if out.is_dir() {
+            header.set_entry_type(tar::EntryType::Directory);
+            header.set_mode(0o755);
+            header.set_size(0);
+            write_tar_header(&mut writer, header, &name, None).await?;
+        } else if out.is_symlink() {
+            let mut reader = out.pretty(
+                resolver.clone(),
+                OutMetrics::new(start_time),
+                ResponseClip::NoClip,
+            )?;
+            let mut target = String::new();
+            reader.read_to_string(&mut target).await?;
+            header.set_entry_type(tar::EntryType::Symlink);
+            header.set_mode(0o777);
+            header.set_size(0);
+            write_tar_header(&mut writer, header, &name, Some(&target)).await?;
+        } else {
+            let size = out
+                .metadata()
+                .size
+                .ok_or_else(|| anyhow::anyhow!("unknown size for {}", name))?;
+            let mut reader = out.pretty(
+                resolver.clone(),
+                OutMetrics::new(start_time),
+                ResponseClip::NoClip,
+            )?;
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_mode(0o644);
+            header.set_size(size);
+            write_tar_header(&mut writer, header, &name, None).await?;
+            let written = tokio::io::copy(&mut reader, &mut writer).await?;
+            anyhow::ensure!(
+                written == size,
+                "size mismatch for {}: expected {} bytes, got {}",
+                name,
+                size,
+                written
+            );
+            write_tar_padding(&mut writer, size).await?;
+        }
+    }
+
+    // end of archive marker
+    writer.write_all(&[0u8; 1024]).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Length of the name and link name fields of a tar header.
+const NAME_LEN: usize = 100;
+
+/// Writes a tar header for `name` and, for symlinks, its `link_name`. Each is preceded
+/// by a GNU long name or long link entry if it does not fit into the header itself.
+async fn write_tar_header<W>(
+    writer: &mut W,
+    mut header: tar::Header,
+    name: &str,
+    link_name: Option<&str>,
+) -> Result<(), anyhow::Error>
+where
+    W: AsyncWrite + Unpin,
+{
+    if let Some(link_name) = link_name {
+        if link_name.len() > NAME_LEN {
+            write_tar_long_entry(writer, tar::EntryType::GNULongLink, link_name).await?;
+            header.set_link_name(truncate_tar_name(link_name))?;
+        } else {
+            header.set_link_name(link_name)?;
+        }
     }
+    if name.len() > NAME_LEN {
+        write_tar_long_entry(writer, tar::EntryType::GNULongName, name).await?;
+        header.set_path(truncate_tar_name(name))?;
+    } else {
+        header.set_path(name)?;
+    }
+    header.set_cksum();
+    writer.write_all(header.as_bytes()).await?;
     Ok(())
 }
 
+/// Writes a GNU extension entry carrying a name or link name too long for the header
+/// that follows it.
+async fn write_tar_long_entry<W>(
+    writer: &mut W,
+    entry_type: tar::EntryType,
+    value: &str,
+) -> Result<(), anyhow::Error>
+where
+    W: AsyncWrite + Unpin,
+{
+    let data = value.as_bytes();
+    let mut long_entry = tar::Header::new_gnu();
+    long_entry.as_gnu_mut().expect("gnu header").name[..13].clone_from_slice(b"././@LongLink");
+    long_entry.set_mode(0o644);
+    long_entry.set_mtime(0);
+    long_entry.set_uid(0);
+    long_entry.set_gid(0);
+    long_entry.set_size(data.len() as u64 + 1);
+    long_entry.set_entry_type(entry_type);
+    long_entry.set_cksum();
+    writer.write_all(long_entry.as_bytes()).await?;
+    writer.write_all(data).await?;
+    writer.write_all(&[0]).await?;
+    write_tar_padding(writer, data.len() as u64 + 1).await?;
+    Ok(())
+}
+
+/// The longest prefix of `name` that fits a header field, the full name is in the preceding
+/// GNU extension entry.
+fn truncate_tar_name(name: &str) -> &str {
+    let mut end = NAME_LEN;
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    &name[..end]
+}
+
+/// Pads an entry of `len` bytes to the next tar block boundary.
+async fn write_tar_padding<W>(writer: &mut W, len: u64) -> Result<(), anyhow::Error>
+where
+    W: AsyncWrite + Unpin,
+{
+    let remainder = (len % 512) as usize;
+    if remainder != 0 {
+        writer.write_all(&[0u8; 512][remainder..]).await?;
+    }
+    Ok(())
+}
+
+/// Records the metrics of a resolved root block. Blocks below it are counted by
+/// [`OutMetrics`] as they are streamed.
 fn record_ttfb_metrics(start_time: std::time::Instant, source: &Source) {
+    record_block_source(source);
     record!(
         GatewayMetrics::TimeToFetchFirstBlock,
         start_time.elapsed().as_millis() as u64
     );
     match *source {
-        Source::Store(_) => observe!(
+        Source::Store(_) | Source::FallbackStore(_) => observe!(
             GatewayHistograms::TimeToFetchFirstBlockCached,
             start_time.elapsed().as_millis() as f64
         ),
@@ -294,3 +1005,915 @@ pub(crate) fn sniff_content_type(body_sample: &[u8]) -> Mime {
         body_sample,
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    use async_trait::async_trait;
+    use iroh_resolver::resolver::{BlockNotFound, ContextId, LoadedCid, LoaderContext, Path};
+    use iroh_resolver::unixfs_builder::{DirectoryBuilder, FileBuilder, SymlinkBuilder};
+    use libipld::IpldCodec;
+
+    use super::*;
+
+    /// Serves a single raw block, after a delay.
+    #[derive(Debug, Clone)]
+    struct SlowLoader {
+        cid: Cid,
+        data: Bytes,
+    }
+
+    #[async_trait]
+    impl ContentLoader for SlowLoader {
+        async fn load_cid(&self, cid: &Cid, _ctx: &LoaderContext) -> Result<LoadedCid> {
+            anyhow::ensure!(*cid == self.cid, "not found: {}", cid);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Ok(LoadedCid {
+                data: self.data.clone(),
+                source: Source::Bitswap,
+            })
+        }
+
+        async fn stop_session(&self, _ctx: ContextId) -> Result<()> {
+            Ok(())
+        }
+
+        async fn has_cid(&self, cid: &Cid) -> Result<bool> {
+            Ok(*cid == self.cid)
+        }
+    }
+
+    /// Serves the blocks it holds.
+    #[derive(Debug, Clone, Default)]
+    struct MapLoader(Arc<Mutex<HashMap<Cid, Bytes>>>);
+
+    impl MapLoader {
+        /// Adds the blocks of `dir`, returning the cid of its root.
+        async fn insert_dir(&self, dir: DirectoryBuilder) -> Cid {
+            let mut parts = dir.build().unwrap().encode();
+            let mut root = None;
+            while let Some(part) = parts.next().await {
+                let (cid, bytes, _) = part.unwrap().into_parts();
+                self.0.lock().unwrap().insert(cid, bytes);
+                root = Some(cid);
+            }
+            root.unwrap()
+        }
+    }
+
+    #[async_trait]
+    impl ContentLoader for MapLoader {
+        async fn load_cid(&self, cid: &Cid, _ctx: &LoaderContext) -> Result<LoadedCid> {
+            let data = self.0.lock().unwrap().get(cid).cloned();
+            match data {
+                Some(data) => Ok(LoadedCid {
+                    data,
+                    source: Source::Bitswap,
+                }),
+                None => Err(BlockNotFound { cid: *cid }.into()),
+            }
+        }
+
+        async fn stop_session(&self, _ctx: ContextId) -> Result<()> {
+            Ok(())
+        }
+
+        async fn has_cid(&self, cid: &Cid) -> Result<bool> {
+            Ok(self.0.lock().unwrap().contains_key(cid))
+        }
+    }
+
+    async fn read_body<T: ContentLoader>(body: PrettyStreamBody<T>) -> Vec<u8> {
+        let chunks: Vec<_> = body.0.map(|chunk| chunk.unwrap()).collect().await;
+        chunks.concat()
+    }
+
+    #[tokio::test]
+    async fn get_file_range() {
+        let data = Bytes::from_static(b"hello world");
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+        let client = Client::new(&SlowLoader { cid, data });
+
+        let ranges = [
+            (Range { start: 0, end: 5 }, &b"hello"[..]),
+            (
+                Range {
+                    start: 6,
+                    end: u64::MAX,
+                },
+                &b"world"[..],
+            ),
+            // clamped to the end of the file
+            (Range { start: 4, end: 100 }, &b"o world"[..]),
+        ];
+        for (range, expected) in ranges {
+            let (body, metadata) = client
+                .get_file(
+                    Path::from_cid(cid),
+                    std::time::Instant::now(),
+                    Some(range),
+                    false,
+                    None,
+                    FetchPolicy::default(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(metadata.size, Some(11));
+            let body = match body {
+                FileResult::Raw(body) => body,
+                _ => panic!("expected a raw file"),
+            };
+            assert_eq!(body.get_size(), Some(expected.len() as u64));
+            assert_eq!(read_body(body).await, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn resolve_timeout() {
+        let data = Bytes::from_static(b"hello world");
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+        let loader = SlowLoader { cid, data };
+
+        let client = Client::new(&loader).with_resolve_timeout(Duration::from_millis(10));
+        let res = client
+            .get_file(
+                Path::from_cid(cid),
+                std::time::Instant::now(),
+                None,
+                false,
+                None,
+                FetchPolicy::default(),
+            )
+            .await;
+        assert!(matches!(res, Err(ClientError::Timeout)));
+
+        let client = Client::new(&loader).with_resolve_timeout(Duration::from_secs(60));
+        let res = client
+            .get_file(
+                Path::from_cid(cid),
+                std::time::Instant::now(),
+                None,
+                false,
+                None,
+                FetchPolicy::default(),
+            )
+            .await;
+        assert!(res.is_ok());
+    }
+
+    #[tokio::test]
+    async fn get_file_recursive_streams_chunks() {
+        use http_body::Body as _;
+
+        let data = Bytes::from(vec![7u8; 10_000]);
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+        let client = Client::new(&SlowLoader {
+            cid,
+            data: data.clone(),
+        });
+
+        let mut body = client
+            .get_file_recursive(Path::from_cid(cid), std::time::Instant::now())
+            .await
+            .unwrap();
+        let mut chunks = 0;
+        let mut received = Vec::new();
+        while let Some(chunk) = body.data().await {
+            chunks += 1;
+            received.extend_from_slice(&chunk.unwrap());
+        }
+        assert!(chunks > 1);
+        assert_eq!(received, data);
+    }
+
+    #[tokio::test]
+    async fn get_file_recursive_client_gone() {
+        let data = Bytes::from(vec![7u8; 10_000]);
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+        let client = Client::new(&SlowLoader { cid, data });
+
+        let (mut sender, body) = axum::body::Body::channel();
+        drop(body);
+        send_file_recursive(
+            &client.resolver,
+            Path::from_cid(cid),
+            &mut sender,
+            std::time::Instant::now(),
+            None,
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn list_dir() {
+        let mut file = FileBuilder::new();
+        file.name("hello.txt").content_bytes(b"ola".to_vec());
+        let mut nested = FileBuilder::new();
+        nested.name("nested.txt").content_bytes(b"hi".to_vec());
+        let mut sub = DirectoryBuilder::new();
+        sub.name("sub").add_file(nested.build().await.unwrap());
+        let mut dir = DirectoryBuilder::new();
+        dir.name("demo")
+            .add_file(file.build().await.unwrap())
+            .add_dir(sub.build().unwrap())
+            .unwrap();
+
+        let loader = MapLoader::default();
+        let root = loader.insert_dir(dir).await;
+        let client = Client::new(&loader);
+
+        let entries = client
+            .list_dir(Path::from_cid(root), FetchPolicy::default())
+            .await
+            .unwrap();
+        let names: Vec<_> = entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["hello.txt", "sub"]);
+        assert!(entries
+            .iter()
+            .all(|e| loader.0.lock().unwrap().contains_key(&e.cid)));
+        assert_eq!(entries[1].typ, None);
+
+        let file_path = format!("/ipfs/{}/hello.txt", root).parse().unwrap();
+        assert!(matches!(
+            client.list_dir(file_path, FetchPolicy::default()).await,
+            Err(ClientError::InvalidPath(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn head_classifies_resolve_errors() {
+        let mut file = FileBuilder::new();
+        file.name("hello.txt").content_bytes(b"ola".to_vec());
+        let mut dir = DirectoryBuilder::new();
+        dir.name("demo").add_file(file.build().await.unwrap());
+
+        let loader = MapLoader::default();
+        let root = loader.insert_dir(dir).await;
+        let client = Client::new(&loader);
+
+        let missing = format!("/ipfs/{}/missing.txt", root).parse().unwrap();
+        assert!(matches!(
+            client.head(missing, FetchPolicy::default()).await,
+            Err(ClientError::NotFound(_))
+        ));
+        let below_file = format!("/ipfs/{}/hello.txt/more", root).parse().unwrap();
+        assert!(matches!(
+            client.head(below_file, FetchPolicy::default()).await,
+            Err(ClientError::InvalidPath(_))
+        ));
+    }
+
+    async fn tar_archive<T: ContentLoader + Unpin>(client: &Client<T>, path: Path) -> Vec<u8> {
+        let mut archive = Vec::new();
+        fetch_tar_recursive(
+            &client.resolver,
+            path,
+            &mut archive,
+            Instant::now(),
+            None,
+            usize::MAX,
+        )
+        .await
+        .unwrap();
+        archive
+    }
+
+    #[tokio::test]
+    async fn get_tar_writes_long_link_names() {
+        let long_name = format!("{}.txt", "n".repeat(150));
+        let long_target = format!("{}/{}", "t".repeat(150), long_name);
+        let mut file = FileBuilder::new();
+        file.name(long_name.as_str()).content_bytes(b"ola".to_vec());
+        let mut link = SymlinkBuilder::new("link");
+        link.target(long_target.as_str());
+        let mut dir = DirectoryBuilder::new();
+        dir.name("demo")
+            .add_file(file.build().await.unwrap())
+            .add_symlink(link.build().await.unwrap());
+
+        let loader = MapLoader::default();
+        let root = loader.insert_dir(dir).await;
+        let client = Client::new(&loader);
+        let archive = tar_archive(&client, Path::from_cid(root)).await;
+
+        let mut archive = tar::Archive::new(&archive[..]);
+        let mut entries = Vec::new();
+        for entry in archive.entries().unwrap() {
+            let entry = entry.unwrap();
+            let path = entry.path().unwrap().to_string_lossy().to_string();
+            let link_name = entry
+                .link_name()
+                .unwrap()
+                .map(|l| l.to_string_lossy().to_string());
+            entries.push((path, entry.header().entry_type(), link_name));
+        }
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            entries,
+            [
+                (
+                    "link".to_string(),
+                    tar::EntryType::Symlink,
+                    Some(long_target)
+                ),
+                (long_name, tar::EntryType::Regular, None),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn get_tar_lists_hamt_directory() {
+        // 10000 files, bar/bar.txt and hello.txt in a HAMT sharded directory, see
+        // test_unixfs_hamt_dir in the resolver
+        let root: Cid = "QmUu8pzQ5yjhDrg4GiHYLeko2oT76vcmYX5bw6sjiEJ82k"
+            .parse()
+            .unwrap();
+        let reader = tokio::io::BufReader::new(
+            tokio::fs::File::open("../iroh-resolver/fixtures/big-foo.car")
+                .await
+                .unwrap(),
+        );
+        let car_reader = iroh_car::CarReader::new(reader).await.unwrap();
+        let blocks: HashMap<Cid, Bytes> = car_reader
+            .stream()
+            .map(|r| r.map(|(k, v)| (k, Bytes::from(v))))
+            .try_collect()
+            .await
+            .unwrap();
+        let loader = MapLoader(Arc::new(Mutex::new(blocks)));
+        let client = Client::new(&loader);
+        let archive = tar_archive(&client, Path::from_cid(root)).await;
+
+        let mut archive = tar::Archive::new(&archive[..]);
+        let mut files = HashMap::new();
+        let mut dirs = Vec::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let path = entry.path().unwrap().to_string_lossy().to_string();
+            if entry.header().entry_type().is_dir() {
+                dirs.push(path);
+            } else {
+                let mut content = String::new();
+                std::io::Read::read_to_string(&mut entry, &mut content).unwrap();
+                files.insert(path, content);
+            }
+        }
+        assert_eq!(dirs, ["bar"]);
+        assert_eq!(files.len(), 10_000 + 2);
+        for i in 1..=10000 {
+            assert_eq!(files[&format!("{}.txt", i)], format!("{}\n", i));
+        }
+        assert_eq!(files["bar/bar.txt"], "world\n");
+        assert!(files.contains_key("hello.txt"));
+    }
+
+    #[tokio::test]
+    async fn get_car_dedups_shared_blocks() {
+        let mut dir = DirectoryBuilder::new();
+        dir.name("demo");
+        for name in ["a.txt", "b.txt"] {
+            let mut file = FileBuilder::new();
+            file.name(name).content_bytes(b"same".to_vec());
+            dir.add_file(file.build().await.unwrap());
+        }
+
+        let loader = MapLoader::default();
+        let root = loader.insert_dir(dir).await;
+        let client = Client::new(&loader);
+
+        let stream = client
+            .get_car(
+                Path::from_cid(root),
+                std::time::Instant::now(),
+                FetchPolicy::default(),
+            )
+            .await
+            .unwrap();
+        let mut reader = iroh_car::CarReader::new(tokio_util::io::StreamReader::new(stream))
+            .await
+            .unwrap();
+        assert_eq!(reader.header().roots(), [root]);
+        let mut cids = Vec::new();
+        while let Some((cid, _)) = reader.next_block().await.unwrap() {
+            cids.push(cid);
+        }
+        // the root and the one block both files consist of
+        assert_eq!(cids.len(), 2);
+        assert_eq!(cids[0], root);
+    }
+
+    /// Counts the loads of a [`SlowLoader`].
+    #[derive(Debug, Clone)]
+    struct CountingLoader {
+        inner: SlowLoader,
+        loads: Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl ContentLoader for CountingLoader {
+        async fn load_cid(&self, cid: &Cid, ctx: &LoaderContext) -> Result<LoadedCid> {
+            self.loads.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            self.inner.load_cid(cid, ctx).await
+        }
+
+        async fn stop_session(&self, ctx: ContextId) -> Result<()> {
+            self.inner.stop_session(ctx).await
+        }
+
+        async fn has_cid(&self, cid: &Cid) -> Result<bool> {
+            self.inner.has_cid(cid).await
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_requests_share_resolve() {
+        let data = Bytes::from_static(b"hello world");
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+        let loader = CountingLoader {
+            inner: SlowLoader { cid, data },
+            loads: Default::default(),
+        };
+        let client = Client::new(&loader);
+
+        let requests = (0..3).map(|_| {
+            client.get_file(
+                Path::from_cid(cid),
+                std::time::Instant::now(),
+                None,
+                false,
+                None,
+                FetchPolicy::default(),
+            )
+        });
+        for res in futures::future::join_all(requests).await {
+            assert_eq!(res.unwrap().1.size, Some(11));
+        }
+        assert_eq!(loader.loads.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        // finished resolves are not cached
+        client
+            .get_file(
+                Path::from_cid(cid),
+                std::time::Instant::now(),
+                None,
+                false,
+                None,
+                FetchPolicy::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(loader.loads.load(std::sync::atomic::Ordering::SeqCst), 2);
+        assert!(client.in_flight.0.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn concurrent_requests_with_other_policies_resolve_separately() {
+        let data = Bytes::from_static(b"hello world");
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+        let loader = CountingLoader {
+            inner: SlowLoader { cid, data },
+            loads: Default::default(),
+        };
+        let client = Client::new(&loader);
+
+        let policies = [
+            FetchPolicy::default(),
+            FetchPolicy {
+                max_provider_retries: 2,
+                per_block_timeout: Some(Duration::from_secs(1)),
+            },
+        ];
+        let requests = policies.iter().map(|policy| {
+            client.get_file(
+                Path::from_cid(cid),
+                std::time::Instant::now(),
+                None,
+                false,
+                None,
+                *policy,
+            )
+        });
+        for res in futures::future::join_all(requests).await {
+            assert_eq!(res.unwrap().1.size, Some(11));
+        }
+        assert_eq!(loader.loads.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    /// Never answers the first load, serves the block to the ones after it.
+    #[derive(Debug, Clone)]
+    struct StallingLoader {
+        inner: SlowLoader,
+        loads: Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl ContentLoader for StallingLoader {
+        async fn load_cid(&self, cid: &Cid, ctx: &LoaderContext) -> Result<LoadedCid> {
+            if self.loads.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 0 {
+                futures::future::pending::<()>().await;
+            }
+            self.inner.load_cid(cid, ctx).await
+        }
+
+        async fn stop_session(&self, ctx: ContextId) -> Result<()> {
+            self.inner.stop_session(ctx).await
+        }
+
+        async fn has_cid(&self, cid: &Cid) -> Result<bool> {
+            self.inner.has_cid(cid).await
+        }
+    }
+
+    #[tokio::test]
+    async fn cancelled_request_leaves_shared_resolve_running() {
+        let data = Bytes::from_static(b"hello world");
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+        let loader = CountingLoader {
+            inner: SlowLoader { cid, data },
+            loads: Default::default(),
+        };
+        let client = Client::new(&loader);
+        let get_file = || {
+            client.get_file(
+                Path::from_cid(cid),
+                std::time::Instant::now(),
+                None,
+                false,
+                None,
+                FetchPolicy::default(),
+            )
+        };
+
+        // cancelled while its resolve is loading the root block
+        assert!(tokio::time::timeout(Duration::from_millis(10), get_file())
+            .await
+            .is_err());
+        assert_eq!(client.in_flight.0.lock().unwrap().len(), 1);
+
+        // joins the resolve that is still running, well before the shared resolve deadline
+        let (_, metadata) = tokio::time::timeout(Duration::from_secs(1), get_file())
+            .await
+            .expect("waited for the cancelled resolve")
+            .unwrap();
+        assert_eq!(metadata.size, Some(11));
+        assert_eq!(loader.loads.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert!(client.in_flight.0.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn stalled_shared_resolve_gives_up() {
+        let data = Bytes::from_static(b"hello world");
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+        let loader = StallingLoader {
+            inner: SlowLoader { cid, data },
+            loads: Default::default(),
+        };
+        let mut client = Client::new(&loader);
+        client.shared_resolve_limit = Duration::from_millis(50);
+
+        let res = tokio::time::timeout(
+            Duration::from_secs(1),
+            client.get_file(
+                Path::from_cid(cid),
+                std::time::Instant::now(),
+                None,
+                false,
+                None,
+                FetchPolicy::default(),
+            ),
+        )
+        .await
+        .expect("shared resolve kept running");
+        assert!(matches!(res, Err(ClientError::Timeout)));
+        assert!(client.in_flight.0.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn slow_shared_resolve_falls_back_to_own_resolve() {
+        let data = Bytes::from_static(b"hello world");
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+        let loader = StallingLoader {
+            inner: SlowLoader { cid, data },
+            loads: Default::default(),
+        };
+        let mut client = Client::new(&loader);
+        client.shared_resolve_deadline = Duration::from_millis(100);
+        let get_file = || {
+            client.get_file(
+                Path::from_cid(cid),
+                std::time::Instant::now(),
+                None,
+                false,
+                None,
+                FetchPolicy::default(),
+            )
+        };
+
+        let stalled = tokio::spawn({
+            let client = client.clone();
+            async move {
+                client
+                    .get_file(
+                        Path::from_cid(cid),
+                        std::time::Instant::now(),
+                        None,
+                        false,
+                        None,
+                        FetchPolicy::default(),
+                    )
+                    .await
+                    .map(|_| ())
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(client.in_flight.0.lock().unwrap().len(), 1);
+
+        let (_, metadata) = tokio::time::timeout(Duration::from_secs(1), get_file())
+            .await
+            .expect("no fallback to an own resolve")
+            .unwrap();
+        assert_eq!(metadata.size, Some(11));
+        assert_eq!(loader.loads.load(std::sync::atomic::Ordering::SeqCst), 2);
+        // the stalled resolve is still the shared one
+        assert_eq!(client.in_flight.0.lock().unwrap().len(), 1);
+        stalled.abort();
+    }
+
+    #[tokio::test]
+    async fn block_cache_serves_repeated_requests() {
+        let data = Bytes::from_static(b"hello world");
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+        let loader = CountingLoader {
+            inner: SlowLoader { cid, data },
+            loads: Default::default(),
+        };
+        let client = Client::with_block_cache(&loader, 1024);
+
+        for _ in 0..2 {
+            let (body, _) = client
+                .get_file(
+                    Path::from_cid(cid),
+                    std::time::Instant::now(),
+                    None,
+                    false,
+                    None,
+                    FetchPolicy::default(),
+                )
+                .await
+                .unwrap();
+            match body {
+                FileResult::Raw(body) => assert_eq!(read_body(body).await, b"hello world"),
+                _ => panic!("expected a raw file"),
+            }
+        }
+        assert_eq!(loader.loads.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn get_file_not_modified() {
+        let data = Bytes::from_static(b"hello world");
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+        let client = Client::new(&SlowLoader { cid, data });
+
+        let etag = format!("\"{}\"", cid);
+        let (body, metadata) = client
+            .get_file(
+                Path::from_cid(cid),
+                std::time::Instant::now(),
+                None,
+                false,
+                Some(format!("W/{}", etag)),
+                FetchPolicy::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(metadata.etag(), Some(etag));
+        assert!(matches!(body, FileResult::NotModified));
+
+        let (body, _) = client
+            .get_file(
+                Path::from_cid(cid),
+                std::time::Instant::now(),
+                None,
+                false,
+                Some("\"other\"".to_string()),
+                FetchPolicy::default(),
+            )
+            .await
+            .unwrap();
+        assert!(matches!(body, FileResult::Raw(_)));
+    }
+
+    #[tokio::test]
+    async fn head_loads_only_the_root() {
+        let content = vec![7u8; 1000];
+        let mut file = FileBuilder::new();
+        file.name("big.bin")
+            .chunk_size(100)
+            .content_bytes(content.clone());
+        let (root, bytes, _) = file
+            .build()
+            .await
+            .unwrap()
+            .encode_root()
+            .await
+            .unwrap()
+            .into_parts();
+        // the leaves are missing, loading them would fail
+        let loader = MapLoader::default();
+        loader.0.lock().unwrap().insert(root, bytes);
+        let client = Client::new(&loader);
+
+        let path = Path::from_cid(root);
+        let metadata = client
+            .head(path.clone(), FetchPolicy::default())
+            .await
+            .unwrap();
+        assert_eq!(metadata.path, path);
+        assert_eq!(metadata.size, Some(content.len() as u64));
+        assert_eq!(metadata.unixfs_type, Some(UnixfsType::File));
+        assert_eq!(metadata.etag(), Some(format!("\"{}\"", root)));
+    }
+
+    #[tokio::test]
+    async fn get_block_verifies_the_hash() {
+        let data = Bytes::from_static(b"hello world");
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+        let corrupted = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(b"hello"));
+        let loader = MapLoader::default();
+        loader.0.lock().unwrap().insert(cid, data.clone());
+        loader.0.lock().unwrap().insert(corrupted, data.clone());
+        let client = Client::new(&loader);
+
+        assert_eq!(client.get_block(cid).await.unwrap(), data);
+        assert!(matches!(
+            client.get_block(corrupted).await,
+            Err(ClientError::HashMismatch(c)) if c == corrupted
+        ));
+        let missing = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(b"missing"));
+        assert!(matches!(
+            client.get_block(missing).await,
+            Err(ClientError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn get_file_aborts_on_corrupted_block() {
+        let content = vec![7u8; 1000];
+        let mut file = FileBuilder::new();
+        file.name("big.bin")
+            .chunk_size(100)
+            .content_bytes(content.clone());
+        let blocks: Vec<_> = file
+            .build()
+            .await
+            .unwrap()
+            .encode()
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        let loader = MapLoader::default();
+        for block in blocks.iter() {
+            loader
+                .0
+                .lock()
+                .unwrap()
+                .insert(*block.cid(), block.data().clone());
+        }
+        let root = *blocks.last().unwrap().cid();
+        let leaf = *blocks[1].cid();
+        let mut corrupted = blocks[1].data().to_vec();
+        *corrupted.last_mut().unwrap() ^= 1;
+        loader.0.lock().unwrap().insert(leaf, corrupted.into());
+
+        let get_file = |client: Client<MapLoader>| async move {
+            let (body, _) = client
+                .get_file(
+                    Path::from_cid(root),
+                    std::time::Instant::now(),
+                    None,
+                    false,
+                    None,
+                    FetchPolicy::default(),
+                )
+                .await
+                .unwrap();
+            let body = match body {
+                FileResult::File(body) => body,
+                _ => panic!("expected a file"),
+            };
+            let chunks: Vec<Bytes> = body.0.try_collect().await?;
+            Ok::<_, std::io::Error>(chunks.concat())
+        };
+
+        let err = get_file(Client::new(&loader)).await.unwrap_err();
+        assert!(matches!(
+            ClientError::from_io_error(&err),
+            Some(ClientError::HashMismatch(cid)) if *cid == leaf
+        ));
+
+        // trusting the loader serves the corrupted block
+        let unverified = CachingLoader::new(loader.clone(), None).with_verification(false);
+        let body = get_file(Client::with_loader(unverified)).await.unwrap();
+        assert_eq!(body.len(), content.len());
+    }
+
+    #[tokio::test]
+    async fn get_file_rejects_corrupted_root() {
+        let data = Bytes::from_static(b"hello world");
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+        let loader = MapLoader::default();
+        loader
+            .0
+            .lock()
+            .unwrap()
+            .insert(cid, Bytes::from_static(b"hello w0rld"));
+
+        let err = Client::new(&loader)
+            .get_file(
+                Path::from_cid(cid),
+                std::time::Instant::now(),
+                None,
+                false,
+                None,
+                FetchPolicy::default(),
+            )
+            .await
+            .err()
+            .expect("served a corrupted root");
+        assert!(matches!(err, ClientError::HashMismatch(c) if c == cid));
+        assert_eq!(err.status_code(), http::StatusCode::BAD_GATEWAY);
+    }
+
+    /// Fails every load like a store that ran out of disk space.
+    #[derive(Debug, Clone)]
+    struct FullStoreLoader;
+
+    #[async_trait]
+    impl ContentLoader for FullStoreLoader {
+        async fn load_cid(&self, _cid: &Cid, _ctx: &LoaderContext) -> Result<LoadedCid> {
+            let err = anyhow::anyhow!("insufficient storage");
+            Err(iroh_rpc_types::ResourceExhausted(err).into())
+        }
+
+        async fn stop_session(&self, _ctx: ContextId) -> Result<()> {
+            Ok(())
+        }
+
+        async fn has_cid(&self, _cid: &Cid) -> Result<bool> {
+            Ok(false)
+        }
+    }
+
+    #[tokio::test]
+    async fn get_file_reports_insufficient_storage() {
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(b"hello world"));
+        let err = Client::new(&FullStoreLoader)
+            .get_file(
+                Path::from_cid(cid),
+                std::time::Instant::now(),
+                None,
+                false,
+                None,
+                FetchPolicy::default(),
+            )
+            .await
+            .err()
+            .expect("loaded from a full store");
+        assert!(matches!(err, ClientError::InsufficientStorage(_)));
+        assert_eq!(err.status_code(), http::StatusCode::INSUFFICIENT_STORAGE);
+    }
+
+    #[tokio::test]
+    async fn get_file_resolves_names() {
+        let data = Bytes::from_static(b"hello world");
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+        let client = Client::new(&SlowLoader { cid, data });
+        // resolved before, so no lookup happens
+        client.names.insert("example.com".to_string(), cid);
+
+        let path: Path = "/ipns/example.com".parse().unwrap();
+        let (body, metadata) = client
+            .get_file(
+                path.clone(),
+                std::time::Instant::now(),
+                None,
+                false,
+                None,
+                FetchPolicy::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(metadata.path, path);
+        assert_eq!(metadata.resolved_path, vec![cid]);
+        match body {
+            FileResult::Raw(body) => assert_eq!(read_body(body).await, b"hello world"),
+            _ => panic!("expected a raw file"),
+        }
+    }
+}
@@ -6,10 +6,12 @@ use headers::{
     AccessControlAllowHeaders, AccessControlAllowMethods, AccessControlAllowOrigin, HeaderMapExt,
 };
 use iroh_metrics::config::Config as MetricsConfig;
+use iroh_resolver::resolver::FetchPolicy;
 use iroh_rpc_client::Config as RpcClientConfig;
 use iroh_rpc_types::{gateway::GatewayServerAddr, Addr};
-use iroh_util::insert_into_config_map;
+use iroh_util::{duration_from_millis, insert_into_config_map};
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 
 /// CONFIG_FILE_NAME is the name of the optional config file located in the iroh home directory
 pub const CONFIG_FILE_NAME: &str = "gateway.config.toml";
@@ -24,6 +26,49 @@ pub struct Config {
     /// Pretty URL to redirect to
     #[serde(default = "String::new")]
     pub public_url_base: String,
+    /// Path prefix the gateway is served under, eg. `/gw` when running behind a reverse
+    /// proxy subpath. Stripped from incoming requests and prepended to generated links.
+    #[serde(default = "String::new")]
+    pub base_path: String,
+    /// Content-type for files that have no extension and whose content can't be sniffed,
+    /// eg. `text/plain`. Empty falls back to `application/octet-stream`.
+    #[serde(default = "String::new")]
+    pub default_content_type: String,
+    /// Maximum number of IPNS/DNSLink names resolved concurrently, `None` for no limit.
+    #[serde(default)]
+    pub max_concurrent_name_resolutions: Option<usize>,
+    /// Name resolutions allowed to wait once the concurrency limit is reached, further
+    /// ones are rejected with `503 Service Unavailable`.
+    #[serde(default)]
+    pub max_queued_name_resolutions: usize,
+    /// Requests that take longer than this many milliseconds to send are logged as a warning,
+    /// with their time to the first byte, `0` disables the log.
+    #[serde(default)]
+    pub slow_request_threshold_ms: u64,
+    /// Requests whose root block isn't found within this many milliseconds fail with
+    /// `504 Gateway Timeout`, `0` waits indefinitely.
+    #[serde(default)]
+    pub resolve_timeout_ms: u64,
+    /// Bytes of recently loaded blocks kept in memory to serve repeated requests from, `0`
+    /// disables the cache.
+    #[serde(default)]
+    pub block_cache_size: u64,
+    /// How often a block that failed to arrive is requested again from other providers found
+    /// in the DHT.
+    #[serde(default)]
+    pub max_provider_retries: u64,
+    /// Fetches of a single block taking longer than this many milliseconds count as failed,
+    /// `0` waits indefinitely.
+    #[serde(default)]
+    pub per_block_timeout_ms: u64,
+    /// Serve blocks without checking that they hash to their cid. Only meant for gateways
+    /// reading from a store they trust.
+    #[serde(default)]
+    pub skip_block_verification: bool,
+    /// Leave out the `X-Ipfs-Path`, `X-Ipfs-Roots` and `X-Ipfs-Gateway-Host` headers, which
+    /// expose the cids a path was resolved through.
+    #[serde(default)]
+    pub hide_ipfs_headers: bool,
     /// default port to listen on
     pub port: u16,
     /// flag to toggle whether the gateway should use denylist on requests
@@ -48,6 +93,17 @@ impl Config {
     pub fn new(port: u16, rpc_client: RpcClientConfig) -> Self {
         Self {
             public_url_base: String::new(),
+            base_path: String::new(),
+            default_content_type: String::new(),
+            max_concurrent_name_resolutions: None,
+            max_queued_name_resolutions: 0,
+            slow_request_threshold_ms: 0,
+            resolve_timeout_ms: 0,
+            block_cache_size: 0,
+            max_provider_retries: 0,
+            per_block_timeout_ms: 0,
+            skip_block_verification: false,
+            hide_ipfs_headers: false,
             headers: HeaderMap::new(),
             port,
             rpc_client,
@@ -116,6 +172,17 @@ impl Default for Config {
         let rpc_client = RpcClientConfig::default_grpc();
         let mut t = Self {
             public_url_base: String::new(),
+            base_path: String::new(),
+            default_content_type: String::new(),
+            max_concurrent_name_resolutions: None,
+            max_queued_name_resolutions: 0,
+            slow_request_threshold_ms: 0,
+            resolve_timeout_ms: 0,
+            block_cache_size: 0,
+            max_provider_retries: 0,
+            per_block_timeout_ms: 0,
+            skip_block_verification: false,
+            hide_ipfs_headers: false,
             headers: HeaderMap::new(),
             port: DEFAULT_PORT,
             rpc_client,
@@ -137,6 +204,47 @@ impl Source for Config {
         let rpc_client = self.rpc_client.collect()?;
         let mut map: Map<String, Value> = Map::new();
         insert_into_config_map(&mut map, "public_url_base", self.public_url_base.clone());
+        insert_into_config_map(&mut map, "base_path", self.base_path.clone());
+        insert_into_config_map(
+            &mut map,
+            "default_content_type",
+            self.default_content_type.clone(),
+        );
+        if let Some(max) = self.max_concurrent_name_resolutions {
+            insert_into_config_map(&mut map, "max_concurrent_name_resolutions", max as i64);
+        }
+        insert_into_config_map(
+            &mut map,
+            "max_queued_name_resolutions",
+            self.max_queued_name_resolutions as i64,
+        );
+        insert_into_config_map(
+            &mut map,
+            "slow_request_threshold_ms",
+            self.slow_request_threshold_ms as i64,
+        );
+        insert_into_config_map(
+            &mut map,
+            "resolve_timeout_ms",
+            self.resolve_timeout_ms as i64,
+        );
+        insert_into_config_map(&mut map, "block_cache_size", self.block_cache_size as i64);
+        insert_into_config_map(
+            &mut map,
+            "max_provider_retries",
+            self.max_provider_retries as i64,
+        );
+        insert_into_config_map(
+            &mut map,
+            "per_block_timeout_ms",
+            self.per_block_timeout_ms as i64,
+        );
+        insert_into_config_map(
+            &mut map,
+            "skip_block_verification",
+            self.skip_block_verification,
+        );
+        insert_into_config_map(&mut map, "hide_ipfs_headers", self.hide_ipfs_headers);
         insert_into_config_map(&mut map, "use_denylist", self.use_denylist);
         // Some issue between deserializing u64 & u16, converting this to
         // an signed int fixes the issue
@@ -162,6 +270,52 @@ impl crate::handlers::StateConfig for Config {
         &self.public_url_base
     }
 
+    fn base_path(&self) -> &str {
+        &self.base_path
+    }
+
+    fn default_content_type(&self) -> &str {
+        &self.default_content_type
+    }
+
+    fn max_concurrent_name_resolutions(&self) -> Option<usize> {
+        self.max_concurrent_name_resolutions
+    }
+
+    fn max_queued_name_resolutions(&self) -> usize {
+        self.max_queued_name_resolutions
+    }
+
+    fn slow_request_threshold(&self) -> Option<Duration> {
+        duration_from_millis(self.slow_request_threshold_ms)
+    }
+
+    fn resolve_timeout(&self) -> Option<Duration> {
+        duration_from_millis(self.resolve_timeout_ms)
+    }
+
+    fn block_cache_size(&self) -> Option<usize> {
+        match self.block_cache_size {
+            0 => None,
+            bytes => Some(bytes as usize),
+        }
+    }
+
+    fn fetch_policy(&self) -> FetchPolicy {
+        FetchPolicy {
+            max_provider_retries: self.max_provider_retries as usize,
+            per_block_timeout: duration_from_millis(self.per_block_timeout_ms),
+        }
+    }
+
+    fn skip_block_verification(&self) -> bool {
+        self.skip_block_verification
+    }
+
+    fn hide_ipfs_headers(&self) -> bool {
+        self.hide_ipfs_headers
+    }
+
     fn port(&self) -> u16 {
         self.port
     }
@@ -210,6 +364,46 @@ mod tests {
             "public_url_base".to_string(),
             Value::new(None, default.public_url_base.clone()),
         );
+        expect.insert(
+            "base_path".to_string(),
+            Value::new(None, default.base_path.clone()),
+        );
+        expect.insert(
+            "default_content_type".to_string(),
+            Value::new(None, default.default_content_type.clone()),
+        );
+        expect.insert(
+            "max_queued_name_resolutions".to_string(),
+            Value::new(None, default.max_queued_name_resolutions as i64),
+        );
+        expect.insert(
+            "slow_request_threshold_ms".to_string(),
+            Value::new(None, default.slow_request_threshold_ms as i64),
+        );
+        expect.insert(
+            "resolve_timeout_ms".to_string(),
+            Value::new(None, default.resolve_timeout_ms as i64),
+        );
+        expect.insert(
+            "block_cache_size".to_string(),
+            Value::new(None, default.block_cache_size as i64),
+        );
+        expect.insert(
+            "max_provider_retries".to_string(),
+            Value::new(None, default.max_provider_retries as i64),
+        );
+        expect.insert(
+            "per_block_timeout_ms".to_string(),
+            Value::new(None, default.per_block_timeout_ms as i64),
+        );
+        expect.insert(
+            "skip_block_verification".to_string(),
+            Value::new(None, default.skip_block_verification),
+        );
+        expect.insert(
+            "hide_ipfs_headers".to_string(),
+            Value::new(None, default.hide_ipfs_headers),
+        );
         expect.insert("port".to_string(), Value::new(None, default.port as i64));
         expect.insert(
             "use_denylist".to_string(),
@@ -8,12 +8,14 @@ pub static HEADER_X_TRACE_ID: HeaderName = HeaderName::from_static("x-trace-id")
 pub static HEADER_X_IPFS_GATEWAY_PREFIX: HeaderName =
     HeaderName::from_static("x-ipfs-gateway-prefix");
 pub static HEADER_X_IPFS_ROOTS: HeaderName = HeaderName::from_static("x-ipfs-roots");
+pub static HEADER_X_IPFS_GATEWAY_HOST: HeaderName = HeaderName::from_static("x-ipfs-gateway-host");
 pub static HEADER_SERVICE_WORKER: HeaderName = HeaderName::from_static("service-worker");
 pub static HEADER_CACHE_CONTROL: HeaderName = HeaderName::from_static("cache-control");
 
 // Common Header Values
 pub static VALUE_XCTO_NOSNIFF: HeaderValue = HeaderValue::from_static("nosniff");
 pub static VALUE_NONE: HeaderValue = HeaderValue::from_static("none");
+pub static VALUE_BYTES: HeaderValue = HeaderValue::from_static("bytes");
 pub static VAL_IMMUTABLE_MAX_AGE: HeaderValue =
     HeaderValue::from_static("public, max-age=31536000, immutable");
 
@@ -26,6 +28,11 @@ pub static CONTENT_TYPE_IPLD_RAW: HeaderValue =
     HeaderValue::from_static("application/vnd.ipld.raw");
 pub static CONTENT_TYPE_IPLD_CAR: HeaderValue =
     HeaderValue::from_static("application/vnd.ipld.car; version=1");
+pub static CONTENT_TYPE_X_TAR: HeaderValue = HeaderValue::from_static("application/x-tar");
+pub static CONTENT_TYPE_IPLD_DAG_CBOR: HeaderValue =
+    HeaderValue::from_static("application/vnd.ipld.dag-cbor");
+pub static CONTENT_TYPE_IPLD_DAG_JSON: HeaderValue =
+    HeaderValue::from_static("application/vnd.ipld.dag-json");
 
 // Schemes
 pub static SCHEME_IPFS: &str = "ipfs";
@@ -34,3 +41,17 @@ pub static SCHEME_IPNS: &str = "ipns";
 // Max number of links to return in a single recursive request.
 // TODO: Make configurable.
 pub static RECURSION_LIMIT: usize = 4096;
+
+// How long the CID an IPNS or DNSLink name resolved to is reused.
+pub const NAME_CACHE_TTL: std::time::Duration = std::time::Duration::from_secs(60);
+
+// How long requests wait for a concurrent resolve of the same path, before resolving it
+// themselves.
+pub const SHARED_RESOLVE_DEADLINE: std::time::Duration = std::time::Duration::from_secs(5);
+
+// How long a shared resolve may run when no resolve timeout is configured.
+pub const SHARED_RESOLVE_LIMIT: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+
+// Max number of byte ranges served in a single multipart response, requests asking for more
+// get the whole content.
+pub const MAX_BYTE_RANGES: usize = 16;
@@ -7,6 +7,7 @@ use tokio::sync::RwLock;
 
 use crate::{
     bad_bits::BadBits,
+    block_cache::{BlockCache, CachingLoader},
     client::Client,
     handlers::{get_app_routes, StateConfig},
     rpc,
@@ -34,8 +35,9 @@ impl<T: ContentLoader + std::marker::Unpin> Core<T> {
         bad_bits: Arc<Option<RwLock<BadBits>>>,
         content_loader: T,
     ) -> anyhow::Result<Self> {
+        let keepalive = config.rpc_client().keepalive();
         tokio::spawn(async move {
-            if let Err(err) = rpc::new(rpc_addr, Gateway::default()).await {
+            if let Err(err) = rpc::new(rpc_addr, Gateway::default(), keepalive).await {
                 tracing::error!("Failed to run gateway rpc handler: {}", err);
             }
         });
@@ -64,8 +66,9 @@ impl<T: ContentLoader + std::marker::Unpin> Core<T> {
         rpc_addr: GatewayServerAddr,
         state: Arc<State<T>>,
     ) -> anyhow::Result<Self> {
+        let keepalive = state.config.rpc_client().keepalive();
         tokio::spawn(async move {
-            if let Err(err) = rpc::new(rpc_addr, Gateway::default()).await {
+            if let Err(err) = rpc::new(rpc_addr, Gateway::default(), keepalive).await {
                 tracing::error!("Failed to run gateway rpc handler: {}", err);
             }
         });
@@ -86,7 +89,18 @@ impl<T: ContentLoader + std::marker::Unpin> Core<T> {
             "not_found".to_string(),
             templates::NOT_FOUND_TEMPLATE.to_string(),
         );
-        let client = Client::new(&content_loader);
+        let cache = config
+            .block_cache_size()
+            .map(|max_bytes| Arc::new(BlockCache::new(max_bytes)));
+        let loader = CachingLoader::new(content_loader, cache)
+            .with_verification(!config.skip_block_verification());
+        let mut client = Client::with_loader(loader);
+        if let Some(limit) = config.max_concurrent_name_resolutions() {
+            client = client.with_name_resolution_limit(limit, config.max_queued_name_resolutions());
+        }
+        if let Some(timeout) = config.resolve_timeout() {
+            client = client.with_resolve_timeout(timeout);
+        }
         Ok(Arc::new(State {
             config,
             client,
@@ -129,6 +143,9 @@ mod tests {
     use tokio_util::io::StreamReader;
 
     use crate::config::Config;
+    use crate::constants::{
+        HEADER_X_IPFS_GATEWAY_HOST, HEADER_X_IPFS_PATH, HEADER_X_IPFS_ROOTS, MAX_BYTE_RANGES,
+    };
 
     async fn spawn_gateway(
         config: Arc<Config>,
@@ -146,6 +163,94 @@ mod tests {
         (addr, rpc_client, core_task)
     }
 
+    fn test_config(store_addr: Option<StoreClientAddr>) -> Config {
+        let mut config = Config::new(
+            0,
+            RpcClientConfig {
+                gateway_addr: None,
+                p2p_addr: None,
+                store_addr,
+                fallback_store_addrs: Vec::new(),
+                channels: Some(1),
+                max_retries: None,
+                keepalive_secs: None,
+            },
+        );
+        config.set_default_headers();
+        config
+    }
+
+    /// A gateway serving from a store of its own.
+    struct TestGateway {
+        addr: SocketAddr,
+        rpc_client: RpcClient,
+        core_task: tokio::task::JoinHandle<()>,
+        store_task: tokio::task::JoinHandle<()>,
+    }
+
+    impl TestGateway {
+        /// Spawns the gateway and its store, with `configure` applied to the default config.
+        async fn spawn(configure: impl FnOnce(&mut Config)) -> Self {
+            let (store_client_addr, store_task) = spawn_store().await;
+            let mut config = test_config(Some(store_client_addr));
+            configure(&mut config);
+            let (addr, rpc_client, core_task) = spawn_gateway(Arc::new(config)).await;
+            TestGateway {
+                addr,
+                rpc_client,
+                core_task,
+                store_task,
+            }
+        }
+
+        /// Adds `dir` to the store, returning the cids of its blocks, the root coming last.
+        async fn put_dir(&self, dir: DirectoryBuilder) -> Vec<Cid> {
+            let store = self.rpc_client.try_store().unwrap();
+            let mut parts = dir.build().unwrap().encode();
+            let mut cids = Vec::new();
+            while let Some(part) = parts.next().await {
+                let (cid, bytes, links) = part.unwrap().into_parts();
+                cids.push(cid);
+                store.put(cid, bytes, links).await.unwrap();
+            }
+            cids
+        }
+
+        fn uri(&self, path_and_query: &str) -> hyper::Uri {
+            hyper::Uri::builder()
+                .scheme("http")
+                .authority(format!("localhost:{}", self.addr.port()))
+                .path_and_query(path_and_query)
+                .build()
+                .unwrap()
+        }
+
+        async fn get(&self, path_and_query: &str) -> hyper::Response<hyper::Body> {
+            hyper::Client::new()
+                .get(self.uri(path_and_query))
+                .await
+                .unwrap()
+        }
+
+        async fn stop(self) {
+            self.core_task.abort();
+            self.core_task.await.unwrap_err();
+            self.store_task.abort();
+            self.store_task.await.unwrap_err();
+        }
+    }
+
+    /// Spawns a gateway like [`TestGateway::spawn`] and adds `dir` to its store, returning
+    /// the cid of its root as well.
+    async fn spawn_gateway_with(
+        dir: DirectoryBuilder,
+        configure: impl FnOnce(&mut Config),
+    ) -> (TestGateway, Cid) {
+        let gateway = TestGateway::spawn(configure).await;
+        let root_cid = *gateway.put_dir(dir).await.last().unwrap();
+        (gateway, root_cid)
+    }
+
     async fn spawn_store() -> (StoreClientAddr, tokio::task::JoinHandle<()>) {
         let (server_addr, client_addr) = Addr::new_mem();
         let store_dir = tempfile::tempdir().unwrap();
@@ -153,26 +258,22 @@ mod tests {
             path: store_dir.path().join("db"),
             rpc_client: RpcClientConfig::default(),
             metrics: iroh_metrics::config::Config::default(),
+            min_free_space: None,
+            write_batch_size: iroh_store::config::DEFAULT_WRITE_BATCH_SIZE,
+            durability: iroh_store::Durability::default(),
         };
         let store = iroh_store::Store::create(config).await.unwrap();
-        let task =
-            tokio::spawn(async move { iroh_store::rpc::new(server_addr, store).await.unwrap() });
+        let task = tokio::spawn(async move {
+            iroh_store::rpc::new(server_addr, store, None)
+                .await
+                .unwrap()
+        });
         (client_addr, task)
     }
 
     #[tokio::test]
     async fn gateway_health() {
-        let mut config = Config::new(
-            0,
-            RpcClientConfig {
-                gateway_addr: None,
-                p2p_addr: None,
-                store_addr: None,
-                channels: Some(1),
-            },
-        );
-        config.set_default_headers();
-
+        let config = test_config(None);
         let (addr, _rpc_client, core_task) = spawn_gateway(Arc::new(config)).await;
 
         let uri = hyper::Uri::builder()
@@ -193,64 +294,33 @@ mod tests {
 
     #[tokio::test]
     async fn fetch_car_recursive() {
-        let (store_client_addr, store_task) = spawn_store().await;
-        let mut config = Config::new(
-            0,
-            RpcClientConfig {
-                gateway_addr: None,
-                p2p_addr: None,
-                store_addr: Some(store_client_addr),
-                channels: Some(1),
-            },
-        );
-        config.set_default_headers();
-
-        let (addr, rpc_client, core_task) = spawn_gateway(Arc::new(config)).await;
+        let gateway = TestGateway::spawn(|_| {}).await;
 
-        let dir = "demo";
         let files = [
             ("hello.txt".to_string(), b"ola".to_vec()),
             ("world.txt".to_string(), b"mundo".to_vec()),
         ];
 
         // add a directory with two files to the store.
-        let (root_cid, all_cids) = {
-            let store = rpc_client.try_store().unwrap();
-            let mut cids = vec![];
-            let mut dir_builder = DirectoryBuilder::new();
-            dir_builder.name(dir);
-            for (name, content) in &files {
-                let mut file = FileBuilder::new();
-                file.name(name).content_bytes(content.clone());
-                dir_builder.add_file(file.build().await.unwrap());
-            }
-
-            let root_dir = dir_builder.build().unwrap();
-            let mut parts = root_dir.encode();
-            while let Some(part) = parts.next().await {
-                let (cid, bytes, links) = part.unwrap().into_parts();
-                cids.push(cid);
-                store.put(cid, bytes, links).await.unwrap();
-            }
-            (*cids.last().unwrap(), cids)
-        };
+        let mut dir_builder = DirectoryBuilder::new();
+        dir_builder.name("demo");
+        for (name, content) in &files {
+            let mut file = FileBuilder::new();
+            file.name(name).content_bytes(content.clone());
+            dir_builder.add_file(file.build().await.unwrap());
+        }
+        let all_cids = gateway.put_dir(dir_builder).await;
+        let root_cid = *all_cids.last().unwrap();
 
         // request the root cid as a recursive car
         let res = {
-            let client = hyper::Client::new();
-            let uri = hyper::Uri::builder()
-                .scheme("http")
-                .authority(format!("localhost:{}", addr.port()))
-                .path_and_query(format!("/ipfs/{}?recursive=true", root_cid))
-                .build()
-                .unwrap();
             let req = hyper::Request::builder()
                 .method("GET")
                 .header("accept", "application/vnd.ipld.car")
-                .uri(uri)
+                .uri(gateway.uri(&format!("/ipfs/{}?recursive=true", root_cid)))
                 .body(hyper::Body::empty())
                 .unwrap();
-            client.request(req).await.unwrap()
+            hyper::Client::new().request(req).await.unwrap()
         };
 
         assert_eq!(http::StatusCode::OK, res.status());
@@ -293,9 +363,403 @@ mod tests {
             assert_eq!(node, &UnixfsNode::Raw(files[i].1.clone().into()));
         }
 
-        core_task.abort();
-        core_task.await.unwrap_err();
-        store_task.abort();
-        store_task.await.unwrap_err();
+        gateway.stop().await;
+    }
+
+    #[tokio::test]
+    async fn fetch_dag_cbor_path() {
+        use bytes::Bytes;
+        use cid::multihash::{Code, MultihashDigest};
+        use libipld::{codec::Encode, prelude::Codec, Ipld, IpldCodec};
+        use std::collections::BTreeMap;
+
+        let gateway = TestGateway::spawn(|_| {}).await;
+
+        fn encode(ipld: &Ipld) -> (Cid, Bytes) {
+            let mut bytes = Vec::new();
+            ipld.encode(IpldCodec::DagCbor, &mut bytes).unwrap();
+            let c = Cid::new_v1(IpldCodec::DagCbor.into(), Code::Sha2_256.digest(&bytes));
+            (c, bytes.into())
+        }
+
+        // { "title": "bar" } <- { "child": <leaf> }
+        let mut leaf = BTreeMap::new();
+        leaf.insert("title".to_string(), Ipld::String("bar".to_string()));
+        let (leaf_cid, leaf_bytes) = encode(&Ipld::Map(leaf));
+        let mut root = BTreeMap::new();
+        root.insert("child".to_string(), Ipld::Link(leaf_cid));
+        let (root_cid, root_bytes) = encode(&Ipld::Map(root));
+
+        let store = gateway.rpc_client.try_store().unwrap();
+        store.put(leaf_cid, leaf_bytes, vec![]).await.unwrap();
+        store
+            .put(root_cid, root_bytes, vec![leaf_cid])
+            .await
+            .unwrap();
+
+        let res = gateway
+            .get(&format!("/ipfs/{}/child/title", root_cid))
+            .await;
+
+        assert_eq!(http::StatusCode::OK, res.status());
+        assert_eq!(
+            res.headers().get(http::header::CONTENT_TYPE).unwrap(),
+            "application/vnd.ipld.dag-cbor"
+        );
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let ipld: Ipld = IpldCodec::DagCbor.decode(&body).unwrap();
+        assert_eq!(ipld, Ipld::String("bar".to_string()));
+
+        gateway.stop().await;
+    }
+
+    async fn get_ipfs_headers(hide_ipfs_headers: bool) -> (String, Vec<Cid>, http::HeaderMap) {
+        let gateway =
+            TestGateway::spawn(|config| config.hide_ipfs_headers = hide_ipfs_headers).await;
+
+        // demo/sub/hello.txt, encoded as file, sub, demo
+        let mut file = FileBuilder::new();
+        file.name("hello.txt").content_bytes(b"ola".to_vec());
+        let mut sub = DirectoryBuilder::new();
+        sub.name("sub").add_file(file.build().await.unwrap());
+        let mut dir_builder = DirectoryBuilder::new();
+        dir_builder
+            .name("demo")
+            .add_dir(sub.build().unwrap())
+            .unwrap();
+        let cids = gateway.put_dir(dir_builder).await;
+
+        let path = format!("/ipfs/{}/sub/hello.txt", cids[2]);
+        let res = gateway.get(&path).await;
+        assert_eq!(http::StatusCode::OK, res.status());
+        let headers = res.headers().clone();
+
+        gateway.stop().await;
+        (path, cids, headers)
+    }
+
+    #[tokio::test]
+    async fn ipfs_headers() {
+        let (path, cids, headers) = get_ipfs_headers(false).await;
+        assert_eq!(headers.get(&HEADER_X_IPFS_PATH).unwrap(), path.as_str());
+        // every cid the path was resolved through, starting at the root
+        let roots = format!("{},{},{}", cids[2], cids[1], cids[0]);
+        assert_eq!(headers.get(&HEADER_X_IPFS_ROOTS).unwrap(), roots.as_str());
+        assert!(headers
+            .get(&HEADER_X_IPFS_GATEWAY_HOST)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .starts_with("localhost:"));
+
+        let (_, _, headers) = get_ipfs_headers(true).await;
+        assert!(headers.get(&HEADER_X_IPFS_PATH).is_none());
+        assert!(headers.get(&HEADER_X_IPFS_ROOTS).is_none());
+        assert!(headers.get(&HEADER_X_IPFS_GATEWAY_HOST).is_none());
+    }
+
+    #[tokio::test]
+    async fn serve_under_base_path() {
+        let mut dir_builder = DirectoryBuilder::new();
+        dir_builder.name("demo");
+        let mut file = FileBuilder::new();
+        file.name("hello.txt").content_bytes(b"ola".to_vec());
+        dir_builder.add_file(file.build().await.unwrap());
+        let (gateway, root_cid) =
+            spawn_gateway_with(dir_builder, |config| config.base_path = "/gw/".to_string()).await;
+
+        // content resolves under the prefix
+        let res = gateway
+            .get(&format!("/gw/ipfs/{}/hello.txt", root_cid))
+            .await;
+        assert_eq!(http::StatusCode::OK, res.status());
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(b"ola", &body[..]);
+
+        // but not without it
+        let res = gateway.get(&format!("/ipfs/{}/hello.txt", root_cid)).await;
+        assert_eq!(http::StatusCode::NOT_FOUND, res.status());
+
+        // directory listings link back through the prefix
+        let res = gateway.get(&format!("/gw/ipfs/{}", root_cid)).await;
+        assert_eq!(http::StatusCode::OK, res.status());
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains(&format!("href=\"/gw/ipfs/{}/hello.txt\"", root_cid)));
+        assert!(body.contains("href=\"/gw/style.css\""));
+        // breadcrumbs too, without an empty segment
+        assert!(body.contains("href=\"/gw/ipfs\""));
+        assert!(body.contains(&format!("href=\"/gw/ipfs/{}\"", root_cid)));
+        assert!(!body.contains("href=\"//"));
+
+        gateway.stop().await;
+    }
+
+    #[tokio::test]
+    async fn serve_default_content_type() {
+        let mut dir_builder = DirectoryBuilder::new();
+        dir_builder.name("demo");
+        let mut file = FileBuilder::new();
+        file.name("blob").content_bytes(vec![0u8, 1, 2, 3]);
+        dir_builder.add_file(file.build().await.unwrap());
+        let (gateway, root_cid) = spawn_gateway_with(dir_builder, |config| {
+            config.default_content_type = "text/plain".to_string()
+        })
+        .await;
+        let content_type = |res: &hyper::Response<hyper::Body>| {
+            res.headers()
+                .get(http::header::CONTENT_TYPE)
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+
+        // the configured default applies
+        let res = gateway.get(&format!("/ipfs/{}/blob", root_cid)).await;
+        assert_eq!(http::StatusCode::OK, res.status());
+        assert_eq!(content_type(&res), "text/plain; charset=utf-8");
+
+        // the request can override it
+        let res = gateway
+            .get(&format!(
+                "/ipfs/{}/blob?default_content_type=application/octet-stream",
+                root_cid
+            ))
+            .await;
+        assert_eq!(http::StatusCode::OK, res.status());
+        assert_eq!(content_type(&res), "application/octet-stream");
+
+        // raw blocks are never served under the default
+        let res = gateway
+            .get(&format!("/ipfs/{}/blob?format=raw", root_cid))
+            .await;
+        assert_eq!(http::StatusCode::OK, res.status());
+        assert_eq!(content_type(&res), "application/vnd.ipld.raw");
+
+        gateway.stop().await;
+    }
+
+    #[tokio::test]
+    async fn fetch_file_ranges() {
+        let content = b"hello world, ranges ahead";
+        let mut dir_builder = DirectoryBuilder::new();
+        dir_builder.name("demo");
+        let mut file = FileBuilder::new();
+        file.name("hello.txt").content_bytes(content.to_vec());
+        dir_builder.add_file(file.build().await.unwrap());
+        let (gateway, root_cid) = spawn_gateway_with(dir_builder, |_| {}).await;
+
+        let get = |range: Option<&str>| {
+            let uri = gateway.uri(&format!("/ipfs/{}/hello.txt", root_cid));
+            let mut req = hyper::Request::builder().uri(uri);
+            if let Some(range) = range {
+                req = req.header(http::header::RANGE, range);
+            }
+            hyper::Client::new().request(req.body(hyper::Body::empty()).unwrap())
+        };
+
+        // full responses advertise range support
+        let res = get(None).await.unwrap();
+        assert_eq!(http::StatusCode::OK, res.status());
+        assert_eq!(
+            res.headers().get(http::header::ACCEPT_RANGES).unwrap(),
+            "bytes"
+        );
+
+        // single range
+        let res = get(Some("bytes=0-4")).await.unwrap();
+        assert_eq!(http::StatusCode::PARTIAL_CONTENT, res.status());
+        assert_eq!(
+            res.headers().get(http::header::CONTENT_RANGE).unwrap(),
+            &format!("bytes 0-4/{}", content.len())
+        );
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(b"hello", &body[..]);
+
+        // multiple ranges
+        let res = get(Some("bytes=0-4,6-10")).await.unwrap();
+        assert_eq!(http::StatusCode::PARTIAL_CONTENT, res.status());
+        let content_type = res
+            .headers()
+            .get(http::header::CONTENT_TYPE)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+        let boundary = content_type
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap();
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        let expected = format!(
+            "\r\n--{b}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 0-4/{len}\r\n\r\nhello\
+             \r\n--{b}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 6-10/{len}\r\n\r\nworld\
+             \r\n--{b}--\r\n",
+            b = boundary,
+            len = content.len()
+        );
+        assert_eq!(body, expected);
+
+        // ranges past the end are dropped before the file is read
+        let res = get(Some("bytes=0-4,1000-2000")).await.unwrap();
+        assert_eq!(http::StatusCode::PARTIAL_CONTENT, res.status());
+        assert_eq!(
+            res.headers().get(http::header::CONTENT_LENGTH).unwrap(),
+            "5"
+        );
+        assert_eq!(
+            res.headers().get(http::header::CONTENT_RANGE).unwrap(),
+            &format!("bytes 0-4/{}", content.len())
+        );
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(b"hello", &body[..]);
+
+        // too many ranges get the whole file
+        let too_many = (0..=MAX_BYTE_RANGES)
+            .map(|i| format!("{}-{}", i, i))
+            .collect::<Vec<_>>()
+            .join(",");
+        let res = get(Some(&format!("bytes={}", too_many))).await.unwrap();
+        assert_eq!(http::StatusCode::OK, res.status());
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(&content[..], &body[..]);
+
+        // out of bounds
+        let res = get(Some("bytes=100-200")).await.unwrap();
+        assert_eq!(http::StatusCode::RANGE_NOT_SATISFIABLE, res.status());
+        assert_eq!(
+            res.headers().get(http::header::CONTENT_RANGE).unwrap(),
+            &format!("bytes */{}", content.len())
+        );
+
+        gateway.stop().await;
+    }
+
+    #[tokio::test]
+    async fn head_matches_get() {
+        let mut dir_builder = DirectoryBuilder::new();
+        dir_builder.name("demo");
+        let mut file = FileBuilder::new();
+        file.name("hello.txt")
+            .content_bytes(b"hello world".to_vec());
+        dir_builder.add_file(file.build().await.unwrap());
+        // without an extension the content-type has to be sniffed
+        let mut file = FileBuilder::new();
+        file.name("page")
+            .content_bytes(b"<html><body>hello</body></html>".to_vec());
+        dir_builder.add_file(file.build().await.unwrap());
+        let (gateway, root_cid) = spawn_gateway_with(dir_builder, |_| {}).await;
+
+        let request = |method: &str, path: &str| {
+            let req = hyper::Request::builder()
+                .method(method)
+                .uri(gateway.uri(&format!("/ipfs/{}/{}", root_cid, path)))
+                .body(hyper::Body::empty())
+                .unwrap();
+            hyper::Client::new().request(req)
+        };
+
+        for path in ["hello.txt", "page"] {
+            let get = request("GET", path).await.unwrap();
+            let head = request("HEAD", path).await.unwrap();
+            assert_eq!(http::StatusCode::OK, get.status());
+            assert_eq!(get.status(), head.status());
+            for name in [
+                http::header::CONTENT_TYPE,
+                http::header::CONTENT_LENGTH,
+                http::header::ETAG,
+                http::header::CACHE_CONTROL,
+                http::header::ACCEPT_RANGES,
+            ] {
+                assert_eq!(
+                    get.headers().get(&name),
+                    head.headers().get(&name),
+                    "{}",
+                    name
+                );
+            }
+            assert!(get.headers().contains_key(http::header::CONTENT_TYPE));
+
+            let body = hyper::body::to_bytes(head.into_body()).await.unwrap();
+            assert!(body.is_empty());
+        }
+
+        gateway.stop().await;
+    }
+
+    #[tokio::test]
+    async fn fetch_tar() {
+        // demo/
+        //   hello.txt
+        //   nested/
+        //     world.txt
+        //     deeper/
+        //       deep.txt
+        let mut deeper = DirectoryBuilder::new();
+        deeper.name("deeper");
+        let mut file = FileBuilder::new();
+        file.name("deep.txt").content_bytes(b"profundo".to_vec());
+        deeper.add_file(file.build().await.unwrap());
+
+        let mut nested = DirectoryBuilder::new();
+        nested.name("nested");
+        let mut file = FileBuilder::new();
+        file.name("world.txt").content_bytes(b"mundo".to_vec());
+        nested.add_file(file.build().await.unwrap());
+        nested.add_dir(deeper.build().unwrap()).unwrap();
+
+        let mut root = DirectoryBuilder::new();
+        root.name("demo");
+        let mut file = FileBuilder::new();
+        file.name("hello.txt").content_bytes(b"ola".to_vec());
+        root.add_file(file.build().await.unwrap());
+        root.add_dir(nested.build().unwrap()).unwrap();
+        let (gateway, root_cid) = spawn_gateway_with(root, |_| {}).await;
+
+        let res = gateway.get(&format!("/ipfs/{}?format=tar", root_cid)).await;
+
+        assert_eq!(http::StatusCode::OK, res.status());
+        assert_eq!(
+            res.headers().get(http::header::CONTENT_TYPE).unwrap(),
+            "application/x-tar"
+        );
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+
+        // extract the archive and compare against the source tree
+        let out_dir = tempfile::tempdir().unwrap();
+        tar::Archive::new(&body[..]).unpack(out_dir.path()).unwrap();
+
+        let expected = [
+            ("hello.txt", &b"ola"[..]),
+            ("nested/world.txt", &b"mundo"[..]),
+            ("nested/deeper/deep.txt", &b"profundo"[..]),
+        ];
+        for (path, content) in expected {
+            let actual = std::fs::read(out_dir.path().join(path)).unwrap();
+            assert_eq!(actual, content, "{}", path);
+        }
+        assert!(out_dir.path().join("nested").is_dir());
+        assert!(out_dir.path().join("nested/deeper").is_dir());
+
+        let mut archive = tar::Archive::new(&body[..]);
+        let mut names = archive
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "hello.txt",
+                "nested",
+                "nested/deeper",
+                "nested/deeper/deep.txt",
+                "nested/world.txt"
+            ]
+        );
+
+        gateway.stop().await;
     }
 }
@@ -8,7 +8,9 @@ use axum::{
     http::StatusCode,
     response::{IntoResponse, Response},
 };
+use cid::Cid;
 use http::{HeaderMap, HeaderValue};
+use iroh_resolver::resolver::{BlockNotFound, LinkNotFound, UnexpectedUnixfsType};
 use opentelemetry::trace::TraceId;
 use serde_json::json;
 
@@ -83,3 +85,105 @@ impl Display for GatewayError {
 }
 
 impl Error for GatewayError {}
+
+/// Errors returned by the gateway [`Client`](crate::client::Client).
+#[derive(Debug)]
+pub enum ClientError {
+    /// A segment of the path doesn't exist.
+    NotFound(String),
+    /// The root block wasn't found within the resolve timeout.
+    Timeout,
+    /// The path leads through content that can't be traversed.
+    InvalidPath(String),
+    /// Loading the content failed after it was resolved.
+    Upstream(anyhow::Error),
+    /// Resolving the path failed.
+    ResolveFailed(String),
+    /// The IPNS or DNSLink name at the root of the path couldn't be resolved to a CID.
+    NameResolution(String),
+    /// A block doesn't hash to the CID it was requested by.
+    HashMismatch(Cid),
+    /// The store has no disk space left for the blocks of the request.
+    InsufficientStorage(String),
+    /// Too many IPNS or DNSLink names are being resolved to take on another one.
+    TooManyNameResolutions(String),
+}
+
+impl ClientError {
+    /// Classifies an error of the resolver by the typed error it was created from.
+    pub(crate) fn from_resolve_error(err: &anyhow::Error) -> Self {
+        if let Some(ClientError::HashMismatch(cid)) = err
+            .downcast_ref::<std::io::Error>()
+            .and_then(ClientError::from_io_error)
+        {
+            return ClientError::HashMismatch(*cid);
+        }
+        if iroh_rpc_types::is_resource_exhausted(err) {
+            return ClientError::InsufficientStorage(err.to_string());
+        }
+        if err.is::<tokio::time::error::Elapsed>() {
+            return ClientError::Timeout;
+        }
+        let message = err.to_string();
+        if err.is::<LinkNotFound>() || err.is::<BlockNotFound>() {
+            ClientError::NotFound(message)
+        } else if err.is::<UnexpectedUnixfsType>() {
+            ClientError::InvalidPath(message)
+        } else {
+            ClientError::ResolveFailed(message)
+        }
+    }
+
+    /// The error an io error was created from, like the [`ClientError::HashMismatch`] that
+    /// aborts the body of a file with a corrupted block.
+    pub fn from_io_error(err: &std::io::Error) -> Option<&ClientError> {
+        err.get_ref()?.downcast_ref()
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            ClientError::NotFound(_) => StatusCode::NOT_FOUND,
+            ClientError::Timeout => StatusCode::GATEWAY_TIMEOUT,
+            ClientError::InvalidPath(_) => StatusCode::BAD_REQUEST,
+            ClientError::NameResolution(_) | ClientError::HashMismatch(_) => {
+                StatusCode::BAD_GATEWAY
+            }
+            ClientError::Upstream(_) | ClientError::ResolveFailed(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            ClientError::InsufficientStorage(_) => StatusCode::INSUFFICIENT_STORAGE,
+            ClientError::TooManyNameResolutions(_) => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+}
+
+impl Display for ClientError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::NotFound(message)
+            | ClientError::InvalidPath(message)
+            | ClientError::ResolveFailed(message)
+            | ClientError::NameResolution(message)
+            | ClientError::InsufficientStorage(message)
+            | ClientError::TooManyNameResolutions(message) => write!(f, "{}", message),
+            ClientError::Timeout => write!(f, "resolve timeout"),
+            ClientError::HashMismatch(cid) => write!(f, "block {} doesn't match its hash", cid),
+            ClientError::Upstream(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for ClientError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ClientError::Upstream(err) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ClientError {
+    fn from(err: std::io::Error) -> Self {
+        ClientError::Upstream(err.into())
+    }
+}
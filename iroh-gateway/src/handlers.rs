@@ -15,7 +15,9 @@ use handlebars::Handlebars;
 use http::Method;
 use iroh_metrics::{core::MRecorder, gateway::GatewayMetrics, get_current_trace_id, inc};
 use iroh_resolver::{
-    resolver::{CidOrDomain, ContentLoader, OutMetrics, UnixfsType},
+    resolver::{
+        CidOrDomain, ContentLoader, FetchPolicy, Metadata, OutMetrics, OutType, UnixfsType,
+    },
     unixfs::Link,
 };
 use iroh_util::human::format_bytes;
@@ -44,9 +46,9 @@ use crate::{
     client::{FileResult, Request},
     constants::*,
     core::State,
-    error::GatewayError,
+    error::{ClientError, GatewayError},
     headers::*,
-    response::{get_response_format, GatewayResponse, ResponseFormat},
+    response::{log_slow_request, GatewayResponse, ResponseFormat},
     templates::{icon_class_name, ICONS_STYLESHEET, STYLESHEET},
 };
 
@@ -55,19 +57,46 @@ use crate::{
 pub trait StateConfig: std::fmt::Debug + Sync + Send {
     fn rpc_client(&self) -> &iroh_rpc_client::Config;
     fn public_url_base(&self) -> &str;
+    fn base_path(&self) -> &str;
+    fn default_content_type(&self) -> &str;
+    fn max_concurrent_name_resolutions(&self) -> Option<usize>;
+    fn max_queued_name_resolutions(&self) -> usize;
+    fn slow_request_threshold(&self) -> Option<Duration>;
+    fn resolve_timeout(&self) -> Option<Duration>;
+    fn block_cache_size(&self) -> Option<usize>;
+    fn fetch_policy(&self) -> FetchPolicy;
+    fn skip_block_verification(&self) -> bool;
+    fn hide_ipfs_headers(&self) -> bool;
     fn port(&self) -> u16;
     fn user_headers(&self) -> &HeaderMap<HeaderValue>;
 }
 
+/// Normalizes a configured base path to either `""` or `/<prefix>`, without a trailing slash.
+pub fn normalize_base_path(base_path: &str) -> String {
+    let trimmed = base_path.trim_matches('/');
+    if trimmed.is_empty() {
+        String::new()
+    } else {
+        format!("/{}", trimmed)
+    }
+}
+
 pub fn get_app_routes<T: ContentLoader + std::marker::Unpin>(state: &Arc<State<T>>) -> Router {
     // todo(arqu): ?uri=... https://github.com/ipfs/go-ipfs/pull/7802
-    Router::new()
+    let routes = Router::new()
         .route("/:scheme/:cid", get(get_handler::<T>))
         .route("/:scheme/:cid/*cpath", get(get_handler::<T>))
         .route("/health", get(health_check))
         .route("/icons.css", get(stylesheet_icons))
-        .route("/style.css", get(stylesheet_main))
-        .layer(Extension(Arc::clone(state)))
+        .route("/style.css", get(stylesheet_main));
+    let base_path = normalize_base_path(state.config.base_path());
+    let app = if base_path.is_empty() {
+        routes
+    } else {
+        Router::new().nest(&base_path, routes)
+    };
+
+    app.layer(Extension(Arc::clone(state)))
         .layer(
             ServiceBuilder::new()
                 // Handle errors from middleware
@@ -105,9 +134,15 @@ pub struct GetParams {
     /// uri query parameter for handling navigator.registerProtocolHandler Web API requests
     uri: Option<String>,
     recursive: Option<bool>,
+    /// overrides the configured content-type for extensionless files that can't be sniffed
+    default_content_type: Option<String>,
 }
 
 impl GetParams {
+    pub fn format(&self) -> Option<&str> {
+        self.format.as_deref()
+    }
+
     pub fn to_query_string(&self) -> String {
         let q = serde_qs::to_string(self).unwrap();
         if q.is_empty() {
@@ -171,12 +206,8 @@ pub async fn get_handler<T: ContentLoader + std::marker::Unpin>(
     }
 
     // parse query params
-    let format = match get_response_format(&request_headers, query_params.format) {
-        Ok(format) => format,
-        Err(err) => {
-            return Err(error(StatusCode::BAD_REQUEST, &err, &state));
-        }
-    };
+    let accept = request_headers.get(ACCEPT).and_then(|v| v.to_str().ok());
+    let format = ResponseFormat::negotiate(accept, &query_params);
 
     let query_file_name = query_params.filename.unwrap_or_default();
     let download = query_params.download.unwrap_or_default();
@@ -201,7 +232,12 @@ pub async fn get_handler<T: ContentLoader + std::marker::Unpin>(
             ));
         }
     };
-    headers.insert(&HEADER_X_IPFS_PATH, hv);
+    if !state.config.hide_ipfs_headers() {
+        headers.insert(&HEADER_X_IPFS_PATH, hv);
+        if let Some(host) = request_headers.get(HOST) {
+            headers.insert(&HEADER_X_IPFS_GATEWAY_HOST, host.clone());
+        }
+    }
 
     // handle request and fetch data
     let req = Request {
@@ -211,17 +247,27 @@ pub async fn get_handler<T: ContentLoader + std::marker::Unpin>(
         query_file_name,
         download,
         query_params: query_params_copy,
+        head: method == Method::HEAD,
     };
 
-    if recursive {
-        serve_car_recursive(&req, state, headers, start_time).await
-    } else {
-        match req.format {
-            ResponseFormat::Raw => serve_raw(&req, state, headers, &http_req, start_time).await,
-            ResponseFormat::Car => serve_car(&req, state, headers, start_time).await,
-            ResponseFormat::Fs(_) => serve_fs(&req, state, headers, &http_req, start_time).await,
-        }
+    let slow_request_threshold = state.config.slow_request_threshold();
+    let mut res = match req.format {
+        // tar archives are always recursive
+        ResponseFormat::Tar => serve_tar(&req, state, headers, start_time).await,
+        _ if recursive => serve_car_recursive(&req, state, headers, start_time).await,
+        ResponseFormat::Raw => serve_raw(&req, state, headers, &http_req, start_time).await,
+        ResponseFormat::Car => serve_car(&req, state, headers, start_time).await,
+        ResponseFormat::Fs(_) => serve_fs(&req, state, headers, &http_req, start_time).await,
+    }?;
+    if let Some(threshold) = slow_request_threshold {
+        res.body = log_slow_request(
+            res.body,
+            req.resolved_path.to_string(),
+            start_time,
+            threshold,
+        );
     }
+    Ok(res)
 }
 
 #[tracing::instrument()]
@@ -396,6 +442,23 @@ fn etag_check<T: ContentLoader>(
     None
 }
 
+fn if_none_match(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty())
+        .map(|v| v.to_string())
+}
+
+/// The ETag of a file served from the filesystem view, the CID the path resolved to, so
+/// repeated requests for it can be answered with a 304 by
+/// [`Client::get_file`](crate::client::Client::get_file).
+fn file_etag(req: &Request, metadata: &Metadata) -> String {
+    metadata
+        .etag()
+        .unwrap_or_else(|| get_etag(&req.cid, Some(req.format.clone())))
+}
+
 #[tracing::instrument()]
 async fn serve_raw<T: ContentLoader + std::marker::Unpin>(
     req: &Request,
@@ -404,17 +467,25 @@ async fn serve_raw<T: ContentLoader + std::marker::Unpin>(
     http_req: &HttpRequest<Body>,
     start_time: std::time::Instant,
 ) -> Result<GatewayResponse, GatewayError> {
-    let range: Option<Range<u64>> = if http_req.headers().contains_key(RANGE) {
-        parse_range_header(http_req.headers().get(RANGE).unwrap())
-    } else {
-        None
+    // raw blocks only support a single range, anything else is served in full
+    let range: Option<Range<u64>> = match http_req.headers().get(RANGE).and_then(parse_range_header)
+    {
+        Some(ranges) if ranges.len() == 1 => ranges.into_iter().next(),
+        _ => None,
     };
     // FIXME: we currently only retrieve full cids
     let (body, metadata) = state
         .client
-        .get_file(req.resolved_path.clone(), start_time, range.clone())
+        .get_file(
+            req.resolved_path.clone(),
+            start_time,
+            range.clone(),
+            !req.head,
+            None,
+            state.config.fetch_policy(),
+        )
         .await
-        .map_err(|e| error(StatusCode::INTERNAL_SERVER_ERROR, &e, &state))?;
+        .map_err(|e| client_error(e, &state))?;
 
     match body {
         FileResult::File(body) | FileResult::Raw(body) => {
@@ -429,13 +500,24 @@ async fn serve_raw<T: ContentLoader + std::marker::Unpin>(
                 return Ok(res);
             }
             add_cache_control_headers(&mut headers, metadata.clone());
-            add_ipfs_roots_headers(&mut headers, metadata.clone());
+            if !state.config.hide_ipfs_headers() {
+                add_ipfs_roots_headers(&mut headers, metadata.clone());
+            }
             add_content_length_header(&mut headers, metadata.clone());
 
-            if let Some(mut capped_range) = range {
-                if let Some(size) = metadata.size {
-                    capped_range.end = std::cmp::min(capped_range.end, size);
-                }
+            if let Some(range) = range {
+                let capped_range = match satisfiable_ranges(vec![range], metadata.size).pop() {
+                    Some(range) => range,
+                    None => {
+                        headers.remove(CONTENT_LENGTH);
+                        add_unsatisfiable_range_headers(&mut headers, metadata.size);
+                        return response(StatusCode::RANGE_NOT_SATISFIABLE, Body::empty(), headers);
+                    }
+                };
+                headers.insert(
+                    CONTENT_LENGTH,
+                    HeaderValue::from(capped_range.end - capped_range.start),
+                );
                 add_etag_range(&mut headers, capped_range.clone());
                 add_content_range_headers(&mut headers, capped_range, metadata.size);
                 response(StatusCode::PARTIAL_CONTENT, body, headers)
@@ -448,6 +530,7 @@ async fn serve_raw<T: ContentLoader + std::marker::Unpin>(
             "cannot serve directory as raw",
             &state,
         )),
+        FileResult::NotModified => Ok(GatewayResponse::not_modified()),
     }
 }
 
@@ -462,9 +545,16 @@ async fn serve_car<T: ContentLoader + std::marker::Unpin>(
     // FIXME: we currently only retrieve full cids
     let (body, metadata) = state
         .client
-        .get_file(req.resolved_path.clone(), start_time, None)
+        .get_file(
+            req.resolved_path.clone(),
+            start_time,
+            None,
+            !req.head,
+            None,
+            state.config.fetch_policy(),
+        )
         .await
-        .map_err(|e| error(StatusCode::INTERNAL_SERVER_ERROR, &e, &state))?;
+        .map_err(|e| client_error(e, &state))?;
 
     match body {
         FileResult::File(body) | FileResult::Raw(body) => {
@@ -482,7 +572,9 @@ async fn serve_car<T: ContentLoader + std::marker::Unpin>(
             if let Some(res) = etag_check(&headers, &req.cid, &req.format, &state) {
                 return Ok(res);
             }
-            add_ipfs_roots_headers(&mut headers, metadata);
+            if !state.config.hide_ipfs_headers() {
+                add_ipfs_roots_headers(&mut headers, metadata);
+            }
             response(StatusCode::OK, body, headers)
         }
         FileResult::Directory(_) => Err(error(
@@ -490,6 +582,7 @@ async fn serve_car<T: ContentLoader + std::marker::Unpin>(
             "cannot serve directory as car file",
             &state,
         )),
+        FileResult::NotModified => Ok(GatewayResponse::not_modified()),
     }
 }
 
@@ -500,27 +593,66 @@ async fn serve_car_recursive<T: ContentLoader + std::marker::Unpin>(
     mut headers: HeaderMap,
     start_time: std::time::Instant,
 ) -> Result<GatewayResponse, GatewayError> {
+    let file_name = match req.query_file_name.is_empty() {
+        true => format!("{}.car", req.cid),
+        false => req.query_file_name.clone(),
+    };
+
+    set_content_disposition_headers(&mut headers, &file_name, DISPOSITION_ATTACHMENT);
+
+    // add_cache_control_headers(&mut headers, metadata.clone());
+    let etag = format!("W/{}", get_etag(&req.cid, Some(req.format.clone())));
+    set_etag_headers(&mut headers, etag);
+    if let Some(res) = etag_check(&headers, &req.cid, &req.format, &state) {
+        return Ok(res);
+    }
+    // add_ipfs_roots_headers(&mut headers, metadata);
+    if req.head {
+        return response(StatusCode::OK, Body::empty(), headers);
+    }
+
     let body = state
         .client
         .clone()
-        .get_car_recursive(req.resolved_path.clone(), start_time)
+        .get_car_recursive(
+            req.resolved_path.clone(),
+            start_time,
+            state.config.fetch_policy(),
+        )
         .await
-        .map_err(|e| error(StatusCode::INTERNAL_SERVER_ERROR, &e, &state))?;
+        .map_err(|e| client_error(e, &state))?;
+    response(StatusCode::OK, body, headers)
+}
 
+#[tracing::instrument()]
+async fn serve_tar<T: ContentLoader + std::marker::Unpin>(
+    req: &Request,
+    state: Arc<State<T>>,
+    mut headers: HeaderMap,
+    start_time: std::time::Instant,
+) -> Result<GatewayResponse, GatewayError> {
     let file_name = match req.query_file_name.is_empty() {
-        true => format!("{}.car", req.cid),
+        true => format!("{}.tar", req.cid),
         false => req.query_file_name.clone(),
     };
 
     set_content_disposition_headers(&mut headers, &file_name, DISPOSITION_ATTACHMENT);
 
-    // add_cache_control_headers(&mut headers, metadata.clone());
     let etag = format!("W/{}", get_etag(&req.cid, Some(req.format.clone())));
     set_etag_headers(&mut headers, etag);
     if let Some(res) = etag_check(&headers, &req.cid, &req.format, &state) {
         return Ok(res);
     }
-    // add_ipfs_roots_headers(&mut headers, metadata);
+    if req.head {
+        return response(StatusCode::OK, Body::empty(), headers);
+    }
+
+    let body = state
+        .client
+        .clone()
+        .get_tar(req.resolved_path.clone(), start_time)
+        .await
+        .map_err(|e| client_error(e, &state))?;
     response(StatusCode::OK, body, headers)
 }
 
@@ -533,24 +665,59 @@ async fn serve_fs<T: ContentLoader + std::marker::Unpin>(
     http_req: &HttpRequest<Body>,
     start_time: std::time::Instant,
 ) -> Result<GatewayResponse, GatewayError> {
-    let range: Option<Range<u64>> = if http_req.headers().contains_key(RANGE) {
-        parse_range_header(http_req.headers().get(RANGE).unwrap())
-    } else {
-        None
+    let mut ranges: Vec<Range<u64>> = http_req
+        .headers()
+        .get(RANGE)
+        .and_then(parse_range_header)
+        .unwrap_or_default();
+    // each range of a multipart response resolves the path again, too many are ignored
+    if ranges.len() > MAX_BYTE_RANGES {
+        ranges.clear();
+    }
+    let range_requested = !ranges.is_empty();
+    if ranges.len() > 1 {
+        // the ranges left once clamped to the size decide how the file is read
+        let size = state
+            .client
+            .head(req.resolved_path.clone(), state.config.fetch_policy())
+            .await
+            .map_err(|e| client_error(e, &state))?
+            .size;
+        ranges = satisfiable_ranges(ranges, size);
+    }
+    // a single range is read straight from the file, several are fetched as multipart below
+    let range = match ranges.as_slice() {
+        [range] if *range != (0..u64::MAX) => Some(range.clone()),
+        _ => None,
     };
 
+    // HEAD requests only read the contents if the name doesn't tell their content-type
+    let sniff = !req.head
+        || mime_guess::from_path(get_content_name(&req.query_file_name, &req.resolved_path))
+            .first()
+            .is_none();
+
     // FIXME: we currently only retrieve full cids
     let (body, metadata) = state
         .client
-        .get_file(req.resolved_path.clone(), start_time, range.clone())
+        .get_file(
+            req.resolved_path.clone(),
+            start_time,
+            range.clone(),
+            sniff,
+            if_none_match(http_req.headers()),
+            state.config.fetch_policy(),
+        )
         .await
-        .map_err(|e| error(StatusCode::INTERNAL_SERVER_ERROR, &e, &state))?;
+        .map_err(|e| client_error(e, &state))?;
 
-    add_ipfs_roots_headers(&mut headers, metadata.clone());
+    if !state.config.hide_ipfs_headers() {
+        add_ipfs_roots_headers(&mut headers, metadata.clone());
+    }
     match body {
         FileResult::Directory(res) => {
             let dir_list: anyhow::Result<Vec<_>> = res
-                .unixfs_read_dir(&state.client.resolver, OutMetrics { start: start_time })
+                .unixfs_read_dir(&state.client.resolver, OutMetrics::new(start_time))
                 .map_err(|e| error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string(), &state))?
                 .expect("already known this is a directory")
                 .try_collect()
@@ -576,7 +743,7 @@ async fn serve_fs<T: ContentLoader + std::marker::Unpin>(
                     // todo(arqu): add lazy seeking
                     add_cache_control_headers(&mut headers, metadata.clone());
                     add_content_length_header(&mut headers, metadata.clone());
-                    set_etag_headers(&mut headers, get_etag(&req.cid, Some(req.format.clone())));
+                    set_etag_headers(&mut headers, file_etag(req, &metadata));
                     if let Some(res) = etag_check(&headers, &req.cid, &req.format, &state) {
                         return Ok(res);
                     }
@@ -593,20 +760,84 @@ async fn serve_fs<T: ContentLoader + std::marker::Unpin>(
                         );
                     } else {
                         let content_sniffed_mime = body.get_mime();
-                        add_content_type_headers(&mut headers, &name, content_sniffed_mime);
+                        add_content_type_headers(
+                            &mut headers,
+                            &name,
+                            content_sniffed_mime,
+                            default_content_type(req, &state),
+                        );
                     }
 
-                    if let Some(mut capped_range) = range {
-                        if let Some(size) = metadata.size {
-                            capped_range.end = std::cmp::min(capped_range.end, size);
+                    add_accept_ranges_header(&mut headers);
+
+                    // the whole content is sent when no range applies, or all of it is
+                    // asked for without knowing its size
+                    if !range_requested || (metadata.size.is_none() && ranges == [0..u64::MAX]) {
+                        return response(StatusCode::OK, body, headers);
+                    }
+                    let ranges = satisfiable_ranges(ranges, metadata.size);
+                    match ranges.len() {
+                        0 => {
+                            headers.remove(CONTENT_LENGTH);
+                            add_unsatisfiable_range_headers(&mut headers, metadata.size);
+                            response(StatusCode::RANGE_NOT_SATISFIABLE, Body::empty(), headers)
+                        }
+                        1 => {
+                            let range = ranges[0].clone();
+                            headers
+                                .insert(CONTENT_LENGTH, HeaderValue::from(range.end - range.start));
+                            add_etag_range(&mut headers, range.clone());
+                            add_content_range_headers(&mut headers, range, metadata.size);
+                            response(StatusCode::PARTIAL_CONTENT, body, headers)
+                        }
+                        _ => {
+                            let boundary = format!("{:016x}", rand::random::<u64>());
+                            let content_type = headers
+                                .get(CONTENT_TYPE)
+                                .and_then(|ct| ct.to_str().ok())
+                                .map(|ct| ct.to_string());
+                            headers.insert(
+                                CONTENT_TYPE,
+                                HeaderValue::from_str(&format!(
+                                    "multipart/byteranges; boundary={}",
+                                    boundary
+                                ))
+                                .unwrap(),
+                            );
+                            headers.remove(CONTENT_LENGTH);
+                            let body = state
+                                .client
+                                .clone()
+                                .get_file_ranges(
+                                    req.resolved_path.clone(),
+                                    start_time,
+                                    ranges,
+                                    boundary,
+                                    content_type,
+                                    state.config.fetch_policy(),
+                                )
+                                .await
+                                .map_err(|e| client_error(e, &state))?;
+                            response(StatusCode::PARTIAL_CONTENT, body, headers)
                         }
-                        add_etag_range(&mut headers, capped_range.clone());
-                        add_content_range_headers(&mut headers, capped_range, metadata.size);
-                        response(StatusCode::PARTIAL_CONTENT, body, headers)
-                    } else {
-                        response(StatusCode::OK, body, headers)
                     }
                 }
+                // a (sub-)node of a dag-cbor or dag-json dag, served in its own codec
+                None if matches!(metadata.typ, OutType::DagCbor | OutType::DagJson) => {
+                    add_cache_control_headers(&mut headers, metadata.clone());
+                    add_content_length_header(&mut headers, metadata.clone());
+                    set_etag_headers(&mut headers, get_etag(&req.cid, Some(req.format.clone())));
+                    if let Some(res) = etag_check(&headers, &req.cid, &req.format, &state) {
+                        return Ok(res);
+                    }
+                    let content_type = if metadata.typ == OutType::DagCbor {
+                        CONTENT_TYPE_IPLD_DAG_CBOR.clone()
+                    } else {
+                        CONTENT_TYPE_IPLD_DAG_JSON.clone()
+                    };
+                    headers.insert(CONTENT_TYPE, content_type);
+                    response(StatusCode::OK, body, headers)
+                }
                 None => Err(error(
                     StatusCode::BAD_REQUEST,
                     "couldn't determine unixfs type",
@@ -619,7 +850,7 @@ async fn serve_fs<T: ContentLoader + std::marker::Unpin>(
             // todo(arqu): add lazy seeking
             add_cache_control_headers(&mut headers, metadata.clone());
             add_content_length_header(&mut headers, metadata.clone());
-            set_etag_headers(&mut headers, get_etag(&req.cid, Some(req.format.clone())));
+            set_etag_headers(&mut headers, file_etag(req, &metadata));
             if let Some(res) = etag_check(&headers, &req.cid, &req.format, &state) {
                 return Ok(res);
             }
@@ -630,12 +861,31 @@ async fn serve_fs<T: ContentLoader + std::marker::Unpin>(
                 req.download,
             );
             let content_sniffed_mime = body.get_mime();
-            add_content_type_headers(&mut headers, &name, content_sniffed_mime);
+            add_content_type_headers(
+                &mut headers,
+                &name,
+                content_sniffed_mime,
+                default_content_type(req, &state),
+            );
             response(StatusCode::OK, body, headers)
         }
+        FileResult::NotModified => {
+            add_cache_control_headers(&mut headers, metadata.clone());
+            set_etag_headers(&mut headers, file_etag(req, &metadata));
+            response(StatusCode::NOT_MODIFIED, Body::empty(), headers)
+        }
     }
 }
 
+/// The content-type to fall back to when neither the name nor the content tell us one,
+/// the request's `default_content_type` taking precedence over the configured one.
+fn default_content_type<'a, T: ContentLoader>(req: &'a Request, state: &'a State<T>) -> &'a str {
+    req.query_params
+        .default_content_type
+        .as_deref()
+        .unwrap_or_else(|| state.config.default_content_type())
+}
+
 #[tracing::instrument()]
 async fn serve_fs_dir<T: ContentLoader + std::marker::Unpin>(
     dir_list: &[Link],
@@ -645,6 +895,7 @@ async fn serve_fs_dir<T: ContentLoader + std::marker::Unpin>(
     http_req: &HttpRequest<Body>,
     start_time: std::time::Instant,
 ) -> Result<GatewayResponse, GatewayError> {
+    let base_path = normalize_base_path(state.config.base_path());
     let force_dir = req.query_params.force_dir.unwrap_or(false);
     let has_index = dir_list.iter().any(|l| {
         l.name
@@ -655,7 +906,8 @@ async fn serve_fs_dir<T: ContentLoader + std::marker::Unpin>(
     if !force_dir && has_index {
         if !req.resolved_path.has_trailing_slash() {
             let redirect_path = format!(
-                "{}/{}",
+                "{}{}/{}",
+                base_path,
                 req.resolved_path,
                 req.query_params.to_query_string()
             );
@@ -687,11 +939,12 @@ async fn serve_fs_dir<T: ContentLoader + std::marker::Unpin>(
             let mut el = HashMap::new();
             let path = match accum.last() {
                 Some(prev) => match prev.get("path") {
-                    Some(base) => format!("/{}/{}", base, encode(path_el)),
+                    // `base` already starts with a slash
+                    Some(base) => format!("{}/{}", base, encode(path_el)),
                     None => format!("/{}", encode(path_el)),
                 },
                 None => {
-                    format!("/{}", encode(path_el))
+                    format!("{}/{}", base_path, encode(path_el))
                 }
             };
             el.insert("name", path_el.to_string());
@@ -712,6 +965,7 @@ async fn serve_fs_dir<T: ContentLoader + std::marker::Unpin>(
         "public_url_base".to_string(),
         Json::String(state.config.public_url_base().to_string()),
     );
+    template_data.insert("base_path".to_string(), Json::String(base_path.clone()));
     // TODO(b5) - add directory size
     template_data.insert("size".to_string(), Json::String("".to_string()));
     let links = dir_list
@@ -726,7 +980,7 @@ async fn serve_fs_dir<T: ContentLoader + std::marker::Unpin>(
             );
             link.insert(
                 "path".to_string(),
-                Json::String(format!("{}{}", root_path, name)),
+                Json::String(format!("{}{}{}", base_path, root_path, name)),
             );
             link.insert("icon".to_string(), Json::String(icon_class_name(name)));
             link
@@ -757,6 +1011,11 @@ where
     })
 }
 
+#[tracing::instrument()]
+fn client_error<T: ContentLoader>(err: ClientError, state: &State<T>) -> GatewayError {
+    error(err.status_code(), &err.to_string(), state)
+}
+
 #[tracing::instrument()]
 fn error<T: ContentLoader>(
     status_code: StatusCode,
@@ -17,11 +17,19 @@ pub fn add_content_type_headers(
     headers: &mut HeaderMap,
     name: &str,
     content_sniffed_mime: Option<Mime>,
+    default_content_type: &str,
 ) {
     let guess = mime_guess::from_path(name);
+    // sniffing falls back to octet-stream when it can't tell what the content is
+    let inconclusive = content_sniffed_mime
+        .as_ref()
+        .map_or(true, |ct| *ct == mime::APPLICATION_OCTET_STREAM);
+    let default_mime = default_content_type.parse::<Mime>().ok();
     let mut content_type = String::new();
     if let Some(ct) = guess.first() {
         content_type = ct.to_string();
+    } else if let (true, Some(ct)) = (inconclusive, default_mime) {
+        content_type = ct.to_string();
     } else if let Some(ct) = content_sniffed_mime {
         content_type = ct.to_string();
     }
@@ -48,10 +56,7 @@ pub fn add_content_disposition_headers(
     content_path: &iroh_resolver::resolver::Path,
     should_download: bool,
 ) -> String {
-    let mut name = get_filename(&content_path.to_string());
-    if !filename.is_empty() {
-        name = filename.to_string();
-    }
+    let name = get_content_name(filename, content_path);
     if !name.is_empty() {
         let disposition = if should_download {
             DISPOSITION_ATTACHMENT
@@ -63,6 +68,16 @@ pub fn add_content_disposition_headers(
     name
 }
 
+/// The name content is served under: the requested `filename` if any, the last path segment
+/// otherwise.
+pub fn get_content_name(filename: &str, content_path: &iroh_resolver::resolver::Path) -> String {
+    if filename.is_empty() {
+        get_filename(&content_path.to_string())
+    } else {
+        filename.to_string()
+    }
+}
+
 #[tracing::instrument()]
 pub fn set_content_disposition_headers(headers: &mut HeaderMap, filename: &str, disposition: &str) {
     // TODO: handle non-ascii filenames https://github.com/ipfs/specs/blob/main/http-gateways/PATH_GATEWAY.md#content-disposition-response-header
@@ -90,20 +105,74 @@ pub fn add_content_range_headers(headers: &mut HeaderMap, range: Range<u64>, siz
     );
 }
 
-pub fn parse_range_header(range: &HeaderValue) -> Option<Range<u64>> {
-    // TODO: potentially support multiple ranges ie bytes=0-100,200-300
+#[tracing::instrument()]
+pub fn add_accept_ranges_header(headers: &mut HeaderMap) {
+    headers.insert(ACCEPT_RANGES, VALUE_BYTES.clone());
+}
+
+#[tracing::instrument()]
+pub fn add_unsatisfiable_range_headers(headers: &mut HeaderMap, size: Option<u64>) {
+    if let Some(size) = size {
+        headers.insert(
+            CONTENT_RANGE,
+            HeaderValue::from_str(&format!("bytes */{}", size)).unwrap(),
+        );
+    }
+}
+
+/// Parses a `Range` header with one or more byte ranges, ie. `bytes=0-99,200-299`.
+///
+/// The returned ranges are end-exclusive, open ended ranges like `bytes=100-` end at
+/// `u64::MAX`. Suffix ranges are not supported.
+pub fn parse_range_header(range: &HeaderValue) -> Option<Vec<Range<u64>>> {
     let range = range.to_str().ok()?;
     let mut parts = range.splitn(2, '=');
     if parts.next() != Some("bytes") {
         return None;
     }
-    let mut range = parts.next()?.splitn(2, '-');
-    let start = range.next()?.parse().ok()?;
-    let end = range.next()?.parse().ok()?;
-    if start >= end || end == 0 {
-        return None;
+    parts
+        .next()?
+        .split(',')
+        .map(|range| {
+            let mut range = range.trim().splitn(2, '-');
+            let start = range.next()?.parse().ok()?;
+            let last = range.next()?;
+            if last.is_empty() {
+                return Some(Range {
+                    start,
+                    end: u64::MAX,
+                });
+            }
+            let last: u64 = last.parse().ok()?;
+            if start > last {
+                return None;
+            }
+            Some(Range {
+                start,
+                end: last + 1,
+            })
+        })
+        .collect()
+}
+
+/// Clamps the ranges to the content size, dropping those that start past its end.
+///
+/// Open ended ranges can't be clamped without knowing the size, they are dropped in that case.
+pub fn satisfiable_ranges(ranges: Vec<Range<u64>>, size: Option<u64>) -> Vec<Range<u64>> {
+    match size {
+        Some(size) => ranges
+            .into_iter()
+            .filter(|range| range.start < size)
+            .map(|range| Range {
+                start: range.start,
+                end: range.end.min(size),
+            })
+            .collect(),
+        None => ranges
+            .into_iter()
+            .filter(|range| range.end != u64::MAX)
+            .collect(),
     }
-    Some(Range { start, end })
 }
 
 #[tracing::instrument()]
@@ -300,7 +369,7 @@ mod tests {
         let name = "test.txt";
         let body = "test body";
         let content_sniffed_mime = Some(crate::client::sniff_content_type(body.as_bytes()));
-        add_content_type_headers(&mut headers, name, content_sniffed_mime.clone());
+        add_content_type_headers(&mut headers, name, content_sniffed_mime.clone(), "");
         assert_eq!(headers.len(), 1);
         assert_eq!(
             headers.get(&CONTENT_TYPE).unwrap(),
@@ -309,19 +378,36 @@ mod tests {
 
         let mut headers = HeaderMap::new();
         let name = "test.RAND_EXT";
-        add_content_type_headers(&mut headers, name, content_sniffed_mime);
+        add_content_type_headers(&mut headers, name, content_sniffed_mime, "");
         assert_eq!(headers.len(), 1);
         assert_eq!(
             headers.get(&CONTENT_TYPE).unwrap(),
             &"text/plain; charset=utf-8".to_string()
         );
+
+        // unsniffable content without an extension uses the default, if any
+        let name = "README";
+        let content_sniffed_mime = Some(crate::client::sniff_content_type(&[0u8, 1, 2, 3]));
+        let mut headers = HeaderMap::new();
+        add_content_type_headers(&mut headers, name, content_sniffed_mime.clone(), "");
+        assert_eq!(
+            headers.get(&CONTENT_TYPE).unwrap(),
+            &mime::APPLICATION_OCTET_STREAM.to_string()
+        );
+
+        let mut headers = HeaderMap::new();
+        add_content_type_headers(&mut headers, name, content_sniffed_mime, "text/plain");
+        assert_eq!(
+            headers.get(&CONTENT_TYPE).unwrap(),
+            &"text/plain; charset=utf-8".to_string()
+        );
     }
 
     #[test]
     fn parse_range_header_test() {
         let range = HeaderValue::from_str("bytes=0-10").unwrap();
         let r = parse_range_header(&range);
-        assert_eq!(r, Some(Range { start: 0, end: 10 }));
+        assert_eq!(r, Some(vec![Range { start: 0, end: 11 }]));
 
         let range = HeaderValue::from_str("byts=0-10").unwrap();
         let r = parse_range_header(&range);
@@ -329,7 +415,13 @@ mod tests {
 
         let range = HeaderValue::from_str("bytes=0-").unwrap();
         let r = parse_range_header(&range);
-        assert_eq!(r, None);
+        assert_eq!(
+            r,
+            Some(vec![Range {
+                start: 0,
+                end: u64::MAX
+            }])
+        );
 
         let range = HeaderValue::from_str("bytes=10-1").unwrap();
         let r = parse_range_header(&range);
@@ -337,23 +429,73 @@ mod tests {
 
         let range = HeaderValue::from_str("bytes=0-0").unwrap();
         let r = parse_range_header(&range);
-        assert_eq!(r, None);
+        assert_eq!(r, Some(vec![Range { start: 0, end: 1 }]));
 
         let range = HeaderValue::from_str("bytes=100-200").unwrap();
         let r = parse_range_header(&range);
         assert_eq!(
             r,
-            Some(Range {
+            Some(vec![Range {
                 start: 100,
-                end: 200
-            })
+                end: 201
+            }])
+        );
+
+        let range = HeaderValue::from_str("bytes=0-10, 20-30").unwrap();
+        let r = parse_range_header(&range);
+        assert_eq!(
+            r,
+            Some(vec![
+                Range { start: 0, end: 11 },
+                Range { start: 20, end: 31 }
+            ])
         );
 
-        let range = HeaderValue::from_str("bytes=0-10,20-30").unwrap();
+        let range = HeaderValue::from_str("bytes=0-10,30-20").unwrap();
         let r = parse_range_header(&range);
         assert_eq!(r, None);
     }
 
+    #[test]
+    fn satisfiable_ranges_test() {
+        let ranges = vec![
+            Range { start: 0, end: 10 },
+            Range {
+                start: 95,
+                end: 200,
+            },
+            Range {
+                start: 100,
+                end: 200,
+            },
+        ];
+        assert_eq!(
+            satisfiable_ranges(ranges.clone(), Some(100)),
+            vec![
+                Range { start: 0, end: 10 },
+                Range {
+                    start: 95,
+                    end: 100
+                }
+            ]
+        );
+        assert_eq!(satisfiable_ranges(ranges.clone(), None), ranges);
+        assert!(satisfiable_ranges(vec![Range { start: 10, end: 20 }], Some(10)).is_empty());
+
+        let open_ended = vec![Range {
+            start: 50,
+            end: u64::MAX,
+        }];
+        assert_eq!(
+            satisfiable_ranges(open_ended.clone(), Some(100)),
+            vec![Range {
+                start: 50,
+                end: 100
+            }]
+        );
+        assert!(satisfiable_ranges(open_ended, None).is_empty());
+    }
+
     #[test]
     fn add_content_disposition_headers_test() {
         // inline
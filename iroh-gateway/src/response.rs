@@ -1,12 +1,20 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
 use axum::{
     body::BoxBody,
     http::{header::*, HeaderValue, StatusCode},
     response::{IntoResponse, Redirect, Response},
 };
+use bytes::Bytes;
+use http_body::{Body as HttpBody, SizeHint};
 use iroh_metrics::get_current_trace_id;
 use opentelemetry::trace::TraceId;
+use tracing::warn;
 
 use crate::constants::*;
+use crate::handlers::GetParams;
 
 pub const ERR_UNSUPPORTED_FORMAT: &str = "unsuported format";
 
@@ -14,6 +22,7 @@ pub const ERR_UNSUPPORTED_FORMAT: &str = "unsuported format";
 pub enum ResponseFormat {
     Raw,
     Car,
+    Tar,
     Fs(String),
 }
 
@@ -24,6 +33,7 @@ impl std::convert::TryFrom<&str> for ResponseFormat {
         match s.to_lowercase().as_str() {
             "application/vnd.ipld.raw" | "raw" => Ok(ResponseFormat::Raw),
             "application/vnd.ipld.car" | "car" => Ok(ResponseFormat::Car),
+            "application/x-tar" | "tar" => Ok(ResponseFormat::Tar),
             "fs" | "" => Ok(ResponseFormat::Fs(String::new())),
             rf => {
                 if rf.starts_with("application/vnd.ipld.") {
@@ -48,6 +58,11 @@ impl ResponseFormat {
                 headers.insert(&HEADER_X_CONTENT_TYPE_OPTIONS, VALUE_XCTO_NOSNIFF.clone());
                 headers.insert(ACCEPT_RANGES, VALUE_NONE.clone());
             }
+            ResponseFormat::Tar => {
+                headers.insert(CONTENT_TYPE, CONTENT_TYPE_X_TAR.clone());
+                headers.insert(&HEADER_X_CONTENT_TYPE_OPTIONS, VALUE_XCTO_NOSNIFF.clone());
+                headers.insert(ACCEPT_RANGES, VALUE_NONE.clone());
+            }
             ResponseFormat::Fs(_) => {
                 // Don't send application/octet-stream in that case, let the
                 // client decide instead.
@@ -59,6 +74,7 @@ impl ResponseFormat {
         match self {
             ResponseFormat::Raw => "bin".to_string(),
             ResponseFormat::Car => "car".to_string(),
+            ResponseFormat::Tar => "tar".to_string(),
             ResponseFormat::Fs(s) => {
                 if s.is_empty() {
                     String::new()
@@ -69,38 +85,38 @@ impl ResponseFormat {
         }
     }
 
-    pub fn try_from_headers(headers: &HeaderMap) -> Result<Self, String> {
-        if headers.contains_key("Accept") {
-            if let Some(h_values) = headers.get("Accept") {
-                let h_values = h_values.to_str().unwrap().split(',');
-                for h_value in h_values {
-                    let h_value = h_value.trim();
-                    if h_value.starts_with("application/vnd.ipld.") {
-                        return ResponseFormat::try_from(h_value);
-                    }
-                }
+    /// Picks the format of a response: a supported `?format=` wins, then the first format
+    /// named in the `Accept` header, otherwise the content is served as is.
+    pub fn negotiate(accept: Option<&str>, query: &GetParams) -> Self {
+        if let Some(format) = query.format().filter(|format| !format.is_empty()) {
+            if let Ok(format) = ResponseFormat::try_from(format) {
+                return format;
             }
         }
-        Ok(ResponseFormat::Fs(String::new()))
+        accept
+            .and_then(Self::from_accept)
+            .unwrap_or_else(|| ResponseFormat::Fs(String::new()))
     }
-}
 
-#[tracing::instrument()]
-pub fn get_response_format(
-    request_headers: &HeaderMap,
-    query_format: Option<String>,
-) -> Result<ResponseFormat, String> {
-    if let Some(format) = query_format {
-        if !format.is_empty() {
-            if let Ok(format) = ResponseFormat::try_from(format.as_str()) {
-                return Ok(format);
+    /// The first media type of an `Accept` header that is a format, ignoring its parameters,
+    /// eg. `application/vnd.ipld.car; version=1`, and types refused with `q=0`.
+    fn from_accept(accept: &str) -> Option<Self> {
+        accept.split(',').find_map(|value| {
+            let mut parts = value.split(';').map(str::trim);
+            let media_type = parts.next()?;
+            let refused = parts.any(|param| match param.split_once('=') {
+                Some((name, q)) if name.eq_ignore_ascii_case("q") => {
+                    q.trim().parse::<f32>().map_or(false, |q| q <= 0.0)
+                }
+                _ => false,
+            });
+            let is_format = media_type.starts_with("application/vnd.ipld.")
+                || media_type.starts_with("application/x-tar");
+            if refused || !is_format {
+                return None;
             }
-        }
-    }
-
-    match ResponseFormat::try_from_headers(request_headers) {
-        Ok(format) => Ok(format),
-        Err(_) => Ok(ResponseFormat::Fs(String::new())),
+            ResponseFormat::try_from(media_type).ok()
+        })
     }
 }
 
@@ -164,16 +180,149 @@ impl GatewayResponse {
     }
 }
 
+/// Wraps the body of a response to `path`, warning once it is sent if the whole request took
+/// at least `threshold`. The warning tells the time to the first byte of the body as well.
+pub fn log_slow_request(
+    body: BoxBody,
+    path: String,
+    start_time: Instant,
+    threshold: Duration,
+) -> BoxBody {
+    axum::body::boxed(SlowRequestBody {
+        inner: body,
+        path,
+        start_time,
+        threshold,
+        first_byte: None,
+    })
+}
+
+struct SlowRequestBody {
+    inner: BoxBody,
+    path: String,
+    start_time: Instant,
+    threshold: Duration,
+    first_byte: Option<Duration>,
+}
+
+impl HttpBody for SlowRequestBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let res = Pin::new(&mut self.inner).poll_data(cx);
+        if self.first_byte.is_none() && matches!(res, Poll::Ready(Some(Ok(_)))) {
+            self.first_byte = Some(self.start_time.elapsed());
+        }
+        res
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+// the body is dropped once it is sent, or the client went away
+impl Drop for SlowRequestBody {
+    fn drop(&mut self) {
+        let total = self.start_time.elapsed();
+        if total < self.threshold {
+            return;
+        }
+        let first_byte = match self.first_byte {
+            Some(first_byte) => format!("{}ms", first_byte.as_millis()),
+            None => "never".to_string(),
+        };
+        warn!(
+            "slow request {}: first byte after {}, done after {}ms (threshold {}ms)",
+            self.path,
+            first_byte,
+            total.as_millis(),
+            self.threshold.as_millis()
+        );
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use std::sync::{Arc, Mutex};
+
     use super::*;
 
+    #[derive(Clone, Default)]
+    struct LogBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for LogBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Logs of sending a body that takes a while to start, with the given threshold.
+    async fn slow_body_logs(threshold: Duration) -> String {
+        let logs = LogBuffer::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let start_time = Instant::now();
+        let body = log_slow_request(
+            axum::body::boxed(axum::body::Body::from("hello")),
+            "/ipfs/bafkqaaa".to_string(),
+            start_time,
+            threshold,
+        );
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let data = hyper::body::to_bytes(body).await.unwrap();
+        assert_eq!(&data[..], b"hello");
+
+        let logs = logs.0.lock().unwrap();
+        String::from_utf8_lossy(&logs).into_owned()
+    }
+
+    #[tokio::test]
+    async fn slow_request_threshold() {
+        let logs = slow_body_logs(Duration::from_millis(10)).await;
+        assert!(logs.contains("slow request /ipfs/bafkqaaa"), "{}", logs);
+        assert!(logs.contains("first byte after"), "{}", logs);
+        assert!(logs.contains("done after"), "{}", logs);
+
+        let logs = slow_body_logs(Duration::from_secs(60)).await;
+        assert!(!logs.contains("slow request"), "{}", logs);
+    }
+
     #[test]
     fn response_format_try_from() {
         let rf = ResponseFormat::try_from("raw");
         assert_eq!(rf, Ok(ResponseFormat::Raw));
         let rf = ResponseFormat::try_from("car");
         assert_eq!(rf, Ok(ResponseFormat::Car));
+        let rf = ResponseFormat::try_from("tar");
+        assert_eq!(rf, Ok(ResponseFormat::Tar));
+        let rf = ResponseFormat::try_from("application/x-tar");
+        assert_eq!(rf, Ok(ResponseFormat::Tar));
         let rf = ResponseFormat::try_from("fs");
         assert_eq!(rf, Ok(ResponseFormat::Fs(String::new())));
         let rf = ResponseFormat::try_from("");
@@ -186,6 +335,51 @@ mod tests {
         assert!(rf.is_err());
     }
 
+    #[test]
+    fn response_format_negotiate() {
+        let query = |q: &str| -> GetParams { serde_qs::from_str(q).unwrap() };
+        let fs = ResponseFormat::Fs(String::new());
+
+        assert_eq!(ResponseFormat::negotiate(None, &query("")), fs);
+        assert_eq!(
+            ResponseFormat::negotiate(Some("text/html, */*"), &query("")),
+            fs
+        );
+        assert_eq!(
+            ResponseFormat::negotiate(Some("application/vnd.ipld.raw"), &query("")),
+            ResponseFormat::Raw
+        );
+        assert_eq!(
+            ResponseFormat::negotiate(
+                Some("text/html, application/vnd.ipld.car; version=1"),
+                &query("")
+            ),
+            ResponseFormat::Car
+        );
+        // refused types are skipped
+        assert_eq!(
+            ResponseFormat::negotiate(
+                Some("application/vnd.ipld.car;q=0, application/vnd.ipld.raw;q=0.5"),
+                &query("")
+            ),
+            ResponseFormat::Raw
+        );
+
+        // the query wins over the header, unless it is unsupported
+        assert_eq!(
+            ResponseFormat::negotiate(Some("application/vnd.ipld.car"), &query("format=raw")),
+            ResponseFormat::Raw
+        );
+        assert_eq!(
+            ResponseFormat::negotiate(Some("application/vnd.ipld.car"), &query("format=nope")),
+            ResponseFormat::Car
+        );
+        assert_eq!(
+            ResponseFormat::negotiate(None, &query("format=tar")),
+            ResponseFormat::Tar
+        );
+    }
+
     #[test]
     fn response_format_write_headers() {
         let rf = ResponseFormat::try_from("raw").unwrap();
@@ -208,6 +402,12 @@ mod tests {
             &VALUE_XCTO_NOSNIFF
         );
 
+        let rf = ResponseFormat::try_from("tar").unwrap();
+        let mut headers = HeaderMap::new();
+        rf.write_headers(&mut headers);
+        assert_eq!(headers.len(), 3);
+        assert_eq!(headers.get(&CONTENT_TYPE).unwrap(), &CONTENT_TYPE_X_TAR);
+
         let rf = ResponseFormat::try_from("fs").unwrap();
         let mut headers = HeaderMap::new();
         rf.write_headers(&mut headers);
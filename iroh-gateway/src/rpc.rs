@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use anyhow::Result;
 use async_trait::async_trait;
 use iroh_rpc_types::gateway::{Gateway as RpcGateway, GatewayServerAddr, VersionResponse};
@@ -19,6 +21,10 @@ impl iroh_rpc_types::NamedService for Gateway {
     const NAME: &'static str = "gateway";
 }
 
-pub async fn new(addr: GatewayServerAddr, gateway: Gateway) -> Result<()> {
-    iroh_rpc_types::gateway::serve(addr, gateway).await
+pub async fn new(
+    addr: GatewayServerAddr,
+    gateway: Gateway,
+    keepalive: Option<Duration>,
+) -> Result<()> {
+    iroh_rpc_types::gateway::serve_with_keepalive(addr, gateway, keepalive).await
 }
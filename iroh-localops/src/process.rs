@@ -1,60 +1,131 @@
 use anyhow::{anyhow, Result};
 #[cfg(any(target_os = "macos", target_os = "linux"))]
 use nix::sys::signal::{kill, Signal};
-#[cfg(any(target_os = "macos", target_os = "linux"))]
-use nix::unistd::Pid;
+use std::collections::HashMap;
+use std::ffi::OsString;
 use std::path::PathBuf;
 #[cfg(any(target_os = "macos", target_os = "linux"))]
 use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
 
-// TODO(b5): instead of using u32's for Process Identifiers, use a proper Pid type
-// something along the lines of:
+/// Identifier of a process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Pid(u32);
 
-// #[cfg(unix)]
-// #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-// pub struct Pid(nix::unistd::Pid);
+impl Pid {
+    pub fn as_u32(self) -> u32 {
+        self.0
+    }
 
-// #[cfg(not(unix))]
-// #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-// pub struct Pid; // TODO: fill in for each platform when supported
+    /// Whether the id names a single process. 0 addresses the caller's process group
+    /// and ids above `i32::MAX` wrap to negative ids once handed to `kill`.
+    pub fn is_valid(self) -> bool {
+        self.0 != 0 && self.0 <= i32::MAX as u32
+    }
+}
 
-// // #[cfg(unix)]
-// impl From nix::Pid for Pid {
-//  // ..
-// }
+impl From<u32> for Pid {
+    fn from(pid: u32) -> Self {
+        Pid(pid)
+    }
+}
 
-// impl std::fmt::Display for Pid {
-//     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-//         write!(f, "{}", self.to_string())
-//     }
-// }
+impl From<Pid> for u32 {
+    fn from(pid: Pid) -> Self {
+        pid.0
+    }
+}
+
+#[cfg(unix)]
+impl From<nix::unistd::Pid> for Pid {
+    fn from(pid: nix::unistd::Pid) -> Self {
+        Pid(pid.as_raw() as u32)
+    }
+}
+
+/// Rejects ids that `kill` would not read as a single process, see [`Pid::is_valid`].
+#[cfg(unix)]
+impl TryFrom<Pid> for nix::unistd::Pid {
+    type Error = anyhow::Error;
+
+    fn try_from(pid: Pid) -> Result<Self> {
+        if !pid.is_valid() {
+            return Err(anyhow!("invalid process id: {}", pid.0));
+        }
+        Ok(nix::unistd::Pid::from_raw(pid.0 as i32))
+    }
+}
+
+impl std::fmt::Display for Pid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
 
 pub fn daemonize(bin_path: PathBuf, log_path: PathBuf) -> Result<()> {
-    daemonize_process(bin_path, log_path)
+    daemonize_with_args(bin_path, &[], &HashMap::new(), log_path)
+}
+
+/// Like [`daemonize`], starting the binary with the given arguments and additional
+/// environment variables.
+pub fn daemonize_with_args(
+    bin_path: PathBuf,
+    args: &[OsString],
+    envs: &HashMap<String, String>,
+    log_path: PathBuf,
+) -> Result<()> {
+    daemonize_process(bin_path, args, envs, log_path)
 }
 
 #[cfg(not(any(target_os = "macos", target_os = "linux", target_os = "windows")))]
-fn daemonize_process(_bin_path: PathBuf, _log_path: PathBuf) -> Result<()> {
+fn daemonize_process(
+    _bin_path: PathBuf,
+    _args: &[OsString],
+    _envs: &HashMap<String, String>,
+    _log_path: PathBuf,
+) -> Result<()> {
     Err(anyhow!(
         "daemonizing processes is not supported on your operating system"
     ))
 }
 
 #[cfg(any(target_os = "macos", target_os = "linux"))]
-fn daemonize_process(bin_path: PathBuf, log_path: PathBuf) -> Result<()> {
-    std::fs::create_dir_all(&log_path.parent().unwrap())?;
-    // ¯\_(ツ)_/¯
-    let status = Command::new("bash")
-        .arg("-c")
-        // TODO(b5): might be nice to capture output in a log file at some point?
-        .arg(format!(
-            "nohup {} > {} 2>&1 &",
-            bin_path.to_str().unwrap(),
-            log_path.to_str().unwrap(),
-        ))
-        .stderr(Stdio::null())
-        .stdout(Stdio::null())
-        .status()?;
+fn daemonize_process(
+    bin_path: PathBuf,
+    args: &[OsString],
+    envs: &HashMap<String, String>,
+    log_path: PathBuf,
+) -> Result<()> {
+    use nix::unistd::{fork, setsid, ForkResult};
+    use std::os::unix::process::CommandExt;
+
+    if let Some(parent) = log_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let log = std::fs::File::create(&log_path)?;
+    let mut command = Command::new(&bin_path);
+    command
+        .args(args)
+        .envs(envs)
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log);
+    // Detach the daemon from our session, and fork once more so the child we wait for exits
+    // right away, leaving the daemon to be reparented to init.
+    // Only async-signal-safe calls are allowed between fork and exec.
+    unsafe {
+        command.pre_exec(|| {
+            setsid()?;
+            match fork()? {
+                ForkResult::Parent { .. } => nix::libc::_exit(0),
+                ForkResult::Child => Ok(()),
+            }
+        });
+    }
+    let status = command
+        .spawn()
+        .map_err(|e| anyhow!("couldn't daemonize binary: {}", e))?
+        .wait()?;
 
     if !status.success() {
         Err(anyhow::anyhow!("couldn't daemonize binary"))?;
@@ -63,31 +134,202 @@ fn daemonize_process(bin_path: PathBuf, log_path: PathBuf) -> Result<()> {
 }
 
 #[cfg(target_os = "windows")]
-fn daemonize_process(_bin_path: PathBuf, _log_path: PathBuf) -> Result<()> {
+fn daemonize_process(
+    _bin_path: PathBuf,
+    _args: &[OsString],
+    _envs: &HashMap<String, String>,
+    _log_path: PathBuf,
+) -> Result<()> {
     Err(anyhow!("daemonizing processes on windows is not supported"))
 }
 
+/// Signals used to stop a process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopSignal {
+    /// `SIGINT`
+    Interrupt,
+    /// `SIGTERM`
+    Terminate,
+    /// `SIGKILL`, can't be handled by the process
+    Kill,
+}
+
+/// How often [`wait_for_exit`] checks whether the process is gone.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
 // TODO(b5) - this level of indirection isn't necessary, factor `stop_process`
 // directly into `stop`
 // https://github.com/n0-computer/iroh/pull/360#discussion_r1002000769
-pub fn stop(pid: u32) -> Result<()> {
-    stop_process(pid)
+pub fn stop(pid: Pid) -> Result<()> {
+    stop_process(pid, StopSignal::Interrupt)
+}
+
+/// Sends `signal` to the process.
+pub fn stop_with(pid: Pid, signal: StopSignal) -> Result<()> {
+    stop_process(pid, signal)
+}
+
+/// Asks the process to terminate, and kills it if it's still running after `timeout`.
+pub fn stop_graceful(pid: Pid, timeout: Duration) -> Result<()> {
+    stop_process(pid, StopSignal::Terminate)?;
+    if wait_for_exit(pid, timeout)? {
+        return Ok(());
+    }
+    stop_process(pid, StopSignal::Kill)
+}
+
+/// Waits up to `timeout` for the process to exit, returns whether it did.
+pub fn wait_for_exit(pid: Pid, timeout: Duration) -> Result<bool> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if !process_exists(pid)? {
+            return Ok(true);
+        }
+        if Instant::now() >= deadline {
+            return Ok(false);
+        }
+        std::thread::sleep(STOP_POLL_INTERVAL);
+    }
 }
 
 #[cfg(not(any(target_os = "macos", target_os = "linux", target_os = "windows")))]
-fn stop_process(pid: u32) -> Result<()> {
+fn stop_process(pid: Pid, _signal: StopSignal) -> Result<()> {
     Err(anyhow!(
         "stopping processes is not supported on your operating system"
     ))
 }
 
 #[cfg(any(target_os = "macos", target_os = "linux"))]
-fn stop_process(pid: u32) -> Result<()> {
-    let id = Pid::from_raw(pid as i32);
-    kill(id, Signal::SIGINT).map_err(|e| anyhow!("killing process: {}", e))
+fn stop_process(pid: Pid, signal: StopSignal) -> Result<()> {
+    let id = nix::unistd::Pid::try_from(pid)?;
+    let signal = match signal {
+        StopSignal::Interrupt => Signal::SIGINT,
+        StopSignal::Terminate => Signal::SIGTERM,
+        StopSignal::Kill => Signal::SIGKILL,
+    };
+    kill(id, signal).map_err(|e| anyhow!("killing process: {}", e))
 }
 
 #[cfg(target_os = "windows")]
-fn stop_process(_pid: u32) -> Result<()> {
+fn stop_process(_pid: Pid, _signal: StopSignal) -> Result<()> {
     Err(anyhow!("stopping processes on windows is not supported"))
 }
+
+/// Checks whether a process with the given id exists.
+pub fn process_exists(pid: Pid) -> Result<bool> {
+    process_exists_inner(pid)
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux", target_os = "windows")))]
+fn process_exists_inner(_pid: Pid) -> Result<bool> {
+    Err(anyhow!(
+        "checking processes is not supported on your operating system"
+    ))
+}
+
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+fn process_exists_inner(pid: Pid) -> Result<bool> {
+    let id = nix::unistd::Pid::try_from(pid)?;
+    // signal 0 only checks whether the process could be signalled
+    match kill(id, None::<Signal>) {
+        Ok(()) | Err(nix::errno::Errno::EPERM) => Ok(true),
+        Err(nix::errno::Errno::ESRCH) => Ok(false),
+        Err(e) => Err(anyhow!("checking process: {}", e)),
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn process_exists_inner(_pid: Pid) -> Result<bool> {
+    Err(anyhow!("checking processes on windows is not supported"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pid_round_trip() {
+        let pid = Pid::from(1234);
+        assert_eq!(u32::from(pid), 1234);
+        assert_eq!(pid.as_u32(), 1234);
+        assert_eq!(pid.to_string(), "1234");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn nix_pid_round_trip() {
+        let nix_pid = nix::unistd::getpid();
+        let pid = Pid::from(nix_pid);
+        assert_eq!(pid.as_u32(), std::process::id());
+        assert_eq!(nix::unistd::Pid::try_from(pid).unwrap(), nix_pid);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn nix_pid_rejects_group_ids() {
+        assert!(nix::unistd::Pid::try_from(Pid::from(0)).is_err());
+        assert!(nix::unistd::Pid::try_from(Pid::from(i32::MAX as u32 + 1)).is_err());
+        assert!(nix::unistd::Pid::try_from(Pid::from(u32::MAX)).is_err());
+        assert!(nix::unistd::Pid::try_from(Pid::from(i32::MAX as u32)).is_ok());
+    }
+
+    #[cfg(any(target_os = "macos", target_os = "linux"))]
+    #[test]
+    fn stop_rejects_group_ids() {
+        assert!(stop_with(Pid::from(0), StopSignal::Terminate).is_err());
+        assert!(process_exists(Pid::from(u32::MAX)).is_err());
+    }
+
+    #[cfg(any(target_os = "macos", target_os = "linux"))]
+    #[test]
+    fn wait_for_exit_of_child() {
+        let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+        let pid = Pid::from(child.id());
+        assert!(process_exists(pid).unwrap());
+        assert!(!wait_for_exit(pid, Duration::from_millis(100)).unwrap());
+
+        stop_with(pid, StopSignal::Kill).unwrap();
+        child.wait().unwrap();
+        assert!(!process_exists(pid).unwrap());
+        assert!(wait_for_exit(pid, Duration::from_millis(100)).unwrap());
+    }
+
+    #[cfg(any(target_os = "macos", target_os = "linux"))]
+    #[test]
+    fn daemonize_reports_exec_errors() {
+        let log_path = std::env::temp_dir().join("iroh-localops-daemonize-test.log");
+        daemonize(PathBuf::from("true"), log_path.clone()).unwrap();
+        assert!(daemonize(PathBuf::from("iroh-no-such-binary"), log_path.clone()).is_err());
+        let _ = std::fs::remove_file(log_path);
+    }
+
+    #[cfg(any(target_os = "macos", target_os = "linux"))]
+    #[test]
+    fn daemonize_with_args_passes_args_and_env() {
+        let dir = std::env::temp_dir().join(format!("iroh-localops-{}", std::process::id()));
+        let log_path = dir.join("daemon.log");
+        let out_path = dir.join("daemon.out");
+        let mut envs = HashMap::new();
+        envs.insert("IROH_TEST_VALUE".to_string(), "from env".to_string());
+        let script = format!("echo \"$0 $IROH_TEST_VALUE\" > '{}'", out_path.display());
+        daemonize_with_args(
+            PathBuf::from("sh"),
+            &["-c".into(), script.into(), "from args".into()],
+            &envs,
+            log_path,
+        )
+        .unwrap();
+
+        // the daemon runs detached, give it a moment
+        let mut out = String::new();
+        for _ in 0..50 {
+            out = std::fs::read_to_string(&out_path).unwrap_or_default();
+            if !out.is_empty() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        let _ = std::fs::remove_dir_all(dir);
+        assert_eq!(out, "from args from env\n");
+    }
+}
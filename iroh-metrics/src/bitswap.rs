@@ -24,6 +24,7 @@ make_metrics! {
     ProvidersTotal: Counter: "Number of providers",
     AttemptedDials: Counter: "",
     Dials: Counter: "",
+    DialFailures: Counter: "Number of failed dials",
     KnownPeers: Counter: "",
     ForgottenPeers: Counter: "",
     WantedBlocks: Counter: "",
@@ -37,6 +38,12 @@ make_metrics! {
     DisconnectedPeers: Counter: "",
     MessagesAttempted: Counter: "",
     MessagesSent: Counter: "",
+    MessageSendRetries: Counter: "Number of retried message sends",
+    MessageSendTimeouts: Counter: "Number of message sends that timed out",
+    MessageSendConnectionClosed: Counter: "Number of message sends failed by a closed connection",
+    MessageSendProtocolNotSupported: Counter: "Number of message sends to peers without bitswap",
+    MessageSendDialFailed: Counter: "Number of message sends failed by dialing the peer",
+    MessageSendOtherFailures: Counter: "Number of message sends failed for other reasons",
     MessagesProcessingClient: Counter: "",
     MessagesProcessingServer: Counter: "",
     MessagesReceived: Counter: "",
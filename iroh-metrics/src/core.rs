@@ -1,6 +1,10 @@
 use std::sync::atomic::{AtomicBool, Ordering};
 
-use prometheus_client::{encoding::text::encode, registry::Registry};
+use prometheus_client::{
+    encoding::text::encode,
+    metrics::{counter::Counter, gauge::Gauge},
+    registry::Registry,
+};
 
 #[cfg(feature = "bitswap")]
 use crate::bitswap;
@@ -10,6 +14,8 @@ use crate::gateway;
 use crate::p2p;
 #[cfg(feature = "resolver")]
 use crate::resolver;
+#[cfg(feature = "rpc")]
+use crate::rpc;
 #[cfg(feature = "store")]
 use crate::store;
 
@@ -24,6 +30,8 @@ pub(crate) struct Core {
     gateway_metrics: gateway::Metrics,
     #[cfg(feature = "resolver")]
     resolver_metrics: resolver::Metrics,
+    #[cfg(feature = "rpc")]
+    rpc_metrics: rpc::Metrics,
     #[cfg(feature = "bitswap")]
     bitswap_metrics: bitswap::Metrics,
     #[cfg(feature = "store")]
@@ -43,6 +51,8 @@ impl Default for Core {
             gateway_metrics: gateway::Metrics::new(&mut reg),
             #[cfg(feature = "resolver")]
             resolver_metrics: resolver::Metrics::new(&mut reg),
+            #[cfg(feature = "rpc")]
+            rpc_metrics: rpc::Metrics::new(&mut reg),
             #[cfg(feature = "bitswap")]
             bitswap_metrics: bitswap::Metrics::new(&mut reg),
             #[cfg(feature = "store")]
@@ -71,6 +81,11 @@ impl Core {
         &self.resolver_metrics
     }
 
+    #[cfg(feature = "rpc")]
+    pub(crate) fn rpc_metrics(&self) -> &rpc::Metrics {
+        &self.rpc_metrics
+    }
+
     #[cfg(feature = "bitswap")]
     pub(crate) fn bitswap_metrics(&self) -> &bitswap::Metrics {
         &self.bitswap_metrics
@@ -104,6 +119,38 @@ impl Core {
     pub(crate) fn enabled(&self) -> bool {
         self.enabled.load(Ordering::Relaxed)
     }
+
+    /// Resets the counters of all collectors, see [`crate::MetricsHandle::reset`].
+    pub(crate) fn reset(&self) {
+        #[cfg(feature = "gateway")]
+        self.gateway_metrics.reset();
+        #[cfg(feature = "resolver")]
+        self.resolver_metrics.reset();
+        #[cfg(feature = "rpc")]
+        self.rpc_metrics.reset();
+        #[cfg(feature = "bitswap")]
+        self.bitswap_metrics.reset();
+        #[cfg(feature = "store")]
+        self.store_metrics.reset();
+        #[cfg(feature = "p2p")]
+        self.p2p_metrics.reset();
+    }
+}
+
+/// A metric that can start a new measurement window.
+pub(crate) trait Reset {
+    fn reset(&self);
+}
+
+impl Reset for Counter {
+    fn reset(&self) {
+        self.inner().store(0, Ordering::Relaxed);
+    }
+}
+
+impl Reset for Gauge {
+    /// Gauges report a current value rather than an accumulation, so they are kept.
+    fn reset(&self) {}
 }
 
 pub trait MetricType {
@@ -4,7 +4,7 @@ use prometheus_client::{
     metrics::{
         counter::Counter,
         gauge::Gauge,
-        histogram::{linear_buckets, Histogram},
+        histogram::{exponential_buckets, linear_buckets, Histogram},
     },
     registry::Registry,
 };
@@ -12,7 +12,7 @@ use tracing::error;
 
 use crate::{
     core::{HistogramType, MetricType},
-    core::{MObserver, MRecorder, MetricsRecorder},
+    core::{MObserver, MRecorder, MetricsRecorder, Reset},
     Collector,
 };
 
@@ -23,11 +23,17 @@ pub(crate) struct Metrics {
     tts_block: Gauge,
     tts_file: Gauge,
     bytes_streamed: Counter,
+    blocks_bitswap: Counter,
+    blocks_cache: Counter,
+    block_cache_hits: Counter,
+    block_cache_misses: Counter,
     error_count: Counter,
     fail_count: Counter,
     hist_ttfb: Histogram,
     hist_ttfb_cached: Histogram,
     hist_ttsf: Histogram,
+    hist_bytes_per_request: Histogram,
+    hist_stream_duration: Histogram,
 }
 
 impl fmt::Debug for Metrics {
@@ -74,6 +80,34 @@ impl Metrics {
             Box::new(bytes_streamed.clone()),
         );
 
+        let blocks_bitswap = Counter::default();
+        sub_registry.register(
+            METRICS_BLOCKS_BITSWAP,
+            "Total number of blocks served, roots included, that were fetched from bitswap",
+            Box::new(blocks_bitswap.clone()),
+        );
+
+        let blocks_cache = Counter::default();
+        sub_registry.register(
+            METRICS_BLOCKS_CACHE,
+            "Total number of blocks served, roots included, that were found in the store",
+            Box::new(blocks_cache.clone()),
+        );
+
+        let block_cache_hits = Counter::default();
+        sub_registry.register(
+            METRICS_BLOCK_CACHE_HITS,
+            "Number of blocks served from the in memory block cache",
+            Box::new(block_cache_hits.clone()),
+        );
+
+        let block_cache_misses = Counter::default();
+        sub_registry.register(
+            METRICS_BLOCK_CACHE_MISSES,
+            "Number of blocks not found in the in memory block cache",
+            Box::new(block_cache_misses.clone()),
+        );
+
         let error_count = Counter::default();
         sub_registry.register(
             METRICS_ERROR,
@@ -108,19 +142,53 @@ impl Metrics {
             Box::new(hist_ttsf.clone()),
         );
 
+        let hist_bytes_per_request = Histogram::new(exponential_buckets(1024.0, 2.0, 24));
+        sub_registry.register(
+            METRICS_HIST_BYTES_PER_REQUEST,
+            "Histogram of bytes streamed per request",
+            Box::new(hist_bytes_per_request.clone()),
+        );
+
+        let hist_stream_duration = Histogram::new(linear_buckets(0.0, 500.0, 240));
+        sub_registry.register(
+            METRICS_HIST_STREAM_DURATION,
+            "Histogram of the time from serving the first to the last byte of a request",
+            Box::new(hist_stream_duration.clone()),
+        );
+
         Self {
             requests_total,
             ttf_block,
             tts_block,
             tts_file,
             bytes_streamed,
+            blocks_bitswap,
+            blocks_cache,
+            block_cache_hits,
+            block_cache_misses,
             error_count,
             fail_count,
             hist_ttfb,
             hist_ttfb_cached,
             hist_ttsf,
+            hist_bytes_per_request,
+            hist_stream_duration,
         }
     }
+
+    pub(crate) fn reset(&self) {
+        self.requests_total.reset();
+        self.ttf_block.reset();
+        self.tts_block.reset();
+        self.tts_file.reset();
+        self.bytes_streamed.reset();
+        self.blocks_bitswap.reset();
+        self.blocks_cache.reset();
+        self.block_cache_hits.reset();
+        self.block_cache_misses.reset();
+        self.error_count.reset();
+        self.fail_count.reset();
+    }
 }
 
 impl Default for Metrics {
@@ -139,6 +207,14 @@ impl MetricsRecorder for Metrics {
             self.requests_total.inc_by(value);
         } else if m.name() == GatewayMetrics::BytesStreamed.name() {
             self.bytes_streamed.inc_by(value);
+        } else if m.name() == GatewayMetrics::BlocksFromBitswap.name() {
+            self.blocks_bitswap.inc_by(value);
+        } else if m.name() == GatewayMetrics::BlocksFromCache.name() {
+            self.blocks_cache.inc_by(value);
+        } else if m.name() == GatewayMetrics::BlockCacheHits.name() {
+            self.block_cache_hits.inc_by(value);
+        } else if m.name() == GatewayMetrics::BlockCacheMisses.name() {
+            self.block_cache_misses.inc_by(value);
         } else if m.name() == GatewayMetrics::ErrorCount.name() {
             self.error_count.inc_by(value);
         } else if m.name() == GatewayMetrics::FailCount.name() {
@@ -164,6 +240,10 @@ impl MetricsRecorder for Metrics {
             self.hist_ttfb_cached.observe(value);
         } else if m.name() == GatewayHistograms::TimeToServeFullFile.name() {
             self.hist_ttsf.observe(value);
+        } else if m.name() == GatewayHistograms::BytesPerRequest.name() {
+            self.hist_bytes_per_request.observe(value);
+        } else if m.name() == GatewayHistograms::StreamDuration.name() {
+            self.hist_stream_duration.observe(value);
         } else {
             error!("observe (gateway): unknown metric {}", m.name());
         }
@@ -174,6 +254,10 @@ impl MetricsRecorder for Metrics {
 pub enum GatewayMetrics {
     Requests,
     BytesStreamed,
+    BlocksFromBitswap,
+    BlocksFromCache,
+    BlockCacheHits,
+    BlockCacheMisses,
     ErrorCount,
     FailCount,
     TimeToFetchFirstBlock,
@@ -186,6 +270,10 @@ impl MetricType for GatewayMetrics {
         match self {
             GatewayMetrics::Requests => METRICS_CNT_REQUESTS_TOTAL,
             GatewayMetrics::BytesStreamed => METRICS_BYTES_STREAMED,
+            GatewayMetrics::BlocksFromBitswap => METRICS_BLOCKS_BITSWAP,
+            GatewayMetrics::BlocksFromCache => METRICS_BLOCKS_CACHE,
+            GatewayMetrics::BlockCacheHits => METRICS_BLOCK_CACHE_HITS,
+            GatewayMetrics::BlockCacheMisses => METRICS_BLOCK_CACHE_MISSES,
             GatewayMetrics::ErrorCount => METRICS_ERROR,
             GatewayMetrics::FailCount => METRICS_FAIL,
             GatewayMetrics::TimeToFetchFirstBlock => METRICS_TIME_TO_FETCH_FIRST_BLOCK,
@@ -212,6 +300,8 @@ pub enum GatewayHistograms {
     TimeToFetchFirstBlock,
     TimeToFetchFirstBlockCached,
     TimeToServeFullFile,
+    BytesPerRequest,
+    StreamDuration,
 }
 
 impl HistogramType for GatewayHistograms {
@@ -220,6 +310,8 @@ impl HistogramType for GatewayHistograms {
             GatewayHistograms::TimeToFetchFirstBlock => METRICS_HIST_TTFB,
             GatewayHistograms::TimeToFetchFirstBlockCached => METRICS_HIST_TTFB_CACHED,
             GatewayHistograms::TimeToServeFullFile => METRICS_HIST_TTSERVE,
+            GatewayHistograms::BytesPerRequest => METRICS_HIST_BYTES_PER_REQUEST,
+            GatewayHistograms::StreamDuration => METRICS_HIST_STREAM_DURATION,
         }
     }
 }
@@ -241,8 +333,14 @@ const METRICS_TIME_TO_FETCH_FIRST_BLOCK: &str = "time_to_fetch_first_block";
 const METRICS_TIME_TO_SERVE_FIRST_BLOCK: &str = "time_to_serve_first_block";
 const METRICS_TIME_TO_SERVE_FULL_FILE: &str = "time_to_serve_full_file";
 const METRICS_BYTES_STREAMED: &str = "bytes_streamed";
+const METRICS_BLOCKS_BITSWAP: &str = "blocks_bitswap";
+const METRICS_BLOCKS_CACHE: &str = "blocks_cache";
+const METRICS_BLOCK_CACHE_HITS: &str = "block_cache_hits";
+const METRICS_BLOCK_CACHE_MISSES: &str = "block_cache_misses";
 const METRICS_HIST_TTFB: &str = "hist_time_to_fetch_first_block";
 const METRICS_HIST_TTFB_CACHED: &str = "hist_time_to_fetch_first_block_cached";
 const METRICS_HIST_TTSERVE: &str = "hist_time_to_serve_full_file";
+const METRICS_HIST_BYTES_PER_REQUEST: &str = "hist_bytes_per_request";
+const METRICS_HIST_STREAM_DURATION: &str = "hist_stream_duration";
 const METRICS_ERROR: &str = "error_count";
 const METRICS_FAIL: &str = "fail_count";
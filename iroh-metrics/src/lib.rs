@@ -12,6 +12,8 @@ pub mod p2p;
 pub mod req;
 #[cfg(feature = "resolver")]
 pub mod resolver;
+#[cfg(feature = "rpc")]
+pub mod rpc;
 #[cfg(feature = "store")]
 pub mod store;
 
@@ -25,6 +27,7 @@ use crate::core::MetricType;
     feature = "bitswap",
     feature = "gateway",
     feature = "resolver",
+    feature = "rpc",
     feature = "store",
     feature = "p2p"
 ))]
@@ -57,6 +60,17 @@ impl MetricsHandle {
         }
     }
 
+    /// Resets all counters to zero, starting a new measurement window without a restart.
+    ///
+    /// Gauges keep their current value. Histograms can't be cleared and keep accumulating,
+    /// as do the libp2p metrics which are owned by libp2p.
+    ///
+    /// Prometheus expects counters to be monotonic and reads a drop as a process restart,
+    /// so this is opt-in: nothing in iroh calls it on its own.
+    pub fn reset(&self) {
+        CORE.reset();
+    }
+
     /// Initialize the tracing and metrics subsystems.
     pub async fn new(cfg: Config) -> Result<Self, Box<dyn std::error::Error>> {
         init_tracer(cfg.clone())?;
@@ -167,6 +181,8 @@ pub enum Collector {
     Gateway,
     #[cfg(feature = "resolver")]
     Resolver,
+    #[cfg(feature = "rpc")]
+    Rpc,
     #[cfg(feature = "bitswap")]
     Bitswap,
     #[cfg(feature = "store")]
@@ -186,6 +202,8 @@ where
             Collector::Gateway => CORE.gateway_metrics().record(m, v),
             #[cfg(feature = "resolver")]
             Collector::Resolver => CORE.resolver_metrics().record(m, v),
+            #[cfg(feature = "rpc")]
+            Collector::Rpc => CORE.rpc_metrics().record(m, v),
             #[cfg(feature = "bitswap")]
             Collector::Bitswap => CORE.bitswap_metrics().record(m, v),
             #[cfg(feature = "store")]
@@ -208,6 +226,8 @@ where
             Collector::Gateway => CORE.gateway_metrics().observe(m, v),
             #[cfg(feature = "resolver")]
             Collector::Resolver => CORE.resolver_metrics().observe(m, v),
+            #[cfg(feature = "rpc")]
+            Collector::Rpc => CORE.rpc_metrics().observe(m, v),
             #[cfg(feature = "bitswap")]
             Collector::Bitswap => CORE.bitswap_metrics().observe(m, v),
             #[cfg(feature = "store")]
@@ -64,6 +64,12 @@ macro_rules! make_metrics {
                         )+
                     }
                 }
+
+                pub(crate) fn reset(&self) {
+                    $(
+                        $crate::core::Reset::reset(&self.[<$name:snake>]);
+                    )+
+                }
             }
 
             impl MetricsRecorder for Metrics {
@@ -4,7 +4,7 @@ use prometheus_client::{metrics::counter::Counter, registry::Registry};
 use tracing::error;
 
 use crate::{
-    core::{HistogramType, MRecorder, MetricType, MetricsRecorder},
+    core::{HistogramType, MRecorder, MetricType, MetricsRecorder, Reset},
     Collector,
 };
 
@@ -16,7 +16,13 @@ pub(crate) struct Metrics {
     bad_peers_removed: Counter,
     skipped_peer_bitswap: Counter,
     skipped_peer_kad: Counter,
+    skipped_peer_protocol: Counter,
     loops: Counter,
+    protocol_negotiation_failed: Counter,
+    peers_rejected_by_policy: Counter,
+    reprovide_cycles: Counter,
+    reprovided: Counter,
+    reprovide_failures: Counter,
 }
 
 impl fmt::Debug for Metrics {
@@ -50,18 +56,77 @@ impl Metrics {
             "",
             Box::new(skipped_peer_kad.clone()),
         );
+        let skipped_peer_protocol = Counter::default();
+        sub_registry.register(
+            P2PMetrics::SkippedPeerProtocol.name(),
+            "Number of DHT providers dropped because they speak no compatible bitswap protocol",
+            Box::new(skipped_peer_protocol.clone()),
+        );
 
         let loops = Counter::default();
         sub_registry.register(P2PMetrics::LoopCounter.name(), "", Box::new(loops.clone()));
 
+        let protocol_negotiation_failed = Counter::default();
+        sub_registry.register(
+            P2PMetrics::ProtocolNegotiationFailed.name(),
+            "Number of connections refused because no allowed protocol could be negotiated",
+            Box::new(protocol_negotiation_failed.clone()),
+        );
+
+        let peers_rejected_by_policy = Counter::default();
+        sub_registry.register(
+            P2PMetrics::PeersRejectedByPolicy.name(),
+            "Number of connections refused by the configured peer allow and deny lists",
+            Box::new(peers_rejected_by_policy.clone()),
+        );
+
+        let reprovide_cycles = Counter::default();
+        sub_registry.register(
+            P2PMetrics::ReprovideCycles.name(),
+            "Number of completed re-provide cycles",
+            Box::new(reprovide_cycles.clone()),
+        );
+        let reprovided = Counter::default();
+        sub_registry.register(
+            P2PMetrics::Reprovided.name(),
+            "Number of provider records announced again by the re-provide loop",
+            Box::new(reprovided.clone()),
+        );
+        let reprovide_failures = Counter::default();
+        sub_registry.register(
+            P2PMetrics::ReprovideFailures.name(),
+            "Number of provider records the re-provide loop failed to announce",
+            Box::new(reprovide_failures.clone()),
+        );
+
         Self {
             bad_peers,
             bad_peers_removed,
             skipped_peer_bitswap,
             skipped_peer_kad,
+            skipped_peer_protocol,
             loops,
+            protocol_negotiation_failed,
+            peers_rejected_by_policy,
+            reprovide_cycles,
+            reprovided,
+            reprovide_failures,
         }
     }
+
+    pub(crate) fn reset(&self) {
+        self.bad_peers.reset();
+        self.bad_peers_removed.reset();
+        self.skipped_peer_bitswap.reset();
+        self.skipped_peer_kad.reset();
+        self.skipped_peer_protocol.reset();
+        self.loops.reset();
+        self.protocol_negotiation_failed.reset();
+        self.peers_rejected_by_policy.reset();
+        self.reprovide_cycles.reset();
+        self.reprovided.reset();
+        self.reprovide_failures.reset();
+    }
 }
 
 impl MetricsRecorder for Metrics {
@@ -77,8 +142,20 @@ impl MetricsRecorder for Metrics {
             self.skipped_peer_bitswap.inc_by(value);
         } else if m.name() == P2PMetrics::SkippedPeerKad.name() {
             self.skipped_peer_kad.inc_by(value);
+        } else if m.name() == P2PMetrics::SkippedPeerProtocol.name() {
+            self.skipped_peer_protocol.inc_by(value);
         } else if m.name() == P2PMetrics::LoopCounter.name() {
             self.loops.inc_by(value);
+        } else if m.name() == P2PMetrics::ProtocolNegotiationFailed.name() {
+            self.protocol_negotiation_failed.inc_by(value);
+        } else if m.name() == P2PMetrics::PeersRejectedByPolicy.name() {
+            self.peers_rejected_by_policy.inc_by(value);
+        } else if m.name() == P2PMetrics::ReprovideCycles.name() {
+            self.reprovide_cycles.inc_by(value);
+        } else if m.name() == P2PMetrics::Reprovided.name() {
+            self.reprovided.inc_by(value);
+        } else if m.name() == P2PMetrics::ReprovideFailures.name() {
+            self.reprovide_failures.inc_by(value);
         } else {
             error!("record (bitswap): unknown metric {}", m.name());
         }
@@ -98,7 +175,13 @@ pub enum P2PMetrics {
     BadPeerRemoved,
     SkippedPeerBitswap,
     SkippedPeerKad,
+    SkippedPeerProtocol,
     LoopCounter,
+    ProtocolNegotiationFailed,
+    PeersRejectedByPolicy,
+    ReprovideCycles,
+    Reprovided,
+    ReprovideFailures,
 }
 
 impl MetricType for P2PMetrics {
@@ -108,7 +191,13 @@ impl MetricType for P2PMetrics {
             P2PMetrics::BadPeerRemoved => "bad_peer_removed",
             P2PMetrics::SkippedPeerBitswap => "skipped_peer_bitswap",
             P2PMetrics::SkippedPeerKad => "skipped_peer_kad",
+            P2PMetrics::SkippedPeerProtocol => "skipped_peer_protocol",
             P2PMetrics::LoopCounter => "loop_counter",
+            P2PMetrics::ProtocolNegotiationFailed => "protocol_negotiation_failed",
+            P2PMetrics::PeersRejectedByPolicy => "peers_rejected_by_policy",
+            P2PMetrics::ReprovideCycles => "reprovide_cycles",
+            P2PMetrics::Reprovided => "reprovided",
+            P2PMetrics::ReprovideFailures => "reprovide_failures",
         }
     }
 }
@@ -4,7 +4,7 @@ use prometheus_client::{metrics::counter::Counter, registry::Registry};
 use tracing::error;
 
 use crate::{
-    core::{HistogramType, MRecorder, MetricType, MetricsRecorder},
+    core::{HistogramType, MRecorder, MetricType, MetricsRecorder, Reset},
     Collector,
 };
 
@@ -12,6 +12,8 @@ use crate::{
 pub(crate) struct Metrics {
     cache_hit: Counter,
     cache_miss: Counter,
+    name_resolutions_queued: Counter,
+    name_resolutions_rejected: Counter,
 }
 
 impl fmt::Debug for Metrics {
@@ -37,11 +39,34 @@ impl Metrics {
             Box::new(cache_miss.clone()),
         );
 
+        let name_resolutions_queued = Counter::default();
+        sub_registry.register(
+            METRICS_NAME_RESOLUTIONS_QUEUED,
+            "Number of name resolutions that waited for a free slot",
+            Box::new(name_resolutions_queued.clone()),
+        );
+
+        let name_resolutions_rejected = Counter::default();
+        sub_registry.register(
+            METRICS_NAME_RESOLUTIONS_REJECTED,
+            "Number of name resolutions rejected because too many were in flight",
+            Box::new(name_resolutions_rejected.clone()),
+        );
+
         Self {
             cache_hit,
             cache_miss,
+            name_resolutions_queued,
+            name_resolutions_rejected,
         }
     }
+
+    pub(crate) fn reset(&self) {
+        self.cache_hit.reset();
+        self.cache_miss.reset();
+        self.name_resolutions_queued.reset();
+        self.name_resolutions_rejected.reset();
+    }
 }
 
 impl Default for Metrics {
@@ -60,6 +85,10 @@ impl MetricsRecorder for Metrics {
             self.cache_hit.inc_by(value);
         } else if m.name() == ResolverMetrics::CacheMiss.name() {
             self.cache_miss.inc_by(value);
+        } else if m.name() == ResolverMetrics::NameResolutionsQueued.name() {
+            self.name_resolutions_queued.inc_by(value);
+        } else if m.name() == ResolverMetrics::NameResolutionsRejected.name() {
+            self.name_resolutions_rejected.inc_by(value);
         } else {
             error!("record (resolver): unknown metric {}", m.name());
         }
@@ -77,6 +106,8 @@ impl MetricsRecorder for Metrics {
 pub enum ResolverMetrics {
     CacheHit,
     CacheMiss,
+    NameResolutionsQueued,
+    NameResolutionsRejected,
 }
 
 impl MetricType for ResolverMetrics {
@@ -84,6 +115,8 @@ impl MetricType for ResolverMetrics {
         match self {
             ResolverMetrics::CacheHit => METRICS_CACHE_HIT,
             ResolverMetrics::CacheMiss => METRICS_CACHE_MISS,
+            ResolverMetrics::NameResolutionsQueued => METRICS_NAME_RESOLUTIONS_QUEUED,
+            ResolverMetrics::NameResolutionsRejected => METRICS_NAME_RESOLUTIONS_REJECTED,
         }
     }
 }
@@ -102,3 +135,5 @@ impl std::fmt::Display for ResolverMetrics {
 
 const METRICS_CACHE_HIT: &str = "cache_hit";
 const METRICS_CACHE_MISS: &str = "cache_miss";
+const METRICS_NAME_RESOLUTIONS_QUEUED: &str = "name_resolutions_queued";
+const METRICS_NAME_RESOLUTIONS_REJECTED: &str = "name_resolutions_rejected";
@@ -0,0 +1,15 @@
+use std::fmt;
+
+use prometheus_client::{metrics::counter::Counter, registry::Registry};
+use tracing::error;
+
+use crate::{
+    core::{HistogramType, MRecorder, MetricType, MetricsRecorder},
+    Collector,
+};
+
+make_metrics! {
+    Rpc,
+    Retries: Counter: "Number of retried idempotent rpc calls",
+    RetriesExhausted: Counter: "Number of idempotent rpc calls that failed after all retries"
+}
@@ -10,7 +10,7 @@ use prometheus_client::{
 use tracing::error;
 
 use crate::{
-    core::{HistogramType, MObserver, MRecorder, MetricType, MetricsRecorder},
+    core::{HistogramType, MObserver, MRecorder, MetricType, MetricsRecorder, Reset},
     Collector,
 };
 
@@ -148,6 +148,18 @@ impl Metrics {
             get_links_request_time,
         }
     }
+
+    pub(crate) fn reset(&self) {
+        self.get_requests_total.reset();
+        self.get_store_hit.reset();
+        self.get_store_miss.reset();
+        self.get_bytes.reset();
+        self.put_requests_total.reset();
+        self.put_bytes.reset();
+        self.get_links_requests_total.reset();
+        self.get_links_hit.reset();
+        self.get_links_miss.reset();
+    }
 }
 
 impl MetricsRecorder for Metrics {
@@ -276,3 +288,27 @@ const METRICS_CNT_GET_LINKS_REQUESTS_TOTAL: &str = "get_links_requests";
 const METRICS_CNT_GET_LINKS_HIT: &str = "get_links_hit";
 const METRICS_CNT_GET_LINKS_MISS: &str = "get_links_miss";
 const METRICS_HIST_GET_LINKS_REQUEST_TIME: &str = "get_links_request_time";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::CORE;
+
+    #[test]
+    fn test_reset() {
+        CORE.set_enabled(true);
+        inc!(StoreMetrics::GetRequests);
+        record!(StoreMetrics::GetBytes, 42);
+        let metrics = CORE.store_metrics();
+        assert!(metrics.get_requests_total.get() > 0);
+        assert!(metrics.get_bytes.get() >= 42);
+
+        CORE.reset();
+        assert_eq!(metrics.get_requests_total.get(), 0);
+        assert_eq!(metrics.get_bytes.get(), 0);
+
+        // counting carries on from zero
+        inc!(StoreMetrics::GetRequests);
+        assert_eq!(metrics.get_requests_total.get(), 1);
+    }
+}
@@ -4,11 +4,13 @@ use config::{ConfigError, Map, Source, Value};
 
 use iroh_metrics::config::Config as MetricsConfig;
 use iroh_p2p::Libp2pConfig;
+use iroh_resolver::resolver::FetchPolicy;
 use iroh_rpc_client::Config as RpcClientConfig;
 use iroh_store::config::config_data_path;
-use iroh_util::insert_into_config_map;
+use iroh_util::{duration_from_millis, insert_into_config_map};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
+use std::time::Duration;
 
 /// CONFIG_FILE_NAME is the name of the optional config file located in the iroh home directory
 pub const CONFIG_FILE_NAME: &str = "one.config.toml";
@@ -75,7 +77,10 @@ impl Config {
             gateway_addr: None,
             p2p_addr: None,
             store_addr: None,
+            fallback_store_addrs: Vec::new(),
             channels: Some(1),
+            max_retries: None,
+            keepalive_secs: None,
         }
     }
 
@@ -121,6 +126,9 @@ fn default_store_config(
         path,
         rpc_client: ipfsd,
         metrics,
+        min_free_space: None,
+        write_batch_size: iroh_store::config::DEFAULT_WRITE_BATCH_SIZE,
+        durability: iroh_store::Durability::default(),
     })
 }
 
@@ -134,6 +142,8 @@ fn default_p2p_config(
         rpc_client: ipfsd,
         metrics,
         key_store_path,
+        keychain: Default::default(),
+        runtime: Default::default(),
     }
 }
 
@@ -171,6 +181,52 @@ impl iroh_gateway::handlers::StateConfig for Config {
         &self.gateway.public_url_base
     }
 
+    fn base_path(&self) -> &str {
+        &self.gateway.base_path
+    }
+
+    fn default_content_type(&self) -> &str {
+        &self.gateway.default_content_type
+    }
+
+    fn max_concurrent_name_resolutions(&self) -> Option<usize> {
+        self.gateway.max_concurrent_name_resolutions
+    }
+
+    fn max_queued_name_resolutions(&self) -> usize {
+        self.gateway.max_queued_name_resolutions
+    }
+
+    fn slow_request_threshold(&self) -> Option<Duration> {
+        duration_from_millis(self.gateway.slow_request_threshold_ms)
+    }
+
+    fn resolve_timeout(&self) -> Option<Duration> {
+        duration_from_millis(self.gateway.resolve_timeout_ms)
+    }
+
+    fn block_cache_size(&self) -> Option<usize> {
+        match self.gateway.block_cache_size {
+            0 => None,
+            bytes => Some(bytes as usize),
+        }
+    }
+
+    fn fetch_policy(&self) -> FetchPolicy {
+        FetchPolicy {
+            max_provider_retries: self.gateway.max_provider_retries as usize,
+            per_block_timeout: duration_from_millis(self.gateway.per_block_timeout_ms),
+        }
+    }
+
+    fn skip_block_verification(&self) -> bool {
+        self.gateway.skip_block_verification
+    }
+
+    fn hide_ipfs_headers(&self) -> bool {
+        self.gateway.hide_ipfs_headers
+    }
+
     fn port(&self) -> u16 {
         self.gateway.port
     }
@@ -6,6 +6,7 @@ use tracing::info;
 
 /// Starts a new store, using the given mem rpc channel.
 pub async fn start(rpc_addr: StoreServerAddr, config: Config) -> anyhow::Result<JoinHandle<()>> {
+    let keepalive = config.rpc_client.keepalive();
     let store = if config.path.exists() {
         info!("Opening store at {}", config.path.display());
         Store::open(config).await?
@@ -14,7 +15,7 @@ pub async fn start(rpc_addr: StoreServerAddr, config: Config) -> anyhow::Result<
         Store::create(config).await?
     };
 
-    let rpc_task = tokio::spawn(async move { rpc::new(rpc_addr, store).await.unwrap() });
+    let rpc_task = tokio::spawn(async move { rpc::new(rpc_addr, store, keepalive).await.unwrap() });
 
     Ok(rpc_task)
 }
@@ -21,7 +21,7 @@ use libp2p::{autonat, dcutr};
 use tracing::{info, warn};
 
 pub(crate) use self::event::Event;
-use self::peer_manager::PeerManager;
+pub(crate) use self::peer_manager::PeerManager;
 use crate::config::Libp2pConfig;
 
 mod event;
@@ -90,7 +90,10 @@ impl NodeBehaviour {
 
         let bitswap = if config.bitswap {
             info!("init bitswap");
-            let bs_config = BitswapConfig::default();
+            let bs_config = BitswapConfig {
+                network: config.bitswap_network(),
+                ..Default::default()
+            };
             Some(Bitswap::new(peer_id, BitswapStore(rpc_client), bs_config).await)
         } else {
             None
@@ -121,6 +124,8 @@ impl NodeBehaviour {
             kad_config.set_parallelism(16usize.try_into().unwrap());
             // TODO: potentially lower (this is per query)
             kad_config.set_query_timeout(Duration::from_secs(60));
+            // republished by the node's `Reprovider`, with bounded concurrency
+            kad_config.set_provider_publication_interval(None);
 
             let mut kademlia = Kademlia::with_config(pub_key.to_peer_id(), store, kad_config);
             for multiaddr in &config.bootstrap_peers {
@@ -5,6 +5,7 @@ use std::{
 
 use ahash::AHashMap;
 use caches::{Cache, PutResult};
+use iroh_bitswap::ProtocolId;
 use iroh_metrics::{core::MRecorder, inc, p2p::P2PMetrics};
 use libp2p::{
     core::{connection::ConnectionId, transport::ListenerId, ConnectedPoint},
@@ -67,6 +68,19 @@ impl PeerManager {
     pub fn info_for_peer(&self, peer_id: &PeerId) -> Option<&Info> {
         self.info.get(peer_id)
     }
+
+    /// Whether the peer advertised a bitswap protocol we can speak, `None` if we have not
+    /// received its identify info yet.
+    pub fn supports_bitswap(&self, peer_id: &PeerId) -> Option<bool> {
+        self.info
+            .get(peer_id)
+            .and_then(|info| info.last_info.as_ref())
+            .map(|info| {
+                info.protocols
+                    .iter()
+                    .any(|p| ProtocolId::try_from(p).is_some())
+            })
+    }
 }
 
 impl NetworkBehaviour for PeerManager {
@@ -191,3 +205,38 @@ impl NetworkBehaviour for PeerManager {
         Poll::Pending
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use libp2p::identity::Keypair;
+
+    use super::*;
+
+    fn identify_info(protocols: &[&str]) -> IdentifyInfo {
+        IdentifyInfo {
+            public_key: Keypair::generate_ed25519().public(),
+            protocol_version: "ipfs/0.1.0".to_string(),
+            agent_version: "test".to_string(),
+            listen_addrs: Vec::new(),
+            protocols: protocols.iter().map(|p| p.to_string()).collect(),
+            observed_addr: "/ip4/127.0.0.1/tcp/4444".parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_supports_bitswap() {
+        let mut pm = PeerManager::default();
+        let kad_only = PeerId::random();
+        let bitswap = PeerId::random();
+        let unknown = PeerId::random();
+        pm.inject_identify_info(kad_only, identify_info(&["/ipfs/kad/1.0.0"]));
+        pm.inject_identify_info(
+            bitswap,
+            identify_info(&["/ipfs/kad/1.0.0", "/ipfs/bitswap/1.2.0"]),
+        );
+
+        assert_eq!(pm.supports_bitswap(&kad_only), Some(false));
+        assert_eq!(pm.supports_bitswap(&bitswap), Some(true));
+        assert_eq!(pm.supports_bitswap(&unknown), None);
+    }
+}
@@ -11,6 +11,14 @@ pub struct Args {
     tracing: bool,
     #[clap(long)]
     pub cfg: Option<PathBuf>,
+    /// Validate the config and print the effective values and where each came from, without
+    /// starting the node.
+    #[clap(long)]
+    pub check: bool,
+    /// Print whether iroh-p2p is already running and the PID holding its lock, without
+    /// starting the node.
+    #[clap(long)]
+    pub status: bool,
 }
 
 impl Args {
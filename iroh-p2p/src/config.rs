@@ -1,7 +1,12 @@
+use std::fmt::{self, Display};
+use std::net::IpAddr;
 use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
 
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use config::{ConfigError, Map, Source, Value};
+use iroh_bitswap::{NetworkConfig as BitswapNetworkConfig, TimeoutPolicy};
 use iroh_metrics::config::Config as MetricsConfig;
 use iroh_rpc_client::Config as RpcClientConfig;
 use iroh_rpc_types::{
@@ -9,7 +14,7 @@ use iroh_rpc_types::{
     Addr,
 };
 use iroh_util::{insert_into_config_map, iroh_data_root};
-use libp2p::Multiaddr;
+use libp2p::{multiaddr::Protocol, Multiaddr, PeerId};
 use serde::{Deserialize, Serialize};
 
 /// CONFIG_FILE_NAME is the name of the optional config file located in the iroh home directory
@@ -33,6 +38,110 @@ pub const DEFAULT_BOOTSTRAP: &[&str] = &[
 
 // "/ip4/104.131.131.82/udp/4001/quic/p2p/QmaCpDMGvV2BGHeYERUEnRQAwe3N8SzbUtfsmvsqQLuvuJ", // mars.i.ipfs.io
 
+/// Security protocols that can be negotiated on a connection.
+///
+/// TLS is not yet supported by the transport stack, so noise is currently the only option.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SecurityProtocol {
+    Noise,
+}
+
+impl SecurityProtocol {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SecurityProtocol::Noise => "noise",
+        }
+    }
+}
+
+/// Stream multiplexers that can be negotiated on a connection.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Muxer {
+    Yamux,
+    Mplex,
+}
+
+impl Muxer {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Muxer::Yamux => "yamux",
+            Muxer::Mplex => "mplex",
+        }
+    }
+}
+
+/// An IP network in CIDR notation, eg. `10.0.0.0/8`. A plain address is a network of one.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct IpNet {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpNet {
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(*ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(*ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpNet {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().with_context(|| format!("invalid ip: {}", s))?;
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(len) => len
+                .parse()
+                .ok()
+                .filter(|len| *len <= max_len)
+                .ok_or_else(|| anyhow!("invalid prefix length: {}", s))?,
+            None => max_len,
+        };
+        Ok(IpNet { addr, prefix_len })
+    }
+}
+
+impl TryFrom<String> for IpNet {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<IpNet> for String {
+    fn from(net: IpNet) -> Self {
+        net.to_string()
+    }
+}
+
+impl Display for IpNet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
 /// Libp2p config for the node.
 #[derive(PartialEq, Eq, Debug, Clone, Deserialize, Serialize)]
 pub struct Libp2pConfig {
@@ -62,6 +171,104 @@ pub struct Libp2pConfig {
     pub notify_handler_buffer_size: usize,
     pub connection_event_buffer_size: usize,
     pub dial_concurrency_factor: u8,
+    /// Security protocols the node is willing to negotiate.
+    /// Connections that can only speak other protocols are refused.
+    pub security_protocols: Vec<SecurityProtocol>,
+    /// Stream multiplexers the node is willing to negotiate, in order of preference.
+    /// Connections that can only speak other multiplexers are refused.
+    pub muxers: Vec<Muxer>,
+    /// Drop DHT providers whose identify info shows no bitswap protocol we can speak.
+    /// Best effort: providers we have no identify info for are kept.
+    pub filter_providers_by_protocol: bool,
+    /// Shut the node down after this many seconds without rpc calls or in-flight fetches.
+    /// Meant for ephemeral nodes started per operation, `None` keeps the node running.
+    pub idle_timeout_secs: Option<u64>,
+    /// Maximum number of provider records announced at the same time when re-providing.
+    pub reprovide_concurrency: usize,
+    /// How long bitswap waits for a dial to a peer, in milliseconds.
+    pub bitswap_connect_timeout_ms: u64,
+    /// Bounds of the time bitswap allows for sending a message, in milliseconds.
+    pub bitswap_min_send_timeout_ms: u64,
+    pub bitswap_max_send_timeout_ms: u64,
+    /// Time bitswap allows for sending a message on top of the transfer, in milliseconds.
+    pub bitswap_send_latency_ms: u64,
+    /// The slowest rate bitswap sends messages with before timing out, in bytes per second.
+    pub bitswap_min_send_rate: u64,
+    /// If not empty, only these peers may connect to us or be connected to.
+    pub allowed_peers: Vec<PeerId>,
+    /// Peers that are never connected with.
+    pub denied_peers: Vec<PeerId>,
+    /// If not empty, direct connections are only made over addresses in these networks.
+    pub allowed_ips: Vec<IpNet>,
+    /// Networks no direct connections are made over.
+    pub denied_ips: Vec<IpNet>,
+}
+
+/// Parameters of the tokio runtime the daemon runs on.
+#[derive(PartialEq, Eq, Debug, Clone, Deserialize, Serialize)]
+pub struct RuntimeConfig {
+    /// Number of worker threads, `None` uses one per cpu core.
+    pub worker_threads: Option<usize>,
+    /// Maximum number of threads for blocking operations.
+    pub max_blocking_threads: usize,
+    /// Stack size of the runtime threads, in bytes.
+    pub thread_stack_size: usize,
+    /// Minimum limit for open file descriptors. If set, the daemon refuses to start when the
+    /// limit can't be raised to it. Only enforced on unix.
+    pub min_fd_limit: Option<u64>,
+}
+
+impl Default for RuntimeConfig {
+    fn default() -> Self {
+        Self {
+            worker_threads: None,
+            max_blocking_threads: 2048,
+            thread_stack_size: 16 * 1024 * 1024,
+            min_fd_limit: None,
+        }
+    }
+}
+
+impl Source for RuntimeConfig {
+    fn clone_into_box(&self) -> Box<dyn Source + Send + Sync> {
+        Box::new(self.clone())
+    }
+
+    fn collect(&self) -> Result<Map<String, Value>, ConfigError> {
+        let mut map: Map<String, Value> = Map::new();
+        // see `Libp2pConfig` for why these are cast to signed ints
+        if let Some(worker_threads) = self.worker_threads {
+            insert_into_config_map(&mut map, "worker_threads", worker_threads as i64);
+        }
+        insert_into_config_map(
+            &mut map,
+            "max_blocking_threads",
+            self.max_blocking_threads as i64,
+        );
+        insert_into_config_map(&mut map, "thread_stack_size", self.thread_stack_size as i64);
+        if let Some(min_fd_limit) = self.min_fd_limit {
+            insert_into_config_map(&mut map, "min_fd_limit", min_fd_limit as i64);
+        }
+        Ok(map)
+    }
+}
+
+/// Where the identity of the node is loaded from.
+#[derive(PartialEq, Eq, Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeychainConfig {
+    /// Key files in `key_store_path`, a new key is created there on first start.
+    Disk,
+    /// A new key on every start, that is never written to disk.
+    Memory,
+    /// An openssh encoded private key, read from the named environment variable.
+    EnvKey(String),
+}
+
+impl Default for KeychainConfig {
+    fn default() -> Self {
+        KeychainConfig::Disk
+    }
 }
 
 /// Configuration for the node.
@@ -71,6 +278,8 @@ pub struct Config {
     pub rpc_client: RpcClientConfig,
     pub metrics: MetricsConfig,
     pub key_store_path: PathBuf,
+    pub keychain: KeychainConfig,
+    pub runtime: RuntimeConfig,
 }
 
 impl Source for Libp2pConfig {
@@ -123,6 +332,44 @@ impl Source for Libp2pConfig {
         insert_into_config_map(&mut map, "relay_server", self.relay_server);
         insert_into_config_map(&mut map, "relay_client", self.relay_client);
         insert_into_config_map(&mut map, "gossipsub", self.gossipsub);
+        insert_into_config_map(
+            &mut map,
+            "filter_providers_by_protocol",
+            self.filter_providers_by_protocol,
+        );
+        if let Some(idle_timeout_secs) = self.idle_timeout_secs {
+            insert_into_config_map(&mut map, "idle_timeout_secs", idle_timeout_secs as i64);
+        }
+        insert_into_config_map(
+            &mut map,
+            "reprovide_concurrency",
+            self.reprovide_concurrency as i64,
+        );
+        insert_into_config_map(
+            &mut map,
+            "bitswap_connect_timeout_ms",
+            self.bitswap_connect_timeout_ms as i64,
+        );
+        insert_into_config_map(
+            &mut map,
+            "bitswap_min_send_timeout_ms",
+            self.bitswap_min_send_timeout_ms as i64,
+        );
+        insert_into_config_map(
+            &mut map,
+            "bitswap_max_send_timeout_ms",
+            self.bitswap_max_send_timeout_ms as i64,
+        );
+        insert_into_config_map(
+            &mut map,
+            "bitswap_send_latency_ms",
+            self.bitswap_send_latency_ms as i64,
+        );
+        insert_into_config_map(
+            &mut map,
+            "bitswap_min_send_rate",
+            self.bitswap_min_send_rate as i64,
+        );
         let peers: Vec<String> = self.bootstrap_peers.iter().map(|b| b.to_string()).collect();
         insert_into_config_map(&mut map, "bootstrap_peers", peers);
         insert_into_config_map(
@@ -130,6 +377,19 @@ impl Source for Libp2pConfig {
             "listening_multiaddr",
             self.listening_multiaddr.to_string(),
         );
+        let security_protocols: Vec<&str> =
+            self.security_protocols.iter().map(|s| s.as_str()).collect();
+        insert_into_config_map(&mut map, "security_protocols", security_protocols);
+        let muxers: Vec<&str> = self.muxers.iter().map(|m| m.as_str()).collect();
+        insert_into_config_map(&mut map, "muxers", muxers);
+        let allowed_peers: Vec<String> = self.allowed_peers.iter().map(|p| p.to_string()).collect();
+        insert_into_config_map(&mut map, "allowed_peers", allowed_peers);
+        let denied_peers: Vec<String> = self.denied_peers.iter().map(|p| p.to_string()).collect();
+        insert_into_config_map(&mut map, "denied_peers", denied_peers);
+        let allowed_ips: Vec<String> = self.allowed_ips.iter().map(|n| n.to_string()).collect();
+        insert_into_config_map(&mut map, "allowed_ips", allowed_ips);
+        let denied_ips: Vec<String> = self.denied_ips.iter().map(|n| n.to_string()).collect();
+        insert_into_config_map(&mut map, "denied_ips", denied_ips);
         Ok(map)
     }
 }
@@ -145,7 +405,17 @@ impl Source for Config {
         insert_into_config_map(&mut map, "libp2p", self.libp2p.collect()?);
         insert_into_config_map(&mut map, "rpc_client", self.rpc_client.collect()?);
         insert_into_config_map(&mut map, "metrics", self.metrics.collect()?);
+        insert_into_config_map(&mut map, "runtime", self.runtime.collect()?);
         insert_into_config_map(&mut map, "key_store_path", self.key_store_path.to_str());
+        match &self.keychain {
+            KeychainConfig::Disk => insert_into_config_map(&mut map, "keychain", "disk"),
+            KeychainConfig::Memory => insert_into_config_map(&mut map, "keychain", "memory"),
+            KeychainConfig::EnvKey(var) => {
+                let mut keychain: Map<String, Value> = Map::new();
+                insert_into_config_map(&mut keychain, "env_key", var.as_str());
+                insert_into_config_map(&mut map, "keychain", keychain);
+            }
+        }
         Ok(map)
     }
 }
@@ -156,6 +426,8 @@ impl Default for Libp2pConfig {
             .iter()
             .map(|node| node.parse().unwrap())
             .collect();
+        let bitswap_network = BitswapNetworkConfig::default();
+        let timeout_policy = bitswap_network.timeout_policy;
 
         Self {
             listening_multiaddr: "/ip4/0.0.0.0/tcp/4444".parse().unwrap(),
@@ -175,6 +447,35 @@ impl Default for Libp2pConfig {
             notify_handler_buffer_size: 256,
             connection_event_buffer_size: 256,
             dial_concurrency_factor: 8,
+            security_protocols: vec![SecurityProtocol::Noise],
+            muxers: vec![Muxer::Yamux, Muxer::Mplex],
+            filter_providers_by_protocol: false,
+            idle_timeout_secs: None,
+            reprovide_concurrency: 16,
+            bitswap_connect_timeout_ms: bitswap_network.connect_timeout.as_millis() as u64,
+            bitswap_min_send_timeout_ms: timeout_policy.min_timeout.as_millis() as u64,
+            bitswap_max_send_timeout_ms: timeout_policy.max_timeout.as_millis() as u64,
+            bitswap_send_latency_ms: timeout_policy.base_latency.as_millis() as u64,
+            bitswap_min_send_rate: timeout_policy.min_rate,
+            allowed_peers: Vec::new(),
+            denied_peers: Vec::new(),
+            allowed_ips: Vec::new(),
+            denied_ips: Vec::new(),
+        }
+    }
+}
+
+impl Libp2pConfig {
+    /// The dial and send timeouts of bitswap.
+    pub fn bitswap_network(&self) -> BitswapNetworkConfig {
+        BitswapNetworkConfig {
+            connect_timeout: Duration::from_millis(self.bitswap_connect_timeout_ms),
+            timeout_policy: TimeoutPolicy {
+                min_rate: self.bitswap_min_send_rate,
+                base_latency: Duration::from_millis(self.bitswap_send_latency_ms),
+                min_timeout: Duration::from_millis(self.bitswap_min_send_timeout_ms),
+                max_timeout: Duration::from_millis(self.bitswap_max_send_timeout_ms),
+            },
         }
     }
 }
@@ -189,6 +490,8 @@ impl Config {
             },
             metrics: MetricsConfig::default(),
             key_store_path: iroh_data_root().unwrap(),
+            keychain: KeychainConfig::default(),
+            runtime: RuntimeConfig::default(),
         }
     }
 
@@ -200,6 +503,88 @@ impl Config {
             rpc_client,
             metrics: MetricsConfig::default(),
             key_store_path: iroh_data_root().unwrap(),
+            keychain: KeychainConfig::default(),
+            runtime: RuntimeConfig::default(),
+        }
+    }
+
+    /// Lists every key of the default config together with the environment variable
+    /// overriding it.
+    ///
+    /// Options that are unset by default, like `libp2p.idle_timeout_secs`, are not listed but
+    /// can be set the same way.
+    pub fn env_keys() -> Vec<(String, String)> {
+        iroh_util::env_keys(&Config::default_grpc(), ENV_PREFIX)
+            .expect("the default config can always be collected")
+    }
+
+    /// Checks the config can be used to run the p2p daemon, listing every invalid field.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut errors = Vec::new();
+        match self.server_rpc_addr() {
+            Ok(Some(_)) => {}
+            Ok(None) => errors.push("rpc_client.p2p_addr: missing".to_string()),
+            Err(err) => errors.push(format!("rpc_client.p2p_addr: {}", err)),
+        }
+
+        let libp2p = &self.libp2p;
+        let has_transport = libp2p
+            .listening_multiaddr
+            .iter()
+            .any(|p| matches!(p, Protocol::Tcp(_) | Protocol::Udp(_)));
+        if !has_transport {
+            errors.push(format!(
+                "libp2p.listening_multiaddr: {} has no tcp or udp port",
+                libp2p.listening_multiaddr
+            ));
+        }
+        for addr in &libp2p.bootstrap_peers {
+            if !matches!(addr.iter().last(), Some(Protocol::P2p(_))) {
+                errors.push(format!(
+                    "libp2p.bootstrap_peers: {} doesn't end in a /p2p/ peer id",
+                    addr
+                ));
+            }
+        }
+        if libp2p.security_protocols.is_empty() {
+            errors.push("libp2p.security_protocols: empty".to_string());
+        }
+        if libp2p.muxers.is_empty() {
+            errors.push("libp2p.muxers: empty".to_string());
+        }
+        if libp2p.dial_concurrency_factor == 0 {
+            errors.push("libp2p.dial_concurrency_factor: must not be 0".to_string());
+        }
+        if libp2p.reprovide_concurrency == 0 {
+            errors.push("libp2p.reprovide_concurrency: must not be 0".to_string());
+        }
+        if libp2p.bitswap_connect_timeout_ms == 0 {
+            errors.push("libp2p.bitswap_connect_timeout_ms: must not be 0".to_string());
+        }
+        if libp2p.bitswap_min_send_timeout_ms > libp2p.bitswap_max_send_timeout_ms {
+            errors.push(
+                "libp2p.bitswap_min_send_timeout_ms: must not be larger than the maximum"
+                    .to_string(),
+            );
+        }
+        if libp2p.bitswap_min_send_rate == 0 {
+            errors.push("libp2p.bitswap_min_send_rate: must not be 0".to_string());
+        }
+
+        if self.runtime.worker_threads == Some(0) {
+            errors.push("runtime.worker_threads: must not be 0".to_string());
+        }
+        if self.runtime.max_blocking_threads == 0 {
+            errors.push("runtime.max_blocking_threads: must not be 0".to_string());
+        }
+        if self.runtime.thread_stack_size == 0 {
+            errors.push("runtime.thread_stack_size: must not be 0".to_string());
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Message(errors.join("\n")))
         }
     }
 
@@ -252,10 +637,15 @@ mod tests {
             "metrics".to_string(),
             Value::new(None, default.metrics.collect().unwrap()),
         );
+        expect.insert(
+            "runtime".to_string(),
+            Value::new(None, default.runtime.collect().unwrap()),
+        );
         expect.insert(
             "key_store_path".to_string(),
             Value::new(None, iroh_data_root().unwrap().to_str()),
         );
+        expect.insert("keychain".to_string(), Value::new(None, "disk"));
 
         let got = default.collect().unwrap();
         for key in got.keys() {
@@ -317,6 +707,34 @@ mod tests {
             Value::new(None, default.relay_client),
         );
         expect.insert("gossipsub".to_string(), Value::new(None, default.gossipsub));
+        expect.insert(
+            "filter_providers_by_protocol".to_string(),
+            Value::new(None, default.filter_providers_by_protocol),
+        );
+        expect.insert(
+            "reprovide_concurrency".to_string(),
+            Value::new(None, default.reprovide_concurrency as i64),
+        );
+        expect.insert(
+            "bitswap_connect_timeout_ms".to_string(),
+            Value::new(None, default.bitswap_connect_timeout_ms as i64),
+        );
+        expect.insert(
+            "bitswap_min_send_timeout_ms".to_string(),
+            Value::new(None, default.bitswap_min_send_timeout_ms as i64),
+        );
+        expect.insert(
+            "bitswap_max_send_timeout_ms".to_string(),
+            Value::new(None, default.bitswap_max_send_timeout_ms as i64),
+        );
+        expect.insert(
+            "bitswap_send_latency_ms".to_string(),
+            Value::new(None, default.bitswap_send_latency_ms as i64),
+        );
+        expect.insert(
+            "bitswap_min_send_rate".to_string(),
+            Value::new(None, default.bitswap_min_send_rate as i64),
+        );
         expect.insert(
             "bootstrap_peers".to_string(),
             Value::new(None, bootstrap_peers),
@@ -325,6 +743,28 @@ mod tests {
             "listening_multiaddr".to_string(),
             Value::new(None, default.listening_multiaddr.to_string()),
         );
+        expect.insert(
+            "security_protocols".to_string(),
+            Value::new(None, vec!["noise"]),
+        );
+        expect.insert(
+            "muxers".to_string(),
+            Value::new(None, vec!["yamux", "mplex"]),
+        );
+        let no_entries: Vec<String> = Vec::new();
+        expect.insert(
+            "allowed_peers".to_string(),
+            Value::new(None, no_entries.clone()),
+        );
+        expect.insert(
+            "denied_peers".to_string(),
+            Value::new(None, no_entries.clone()),
+        );
+        expect.insert(
+            "allowed_ips".to_string(),
+            Value::new(None, no_entries.clone()),
+        );
+        expect.insert("denied_ips".to_string(), Value::new(None, no_entries));
 
         let got = default.collect().unwrap();
         for key in got.keys() {
@@ -347,4 +787,102 @@ mod tests {
 
         assert_eq!(expect, got);
     }
+
+    #[test]
+    fn test_build_config_non_default() {
+        for keychain in [
+            KeychainConfig::Disk,
+            KeychainConfig::Memory,
+            KeychainConfig::EnvKey("IROH_P2P_KEY".to_string()),
+        ] {
+            let mut expect = Config::default_grpc();
+            expect.keychain = keychain;
+            expect.runtime.worker_threads = Some(4);
+            expect.runtime.min_fd_limit = Some(8192);
+            expect.libp2p.bitswap_connect_timeout_ms = 5000;
+            let got: Config = ConfigBuilder::builder()
+                .add_source(expect.clone())
+                .build()
+                .unwrap()
+                .try_deserialize()
+                .unwrap();
+            assert_eq!(expect, got);
+        }
+    }
+
+    #[test]
+    fn test_env_keys() {
+        let keys = Config::env_keys();
+        assert!(keys.contains(&(
+            "libp2p.mdns".to_string(),
+            "IROH_P2P_LIBP2P.MDNS".to_string()
+        )));
+        assert!(keys.contains(&("keychain".to_string(), "IROH_P2P_KEYCHAIN".to_string())));
+    }
+
+    #[test]
+    fn test_validate() {
+        let config = Config::default_grpc();
+        config.validate().unwrap();
+
+        let mut invalid = config.clone();
+        invalid.rpc_client.p2p_addr = None;
+        invalid.libp2p.listening_multiaddr = "/ip4/0.0.0.0".parse().unwrap();
+        invalid.libp2p.bootstrap_peers = vec!["/ip4/104.131.131.82/tcp/4001".parse().unwrap()];
+        invalid.libp2p.muxers = Vec::new();
+        invalid.libp2p.reprovide_concurrency = 0;
+        invalid.libp2p.bitswap_connect_timeout_ms = 0;
+        invalid.runtime.worker_threads = Some(0);
+        let message = match invalid.validate() {
+            Err(ConfigError::Message(message)) => message,
+            res => panic!("unexpected result: {:?}", res),
+        };
+        let fields: Vec<_> = message
+            .lines()
+            .map(|line| line.split(':').next().unwrap())
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                "rpc_client.p2p_addr",
+                "libp2p.listening_multiaddr",
+                "libp2p.bootstrap_peers",
+                "libp2p.muxers",
+                "libp2p.reprovide_concurrency",
+                "libp2p.bitswap_connect_timeout_ms",
+                "runtime.worker_threads",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bitswap_network() {
+        let mut config = Libp2pConfig::default();
+        assert_eq!(config.bitswap_network(), BitswapNetworkConfig::default());
+
+        config.bitswap_connect_timeout_ms = 1500;
+        config.bitswap_max_send_timeout_ms = 30_000;
+        let network = config.bitswap_network();
+        assert_eq!(network.connect_timeout, Duration::from_millis(1500));
+        assert_eq!(network.timeout_policy.max_timeout, Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_ip_net() {
+        let net: IpNet = "10.1.0.0/16".parse().unwrap();
+        assert!(net.contains(&"10.1.2.3".parse().unwrap()));
+        assert!(!net.contains(&"10.2.0.1".parse().unwrap()));
+        assert!(!net.contains(&"::1".parse().unwrap()));
+        assert_eq!(net.to_string(), "10.1.0.0/16");
+
+        let single: IpNet = "fe80::1".parse().unwrap();
+        assert!(single.contains(&"fe80::1".parse().unwrap()));
+        assert!(!single.contains(&"fe80::2".parse().unwrap()));
+
+        let all: IpNet = "0.0.0.0/0".parse().unwrap();
+        assert!(all.contains(&"192.168.1.1".parse().unwrap()));
+
+        assert!("10.0.0.0/33".parse::<IpNet>().is_err());
+        assert!("localhost".parse::<IpNet>().is_err());
+    }
 }
@@ -2,7 +2,7 @@
 
 use std::path::{Path, PathBuf};
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
 use futures::{Stream, StreamExt, TryStreamExt};
 use ssh_key::LineEnding;
@@ -10,6 +10,8 @@ use tokio::fs;
 use tracing::warn;
 use zeroize::Zeroizing;
 
+use crate::config::{Config, KeychainConfig};
+
 /// Supported keypairs.
 #[derive(Clone, Debug)]
 #[non_exhaustive]
@@ -126,12 +128,39 @@ impl Keychain<DiskStorage> {
     }
 }
 
+impl Keychain<ConfiguredStorage> {
+    /// Creates the keychain selected by the `keychain` field of the config.
+    pub async fn from_config(config: &Config) -> Result<Self> {
+        let storage = match &config.keychain {
+            KeychainConfig::Disk => {
+                ConfiguredStorage::Disk(DiskStorage::new(&config.key_store_path).await?)
+            }
+            KeychainConfig::Memory => ConfiguredStorage::Memory(MemoryStorage::default()),
+            KeychainConfig::EnvKey(var) => ConfiguredStorage::Memory(MemoryStorage::from_env(var)?),
+        };
+        Ok(Self::from_storage(storage))
+    }
+}
+
 /// In memory storage backend for [`Keychain`].
 #[derive(Debug, Default)]
 pub struct MemoryStorage {
     keys: Vec<Keypair>,
 }
 
+impl MemoryStorage {
+    /// Loads the openssh encoded private key stored in the environment variable `var`.
+    fn from_env(var: &str) -> Result<Self> {
+        let content =
+            std::env::var(var).with_context(|| format!("failed to read the key from ${}", var))?;
+        let key = ssh_key::private::PrivateKey::from_openssh(&content)
+            .map_err(|err| anyhow!("invalid key in ${}: {:?}", var, err))?;
+        Ok(MemoryStorage {
+            keys: vec![Keypair::try_from(&key)?],
+        })
+    }
+}
+
 /// On disk storage backend for [`Keychain`].
 #[derive(Debug)]
 pub struct DiskStorage {
@@ -264,6 +293,37 @@ impl Storage for DiskStorage {
     }
 }
 
+/// Storage backend of a [`Keychain`] created from the config, see [`Keychain::from_config`].
+#[derive(Debug)]
+pub enum ConfiguredStorage {
+    Disk(DiskStorage),
+    Memory(MemoryStorage),
+}
+
+#[async_trait]
+impl Storage for ConfiguredStorage {
+    async fn put(&mut self, keypair: Keypair) -> Result<()> {
+        match self {
+            ConfiguredStorage::Disk(storage) => storage.put(keypair).await,
+            ConfiguredStorage::Memory(storage) => storage.put(keypair).await,
+        }
+    }
+
+    async fn len(&self) -> Result<usize> {
+        match self {
+            ConfiguredStorage::Disk(storage) => storage.len().await,
+            ConfiguredStorage::Memory(storage) => storage.len().await,
+        }
+    }
+
+    fn keys(&self) -> Box<dyn Stream<Item = Result<Keypair>> + Unpin + Send + '_> {
+        match self {
+            ConfiguredStorage::Disk(storage) => storage.keys(),
+            ConfiguredStorage::Memory(storage) => storage.keys(),
+        }
+    }
+}
+
 /// Checks if the provided path is likely to contain a private key of the form
 /// `id_<algorithm>_<id>`.
 fn path_is_private_key<P: AsRef<Path>>(path: P) -> bool {
@@ -360,4 +420,24 @@ mod tests {
         let keys: Vec<_> = kc.keys().try_collect().await.unwrap();
         assert_eq!(keys.len(), 2);
     }
+
+    #[tokio::test]
+    async fn env_key_keychain() {
+        let keypair = ssh_key::private::Ed25519Keypair::random(rand::thread_rng());
+        let encoded = Keypair::Ed25519(keypair.clone())
+            .to_private_openssh()
+            .unwrap();
+        std::env::set_var("IROH_P2P_TEST_KEY", encoded.as_str());
+
+        let mut config = Config::default_grpc();
+        config.keychain = KeychainConfig::EnvKey("IROH_P2P_TEST_KEY".to_string());
+        let kc = Keychain::from_config(&config).await.unwrap();
+        let keys: Vec<_> = kc.keys().try_collect().await.unwrap();
+        assert_eq!(keys.len(), 1);
+        let Keypair::Ed25519(loaded) = &keys[0];
+        assert_eq!(loaded.public, keypair.public);
+
+        config.keychain = KeychainConfig::EnvKey("IROH_P2P_TEST_MISSING_KEY".to_string());
+        assert!(Keychain::from_config(&config).await.is_err());
+    }
 }
@@ -5,9 +5,10 @@ mod keys;
 pub mod metrics;
 mod node;
 mod providers;
+mod reprovider;
 pub mod rpc;
 mod swarm;
 
 pub use self::config::*;
-pub use self::keys::{DiskStorage, Keychain, MemoryStorage};
+pub use self::keys::{ConfiguredStorage, DiskStorage, Keychain, MemoryStorage};
 pub use self::node::*;
@@ -1,51 +1,109 @@
 use anyhow::{anyhow, Context, Result};
 use clap::Parser;
 use iroh_p2p::config::{Config, CONFIG_FILE_NAME, ENV_PREFIX};
-use iroh_p2p::{cli::Args, metrics, DiskStorage, Keychain, Node};
-use iroh_util::lock::ProgramLock;
-use iroh_util::{iroh_config_path, make_config};
-use tokio::task;
-use tracing::error;
+use iroh_p2p::{cli::Args, metrics, Keychain, Node};
+use iroh_util::lock::{read_lock_pid, LockError, ProgramLock};
+use iroh_util::{iroh_config_path, make_config_with_origins, run_until};
+use std::time::Duration;
+use sysinfo::PidExt;
+use tracing::{debug, error, info, warn};
+
+/// How long open connections and bitswap sessions get to finish when stopping.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long the bootstrap peers get to answer at startup.
+const BOOTSTRAP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Prints whether another iroh-p2p process holds the lock.
+fn print_status() -> Result<()> {
+    match read_lock_pid("iroh-p2p") {
+        Ok(pid) => {
+            let pid = iroh_localops::process::Pid::from(pid.as_u32());
+            if pid.is_valid() {
+                if iroh_localops::process::process_exists(pid)? {
+                    println!("running, pid={}", pid);
+                } else {
+                    println!("not running, stale lock left by pid={}", pid);
+                }
+            } else {
+                println!("not running, lock holds invalid pid={}", pid);
+            }
+        }
+        Err(LockError::NoLock(_)) => println!("not running"),
+        Err(LockError::CorruptLock(path)) => {
+            println!("not running, corrupt lock at {}", path.display())
+        }
+        Err(err) => return Err(err.into()),
+    }
+    Ok(())
+}
 
 /// Starts daemon process
 fn main() -> Result<()> {
+    let args = Args::parse();
+
+    if args.status {
+        return print_status();
+    }
+
+    // TODO: configurable network
+    let cfg_path = iroh_config_path(CONFIG_FILE_NAME)?;
+    let sources = vec![Some(cfg_path), args.cfg.clone()];
+    let (network_config, origins) = make_config_with_origins(
+        // default
+        Config::default_grpc(),
+        // potential config files
+        sources,
+        // env var prefix for this config
+        ENV_PREFIX,
+        // map of present command line arguments
+        args.make_overrides_map(),
+    )
+    .context("invalid config")?;
+    network_config.validate().context("invalid config")?;
+
+    if args.check {
+        let effective = toml::Value::try_from(&network_config)?;
+        print!("{}", effective);
+        println!();
+        for (key, origin) in origins {
+            println!("# {}: {}", key, origin);
+        }
+        return Ok(());
+    }
+
     let mut lock = ProgramLock::new("iroh-p2p")?;
     lock.acquire_or_exit();
 
-    let runtime = tokio::runtime::Builder::new_multi_thread()
-        .max_blocking_threads(2048)
-        .thread_stack_size(16 * 1024 * 1024)
+    let version = option_env!("IROH_VERSION").unwrap_or(env!("CARGO_PKG_VERSION"));
+    println!("Starting iroh-p2p, version {version}");
+
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    if let Some(worker_threads) = network_config.runtime.worker_threads {
+        runtime.worker_threads(worker_threads);
+    }
+    let runtime = runtime
+        .max_blocking_threads(network_config.runtime.max_blocking_threads)
+        .thread_stack_size(network_config.runtime.thread_stack_size)
         .enable_all()
         .build()
-        .unwrap();
+        .context("failed to start the tokio runtime")?;
 
-    runtime.block_on(async move {
-        let version = option_env!("IROH_VERSION").unwrap_or(env!("CARGO_PKG_VERSION"));
-        println!("Starting iroh-p2p, version {version}");
-
-        let args = Args::parse();
-
-        // TODO: configurable network
-        let cfg_path = iroh_config_path(CONFIG_FILE_NAME)?;
-        let sources = vec![Some(cfg_path), args.cfg.clone()];
-        let network_config = make_config(
-            // default
-            Config::default_grpc(),
-            // potential config files
-            sources,
-            // env var prefix for this config
-            ENV_PREFIX,
-            // map of present command line arguments
-            args.make_overrides_map(),
-        )
-        .context("invalid config")?;
+    // Installed before anything else, so an interrupt during startup still releases
+    // the lock and flushes metrics. The signal handlers need the runtime.
+    let mut sigint = {
+        let _guard = runtime.enter();
+        Box::pin(iroh_util::block_until_sigint())
+    };
 
+    runtime.block_on(async move {
         let metrics_config =
             metrics::metrics_config_with_compile_time_info(network_config.metrics.clone());
 
-        let metrics_handle = iroh_metrics::MetricsHandle::new(metrics_config)
-            .await
-            .map_err(|e| anyhow!("metrics init failed: {:?}", e))?;
+        let metrics_handle = iroh_metrics::MetricsHandle::new(metrics_config);
+        let metrics_handle = match run_until(&mut sigint, metrics_handle).await {
+            Some(handle) => handle.map_err(|e| anyhow!("metrics init failed: {:?}", e))?,
+            None => return Ok(()),
+        };
 
         #[cfg(unix)]
         {
@@ -53,28 +111,66 @@ fn main() -> Result<()> {
                 Ok(soft) => tracing::debug!("NOFILE limit: soft = {}", soft),
                 Err(err) => error!("Error increasing NOFILE limit: {}", err),
             }
+            if let Some(min) = network_config.runtime.min_fd_limit {
+                let soft = iroh_util::increase_fd_limit_to(min)
+                    .context("failed to increase NOFILE limit")?;
+                if soft < min {
+                    return Err(anyhow!(
+                        "NOFILE limit {} is below runtime.min_fd_limit {}, raise the hard limit",
+                        soft,
+                        min
+                    ));
+                }
+            }
         }
 
-        let kc = Keychain::<DiskStorage>::new(network_config.key_store_path.clone()).await?;
-        let rpc_addr = network_config
-            .server_rpc_addr()?
-            .ok_or_else(|| anyhow!("missing p2p rpc addr"))?;
-        let mut p2p = Node::new(network_config, rpc_addr, kc).await?;
-
-        // Start services
-        let p2p_task = task::spawn(async move {
-            if let Err(err) = p2p.run().await {
-                error!("{:?}", err);
+        let p2p = run_until(&mut sigint, async {
+            let kc = Keychain::from_config(&network_config).await?;
+            let rpc_addr = network_config
+                .server_rpc_addr()?
+                .ok_or_else(|| anyhow!("missing p2p rpc addr"))?;
+            Node::new(network_config, rpc_addr, kc).await
+        })
+        .await;
+        let mut p2p = match p2p {
+            Some(p2p) => p2p?,
+            None => {
+                metrics_handle.shutdown();
+                return Ok(());
             }
-        });
-
-        iroh_util::block_until_sigint().await;
+        };
+        println!("Peer ID: {}", p2p.local_peer_id());
+        p2p.hold_lock(lock);
+        p2p.set_metrics_handle(metrics_handle);
 
-        // Cancel all async services
-        p2p_task.abort();
-        p2p_task.await.ok();
+        let verify = p2p.bootstrap_and_verify(BOOTSTRAP_TIMEOUT);
+        let report = match run_until(&mut sigint, verify).await {
+            Some(report) => report,
+            None => {
+                p2p.shutdown(SHUTDOWN_TIMEOUT).await;
+                return Ok(());
+            }
+        };
+        for (addr, res) in &report.peers {
+            if let Err(err) = res {
+                debug!("bootstrap peer {} failed: {}", addr, err);
+            }
+        }
+        if !report.peers.is_empty() && report.connected() == 0 {
+            warn!("{}, the node is isolated", report);
+        } else {
+            info!("{}", report);
+        }
 
-        metrics_handle.shutdown();
+        tokio::select! {
+            res = p2p.run() => {
+                if let Err(err) = res {
+                    error!("{:?}", err);
+                }
+            }
+            _ = &mut sigint => {}
+        }
+        p2p.shutdown(SHUTDOWN_TIMEOUT).await;
         Ok(())
     })
 }
@@ -1,14 +1,18 @@
-use std::collections::{HashMap, HashSet};
-use std::time::Duration;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::{Duration, Instant};
 
 use ahash::AHashMap;
 use anyhow::{anyhow, bail, Context, Result};
 use cid::Cid;
 use futures_util::stream::StreamExt;
-use iroh_metrics::{core::MRecorder, inc, libp2p_metrics, p2p::P2PMetrics};
+use iroh_metrics::{core::MRecorder, inc, libp2p_metrics, p2p::P2PMetrics, MetricsHandle};
 use iroh_rpc_client::Client as RpcClient;
 use iroh_rpc_types::p2p::P2pServerAddr;
-use libp2p::core::Multiaddr;
+#[cfg(feature = "rpc-grpc")]
+use iroh_rpc_types::Addr;
+#[cfg(feature = "rpc-grpc")]
+use iroh_util::lock::ProgramLock;
+use libp2p::core::{transport::ListenerId, Multiaddr, ProtocolName};
 use libp2p::gossipsub::{GossipsubMessage, MessageId, TopicHash};
 pub use libp2p::gossipsub::{IdentTopic, Topic};
 use libp2p::identify::{Event as IdentifyEvent, Info as IdentifyInfo};
@@ -22,21 +26,24 @@ use libp2p::metrics::Recorder;
 use libp2p::multiaddr::Protocol;
 use libp2p::ping::Result as PingResult;
 use libp2p::swarm::dial_opts::{DialOpts, PeerCondition};
-use libp2p::swarm::{ConnectionHandler, IntoConnectionHandler, NetworkBehaviour, SwarmEvent};
+use libp2p::swarm::{
+    ConnectionHandler, DialError, IntoConnectionHandler, NetworkBehaviour, SwarmEvent,
+};
 use libp2p::{PeerId, Swarm};
 use tokio::sync::mpsc::{channel, Receiver, Sender};
 use tokio::sync::oneshot::{self, Sender as OneShotSender};
 use tokio::task::JoinHandle;
 use tracing::{debug, error, info, trace, warn};
 
-use iroh_bitswap::{BitswapEvent, Block};
+use iroh_bitswap::{BitswapEvent, Block, ProtocolId, ProviderRecord};
 
 use crate::keys::{Keychain, Storage};
-use crate::providers::Providers;
-use crate::rpc::ProviderRequestKey;
-use crate::swarm::build_swarm;
+use crate::providers::{Providers, ResponseChannel};
+use crate::reprovider::{Reprovider, REPROVIDE_INTERVAL};
+use crate::rpc::{ProtocolStats, ProviderRequestKey};
+use crate::swarm::{build_swarm, is_negotiation_error};
 use crate::{
-    behaviour::{Event, NodeBehaviour},
+    behaviour::{Event, NodeBehaviour, PeerManager},
     rpc::{self, RpcMessage},
     Config,
 };
@@ -68,6 +75,7 @@ pub enum GossipsubEvent {
 
 pub struct Node<KeyStorage: Storage> {
     swarm: Swarm<NodeBehaviour>,
+    listener: ListenerId,
     net_receiver_in: Receiver<RpcMessage>,
     dial_queries: AHashMap<PeerId, Vec<OneShotSender<Result<()>>>>,
     lookup_queries: AHashMap<PeerId, Vec<oneshot::Sender<Result<IdentifyInfo>>>>,
@@ -81,8 +89,49 @@ pub struct Node<KeyStorage: Storage> {
     kad_last_range: Option<(Distance, Distance)>,
     rpc_task: JoinHandle<()>,
     use_dht: bool,
+    filter_providers_by_protocol: bool,
     bitswap_sessions: BitswapSessions,
     providers: Providers,
+    reprovider: Reprovider,
+    idle_timeout: Option<Duration>,
+    last_activity: Instant,
+    bootstrap_peers: Vec<Multiaddr>,
+    /// Released by [`Node::shutdown`].
+    lock: Option<ProgramLock>,
+    /// Flushed by [`Node::shutdown`].
+    metrics_handle: Option<MetricsHandle>,
+}
+
+/// Outcome of [`Node::bootstrap_and_verify`].
+#[derive(Debug)]
+pub struct BootstrapReport {
+    /// Result of dialing each bootstrap peer, in the order they are configured.
+    pub peers: Vec<(Multiaddr, std::result::Result<(), String>)>,
+    /// Whether a peer in the DHT routing table is connected, `None` if the DHT is disabled.
+    pub dht_ready: Option<bool>,
+}
+
+impl BootstrapReport {
+    /// Number of bootstrap peers that were connected to.
+    pub fn connected(&self) -> usize {
+        self.peers.iter().filter(|(_, res)| res.is_ok()).count()
+    }
+}
+
+impl std::fmt::Display for BootstrapReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "connected to {}/{} bootstrap peers",
+            self.connected(),
+            self.peers.len()
+        )?;
+        match self.dht_ready {
+            Some(true) => write!(f, ", DHT ready"),
+            Some(false) => write!(f, ", DHT not ready"),
+            None => Ok(()),
+        }
+    }
 }
 
 // TODO(ramfox): use new providers queue instead
@@ -115,9 +164,12 @@ impl<KeyStorage: Storage> Node<KeyStorage> {
             ..
         } = config;
 
+        check_rpc_addr(&rpc_addr)?;
+        let keepalive = rpc_client.keepalive();
         let rpc_task = tokio::task::spawn(async move {
-            // TODO: handle error
-            rpc::new(rpc_addr, network_sender_in).await.unwrap()
+            if let Err(err) = rpc::new(rpc_addr, network_sender_in, keepalive).await {
+                error!("rpc server failed: {:?}", err);
+            }
         });
 
         let rpc_client = RpcClient::new(rpc_client)
@@ -127,11 +179,15 @@ impl<KeyStorage: Storage> Node<KeyStorage> {
         let keypair = load_identity(&mut keychain).await?;
         let mut swarm = build_swarm(&libp2p_config, &keypair, rpc_client.clone()).await?;
 
-        Swarm::listen_on(&mut swarm, libp2p_config.listening_multiaddr.clone()).unwrap();
+        let listener = Swarm::listen_on(&mut swarm, libp2p_config.listening_multiaddr.clone())
+            .with_context(|| {
+                format!("failed to listen on {}", libp2p_config.listening_multiaddr)
+            })?;
         println!("{}", libp2p_config.listening_multiaddr);
 
         Ok(Node {
             swarm,
+            listener,
             net_receiver_in: network_receiver_in,
             dial_queries: Default::default(),
             lookup_queries: Default::default(),
@@ -143,11 +199,122 @@ impl<KeyStorage: Storage> Node<KeyStorage> {
             kad_last_range: None,
             rpc_task,
             use_dht: libp2p_config.kademlia,
+            filter_providers_by_protocol: libp2p_config.filter_providers_by_protocol,
             bitswap_sessions: Default::default(),
             providers: Providers::new(4),
+            reprovider: Reprovider::new(REPROVIDE_INTERVAL, libp2p_config.reprovide_concurrency),
+            idle_timeout: libp2p_config.idle_timeout_secs.map(Duration::from_secs),
+            last_activity: Instant::now(),
+            bootstrap_peers: libp2p_config.bootstrap_peers.clone(),
+            lock: None,
+            metrics_handle: None,
         })
     }
 
+    /// Keeps the program `lock` until the node is shut down.
+    pub fn hold_lock(&mut self, lock: ProgramLock) {
+        self.lock = Some(lock);
+    }
+
+    /// Flushes the metrics of `handle` once the node is shut down.
+    pub fn set_metrics_handle(&mut self, handle: MetricsHandle) {
+        self.metrics_handle = Some(handle);
+    }
+
+    /// The peer id derived from the identity in the keychain.
+    pub fn local_peer_id(&self) -> &PeerId {
+        self.swarm.local_peer_id()
+    }
+
+    /// The addresses the node is listening on.
+    ///
+    /// Wildcard addresses and ports are resolved by the transports once the swarm is polled,
+    /// so this can be empty before [`Node::run`] started.
+    pub fn listen_addrs(&self) -> Vec<Multiaddr> {
+        self.swarm.listeners().cloned().collect()
+    }
+
+    /// Dials the configured bootstrap peers, waiting up to `timeout` for the outcome.
+    ///
+    /// Meant to be called once before [`Node::run`], to tell an isolated node from a connected
+    /// one. Swarm events are handled as in [`Node::run`] in the meantime, RPC messages wait.
+    pub async fn bootstrap_and_verify(&mut self, timeout: Duration) -> BootstrapReport {
+        let deadline = tokio::time::Instant::now() + timeout;
+        let mut dials = Vec::new();
+        for addr in self.bootstrap_peers.clone() {
+            let (s, r) = oneshot::channel();
+            match addr.iter().last() {
+                Some(Protocol::P2p(mh)) => match PeerId::from_multihash(mh) {
+                    Ok(peer_id) if self.swarm.is_connected(&peer_id) => {
+                        s.send(Ok(())).ok();
+                    }
+                    Ok(peer_id) => {
+                        // `DialOpts::peer_id` doesn't accept addresses that contain the peer id
+                        let dial_addr = addr
+                            .iter()
+                            .filter(|p| !matches!(*p, Protocol::P2p(_)))
+                            .collect();
+                        let dial_opts = DialOpts::peer_id(peer_id)
+                            .addresses(vec![dial_addr])
+                            .condition(PeerCondition::Always)
+                            .build();
+                        match Swarm::dial(&mut self.swarm, dial_opts) {
+                            Ok(()) => self.dial_queries.entry(peer_id).or_default().push(s),
+                            Err(e) => {
+                                s.send(Err(anyhow!("error dialing peer {:?}: {}", peer_id, e)))
+                                    .ok();
+                            }
+                        }
+                    }
+                    Err(_) => {
+                        s.send(Err(anyhow!("invalid peer id"))).ok();
+                    }
+                },
+                _ => {
+                    s.send(Err(anyhow!("missing peer id"))).ok();
+                }
+            }
+            dials.push((addr, r, None));
+        }
+
+        loop {
+            for (_, r, res) in dials.iter_mut().filter(|(_, _, res)| res.is_none()) {
+                match r.try_recv() {
+                    Ok(dialed) => *res = Some(dialed.map_err(|e| e.to_string())),
+                    Err(oneshot::error::TryRecvError::Empty) => {}
+                    Err(oneshot::error::TryRecvError::Closed) => {
+                        *res = Some(Err("dial dropped".to_string()))
+                    }
+                }
+            }
+            if dials.iter().all(|(_, _, res)| res.is_some()) {
+                break;
+            }
+            match tokio::time::timeout_at(deadline, self.swarm.next()).await {
+                Ok(swarm_event) => {
+                    let swarm_event = swarm_event.expect("the swarm will never die");
+                    if let Err(err) = self.handle_swarm_event(swarm_event) {
+                        error!("swarm error: {:?}", err);
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+
+        let dht_ready = self.swarm.behaviour_mut().kad.as_mut().map(|kad| {
+            kad.kbuckets().any(|kbucket| {
+                kbucket
+                    .iter()
+                    .any(|entry| entry.status == NodeStatus::Connected)
+            })
+        });
+        let peers = dials
+            .into_iter()
+            .map(|(addr, _, res)| (addr, res.unwrap_or_else(|| Err("timed out".to_string()))))
+            .collect();
+        BootstrapReport { peers, dht_ready }
+    }
+
     /// Starts the libp2p service networking stack. This Future resolves when shutdown occurs.
     pub async fn run(&mut self) -> anyhow::Result<()> {
         info!("Local Peer ID: {}", self.swarm.local_peer_id());
@@ -177,6 +344,7 @@ impl<KeyStorage: Storage> Node<KeyStorage> {
                 rpc_message = self.net_receiver_in.recv() => {
                     match rpc_message {
                         Some(rpc_message) => {
+                            self.last_activity = Instant::now();
                             match self.handle_rpc_message(rpc_message) {
                                 Ok(true) => {
                                     // shutdown
@@ -215,9 +383,123 @@ impl<KeyStorage: Storage> Node<KeyStorage> {
                     if let Err(err) = self.expiry() {
                         warn!("expiry error {:?}", err);
                     }
+                    if let Some(kad) = self.swarm.behaviour_mut().kad.as_mut() {
+                        self.reprovider.poll(kad);
+                    }
+                    if self.is_idle() {
+                        info!("idle for {:?}, shutting down", self.last_activity.elapsed());
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Stops the node once [`Node::run`] returned or was cancelled.
+    ///
+    /// Stops listening, stops all bitswap sessions and closes the connections to all peers,
+    /// waiting up to `timeout` for them to finish. Then flushes the metrics and releases the
+    /// program lock, if the node was given them.
+    pub async fn shutdown(mut self, timeout: Duration) {
+        info!("shutting down");
+        self.rpc_task.abort();
+        self.swarm.remove_listener(self.listener);
+
+        let mut workers = Vec::new();
+        for (_, session) in self.bitswap_sessions.drain() {
+            for (closer, worker) in session {
+                if closer.send(()).is_ok() {
+                    workers.push(worker);
+                }
+            }
+        }
+        let peers: Vec<PeerId> = self.swarm.connected_peers().copied().collect();
+        for peer in peers {
+            self.swarm.disconnect_peer_id(peer).ok();
+        }
+
+        let swarm = &mut self.swarm;
+        let drain = async move {
+            futures::future::join_all(workers).await;
+            while swarm.network_info().num_peers() > 0 {
+                if swarm.next().await.is_none() {
+                    break;
+                }
+            }
+        };
+        if tokio::time::timeout(timeout, drain).await.is_err() {
+            warn!("shutdown didn't finish within {:?}", timeout);
+        }
+
+        if let Some(metrics_handle) = self.metrics_handle.take() {
+            metrics_handle.shutdown();
+        }
+        // removes the lock file
+        drop(self.lock.take());
+    }
+
+    /// Returns `true` once the idle timeout passed without rpc calls or in-flight work.
+    fn is_idle(&mut self) -> bool {
+        let idle_timeout = match self.idle_timeout {
+            Some(idle_timeout) => idle_timeout,
+            None => return false,
+        };
+        let busy = !self.bitswap_sessions.is_empty()
+            || !self.providers.is_empty()
+            || !self.reprovider.is_empty()
+            || !self.find_on_dht_queries.is_empty()
+            || !self.dial_queries.is_empty()
+            || !self.lookup_queries.is_empty();
+        if busy {
+            // the timer only starts once the work is done
+            self.last_activity = Instant::now();
+            return false;
+        }
+        self.last_activity.elapsed() >= idle_timeout
+    }
+
+    /// Connected peers advertising each protocol and open bitswap substreams, read from the
+    /// live swarm state.
+    fn protocol_stats(&self) -> Vec<ProtocolStats> {
+        let behaviour = self.swarm.behaviour();
+        let mut stats: BTreeMap<String, ProtocolStats> = BTreeMap::new();
+        for peer_id in self.swarm.connected_peers() {
+            let protocols = behaviour
+                .peer_manager
+                .info_for_peer(peer_id)
+                .and_then(|info| info.last_info.as_ref())
+                .map(|info| info.protocols.as_slice())
+                .unwrap_or_default();
+            for protocol in protocols {
+                stats
+                    .entry(protocol.clone())
+                    .or_insert_with(|| ProtocolStats {
+                        protocol: protocol.clone(),
+                        peers: 0,
+                        substreams: None,
+                    })
+                    .peers += 1;
+            }
+        }
+        if let Some(bs) = behaviour.bitswap.as_ref() {
+            let counts = bs.substream_counts();
+            for protocol_id in ProtocolId::all() {
+                let substreams = counts.get(protocol_id);
+                let protocol = String::from_utf8_lossy(protocol_id.protocol_name()).to_string();
+                if substreams == 0 && !stats.contains_key(&protocol) {
+                    continue;
                 }
+                stats
+                    .entry(protocol.clone())
+                    .or_insert_with(|| ProtocolStats {
+                        protocol,
+                        peers: 0,
+                        substreams: None,
+                    })
+                    .substreams = Some(substreams);
             }
         }
+        stats.into_values().collect()
     }
 
     fn expiry(&mut self) -> Result<()> {
@@ -331,11 +613,12 @@ impl<KeyStorage: Storage> Node<KeyStorage> {
         &mut self,
         ctx: u64,
         cid: Cid,
-        _providers: HashSet<PeerId>,
+        providers: HashSet<PeerId>,
         mut chan: OneShotSender<Result<Block, String>>,
     ) -> Result<()> {
         if let Some(bs) = self.swarm.behaviour().bitswap.as_ref() {
             let client = bs.client().clone();
+            let providers: Vec<_> = providers.into_iter().collect();
             let (closer_s, closer_r) = oneshot::channel();
 
             let entry = self.bitswap_sessions.entry(ctx).or_default();
@@ -349,7 +632,7 @@ impl<KeyStorage: Storage> Node<KeyStorage> {
                         // RPC dropped
                         debug!("session {}: stopped: request canceled", ctx);
                     }
-                    block = client.get_block_with_session_id(ctx, &cid) => match block {
+                    block = client.get_block_with_providers(ctx, &cid, &providers) => match block {
                         Ok(block) => {
                             if let Err(e) = chan.send(Ok(block)) {
                                 warn!("failed to send block response: {:?}", e);
@@ -409,8 +692,28 @@ impl<KeyStorage: Storage> Node<KeyStorage> {
                 trace!("ConnectionClosed: {:}", peer_id);
                 Ok(())
             }
+            SwarmEvent::IncomingConnectionError { error, .. } => {
+                if is_negotiation_error(&error) {
+                    inc!(P2PMetrics::ProtocolNegotiationFailed);
+                }
+                trace!("incoming connection failed: {:?}", error);
+                Ok(())
+            }
+            SwarmEvent::NewListenAddr { address, .. } => {
+                info!(
+                    "Listening on {}/p2p/{}",
+                    address,
+                    self.swarm.local_peer_id()
+                );
+                Ok(())
+            }
             SwarmEvent::OutgoingConnectionError { peer_id, error } => {
                 trace!("failed to dial: {:?}, {:?}", peer_id, error);
+                if let DialError::Transport(errors) = &error {
+                    if errors.iter().any(|(_, err)| is_negotiation_error(err)) {
+                        inc!(P2PMetrics::ProtocolNegotiationFailed);
+                    }
+                }
 
                 if let Some(peer_id) = peer_id {
                     if let Some(channels) = self.dial_queries.get_mut(&peer_id) {
@@ -466,7 +769,7 @@ impl<KeyStorage: Storage> Node<KeyStorage> {
                         info!("bitswap find providers {}", key);
                         self.handle_rpc_message(RpcMessage::ProviderRequest {
                             key: ProviderRequestKey::Dht(key.hash().to_bytes().into()),
-                            response_channel: response,
+                            response_channel: ResponseChannel::Records(response),
                             limit,
                         })?;
                     }
@@ -493,24 +796,41 @@ impl<KeyStorage: Storage> Node<KeyStorage> {
                         QueryResult::GetProviders(Ok(GetProvidersOk {
                             key, providers, ..
                         })) => {
+                            let filter_by_protocol = self.filter_providers_by_protocol;
+                            let connected: HashSet<_> = providers
+                                .iter()
+                                .filter(|p| self.swarm.is_connected(p))
+                                .copied()
+                                .collect();
                             let swarm = self.swarm.behaviour_mut();
-                            if let Some(kad) = swarm.kad.as_mut() {
+                            if swarm.kad.is_enabled() {
                                 debug!("provider results for {:?} last: {}", key, step.last);
 
                                 // filter out bad providers.
-                                let providers: HashSet<_> = providers
+                                let providers: Vec<_> = providers
                                     .into_iter()
                                     .filter(|provider| {
-                                        let is_bad = swarm.peer_manager.is_bad_peer(provider);
-                                        if is_bad {
-                                            inc!(P2PMetrics::SkippedPeerKad);
-                                        }
-                                        !is_bad
+                                        keep_provider(
+                                            &swarm.peer_manager,
+                                            filter_by_protocol,
+                                            provider,
+                                        )
+                                    })
+                                    .collect();
+                                let providers = providers
+                                    .into_iter()
+                                    .map(|peer| ProviderRecord {
+                                        peer,
+                                        addrs: swarm.addresses_of_peer(&peer),
+                                        connected: connected.contains(&peer),
                                     })
                                     .collect();
 
-                                self.providers
-                                    .handle_get_providers_ok(id, step.last, key, providers, kad);
+                                if let Some(kad) = swarm.kad.as_mut() {
+                                    self.providers.handle_get_providers_ok(
+                                        id, step.last, key, providers, kad,
+                                    );
+                                }
                             }
                         }
                         QueryResult::GetProviders(Err(error)) => {
@@ -518,6 +838,12 @@ impl<KeyStorage: Storage> Node<KeyStorage> {
                                 self.providers.handle_get_providers_error(id, error, kad);
                             }
                         }
+                        QueryResult::StartProviding(result) => {
+                            if let Some(kad) = self.swarm.behaviour_mut().kad.as_mut() {
+                                self.reprovider
+                                    .handle_start_providing_result(id, result, kad);
+                            }
+                        }
                         QueryResult::Bootstrap(Ok(BootstrapOk {
                             peer,
                             num_remaining,
@@ -718,6 +1044,22 @@ impl<KeyStorage: Storage> Node<KeyStorage> {
             } => {
                 self.destroy_session(ctx, response_channel);
             }
+            RpcMessage::BitswapWantlist(response_channel) => {
+                if let Some(bs) = self.swarm.behaviour().bitswap.as_ref() {
+                    let client = bs.client().clone();
+                    tokio::task::spawn(async move {
+                        let wants = client.get_wantlist_entries().await;
+                        response_channel.send(Ok(wants)).ok();
+                    });
+                } else {
+                    response_channel
+                        .send(Err(anyhow!("no bitswap available")))
+                        .ok();
+                }
+            }
+            RpcMessage::ProtocolStats(response_channel) => {
+                response_channel.send(self.protocol_stats()).ok();
+            }
             RpcMessage::ProviderRequest {
                 key,
                 limit,
@@ -728,12 +1070,7 @@ impl<KeyStorage: Storage> Node<KeyStorage> {
                     if self.swarm.behaviour().kad.is_enabled() {
                         self.providers.push(key, limit, response_channel);
                     } else {
-                        tokio::task::spawn(async move {
-                            response_channel
-                                .send(Err("kademlia is not available".into()))
-                                .await
-                                .ok();
-                        });
+                        response_channel.send_error("kademlia is not available".into());
                     }
                 }
                 ProviderRequestKey::Bitswap(_, _) => {
@@ -964,6 +1301,35 @@ impl<KeyStorage: Storage> Node<KeyStorage> {
     }
 }
 
+/// Fails with a readable error if the rpc address is already taken, which would otherwise
+/// only be noticed by the rpc server task.
+fn check_rpc_addr(addr: &P2pServerAddr) -> Result<()> {
+    #[allow(unreachable_patterns)]
+    match addr {
+        #[cfg(feature = "rpc-grpc")]
+        Addr::GrpcHttp2(socket) => match std::net::TcpListener::bind(socket) {
+            Ok(_) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::AddrInUse => {
+                let holder = match ProgramLock::new("iroh-p2p").and_then(|mut l| l.active_pid()) {
+                    Ok(pid) if usize::from(pid) != std::process::id() as usize => {
+                        format!(" (iroh-p2p is running with pid {})", pid)
+                    }
+                    _ => String::new(),
+                };
+                bail!(
+                    "RPC address {} already in use, is another iroh-p2p running?{}",
+                    addr,
+                    holder
+                )
+            }
+            Err(err) => {
+                Err(err).with_context(|| format!("failed to bind the RPC address {}", addr))
+            }
+        },
+        _ => Ok(()),
+    }
+}
+
 async fn load_identity<S: Storage>(kc: &mut Keychain<S>) -> Result<Keypair> {
     if kc.is_empty().await? {
         info!("no identity found, creating",);
@@ -981,6 +1347,20 @@ async fn load_identity<S: Storage>(kc: &mut Keychain<S>) -> Result<Keypair> {
     Err(anyhow!("inconsistent keystate"))
 }
 
+/// Whether a provider found in the DHT is worth asking, skipping bad peers and, if
+/// `filter_by_protocol` is set, peers whose identify info shows no bitswap support.
+fn keep_provider(peer_manager: &PeerManager, filter_by_protocol: bool, provider: &PeerId) -> bool {
+    if peer_manager.is_bad_peer(provider) {
+        inc!(P2PMetrics::SkippedPeerKad);
+        return false;
+    }
+    if filter_by_protocol && peer_manager.supports_bitswap(provider) == Some(false) {
+        inc!(P2PMetrics::SkippedPeerProtocol);
+        return false;
+    }
+    true
+}
+
 #[cfg(test)]
 mod tests {
     use crate::keys::MemoryStorage;
@@ -1042,6 +1422,206 @@ mod tests {
         Ok(())
     }
 
+    #[cfg(feature = "rpc-mem")]
+    #[tokio::test]
+    async fn test_idle_shutdown() -> Result<()> {
+        let (rpc_server_addr, rpc_client_addr) = Addr::new_mem();
+        let mut network_config = Config::default_with_rpc(rpc_client_addr.clone());
+        network_config.libp2p.listening_multiaddr = "/ip4/127.0.0.1/tcp/0".parse().unwrap();
+        network_config.libp2p.bootstrap_peers = Vec::new();
+        network_config.libp2p.idle_timeout_secs = Some(2);
+
+        let kc = Keychain::<MemoryStorage>::new();
+        let mut p2p = Node::new(network_config, rpc_server_addr, kc).await?;
+        let p2p_task = tokio::task::spawn(async move { p2p.run().await });
+
+        let cfg = iroh_rpc_client::Config {
+            p2p_addr: Some(rpc_client_addr),
+            channels: Some(1),
+            ..Default::default()
+        };
+        let client = RpcClient::new(cfg).await?;
+
+        // an active node keeps running past the idle timeout
+        for _ in 0..8 {
+            client.try_p2p().unwrap().local_peer_id().await?;
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+        assert!(!p2p_task.is_finished());
+
+        // once the calls stop it shuts itself down
+        tokio::time::timeout(Duration::from_secs(5), p2p_task).await???;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "rpc-grpc")]
+    #[test]
+    fn test_check_rpc_addr() {
+        let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = Addr::GrpcHttp2(taken.local_addr().unwrap());
+        let err = check_rpc_addr(&addr).unwrap_err();
+        assert!(err.to_string().contains("already in use"), "{}", err);
+
+        drop(taken);
+        check_rpc_addr(&addr).unwrap();
+    }
+
+    #[cfg(feature = "rpc-mem")]
+    #[tokio::test]
+    async fn test_listen_addrs() -> Result<()> {
+        let (rpc_server_addr, rpc_client_addr) = Addr::new_mem();
+        let mut network_config = Config::default_with_rpc(rpc_client_addr);
+        network_config.libp2p.listening_multiaddr = "/ip4/127.0.0.1/tcp/0".parse().unwrap();
+        network_config.libp2p.bootstrap_peers = Vec::new();
+
+        let kc = Keychain::<MemoryStorage>::new();
+        let mut p2p = Node::new(network_config, rpc_server_addr, kc).await?;
+        assert_eq!(p2p.local_peer_id(), p2p.swarm.local_peer_id());
+
+        // the port is assigned once the transport reported the listener
+        while p2p.listen_addrs().is_empty() {
+            p2p.swarm.next().await;
+        }
+        for addr in p2p.listen_addrs() {
+            assert!(addr
+                .iter()
+                .any(|p| matches!(p, libp2p::multiaddr::Protocol::Tcp(port) if port != 0)));
+        }
+
+        Ok(())
+    }
+
+    /// Starts a node listening on localhost with bitswap enabled or not, returning the
+    /// address it is reachable at.
+    #[cfg(feature = "rpc-mem")]
+    async fn spawn_node(bitswap: bool) -> Result<(PeerId, Multiaddr, JoinHandle<()>)> {
+        let (rpc_server_addr, rpc_client_addr) = Addr::new_mem();
+        let mut network_config = Config::default_with_rpc(rpc_client_addr);
+        network_config.libp2p.listening_multiaddr = "/ip4/127.0.0.1/tcp/0".parse().unwrap();
+        network_config.libp2p.bootstrap_peers = Vec::new();
+        network_config.libp2p.bitswap = bitswap;
+        let mut node = Node::new(
+            network_config,
+            rpc_server_addr,
+            Keychain::<MemoryStorage>::new(),
+        )
+        .await?;
+        while node.listen_addrs().is_empty() {
+            node.swarm.next().await;
+        }
+        let peer_id = *node.local_peer_id();
+        let addr = node.listen_addrs()[0].clone();
+        let task = tokio::task::spawn(async move {
+            node.run().await.ok();
+        });
+        Ok((peer_id, addr, task))
+    }
+
+    #[cfg(feature = "rpc-mem")]
+    #[tokio::test]
+    async fn test_filter_providers_by_protocol() -> Result<()> {
+        let (bitswap_peer, bitswap_addr, bitswap_task) = spawn_node(true).await?;
+        let (kad_peer, kad_addr, kad_task) = spawn_node(false).await?;
+
+        let (rpc_server_addr, rpc_client_addr) = Addr::new_mem();
+        let mut network_config = Config::default_with_rpc(rpc_client_addr);
+        network_config.libp2p.listening_multiaddr = "/ip4/127.0.0.1/tcp/0".parse().unwrap();
+        network_config.libp2p.bootstrap_peers = Vec::new();
+        network_config.libp2p.filter_providers_by_protocol = true;
+        let mut p2p = Node::new(
+            network_config,
+            rpc_server_addr,
+            Keychain::<MemoryStorage>::new(),
+        )
+        .await?;
+        p2p.swarm
+            .dial(bitswap_addr.with(Protocol::P2p(bitswap_peer.into())))?;
+        p2p.swarm
+            .dial(kad_addr.with(Protocol::P2p(kad_peer.into())))?;
+
+        // the identify info of both peers arrives through the swarm
+        tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                let peer_manager = &p2p.swarm.behaviour().peer_manager;
+                if peer_manager.supports_bitswap(&bitswap_peer).is_some()
+                    && peer_manager.supports_bitswap(&kad_peer).is_some()
+                {
+                    break;
+                }
+                let event = p2p.swarm.select_next_some().await;
+                p2p.handle_swarm_event(event).unwrap();
+            }
+        })
+        .await?;
+
+        let peer_manager = &p2p.swarm.behaviour().peer_manager;
+        assert!(keep_provider(peer_manager, true, &bitswap_peer));
+        assert!(!keep_provider(peer_manager, true, &kad_peer));
+        assert!(keep_provider(peer_manager, false, &kad_peer));
+        // providers without identify info are kept
+        assert!(keep_provider(peer_manager, true, &PeerId::random()));
+
+        bitswap_task.abort();
+        kad_task.abort();
+        Ok(())
+    }
+
+    #[cfg(feature = "rpc-mem")]
+    #[tokio::test]
+    async fn test_bootstrap_and_verify() -> Result<()> {
+        let (rpc_server_addr, rpc_client_addr) = Addr::new_mem();
+        let mut network_config = Config::default_with_rpc(rpc_client_addr);
+        network_config.libp2p.listening_multiaddr = "/ip4/127.0.0.1/tcp/0".parse().unwrap();
+        network_config.libp2p.bootstrap_peers = Vec::new();
+        let mut bootstrap = Node::new(
+            network_config,
+            rpc_server_addr,
+            Keychain::<MemoryStorage>::new(),
+        )
+        .await?;
+        while bootstrap.listen_addrs().is_empty() {
+            bootstrap.swarm.next().await;
+        }
+        let reachable = bootstrap.listen_addrs()[0]
+            .clone()
+            .with(Protocol::P2p((*bootstrap.local_peer_id()).into()));
+        let bootstrap_task = tokio::task::spawn(async move { bootstrap.run().await });
+
+        // nothing listens on a port that was just released
+        let port = std::net::TcpListener::bind("127.0.0.1:0")?
+            .local_addr()?
+            .port();
+        let unreachable: Multiaddr = format!("/ip4/127.0.0.1/tcp/{}", port).parse()?;
+        let unreachable = unreachable.with(Protocol::P2p(PeerId::random().into()));
+
+        let (rpc_server_addr, rpc_client_addr) = Addr::new_mem();
+        let mut network_config = Config::default_with_rpc(rpc_client_addr);
+        network_config.libp2p.listening_multiaddr = "/ip4/127.0.0.1/tcp/0".parse().unwrap();
+        network_config.libp2p.bootstrap_peers = vec![reachable.clone(), unreachable.clone()];
+        let mut p2p = Node::new(
+            network_config,
+            rpc_server_addr,
+            Keychain::<MemoryStorage>::new(),
+        )
+        .await?;
+
+        let report = p2p.bootstrap_and_verify(Duration::from_secs(10)).await;
+        assert_eq!(report.peers.len(), 2);
+        assert_eq!(report.peers[0], (reachable, Ok(())));
+        assert_eq!(report.peers[1].0, unreachable);
+        assert!(report.peers[1].1.is_err());
+        assert_eq!(report.connected(), 1);
+        assert_eq!(report.dht_ready, Some(true));
+        assert_eq!(
+            report.to_string(),
+            "connected to 1/2 bootstrap peers, DHT ready"
+        );
+
+        bootstrap_task.abort();
+        Ok(())
+    }
+
     async fn fetch_providers(
         addr: Multiaddr,
         rpc_server_addr: P2pServerAddr,
@@ -1056,6 +1636,8 @@ mod tests {
         let cfg = iroh_rpc_client::Config {
             p2p_addr: Some(rpc_client_addr),
             channels: Some(1),
+            max_retries: None,
+            keepalive_secs: None,
             ..Default::default()
         };
         let p2p_task = tokio::task::spawn(async move {
@@ -1,13 +1,47 @@
 use std::collections::{HashSet, VecDeque};
 
 use ahash::AHashMap;
+use iroh_bitswap::ProviderRecord;
 use libp2p::{
     kad::{record::Key, store::MemoryStore, GetProvidersError, Kademlia, QueryId},
     PeerId,
 };
 use tokio::sync::mpsc;
 
-type ResponseChannel = mpsc::Sender<Result<HashSet<PeerId>, String>>;
+/// Where the providers found by a query are sent to, in batches of new providers.
+#[derive(Debug, Clone)]
+pub enum ResponseChannel {
+    Peers(mpsc::Sender<Result<HashSet<PeerId>, String>>),
+    Records(mpsc::Sender<Result<Vec<ProviderRecord>, String>>),
+}
+
+impl ResponseChannel {
+    fn is_closed(&self) -> bool {
+        match self {
+            ResponseChannel::Peers(s) => s.is_closed(),
+            ResponseChannel::Records(s) => s.is_closed(),
+        }
+    }
+
+    async fn send(&self, providers: Result<Vec<ProviderRecord>, String>) {
+        match self {
+            ResponseChannel::Peers(s) => {
+                let peers = providers.map(|records| records.into_iter().map(|r| r.peer).collect());
+                let _ = s.send(peers).await;
+            }
+            ResponseChannel::Records(s) => {
+                let _ = s.send(providers).await;
+            }
+        }
+    }
+
+    /// Sends `err` from a new task.
+    pub fn send_error(self, err: String) {
+        tokio::task::spawn(async move {
+            self.send(Err(err)).await;
+        });
+    }
+}
 
 const OUTSTANDING_LIMIT: usize = 2048;
 
@@ -28,7 +62,7 @@ struct Query {
 #[derive(Debug)]
 struct RunningQuery {
     query_id: QueryId,
-    found_providers: HashSet<PeerId>,
+    found_providers: AHashMap<PeerId, ProviderRecord>,
     queries: Vec<QueryDetails>,
 }
 
@@ -47,21 +81,26 @@ impl Providers {
         }
     }
 
+    /// Returns `true` if no queries are queued or running.
+    pub fn is_empty(&self) -> bool {
+        self.outstanding_queries.is_empty() && self.current_queries.is_empty()
+    }
+
     /// Drops queries if the queue is full.
     pub fn push(&mut self, key: Key, limit: usize, response_channel: ResponseChannel) -> bool {
         // Check if we already have a query running
         if let Some(running_query) = self.current_queries.get_mut(&key) {
             // send all found providers
-            let providers = running_query
+            let providers: Vec<_> = running_query
                 .found_providers
-                .iter()
-                .copied()
+                .values()
                 .take(limit)
-                .collect::<HashSet<_>>();
+                .cloned()
+                .collect();
             if !providers.is_empty() {
                 let channel = response_channel.clone();
                 tokio::task::spawn(async move {
-                    let _ = channel.send(Ok(providers)).await;
+                    channel.send(Ok(providers)).await;
                 });
             }
 
@@ -97,7 +136,7 @@ impl Providers {
         id: QueryId,
         is_last: bool,
         key: Key,
-        providers: HashSet<PeerId>,
+        providers: Vec<ProviderRecord>,
         kad: &mut Kademlia<MemoryStore>,
     ) {
         if let Some(query) = self.current_queries.get_mut(&key) {
@@ -121,9 +160,9 @@ impl Providers {
             }
 
             // Determine new providers.
-            let new_providers: HashSet<PeerId> = providers
-                .difference(&query.found_providers)
-                .copied()
+            let new_providers: Vec<ProviderRecord> = providers
+                .into_iter()
+                .filter(|p| !query.found_providers.contains_key(&p.peer))
                 .collect();
 
             // Send out providers
@@ -132,11 +171,13 @@ impl Providers {
                 let np = new_providers.clone();
                 tokio::task::spawn(async move {
                     for query in queries {
-                        let _ = query.response_channel.send(Ok(np.clone())).await;
+                        query.response_channel.send(Ok(np.clone())).await;
                     }
                 });
 
-                query.found_providers.extend(new_providers);
+                query
+                    .found_providers
+                    .extend(new_providers.into_iter().map(|p| (p.peer, p)));
             }
 
             if is_last {
@@ -183,7 +224,7 @@ impl Providers {
 
             tokio::task::spawn(async move {
                 for q in query.queries {
-                    let _ = q.response_channel.send(Err("timeout".to_string())).await;
+                    q.response_channel.send(Err("timeout".to_string())).await;
                 }
             });
 
@@ -208,7 +249,15 @@ impl Providers {
             }
         }
 
-        // Cleanup
+        // Cleanup, a closed response channel cancels the request
+        for query in self.outstanding_queries.iter_mut() {
+            query
+                .queries
+                .retain(|query| !query.response_channel.is_closed());
+        }
+        self.outstanding_queries
+            .retain(|query| !query.queries.is_empty());
+
         for query in self.current_queries.values_mut() {
             query
                 .queries
@@ -226,3 +275,69 @@ impl Providers {
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use libp2p::identity::Keypair;
+
+    use super::*;
+
+    #[test]
+    fn test_cancelled_queries_are_removed() {
+        let peer_id = PeerId::from(Keypair::generate_ed25519().public());
+        let mut kad = Kademlia::new(peer_id, MemoryStore::new(peer_id));
+        let mut providers = Providers::new(1);
+
+        let (running_s, running_r) = mpsc::channel(1);
+        let (queued_s, queued_r) = mpsc::channel(1);
+        assert!(providers.push(Key::new(&[1]), 10, ResponseChannel::Peers(running_s)));
+        assert!(providers.push(Key::new(&[2]), 10, ResponseChannel::Records(queued_s)));
+        providers.poll(&mut kad);
+        assert_eq!(providers.current_queries.len(), 1);
+        assert_eq!(providers.outstanding_queries.len(), 1);
+
+        drop(queued_r);
+        providers.poll(&mut kad);
+        assert!(providers.outstanding_queries.is_empty());
+
+        drop(running_r);
+        providers.poll(&mut kad);
+        assert!(providers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_providers_sent_as_peers_and_records() {
+        let peer_id = PeerId::from(Keypair::generate_ed25519().public());
+        let mut kad = Kademlia::new(peer_id, MemoryStore::new(peer_id));
+        let mut providers = Providers::new(1);
+
+        let key = Key::new(&[1]);
+        let (peers_s, mut peers_r) = mpsc::channel(4);
+        let (records_s, mut records_r) = mpsc::channel(4);
+        assert!(providers.push(key.clone(), 10, ResponseChannel::Peers(peers_s)));
+        assert!(providers.push(key.clone(), 10, ResponseChannel::Records(records_s)));
+        providers.poll(&mut kad);
+        let id = providers.current_queries[&key].query_id;
+
+        let record = ProviderRecord {
+            peer: PeerId::random(),
+            addrs: vec!["/ip4/127.0.0.1/tcp/4001".parse().unwrap()],
+            connected: true,
+        };
+        providers.handle_get_providers_ok(id, false, key.clone(), vec![record.clone()], &mut kad);
+        assert_eq!(
+            peers_r.recv().await.unwrap().unwrap(),
+            HashSet::from([record.peer])
+        );
+        assert_eq!(
+            records_r.recv().await.unwrap().unwrap(),
+            vec![record.clone()]
+        );
+
+        // known providers are not sent again
+        providers.handle_get_providers_ok(id, true, key, vec![record], &mut kad);
+        assert!(providers.is_empty());
+        assert!(peers_r.recv().await.is_none());
+        assert!(records_r.recv().await.is_none());
+    }
+}
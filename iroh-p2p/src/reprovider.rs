@@ -0,0 +1,156 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use ahash::AHashSet;
+use iroh_metrics::{core::MRecorder, inc, p2p::P2PMetrics};
+use libp2p::kad::{
+    record::Key,
+    store::{MemoryStore, RecordStore},
+    Kademlia, QueryId,
+};
+use tracing::{debug, info, warn};
+
+/// How often all provider records are announced again, the libp2p default.
+pub const REPROVIDE_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// Periodically announces all provider records of the node again, keeping a bounded number of
+/// announcements in flight.
+///
+/// Replaces the republishing built into kademlia, which starts all announcements at once.
+#[derive(Debug)]
+pub struct Reprovider {
+    interval: Duration,
+    max_running_queries: usize,
+    next_cycle: Instant,
+    cycle: Option<Cycle>,
+    /// Keys of the current cycle that have not been announced yet.
+    outstanding_keys: VecDeque<Key>,
+    current_queries: AHashSet<QueryId>,
+}
+
+#[derive(Debug)]
+struct Cycle {
+    started: Instant,
+    records: usize,
+    failures: usize,
+}
+
+impl Reprovider {
+    pub fn new(interval: Duration, max_running_queries: usize) -> Self {
+        assert!(max_running_queries > 0);
+        Self {
+            interval,
+            max_running_queries,
+            next_cycle: Instant::now() + interval,
+            cycle: None,
+            outstanding_keys: Default::default(),
+            current_queries: Default::default(),
+        }
+    }
+
+    /// Returns `true` if no announcements are queued or running.
+    pub fn is_empty(&self) -> bool {
+        self.outstanding_keys.is_empty() && self.current_queries.is_empty()
+    }
+
+    /// Handles the result of a `StartProviding` query, ignoring queries we didn't start.
+    pub fn handle_start_providing_result<T, E: std::fmt::Debug>(
+        &mut self,
+        id: QueryId,
+        result: Result<T, E>,
+        kad: &mut Kademlia<MemoryStore>,
+    ) {
+        if !self.current_queries.remove(&id) {
+            return;
+        }
+        match result {
+            Ok(_) => inc!(P2PMetrics::Reprovided),
+            Err(err) => {
+                debug!("failed to reprovide: {:?}", err);
+                self.record_failure();
+            }
+        }
+        // we freed a spot, poll for advancing the cycle
+        self.poll(kad);
+    }
+
+    pub fn poll(&mut self, kad: &mut Kademlia<MemoryStore>) {
+        if self.cycle.is_none() && Instant::now() >= self.next_cycle {
+            self.outstanding_keys = kad.store_mut().provided().map(|r| r.key.clone()).collect();
+            self.next_cycle = Instant::now() + self.interval;
+            self.cycle = Some(Cycle {
+                started: Instant::now(),
+                records: self.outstanding_keys.len(),
+                failures: 0,
+            });
+            debug!("reproviding {} records", self.outstanding_keys.len());
+        }
+
+        while self.current_queries.len() < self.max_running_queries {
+            let key = match self.outstanding_keys.pop_front() {
+                Some(key) => key,
+                None => break,
+            };
+            match kad.start_providing(key) {
+                Ok(query_id) => {
+                    self.current_queries.insert(query_id);
+                }
+                Err(err) => {
+                    warn!("failed to reprovide: {:?}", err);
+                    self.record_failure();
+                }
+            }
+        }
+
+        if self.is_empty() {
+            if let Some(cycle) = self.cycle.take() {
+                info!(
+                    "reprovided {} records in {:?}, {} failed",
+                    cycle.records,
+                    cycle.started.elapsed(),
+                    cycle.failures
+                );
+                inc!(P2PMetrics::ReprovideCycles);
+            }
+        }
+    }
+
+    fn record_failure(&mut self) {
+        if let Some(cycle) = self.cycle.as_mut() {
+            cycle.failures += 1;
+        }
+        inc!(P2PMetrics::ReprovideFailures);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libp2p::{identity::Keypair, kad::record::ProviderRecord, PeerId};
+
+    use super::*;
+
+    #[test]
+    fn test_reprovide_concurrency() {
+        let peer_id = PeerId::from(Keypair::generate_ed25519().public());
+        let mut kad = Kademlia::new(peer_id, MemoryStore::new(peer_id));
+        for i in 0..10u8 {
+            let record = ProviderRecord::new(Key::new(&[i]), peer_id, Vec::new());
+            kad.store_mut().add_provider(record).unwrap();
+        }
+
+        let mut reprovider = Reprovider::new(Duration::ZERO, 3);
+        reprovider.poll(&mut kad);
+        assert_eq!(reprovider.current_queries.len(), 3);
+
+        let mut announced = 0;
+        while let Some(id) = reprovider.current_queries.iter().next().copied() {
+            assert!(reprovider.current_queries.len() <= 3);
+            let result: Result<(), ()> = Ok(());
+            reprovider.handle_start_providing_result(id, result, &mut kad);
+            announced += 1;
+        }
+        assert_eq!(announced, 10);
+        assert!(reprovider.is_empty());
+        assert!(reprovider.cycle.is_none());
+    }
+}
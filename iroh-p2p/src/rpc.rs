@@ -1,6 +1,7 @@
 use std::collections::{HashMap, HashSet};
 use std::io;
 use std::pin::Pin;
+use std::time::Duration;
 
 use anyhow::{anyhow, ensure, Context, Result};
 use bytes::Bytes;
@@ -19,17 +20,22 @@ use tokio::sync::oneshot;
 use tracing::{debug, trace};
 
 use async_trait::async_trait;
-use iroh_bitswap::Block;
+use iroh_bitswap::{message::WantType, Block, WantlistEntry as BitswapWantlistEntry};
 use iroh_rpc_types::p2p::{
     BitswapRequest, BitswapResponse, ConnectByPeerIdRequest, ConnectRequest, DisconnectRequest,
     GetListeningAddrsResponse, GetPeersResponse, GossipsubAllPeersResponse, GossipsubPeerAndTopics,
     GossipsubPeerIdMsg, GossipsubPeersResponse, GossipsubPublishRequest, GossipsubPublishResponse,
     GossipsubSubscribeResponse, GossipsubTopicHashMsg, GossipsubTopicsResponse, Key as ProviderKey,
     LookupRequest, Multiaddrs, NotifyNewBlocksBitswapRequest, P2p as RpcP2p, P2pServerAddr,
-    PeerIdResponse, PeerInfo, Providers, StopSessionBitswapRequest, VersionResponse,
+    PeerIdResponse, PeerInfo, ProtocolStat, ProtocolStatsResponse, Providers,
+    StopSessionBitswapRequest, VersionResponse, WantlistEntry, WantlistRequest, WantlistResponse,
 };
 
 use super::node::DEFAULT_PROVIDER_LIMIT;
+use crate::providers::ResponseChannel;
+
+/// Maximum number of entries returned by a single wantlist request.
+const MAX_WANTLIST_PAGE: usize = 1000;
 
 struct P2p {
     sender: Sender<RpcMessage>,
@@ -147,6 +153,50 @@ impl RpcP2p for P2p {
         Ok(())
     }
 
+    #[tracing::instrument(skip(self))]
+    async fn wantlist(&self, req: WantlistRequest) -> Result<WantlistResponse> {
+        let (s, r) = oneshot::channel();
+        self.sender.send(RpcMessage::BitswapWantlist(s)).await?;
+        let wants = r.await?.context("bitswap wantlist")?;
+
+        let limit = match req.limit as usize {
+            0 => MAX_WANTLIST_PAGE,
+            limit => limit.min(MAX_WANTLIST_PAGE),
+        };
+        let total = wants.len() as u64;
+        let entries = wants
+            .into_iter()
+            .skip(req.offset as usize)
+            .take(limit)
+            .map(|w| WantlistEntry {
+                cid: w.cid.to_bytes(),
+                peer_id: w.peer.to_bytes(),
+                priority: w.priority,
+                want_block: w.want_type == WantType::Block,
+                age_ms: w.age.map(|age| age.as_millis() as u64),
+            })
+            .collect();
+
+        Ok(WantlistResponse { entries, total })
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn protocol_stats(&self, _: ()) -> Result<ProtocolStatsResponse> {
+        let (s, r) = oneshot::channel();
+        self.sender.send(RpcMessage::ProtocolStats(s)).await?;
+        let stats = r.await?;
+
+        let protocols = stats
+            .into_iter()
+            .map(|s| ProtocolStat {
+                protocol: s.protocol,
+                peers: s.peers as u64,
+                substreams: s.substreams.map(|n| n as u64),
+            })
+            .collect();
+        Ok(ProtocolStatsResponse { protocols })
+    }
+
     #[tracing::instrument(skip(self, req))]
     async fn notify_new_blocks_bitswap(&self, req: NotifyNewBlocksBitswapRequest) -> Result<()> {
         let blocks = req
@@ -181,7 +231,7 @@ impl RpcP2p for P2p {
 
         let msg = RpcMessage::ProviderRequest {
             key: ProviderRequestKey::Dht(req.key.into()),
-            response_channel: s,
+            response_channel: ResponseChannel::Peers(s),
             limit: DEFAULT_PROVIDER_LIMIT,
         };
 
@@ -488,10 +538,14 @@ impl RpcP2p for P2p {
     }
 }
 
-pub async fn new(addr: P2pServerAddr, sender: Sender<RpcMessage>) -> Result<()> {
+pub async fn new(
+    addr: P2pServerAddr,
+    sender: Sender<RpcMessage>,
+    keepalive: Option<Duration>,
+) -> Result<()> {
     let p2p = P2p { sender };
 
-    iroh_rpc_types::p2p::serve(addr, p2p).await
+    iroh_rpc_types::p2p::serve_with_keepalive(addr, p2p, keepalive).await
 }
 
 fn peer_info_from_identify_info(i: IdentifyInfo) -> PeerInfo {
@@ -529,6 +583,16 @@ pub enum ProviderRequestKey {
     Bitswap(u64, Cid),
 }
 
+/// Peers and substreams of a single protocol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProtocolStats {
+    pub protocol: String,
+    /// Connected peers that advertised the protocol via identify.
+    pub peers: usize,
+    /// Open substreams, only tracked for bitswap.
+    pub substreams: Option<usize>,
+}
+
 /// Rpc specific messages handled by the p2p node
 #[derive(Debug)]
 pub enum RpcMessage {
@@ -548,9 +612,11 @@ pub enum RpcMessage {
         ctx: u64,
         response_channel: oneshot::Sender<Result<()>>,
     },
+    BitswapWantlist(oneshot::Sender<Result<Vec<BitswapWantlistEntry>>>),
+    ProtocolStats(oneshot::Sender<Vec<ProtocolStats>>),
     ProviderRequest {
         key: ProviderRequestKey,
-        response_channel: Sender<Result<HashSet<PeerId>, String>>,
+        response_channel: ResponseChannel,
         limit: usize,
     },
     StartProviding(oneshot::Sender<Result<libp2p::kad::QueryId>>, Key),
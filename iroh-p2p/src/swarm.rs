@@ -1,32 +1,185 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::Arc;
 use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
+use futures::future;
+use iroh_metrics::{core::MRecorder, inc, p2p::P2PMetrics};
 use iroh_rpc_client::Client;
 use libp2p::{
     core::{
         self,
+        multiaddr::Protocol,
         muxing::StreamMuxerBox,
         transport::{timeout::TransportTimeout, Boxed, OrTransport},
+        upgrade::NegotiationError,
     },
     dns,
     identity::Keypair,
     mplex, noise,
     swarm::{ConnectionLimits, SwarmBuilder},
     yamux::{self, WindowUpdateMode},
-    PeerId, Swarm, Transport,
+    Multiaddr, PeerId, Swarm, Transport,
 };
 
-use crate::{behaviour::NodeBehaviour, Libp2pConfig};
+use crate::{
+    behaviour::NodeBehaviour,
+    config::{IpNet, Muxer, SecurityProtocol},
+    Libp2pConfig,
+};
+
+/// The configured allow and deny lists, enforced on every connection before it reaches the
+/// swarm.
+#[derive(Debug, Default)]
+struct PeerPolicy {
+    allowed_peers: HashSet<PeerId>,
+    denied_peers: HashSet<PeerId>,
+    allowed_ips: Vec<IpNet>,
+    denied_ips: Vec<IpNet>,
+}
+
+/// A connection refused by the [`PeerPolicy`].
+#[derive(Debug)]
+pub(crate) struct PeerRejected(String);
+
+impl fmt::Display for PeerRejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rejected by peer policy: {}", self.0)
+    }
+}
+
+impl std::error::Error for PeerRejected {}
+
+impl PeerPolicy {
+    fn new(config: &Libp2pConfig) -> Self {
+        PeerPolicy {
+            allowed_peers: config.allowed_peers.iter().copied().collect(),
+            denied_peers: config.denied_peers.iter().copied().collect(),
+            allowed_ips: config.allowed_ips.clone(),
+            denied_ips: config.denied_ips.clone(),
+        }
+    }
+
+    fn check_peer(&self, peer_id: &PeerId) -> Result<(), PeerRejected> {
+        let allowed = self.allowed_peers.is_empty() || self.allowed_peers.contains(peer_id);
+        if !allowed || self.denied_peers.contains(peer_id) {
+            inc!(P2PMetrics::PeersRejectedByPolicy);
+            return Err(PeerRejected(peer_id.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Checks the ip the connection runs over, addresses without one are not restricted.
+    fn check_addr(&self, addr: &Multiaddr) -> Result<(), PeerRejected> {
+        let ip = addr.iter().find_map(|p| match p {
+            Protocol::Ip4(ip) => Some(IpAddr::V4(ip)),
+            Protocol::Ip6(ip) => Some(IpAddr::V6(ip)),
+            _ => None,
+        });
+        if let Some(ip) = ip {
+            let allowed =
+                self.allowed_ips.is_empty() || self.allowed_ips.iter().any(|n| n.contains(&ip));
+            if !allowed || self.denied_ips.iter().any(|n| n.contains(&ip)) {
+                inc!(P2PMetrics::PeersRejectedByPolicy);
+                return Err(PeerRejected(addr.to_string()));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Upgrades the given transport with authentication and multiplexing, restricted to the
+/// configured set of muxers.
+///
+/// Listing a single muxer means peers that only speak other multiplexers fail the
+/// negotiation and the connection is refused.
+macro_rules! upgrade_transport {
+    ($transport:expr, $auth_config:expr, $config:expr, $timeout:expr) => {{
+        let mut mplex_config = mplex::MplexConfig::new();
+        mplex_config.set_max_buffer_size(usize::MAX);
+
+        let mut yamux_config = yamux::YamuxConfig::default();
+        yamux_config.set_max_buffer_size(16 * 1024 * 1024); // TODO: configurable
+        yamux_config.set_receive_window_size(16 * 1024 * 1024); // TODO: configurable
+        yamux_config.set_window_update_mode(WindowUpdateMode::on_receive());
+
+        let upgraded = $transport
+            .upgrade(core::upgrade::Version::V1Lazy)
+            .authenticate($auth_config);
+        match $config.muxers.as_slice() {
+            [Muxer::Yamux] => upgraded.multiplex(yamux_config).timeout($timeout).boxed(),
+            [Muxer::Mplex] => upgraded.multiplex(mplex_config).timeout($timeout).boxed(),
+            [Muxer::Mplex, Muxer::Yamux] => upgraded
+                .multiplex(core::upgrade::SelectUpgrade::new(
+                    mplex_config,
+                    yamux_config,
+                ))
+                .timeout($timeout)
+                .boxed(),
+            _ => upgraded
+                .multiplex(core::upgrade::SelectUpgrade::new(
+                    yamux_config,
+                    mplex_config,
+                ))
+                .timeout($timeout)
+                .boxed(),
+        }
+    }};
+}
+
+fn validate_protocols(config: &Libp2pConfig) -> Result<()> {
+    if !config.security_protocols.contains(&SecurityProtocol::Noise) {
+        bail!("no supported security protocol configured, noise must be allowed");
+    }
+    if config.muxers.is_empty() {
+        bail!("at least one muxer must be allowed");
+    }
+    let mut seen = Vec::with_capacity(config.muxers.len());
+    for muxer in &config.muxers {
+        if seen.contains(muxer) {
+            bail!("muxer {} listed more than once", muxer.as_str());
+        }
+        seen.push(*muxer);
+    }
+    Ok(())
+}
+
+/// Returns true if the given error was caused by a failed protocol negotiation, eg. because
+/// the remote only offered security protocols or muxers that we do not allow.
+pub(crate) fn is_negotiation_error(err: &(dyn std::error::Error + 'static)) -> bool {
+    let mut current = Some(err);
+    while let Some(err) = current {
+        if err.downcast_ref::<NegotiationError>().is_some() {
+            return true;
+        }
+        // `io::Error` skips its wrapped error when reporting the source, so look inside.
+        if let Some(inner) = err
+            .downcast_ref::<std::io::Error>()
+            .and_then(|err| err.get_ref())
+        {
+            if is_negotiation_error(inner) {
+                return true;
+            }
+        }
+        current = err.source();
+    }
+    false
+}
 
 /// Builds the transport stack that LibP2P will communicate over.
 async fn build_transport(
     keypair: &Keypair,
     config: &Libp2pConfig,
-) -> (
+) -> Result<(
     Boxed<(PeerId, StreamMuxerBox)>,
     Option<libp2p::relay::v2::client::Client>,
-) {
+)> {
     // TODO: make transports configurable
+    validate_protocols(config)?;
+
+    let policy = Arc::new(PeerPolicy::new(config));
 
     let tcp_config = libp2p::tcp::GenTcpConfig::default().port_reuse(true);
     let transport = libp2p::tcp::TokioTcpTransport::new(tcp_config.clone());
@@ -36,9 +189,18 @@ async fn build_transport(
 
     // TODO: configurable
     let transport = TransportTimeout::new(transport, Duration::from_secs(10));
+    // refuse direct connections from or to disallowed addresses before any handshake
+    let ip_policy = policy.clone();
+    let transport = transport.and_then(move |stream, endpoint| {
+        future::ready(
+            ip_policy
+                .check_addr(endpoint.get_remote_address())
+                .map(|_| stream),
+        )
+    });
     let dns_cfg = dns::ResolverConfig::cloudflare();
     let dns_opts = dns::ResolverOpts::default();
-    let transport = dns::TokioDnsConfig::custom(transport, dns_cfg, dns_opts).unwrap();
+    let transport = dns::TokioDnsConfig::custom(transport, dns_cfg, dns_opts)?;
 
     let auth_config = {
         let dh_keys = noise::Keypair::<noise::X25519Spec>::new()
@@ -48,17 +210,6 @@ async fn build_transport(
         noise::NoiseConfig::xx(dh_keys).into_authenticated()
     };
 
-    let muxer_config = {
-        let mut mplex_config = mplex::MplexConfig::new();
-        mplex_config.set_max_buffer_size(usize::MAX);
-
-        let mut yamux_config = yamux::YamuxConfig::default();
-        yamux_config.set_max_buffer_size(16 * 1024 * 1024); // TODO: configurable
-        yamux_config.set_receive_window_size(16 * 1024 * 1024); // TODO: configurable
-        yamux_config.set_window_update_mode(WindowUpdateMode::on_receive());
-        core::upgrade::SelectUpgrade::new(yamux_config, mplex_config)
-    };
-
     // TODO: configurable
     let connection_timeout = Duration::from_secs(30);
     if config.relay_client {
@@ -68,26 +219,28 @@ async fn build_transport(
             );
 
         let transport = OrTransport::new(relay_transport, transport);
-        let transport = transport
-            .upgrade(core::upgrade::Version::V1Lazy)
-            .authenticate(auth_config)
-            .multiplex(muxer_config)
-            .timeout(connection_timeout)
-            .boxed();
+        let transport = upgrade_transport!(transport, auth_config, config, connection_timeout);
 
-        (transport, Some(relay_client))
+        Ok((with_peer_policy(transport, policy), Some(relay_client)))
     } else {
-        let transport = transport
-            .upgrade(core::upgrade::Version::V1Lazy)
-            .authenticate(auth_config)
-            .multiplex(muxer_config)
-            .timeout(connection_timeout)
-            .boxed();
+        let transport = upgrade_transport!(transport, auth_config, config, connection_timeout);
 
-        (transport, None)
+        Ok((with_peer_policy(transport, policy), None))
     }
 }
 
+/// Refuses connections with disallowed peers, as soon as their peer id is authenticated.
+fn with_peer_policy(
+    transport: Boxed<(PeerId, StreamMuxerBox)>,
+    policy: Arc<PeerPolicy>,
+) -> Boxed<(PeerId, StreamMuxerBox)> {
+    transport
+        .and_then(move |(peer_id, muxer), _| {
+            future::ready(policy.check_peer(&peer_id).map(|_| (peer_id, muxer)))
+        })
+        .boxed()
+}
+
 pub(crate) async fn build_swarm(
     config: &Libp2pConfig,
     keypair: &Keypair,
@@ -95,7 +248,7 @@ pub(crate) async fn build_swarm(
 ) -> Result<Swarm<NodeBehaviour>> {
     let peer_id = keypair.public().to_peer_id();
 
-    let (transport, relay_client) = build_transport(keypair, config).await;
+    let (transport, relay_client) = build_transport(keypair, config).await?;
     let behaviour = NodeBehaviour::new(keypair, config, relay_client, rpc_client).await?;
 
     let limits = ConnectionLimits::default()
@@ -116,3 +269,182 @@ pub(crate) async fn build_swarm(
 
     Ok(swarm)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use libp2p::ping;
+    use libp2p::swarm::SwarmEvent;
+    use libp2p::Multiaddr;
+
+    async fn ping_swarm(config: &Libp2pConfig) -> Result<Swarm<ping::Behaviour>> {
+        ping_swarm_with_keypair(config, Keypair::generate_ed25519()).await
+    }
+
+    async fn ping_swarm_with_keypair(
+        config: &Libp2pConfig,
+        keypair: Keypair,
+    ) -> Result<Swarm<ping::Behaviour>> {
+        let peer_id = keypair.public().to_peer_id();
+        let (transport, _) = build_transport(&keypair, config).await?;
+        let swarm = SwarmBuilder::new(transport, ping::Behaviour::default(), peer_id)
+            .executor(Box::new(|fut| {
+                tokio::task::spawn(fut);
+            }))
+            .build();
+        Ok(swarm)
+    }
+
+    #[test]
+    fn test_validate_protocols() {
+        let mut config = Libp2pConfig::default();
+        assert!(validate_protocols(&config).is_ok());
+
+        config.muxers = vec![];
+        assert!(validate_protocols(&config).is_err());
+
+        config.muxers = vec![Muxer::Yamux, Muxer::Yamux];
+        assert!(validate_protocols(&config).is_err());
+
+        config.muxers = vec![Muxer::Mplex];
+        config.security_protocols = vec![];
+        assert!(validate_protocols(&config).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_disallowed_muxer_is_refused() -> Result<()> {
+        let listener_config = Libp2pConfig {
+            relay_client: false,
+            muxers: vec![Muxer::Yamux],
+            ..Default::default()
+        };
+        let dialer_config = Libp2pConfig {
+            relay_client: false,
+            muxers: vec![Muxer::Mplex],
+            ..Default::default()
+        };
+
+        let mut listener = ping_swarm(&listener_config).await?;
+        let mut dialer = ping_swarm(&dialer_config).await?;
+
+        listener.listen_on("/ip4/127.0.0.1/tcp/0".parse()?)?;
+        let addr: Multiaddr = loop {
+            if let Some(SwarmEvent::NewListenAddr { address, .. }) = listener.next().await {
+                break address;
+            }
+        };
+
+        dialer.dial(addr)?;
+        tokio::task::spawn(async move { while dialer.next().await.is_some() {} });
+
+        let res = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                match listener.next().await {
+                    Some(SwarmEvent::ConnectionEstablished { .. }) => {
+                        panic!("connection with a disallowed muxer must not be established");
+                    }
+                    Some(SwarmEvent::IncomingConnectionError { error, .. }) => {
+                        return is_negotiation_error(&error);
+                    }
+                    _ => {}
+                }
+            }
+        })
+        .await?;
+        assert!(res, "expected a negotiation error");
+
+        Ok(())
+    }
+
+    /// Dials a listener with the given config, returns whether the listener accepted the
+    /// connection.
+    async fn listener_accepts(listener_config: &Libp2pConfig, dialer: Keypair) -> Result<bool> {
+        let dialer_config = Libp2pConfig {
+            relay_client: false,
+            ..Default::default()
+        };
+        let mut listener = ping_swarm(listener_config).await?;
+        let mut dialer = ping_swarm_with_keypair(&dialer_config, dialer).await?;
+
+        listener.listen_on("/ip4/127.0.0.1/tcp/0".parse()?)?;
+        let addr: Multiaddr = loop {
+            if let Some(SwarmEvent::NewListenAddr { address, .. }) = listener.next().await {
+                break address;
+            }
+        };
+
+        dialer.dial(addr)?;
+        tokio::task::spawn(async move { while dialer.next().await.is_some() {} });
+
+        let accepted = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                match listener.next().await {
+                    Some(SwarmEvent::ConnectionEstablished { .. }) => return true,
+                    Some(SwarmEvent::IncomingConnectionError { error, .. }) => {
+                        // the rejection is wrapped in transport errors that hide their sources
+                        let debug = format!("{:?}", error);
+                        assert!(
+                            debug.contains("PeerRejected"),
+                            "unexpected error: {}",
+                            debug
+                        );
+                        return false;
+                    }
+                    _ => {}
+                }
+            }
+        })
+        .await?;
+        Ok(accepted)
+    }
+
+    #[tokio::test]
+    async fn test_allowed_peers_are_exclusive() -> Result<()> {
+        let allowed = Keypair::generate_ed25519();
+        let config = Libp2pConfig {
+            relay_client: false,
+            allowed_peers: vec![allowed.public().to_peer_id()],
+            ..Default::default()
+        };
+
+        assert!(listener_accepts(&config, allowed).await?);
+        assert!(!listener_accepts(&config, Keypair::generate_ed25519()).await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_denied_peers_are_refused() -> Result<()> {
+        let denied = Keypair::generate_ed25519();
+        let config = Libp2pConfig {
+            relay_client: false,
+            denied_peers: vec![denied.public().to_peer_id()],
+            ..Default::default()
+        };
+
+        assert!(!listener_accepts(&config, denied).await?);
+        assert!(listener_accepts(&config, Keypair::generate_ed25519()).await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_denied_ips_are_refused() -> Result<()> {
+        let config = Libp2pConfig {
+            relay_client: false,
+            denied_ips: vec!["127.0.0.0/8".parse()?],
+            ..Default::default()
+        };
+        assert!(!listener_accepts(&config, Keypair::generate_ed25519()).await?);
+
+        let config = Libp2pConfig {
+            relay_client: false,
+            allowed_ips: vec!["10.0.0.0/8".parse()?],
+            ..Default::default()
+        };
+        assert!(!listener_accepts(&config, Keypair::generate_ed25519()).await?);
+
+        Ok(())
+    }
+}
@@ -1,6 +1,7 @@
 use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
 use futures::TryStreamExt;
 use iroh_metrics::config::Config as MetricsConfig;
+use iroh_resolver::chunker::Chunker;
 use iroh_rpc_client::Client;
 use iroh_rpc_client::Config as RpcClientConfig;
 use iroh_rpc_types::Addr;
@@ -38,11 +39,16 @@ pub fn add_benchmark(c: &mut Criterion) {
                     path: dir.path().join("db"),
                     rpc_client: rpc_client.clone(),
                     metrics: MetricsConfig::default(),
+                    min_free_space: None,
+                    write_batch_size: iroh_store::config::DEFAULT_WRITE_BATCH_SIZE,
+                    durability: iroh_store::Durability::default(),
                 };
                 let (_task, rpc) = executor.block_on(async {
                     let store = Store::create(config).await.unwrap();
                     let task = executor.spawn(async move {
-                        iroh_store::rpc::new(server_addr, store).await.unwrap()
+                        iroh_store::rpc::new(server_addr, store, None)
+                            .await
+                            .unwrap()
                     });
                     // wait for a moment until the transport is setup
                     // TODO: signal this more clearly
@@ -53,10 +59,15 @@ pub fn add_benchmark(c: &mut Criterion) {
                 b.to_async(&executor).iter(|| {
                     let rpc = rpc.clone();
                     async move {
-                        let stream =
-                            iroh_resolver::unixfs_builder::add_file(Some(rpc), path, false)
-                                .await
-                                .unwrap();
+                        let stream = iroh_resolver::unixfs_builder::add_file(
+                            Some(rpc),
+                            path,
+                            false,
+                            Chunker::default(),
+                            iroh_resolver::unixfs::DEFAULT_HASH,
+                        )
+                        .await
+                        .unwrap();
 
                         let res: Vec<_> = stream.try_collect().await.unwrap();
                         black_box(res)
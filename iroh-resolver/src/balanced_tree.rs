@@ -3,11 +3,11 @@ use std::collections::VecDeque;
 use anyhow::Result;
 use async_stream::try_stream;
 use bytes::{Bytes, BytesMut};
-use cid::Cid;
+use cid::{multihash::Code, Cid};
 use futures::{Stream, StreamExt, TryFutureExt, TryStreamExt};
 
 use crate::resolver::Block;
-use crate::unixfs::{dag_pb, unixfs_pb, DataType, Node, UnixfsNode};
+use crate::unixfs::{dag_pb, unixfs_pb, DataType, Node, UnixfsNode, DEFAULT_HASH};
 use crate::unixfs_builder::encode_unixfs_pb;
 
 /// Default degree number for balanced tree, taken from unixfs specs
@@ -17,8 +17,8 @@ pub const DEFAULT_DEGREE: usize = 174;
 #[derive(Debug, PartialEq, Eq)]
 pub enum TreeBuilder {
     /// TreeBuilder that builds a "balanced tree" with a max degree size of
-    /// degree
-    Balanced { degree: usize },
+    /// degree, hashing every node with `hash`
+    Balanced { degree: usize, hash: Code },
 }
 
 impl TreeBuilder {
@@ -27,8 +27,12 @@ impl TreeBuilder {
     }
 
     pub fn balanced_tree_with_degree(degree: usize) -> Self {
+        Self::balanced_tree_with_degree_and_hash(degree, DEFAULT_HASH)
+    }
+
+    pub fn balanced_tree_with_degree_and_hash(degree: usize, hash: Code) -> Self {
         assert!(degree > 1);
-        TreeBuilder::Balanced { degree }
+        TreeBuilder::Balanced { degree, hash }
     }
 
     pub fn stream_tree(
@@ -36,7 +40,7 @@ impl TreeBuilder {
         chunks: impl Stream<Item = std::io::Result<BytesMut>>,
     ) -> impl Stream<Item = Result<Block>> {
         match self {
-            TreeBuilder::Balanced { degree } => stream_balanced_tree(chunks, *degree),
+            TreeBuilder::Balanced { degree, hash } => stream_balanced_tree(chunks, *degree, *hash),
         }
     }
 }
@@ -50,6 +54,7 @@ struct LinkInfo {
 fn stream_balanced_tree(
     in_stream: impl Stream<Item = std::io::Result<BytesMut>>,
     degree: usize,
+    hash: Code,
 ) -> impl Stream<Item = Result<Block>> {
     try_stream! {
         // degree = 8
@@ -80,8 +85,8 @@ fn stream_balanced_tree(
         let hash_par: usize = 8;
 
         let in_stream = in_stream.err_into::<anyhow::Error>().map(|chunk| {
-            tokio::task::spawn_blocking(|| {
-                chunk.and_then(|chunk| TreeNode::Leaf(chunk.freeze()).encode())
+            tokio::task::spawn_blocking(move || {
+                chunk.and_then(|chunk| TreeNode::Leaf(chunk.freeze()).encode(hash))
             }).err_into::<anyhow::Error>()
         }).buffered(hash_par).map(|x| x.and_then(|x| x));
 
@@ -108,7 +113,7 @@ fn stream_balanced_tree(
 
                     // create node, keeping the cid
                     let links = std::mem::replace(&mut tree[i], Vec::with_capacity(degree));
-                    let (block, link_info) = TreeNode::Stem(links).encode()?;
+                    let (block, link_info) = TreeNode::Stem(links).encode(hash)?;
                     let cid = *block.cid();
                     yield block;
 
@@ -137,7 +142,7 @@ fn stream_balanced_tree(
         // since all the stem nodes are able to recieve links
         // we don't have to worry about "overflow"
         while let Some(links) = tree.pop_front() {
-            let (block, link_info) = TreeNode::Stem(links).encode()?;
+            let (block, link_info) = TreeNode::Stem(links).encode(hash)?;
             let cid = *block.cid();
             yield block;
 
@@ -197,12 +202,12 @@ enum TreeNode {
 }
 
 impl TreeNode {
-    fn encode(self) -> Result<(Block, LinkInfo)> {
+    fn encode(self, hash: Code) -> Result<(Block, LinkInfo)> {
         match self {
             TreeNode::Leaf(bytes) => {
                 let len = bytes.len();
                 let node = UnixfsNode::Raw(bytes);
-                let block = node.encode()?;
+                let block = node.encode_with_hash(hash)?;
                 let link_info = LinkInfo {
                     // in a leaf the raw data len and encoded len are the same since our leaf
                     // nodes are raw unixfs nodes
@@ -214,7 +219,7 @@ impl TreeNode {
             TreeNode::Stem(links) => {
                 let mut encoded_len: u64 = links.iter().map(|(_, l)| l.encoded_len).sum();
                 let node = create_unixfs_node_from_links(links)?;
-                let block = node.encode()?;
+                let block = node.encode_with_hash(hash)?;
                 encoded_len += block.data().len() as u64;
                 let raw_data_len = node
                     .filesize()
@@ -252,7 +257,7 @@ mod tests {
         if num_chunks / degree == 0 {
             let chunk = chunks.next().await.unwrap().unwrap();
             let leaf = TreeNode::Leaf(chunk.freeze());
-            let (block, _) = leaf.encode().unwrap();
+            let (block, _) = leaf.encode(DEFAULT_HASH).unwrap();
             tree[0].push(block);
             return tree;
         }
@@ -260,7 +265,7 @@ mod tests {
         while let Some(chunk) = chunks.next().await {
             let chunk = chunk.unwrap();
             let leaf = TreeNode::Leaf(chunk.freeze());
-            let (block, link_info) = leaf.encode().unwrap();
+            let (block, link_info) = leaf.encode(DEFAULT_HASH).unwrap();
             links[0].push((*block.cid(), link_info));
             tree[0].push(block);
         }
@@ -272,7 +277,7 @@ mod tests {
             let mut links_layer = Vec::with_capacity(count);
             for links in prev_layer.chunks(degree) {
                 let stem = TreeNode::Stem(links.to_vec());
-                let (block, link_info) = stem.encode().unwrap();
+                let (block, link_info) = stem.encode(DEFAULT_HASH).unwrap();
                 links_layer.push((*block.cid(), link_info));
                 tree_layer.push(block);
             }
@@ -336,12 +341,12 @@ mod tests {
 
     fn make_leaf(data: usize) -> (Block, LinkInfo) {
         TreeNode::Leaf(BytesMut::from(&data.to_be_bytes()[..]).freeze())
-            .encode()
+            .encode(DEFAULT_HASH)
             .unwrap()
     }
 
     fn make_stem(links: Vec<(Cid, LinkInfo)>) -> (Block, LinkInfo) {
-        TreeNode::Stem(links).encode().unwrap()
+        TreeNode::Stem(links).encode(DEFAULT_HASH).unwrap()
     }
 
     #[tokio::test]
@@ -449,7 +454,7 @@ mod tests {
     async fn balanced_tree_test_leaf() {
         let num_chunks = 1;
         let expect = build_expect(num_chunks, 3).await;
-        let got = stream_balanced_tree(test_chunk_stream(1), 3);
+        let got = stream_balanced_tree(test_chunk_stream(1), 3, DEFAULT_HASH);
         tokio::pin!(got);
         ensure_equal(expect, got, num_chunks as u64 * CHUNK_SIZE).await;
     }
@@ -459,7 +464,7 @@ mod tests {
         let num_chunks = 3;
         let degrees = 3;
         let expect = build_expect(num_chunks, degrees).await;
-        let got = stream_balanced_tree(test_chunk_stream(num_chunks), degrees);
+        let got = stream_balanced_tree(test_chunk_stream(num_chunks), degrees, DEFAULT_HASH);
         tokio::pin!(got);
         ensure_equal(expect, got, num_chunks as u64 * CHUNK_SIZE).await;
     }
@@ -469,7 +474,7 @@ mod tests {
         let degrees = 3;
         let num_chunks = 9;
         let expect = build_expect(num_chunks, degrees).await;
-        let got = stream_balanced_tree(test_chunk_stream(num_chunks), degrees);
+        let got = stream_balanced_tree(test_chunk_stream(num_chunks), degrees, DEFAULT_HASH);
         tokio::pin!(got);
         ensure_equal(expect, got, num_chunks as u64 * CHUNK_SIZE).await;
     }
@@ -479,7 +484,7 @@ mod tests {
         let degrees = 3;
         let num_chunks = 10;
         let expect = build_expect(num_chunks, degrees).await;
-        let got = stream_balanced_tree(test_chunk_stream(num_chunks), degrees);
+        let got = stream_balanced_tree(test_chunk_stream(num_chunks), degrees, DEFAULT_HASH);
         tokio::pin!(got);
         ensure_equal(expect, got, num_chunks as u64 * CHUNK_SIZE).await;
     }
@@ -489,7 +494,7 @@ mod tests {
         let num_chunks = 125;
         let degrees = 5;
         let expect = build_expect(num_chunks, degrees).await;
-        let got = stream_balanced_tree(test_chunk_stream(num_chunks), degrees);
+        let got = stream_balanced_tree(test_chunk_stream(num_chunks), degrees, DEFAULT_HASH);
         tokio::pin!(got);
         ensure_equal(expect, got, num_chunks as u64 * CHUNK_SIZE).await;
     }
@@ -499,7 +504,7 @@ mod tests {
         let num_chunks = 780;
         let degrees = 11;
         let expect = build_expect(num_chunks, degrees).await;
-        let got = stream_balanced_tree(test_chunk_stream(num_chunks), degrees);
+        let got = stream_balanced_tree(test_chunk_stream(num_chunks), degrees, DEFAULT_HASH);
         tokio::pin!(got);
         ensure_equal(expect, got, num_chunks as u64 * CHUNK_SIZE).await;
     }
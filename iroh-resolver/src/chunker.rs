@@ -1,19 +1,79 @@
-use std::io;
+use std::{io, str::FromStr};
 
+use anyhow::{anyhow, ensure};
 use bytes::BytesMut;
-use futures::Stream;
+use futures::{future::Either, Stream};
 use tokio::io::{AsyncRead, AsyncReadExt};
 
-/// Default size for chunks.
+mod rabin;
+
+pub use self::rabin::Rabin;
+
+/// Default size for chunks, the same as go-ipfs uses.
 pub const DEFAULT_CHUNKS_SIZE: usize = 1024 * 256;
 
 /// Chunks are limited to 1MiB by default
 pub const DEFAULT_CHUNK_SIZE_LIMIT: usize = 1024 * 1024;
 
+/// How content is split into leaves when importing it.
+///
+/// Together with the tree layout this determines the resulting cid, so the same content
+/// imported with the same chunker always yields the same cid. The default, fixed size chunks of
+/// [`DEFAULT_CHUNKS_SIZE`], matches the go-ipfs default (`size-262144`).
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Chunker {
     /// Chunker that splits the given content
     FixedSize { chunk_size: usize },
+    /// Content defined chunker, see [`Rabin`].
+    Rabin(Rabin),
+}
+
+impl Default for Chunker {
+    fn default() -> Self {
+        Self::fixed_size()
+    }
+}
+
+/// Parses the go-ipfs `--chunker` syntax: `size-<size>`, `rabin`, `rabin-<avg>` or
+/// `rabin-<min>-<avg>-<max>`.
+impl FromStr for Chunker {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |size: &str| {
+            size.parse::<usize>()
+                .map_err(|_| anyhow!("invalid chunk size {:?} in chunker {:?}", size, s))
+        };
+        let parts: Vec<&str> = s.split('-').collect();
+        let chunker = match parts[..] {
+            ["size", size] => {
+                let size = parse(size)?;
+                ensure!(size > 0, "chunk size must be positive");
+                Chunker::fixed_with_size(size)
+            }
+            ["rabin"] => Chunker::Rabin(Rabin::with_avg_size(DEFAULT_CHUNKS_SIZE)),
+            ["rabin", avg] => {
+                let avg = parse(avg)?;
+                ensure!(avg >= 3, "average chunk size must be at least 3");
+                Chunker::Rabin(Rabin::with_avg_size(avg))
+            }
+            ["rabin", min, avg, max] => {
+                let (min, avg, max) = (parse(min)?, parse(avg)?, parse(max)?);
+                ensure!(
+                    0 < min && min <= avg && avg <= max,
+                    "rabin chunk sizes must satisfy 0 < min <= avg <= max"
+                );
+                Chunker::Rabin(Rabin::new(min, avg, max))
+            }
+            _ => anyhow::bail!("unknown chunker {:?}", s),
+        };
+        ensure!(
+            chunker.max_chunk_size() <= DEFAULT_CHUNK_SIZE_LIMIT,
+            "chunks must not be larger than {} bytes",
+            DEFAULT_CHUNK_SIZE_LIMIT
+        );
+        Ok(chunker)
+    }
 }
 
 impl Chunker {
@@ -26,6 +86,14 @@ impl Chunker {
         Chunker::FixedSize { chunk_size: size }
     }
 
+    /// Upper bound for the size of the emitted chunks.
+    pub fn max_chunk_size(&self) -> usize {
+        match self {
+            Chunker::FixedSize { chunk_size } => *chunk_size,
+            Chunker::Rabin(rabin) => rabin.max_size(),
+        }
+    }
+
     pub fn chunks<'a, R: AsyncRead + Unpin + 'a>(
         &self,
         mut source: R,
@@ -33,7 +101,7 @@ impl Chunker {
         match self {
             Chunker::FixedSize { chunk_size } => {
                 let chunk_size = *chunk_size;
-                async_stream::stream! {
+                Either::Left(async_stream::stream! {
                     let mut buffer = BytesMut::with_capacity(chunk_size);
                     let mut current_len = 0;
 
@@ -70,8 +138,9 @@ impl Chunker {
                             }
                         }
                     }
-                }
+                })
             }
+            Chunker::Rabin(rabin) => Either::Right(rabin.chunks(source)),
         }
     }
 }
@@ -123,4 +192,63 @@ mod tests {
             assert_eq!(&chunks[4], &[5u8; 2][..]);
         }
     }
+
+    #[tokio::test]
+    async fn test_rabin_chunker() {
+        use rand::prelude::*;
+        use rand_chacha::ChaCha8Rng;
+
+        let mut rng = ChaCha8Rng::from_seed([0; 32]);
+        let mut content = vec![0u8; 1024 * 64];
+        rng.fill_bytes(&mut content);
+
+        let chunker: Chunker = "rabin-512-1024-2048".parse().unwrap();
+        let chunks: Vec<_> = chunker
+            .chunks(std::io::Cursor::new(content.clone()))
+            .try_collect()
+            .await
+            .unwrap();
+        assert!(chunks.len() > 1);
+        for chunk in &chunks[..chunks.len() - 1] {
+            assert!(chunk.len() >= 512 && chunk.len() <= 2048);
+        }
+        assert_eq!(chunks.concat(), content);
+
+        // boundaries depend on the content, so changing the start only changes the chunks
+        // around it
+        let mut shifted = vec![42u8; 7];
+        shifted.extend_from_slice(&content);
+        let shifted_chunks: Vec<_> = chunker
+            .chunks(std::io::Cursor::new(shifted))
+            .try_collect()
+            .await
+            .unwrap();
+        let unchanged = chunks.iter().filter(|c| shifted_chunks.contains(c)).count();
+        assert!(unchanged >= chunks.len() - 3);
+        assert_eq!(shifted_chunks.last(), chunks.last());
+    }
+
+    #[test]
+    fn test_parse_chunker() {
+        assert_eq!(
+            "size-1024".parse::<Chunker>().unwrap(),
+            Chunker::fixed_with_size(1024)
+        );
+        assert_eq!(
+            "rabin".parse::<Chunker>().unwrap(),
+            Chunker::Rabin(Rabin::with_avg_size(DEFAULT_CHUNKS_SIZE))
+        );
+        assert_eq!(
+            "rabin-3000".parse::<Chunker>().unwrap(),
+            Chunker::Rabin(Rabin::new(1000, 3000, 4500))
+        );
+        assert_eq!(
+            "rabin-10-20-30".parse::<Chunker>().unwrap(),
+            Chunker::Rabin(Rabin::new(10, 20, 30))
+        );
+        assert!("size-0".parse::<Chunker>().is_err());
+        assert!("size-2000000".parse::<Chunker>().is_err());
+        assert!("rabin-30-20-10".parse::<Chunker>().is_err());
+        assert!("buzhash".parse::<Chunker>().is_err());
+    }
 }
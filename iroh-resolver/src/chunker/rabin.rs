@@ -0,0 +1,203 @@
+//! Content defined chunking, based on a rolling Rabin fingerprint.
+//!
+//! The fingerprint is computed over a sliding window of the last [`WINDOW_SIZE`] bytes, modulo
+//! an irreducible polynomial, and a chunk ends wherever its low bits are all zero. Boundaries
+//! therefore only depend on the surrounding content, so an insertion only changes the chunks
+//! around it.
+//!
+//! The polynomial and the size bounds are the ones of go-ipfs, but the boundaries are not
+//! checked against it, so content chunked this way may get other cids than in go-ipfs.
+
+use std::io;
+
+use bytes::BytesMut;
+use futures::Stream;
+use once_cell::sync::Lazy;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Polynomial of degree 53 used by go-ipfs for its rabin chunker.
+const POLYNOMIAL: u64 = 17437180132763653;
+
+/// Number of bytes the fingerprint is computed over.
+const WINDOW_SIZE: usize = 16;
+
+static TABLES: Lazy<Tables> = Lazy::new(|| Tables::new(POLYNOMIAL));
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rabin {
+    min_size: usize,
+    avg_size: usize,
+    max_size: usize,
+}
+
+impl Rabin {
+    /// go-ipfs derives the bounds from the average size, when only that is given.
+    pub fn with_avg_size(avg_size: usize) -> Self {
+        Self::new(avg_size / 3, avg_size, avg_size + avg_size / 2)
+    }
+
+    pub fn new(min_size: usize, avg_size: usize, max_size: usize) -> Self {
+        assert!(0 < min_size && min_size <= avg_size && avg_size <= max_size);
+        Rabin {
+            min_size,
+            avg_size,
+            max_size,
+        }
+    }
+
+    pub fn min_size(&self) -> usize {
+        self.min_size
+    }
+
+    pub fn avg_size(&self) -> usize {
+        self.avg_size
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    pub fn chunks<'a, R: AsyncRead + Unpin + 'a>(
+        &self,
+        mut source: R,
+    ) -> impl Stream<Item = io::Result<BytesMut>> + 'a {
+        let mut hasher = Hasher::new(self);
+        let max_size = self.max_size;
+        async_stream::stream! {
+            let mut buffer = BytesMut::with_capacity(max_size);
+            // bytes at the start of `buffer` that have already been fed to the hasher
+            let mut scanned = 0;
+
+            loop {
+                if let Some(len) = hasher.next_boundary(&buffer[scanned..]) {
+                    let chunk = buffer.split_to(scanned + len);
+                    scanned = 0;
+                    hasher.reset();
+                    yield Ok(chunk);
+                    continue;
+                }
+                scanned = buffer.len();
+
+                buffer.reserve(max_size);
+                match source.read_buf(&mut buffer).await {
+                    Ok(0) => {
+                        // finished reading
+                        if !buffer.is_empty() {
+                            yield Ok(buffer.split());
+                        }
+                        break;
+                    }
+                    Ok(_) => {}
+                    Err(err) => {
+                        yield Err(err);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Precomputed reductions for the polynomial.
+struct Tables {
+    /// Contribution of a byte leaving the window.
+    out: [u64; 256],
+    /// Reduction of the bits shifted past the degree of the polynomial.
+    reduce: [u64; 256],
+    degree: u32,
+}
+
+impl Tables {
+    fn new(polynomial: u64) -> Self {
+        let degree = deg(polynomial) as u32;
+        let mut out = [0; 256];
+        let mut reduce = [0; 256];
+        for b in 0..256u64 {
+            let mut hash = modulo(b, polynomial);
+            for _ in 0..WINDOW_SIZE - 1 {
+                hash = modulo(hash << 8, polynomial);
+            }
+            out[b as usize] = hash;
+            reduce[b as usize] = modulo(b << degree, polynomial) | (b << degree);
+        }
+        Tables {
+            out,
+            reduce,
+            degree,
+        }
+    }
+}
+
+struct Hasher {
+    tables: &'static Tables,
+    window: [u8; WINDOW_SIZE],
+    pos: usize,
+    digest: u64,
+    count: usize,
+    min_size: usize,
+    max_size: usize,
+    mask: u64,
+}
+
+impl Hasher {
+    fn new(config: &Rabin) -> Self {
+        let bits = usize::BITS - 1 - config.avg_size.leading_zeros();
+        let mut hasher = Hasher {
+            tables: &TABLES,
+            window: [0; WINDOW_SIZE],
+            pos: 0,
+            digest: 0,
+            count: 0,
+            min_size: config.min_size,
+            max_size: config.max_size,
+            mask: (1 << bits) - 1,
+        };
+        hasher.reset();
+        hasher
+    }
+
+    fn reset(&mut self) {
+        self.window = [0; WINDOW_SIZE];
+        self.pos = 0;
+        self.digest = 0;
+        self.count = 0;
+        self.slide(1);
+    }
+
+    fn slide(&mut self, b: u8) {
+        let out = std::mem::replace(&mut self.window[self.pos], b);
+        self.pos = (self.pos + 1) % WINDOW_SIZE;
+        self.digest ^= self.tables.out[out as usize];
+
+        let index = (self.digest >> (self.tables.degree - 8)) as usize;
+        self.digest = ((self.digest << 8) | b as u64) ^ self.tables.reduce[index];
+    }
+
+    /// Feeds `data` to the hasher, returning the length of the prefix that completes the
+    /// current chunk, if any.
+    fn next_boundary(&mut self, data: &[u8]) -> Option<usize> {
+        for (i, b) in data.iter().enumerate() {
+            self.slide(*b);
+            self.count += 1;
+            if self.count >= self.max_size
+                || (self.count >= self.min_size && self.digest & self.mask == 0)
+            {
+                return Some(i + 1);
+            }
+        }
+        None
+    }
+}
+
+/// Degree of a polynomial over GF(2), -1 for the zero polynomial.
+fn deg(x: u64) -> i32 {
+    63 - x.leading_zeros() as i32
+}
+
+fn modulo(mut x: u64, polynomial: u64) -> u64 {
+    let degree = deg(polynomial);
+    while deg(x) >= degree {
+        x ^= polynomial << (deg(x) - degree);
+    }
+    x
+}
@@ -1,5 +1,6 @@
 use crate::resolver::{
-    parse_links, ContentLoader, ContextId, LoadedCid, LoaderContext, Source, IROH_STORE,
+    fetch_bitswap, load_from_stores, parse_links, BlockNotFound, ContentLoader, ContextId,
+    LoadedCid, LoaderContext, Source,
 };
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
@@ -37,10 +38,9 @@ impl RacingLoader {
         }
     }
 
-    async fn fetch_p2p(&self, ctx: ContextId, cid: &Cid) -> Result<Bytes, anyhow::Error> {
+    async fn fetch_p2p(&self, ctx: &LoaderContext, cid: &Cid) -> Result<Bytes, anyhow::Error> {
         let p2p = self.rpc_client.try_p2p()?;
-        p2p.fetch_bitswap(ctx.into(), *cid, Default::default())
-            .await
+        fetch_bitswap(&p2p, ctx, *cid).await
     }
 
     async fn fetch_http(&self, cid: &Cid) -> Result<(Bytes, String), anyhow::Error> {
@@ -69,21 +69,12 @@ impl ContentLoader for RacingLoader {
         // TODO: better strategy
 
         let cid = *cid;
-        match self.rpc_client.try_store()?.get(cid).await {
-            Ok(Some(data)) => {
-                trace!("retrieved from store");
-                return Ok(LoadedCid {
-                    data,
-                    source: Source::Store(IROH_STORE),
-                });
-            }
-            Ok(None) => {}
-            Err(err) => {
-                warn!("failed to fetch data from store {}: {:?}", cid, err);
-            }
+        if let Some(loaded) = load_from_stores(&self.rpc_client, cid).await? {
+            trace!("retrieved from {:?}", loaded.source);
+            return Ok(loaded);
         }
 
-        let p2p_fut = self.fetch_p2p(ctx.id(), &cid).fuse();
+        let p2p_fut = self.fetch_p2p(ctx, &cid).fuse();
         let http_fut = self.fetch_http(&cid).fuse();
         pin_mut!(p2p_fut, http_fut);
 
@@ -144,7 +135,7 @@ impl ContentLoader for RacingLoader {
                 source,
             })
         } else {
-            Err(anyhow::anyhow!("Failed to load from p2p & http"))
+            Err(anyhow::anyhow!("Failed to load from p2p & http").context(BlockNotFound { cid }))
         }
     }
 
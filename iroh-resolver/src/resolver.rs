@@ -1,11 +1,11 @@
-use std::collections::{BTreeSet, VecDeque};
+use std::collections::{BTreeSet, HashSet, VecDeque};
 use std::fmt::{self, Debug, Display, Formatter};
 use std::pin::Pin;
 use std::str::FromStr;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::task::{Context, Poll};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, bail, Context as _, Result};
 use async_trait::async_trait;
@@ -14,13 +14,14 @@ use cid::multihash::{Code, MultihashDigest};
 use cid::Cid;
 use futures::{Future, Stream, TryStreamExt};
 use iroh_metrics::inc;
-use iroh_rpc_client::Client;
+use iroh_rpc_client::{Client, P2pClient};
 use libipld::codec::Encode;
 use libipld::error::{InvalidMultihash, UnsupportedMultihash};
 use libipld::prelude::Codec as _;
 use libipld::{Ipld, IpldCodec};
+use libp2p::PeerId;
 use tokio::io::{AsyncRead, AsyncSeek};
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, Semaphore, SemaphorePermit};
 use tokio::task::JoinHandle;
 use tracing::{debug, trace, warn};
 
@@ -33,11 +34,162 @@ use iroh_metrics::{
 
 use crate::codecs::Codec;
 use crate::unixfs::{
-    poll_read_buf_at_pos, DataType, Link, UnixfsChildStream, UnixfsContentReader, UnixfsNode,
+    count_pb_links, poll_read_buf_at_pos, DataType, Link, UnixfsChildStream, UnixfsContentReader,
+    UnixfsNode,
 };
 
 pub const IROH_STORE: &str = "iroh-store";
 
+/// Default maximum number of links a single node may have during resolution.
+pub const DEFAULT_MAX_LINKS_PER_NODE: usize = 100_000;
+
+/// Returned when a single node has more links than the resolver allows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LimitExceeded {
+    pub links: usize,
+    pub limit: usize,
+}
+
+impl Display for LimitExceeded {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "node has {} links, exceeding the limit of {}",
+            self.links, self.limit
+        )
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+/// Default maximum depth, counted in links from the root, of recursive resolution.
+pub const DEFAULT_MAX_DEPTH: usize = 4096;
+
+/// Returned when recursive resolution descends deeper than the resolver allows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DepthExceeded {
+    pub limit: usize,
+}
+
+impl Display for DepthExceeded {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "dag is nested deeper than the limit of {}", self.limit)
+    }
+}
+
+impl std::error::Error for DepthExceeded {}
+
+/// Returned when an IPNS or DNSLink name is not resolved because the maximum number of
+/// concurrent resolutions is in flight and no queue slot is left.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NameResolutionsExceeded {
+    pub limit: usize,
+}
+
+impl Display for NameResolutionsExceeded {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "too many concurrent name resolutions, the limit of {} is reached",
+            self.limit
+        )
+    }
+}
+
+impl std::error::Error for NameResolutionsExceeded {}
+
+/// Returned when a segment of the path names no link of the node it is resolved in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkNotFound {
+    pub name: String,
+}
+
+impl Display for LinkNotFound {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "link '{}' not found", self.name)
+    }
+}
+
+impl std::error::Error for LinkNotFound {}
+
+/// Returned when a path continues below a unixfs node that has no links to follow, like a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnexpectedUnixfsType {
+    pub typ: Option<DataType>,
+}
+
+impl Display for UnexpectedUnixfsType {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "unexpected unixfs type {:?}", self.typ)
+    }
+}
+
+impl std::error::Error for UnexpectedUnixfsType {}
+
+/// Returned by a [`ContentLoader`] that doesn't have a block and has no way to fetch it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockNotFound {
+    pub cid: Cid,
+}
+
+impl Display for BlockNotFound {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "block {} not found", self.cid)
+    }
+}
+
+impl std::error::Error for BlockNotFound {}
+
+/// Bounds the number of name resolutions in flight, letting a limited number of excess
+/// resolutions wait for a free slot.
+#[derive(Debug)]
+struct NameResolutionLimiter {
+    permits: Semaphore,
+    limit: usize,
+    max_queued: usize,
+    queued: AtomicUsize,
+}
+
+impl NameResolutionLimiter {
+    fn new(limit: usize, max_queued: usize) -> Self {
+        let limit = limit.max(1);
+        NameResolutionLimiter {
+            permits: Semaphore::new(limit),
+            limit,
+            max_queued,
+            queued: AtomicUsize::new(0),
+        }
+    }
+
+    async fn acquire(&self) -> Result<SemaphorePermit<'_>, NameResolutionsExceeded> {
+        if let Ok(permit) = self.permits.try_acquire() {
+            return Ok(permit);
+        }
+        if self.queued.fetch_add(1, Ordering::SeqCst) >= self.max_queued {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+            inc!(ResolverMetrics::NameResolutionsRejected);
+            return Err(NameResolutionsExceeded { limit: self.limit });
+        }
+        inc!(ResolverMetrics::NameResolutionsQueued);
+        // frees the queue slot also when the waiting resolution is cancelled
+        let _slot = QueueSlot(&self.queued);
+        let permit = self
+            .permits
+            .acquire()
+            .await
+            .expect("name resolution semaphore is never closed");
+        Ok(permit)
+    }
+}
+
+struct QueueSlot<'a>(&'a AtomicUsize);
+
+impl Drop for QueueSlot<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Block {
     cid: Cid,
@@ -262,6 +414,12 @@ impl FromStr for Path {
 pub trait LinksContainer: Sync + Send + std::fmt::Debug + Clone + 'static {
     /// Extract links out of a container struct.
     fn links(&self) -> Result<Vec<Cid>>;
+
+    /// The number of links, if it can be told without extracting them. Checked against the
+    /// link limit before [`LinksContainer::links`] is called.
+    fn links_count_hint(&self) -> Option<usize> {
+        None
+    }
 }
 
 #[async_trait]
@@ -269,6 +427,13 @@ impl LinksContainer for OutRaw {
     fn links(&self) -> Result<Vec<Cid>> {
         parse_links(&self.cid, &self.content)
     }
+
+    fn links_count_hint(&self) -> Option<usize> {
+        if self.cid.codec() != Codec::DagPb as u64 {
+            return None;
+        }
+        count_pb_links(&self.content).ok()
+    }
 }
 
 #[async_trait]
@@ -466,6 +631,14 @@ pub struct Metadata {
     pub source: Source,
 }
 
+impl Metadata {
+    /// A strong ETag for the content, the quoted CID the path resolved to. Holds for mutable
+    /// paths too, as the CID changes along with the content.
+    pub fn etag(&self) -> Option<String> {
+        self.resolved_path.last().map(|cid| format!("\"{}\"", cid))
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum OutType {
     DagPb,
@@ -528,18 +701,48 @@ impl BytesReader {
     }
 }
 
+/// Records the gateway metrics of streaming a response.
 pub struct OutMetrics {
     pub start: Instant,
+    /// When the first byte was served and how many have been since.
+    streamed: Option<(Instant, u64)>,
+    finished: bool,
+    enabled: bool,
 }
 
 impl OutMetrics {
-    pub fn observe_bytes_read(&self, pos: usize, bytes_read: usize) {
+    pub fn new(start: Instant) -> Self {
+        OutMetrics {
+            start,
+            streamed: None,
+            finished: false,
+            enabled: true,
+        }
+    }
+
+    /// Records nothing, for reads that aren't gateway responses, which would skew the
+    /// gateway metrics of processes that also serve some.
+    pub fn disabled() -> Self {
+        OutMetrics {
+            enabled: false,
+            ..Self::new(Instant::now())
+        }
+    }
+
+    pub fn observe_bytes_read(&mut self, pos: usize, bytes_read: usize) {
+        if !self.enabled {
+            return;
+        }
         if pos == 0 && bytes_read > 0 {
             record!(
                 GatewayMetrics::TimeToServeFirstBlock,
                 self.start.elapsed().as_millis() as u64
             );
         }
+        if bytes_read > 0 {
+            let (_, streamed) = self.streamed.get_or_insert_with(|| (Instant::now(), 0));
+            *streamed += bytes_read as u64;
+        }
         if bytes_read == 0 {
             record!(
                 GatewayMetrics::TimeToServeFullFile,
@@ -549,16 +752,47 @@ impl OutMetrics {
                 GatewayHistograms::TimeToServeFullFile,
                 self.start.elapsed().as_millis() as f64
             );
+            // readers are polled again after the end, only the first time counts
+            if !self.finished {
+                self.finished = true;
+                let (first_byte, streamed) = self.streamed.unwrap_or((Instant::now(), 0));
+                observe!(GatewayHistograms::BytesPerRequest, streamed as f64);
+                observe!(
+                    GatewayHistograms::StreamDuration,
+                    first_byte.elapsed().as_millis() as f64
+                );
+            }
         }
         record!(GatewayMetrics::BytesStreamed, bytes_read as u64);
     }
+
+    /// Counts a block loaded while streaming by where it came from, see
+    /// [`record_block_source`].
+    pub fn observe_block_loaded(&self, source: &Source) {
+        if !self.enabled {
+            return;
+        }
+        record_block_source(source);
+    }
+}
+
+/// Adds a loaded block to the gateway block counters by where it came from.
+///
+/// The counters are totals across all requests of the process, not per request.
+pub fn record_block_source(source: &Source) {
+    match source {
+        Source::Store(_) | Source::FallbackStore(_) => {
+            inc!(GatewayMetrics::BlocksFromCache)
+        }
+        Source::Bitswap => inc!(GatewayMetrics::BlocksFromBitswap),
+        // neither fetched from bitswap nor cached
+        Source::Http(_) => {}
+    }
 }
 
 impl Default for OutMetrics {
     fn default() -> Self {
-        Self {
-            start: Instant::now(),
-        }
+        Self::new(Instant::now())
     }
 }
 
@@ -653,19 +887,39 @@ pub enum Source {
     Bitswap,
     Http(String),
     Store(&'static str),
+    /// One of the configured fallback stores, by its position in the chain.
+    FallbackStore(usize),
 }
 
 #[derive(Debug, Clone)]
 pub struct Resolver<T: ContentLoader> {
     loader: T,
     next_id: Arc<AtomicU64>,
+    max_links_per_node: usize,
+    max_depth: usize,
+    name_resolutions: Option<Arc<NameResolutionLimiter>>,
     _worker: Arc<JoinHandle<()>>,
     session_closer: async_channel::Sender<ContextId>,
 }
 
+/// How fetches of blocks from the network are bounded and retried.
+///
+/// Applies to each block on its own. A deadline on the whole request, like the resolve timeout
+/// of the gateway, keeps running while blocks are retried and cuts retries short.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct FetchPolicy {
+    /// How often a failed fetch is retried, asking providers found in the DHT that weren't
+    /// asked before.
+    pub max_provider_retries: usize,
+    /// Time a single fetch attempt gets before it counts as failed, `None` waits indefinitely.
+    pub per_block_timeout: Option<Duration>,
+}
+
 #[derive(Debug, Clone)]
 pub struct LoaderContext {
     id: ContextId,
+    provider_hints: HashSet<PeerId>,
+    fetch_policy: FetchPolicy,
     inner: Arc<Mutex<InnerLoaderContext>>,
 }
 
@@ -674,13 +928,35 @@ impl LoaderContext {
         trace!("new loader context: {:?}", id);
         LoaderContext {
             id,
+            provider_hints: HashSet::new(),
+            fetch_policy: FetchPolicy::default(),
             inner: Arc::new(Mutex::new(InnerLoaderContext { path, closer })),
         }
     }
 
+    /// Peers that are asked for blocks directly, before falling back to a provider search.
+    pub fn with_provider_hints(mut self, providers: HashSet<PeerId>) -> Self {
+        self.provider_hints = providers;
+        self
+    }
+
+    /// Bounds and retries fetches from the network as set by `policy`.
+    pub fn with_fetch_policy(mut self, policy: FetchPolicy) -> Self {
+        self.fetch_policy = policy;
+        self
+    }
+
     pub fn id(&self) -> ContextId {
         self.id
     }
+
+    pub fn provider_hints(&self) -> &HashSet<PeerId> {
+        &self.provider_hints
+    }
+
+    pub fn fetch_policy(&self) -> FetchPolicy {
+        self.fetch_policy
+    }
 }
 
 impl Drop for LoaderContext {
@@ -757,6 +1033,150 @@ impl<T: ContentLoader> ContentLoader for Arc<T> {
     }
 }
 
+/// Looks `cid` up in the store, then in each of the fallback stores in order.
+///
+/// A block found in a fallback store is copied into all the faster stores in the background,
+/// so the next lookup is served from the store.
+pub(crate) async fn load_from_stores(client: &Client, cid: Cid) -> Result<Option<LoadedCid>> {
+    let mut stores = vec![client.try_store()?];
+    stores.extend(client.fallback_stores());
+
+    for (tier, store) in stores.iter().enumerate() {
+        match store.get(cid).await {
+            Ok(Some(data)) => {
+                if tier == 0 {
+                    return Ok(Some(LoadedCid {
+                        data,
+                        source: Source::Store(IROH_STORE),
+                    }));
+                }
+
+                let faster = stores[..tier].to_vec();
+                let clone = data.clone();
+                tokio::spawn(async move {
+                    let clone2 = clone.clone();
+                    let links = tokio::task::spawn_blocking(move || {
+                        parse_links(&cid, &clone2).unwrap_or_default()
+                    })
+                    .await
+                    .unwrap_or_default();
+                    for store in faster {
+                        if let Err(err) = store.put(cid, clone.clone(), links.clone()).await {
+                            warn!("failed to promote {}: {:?}", cid, err);
+                        }
+                    }
+                });
+
+                return Ok(Some(LoadedCid {
+                    data,
+                    source: Source::FallbackStore(tier - 1),
+                }));
+            }
+            Ok(None) => {}
+            Err(err) => {
+                warn!("failed to fetch data from store {}: {:?}", cid, err);
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Fetches `cid` over bitswap, asking the provider hints of `ctx` first.
+///
+/// Failed attempts are retried as allowed by the [`FetchPolicy`] of `ctx`, with the providers
+/// of `cid` in the DHT that weren't asked yet. Retries stop early once the DHT has no such
+/// providers left. Once no more attempts are made, fails with [`BlockNotFound`] caused by the
+/// error of the last attempt.
+pub(crate) async fn fetch_bitswap(p2p: &P2pClient, ctx: &LoaderContext, cid: Cid) -> Result<Bytes> {
+    fetch_with_retries(p2p, ctx, cid).await
+}
+
+/// Where [`fetch_bitswap`] fetches blocks and finds their providers.
+#[async_trait]
+trait BitswapFetcher {
+    async fn fetch(&self, ctx: ContextId, cid: Cid, providers: HashSet<PeerId>) -> Result<Bytes>;
+
+    /// Providers of `cid` that aren't in `asked`, empty if none are found.
+    async fn find_new_providers(&self, cid: Cid, asked: &HashSet<PeerId>) -> HashSet<PeerId>;
+}
+
+#[async_trait]
+impl BitswapFetcher for P2pClient {
+    async fn fetch(&self, ctx: ContextId, cid: Cid, providers: HashSet<PeerId>) -> Result<Bytes> {
+        self.fetch_bitswap(ctx.into(), cid, providers).await
+    }
+
+    /// Looks up providers of `cid` in the DHT until some that aren't in `asked` are found.
+    async fn find_new_providers(&self, cid: Cid, asked: &HashSet<PeerId>) -> HashSet<PeerId> {
+        let mut found = HashSet::new();
+        let providers = match self.fetch_providers_dht(&cid).await {
+            Ok(providers) => providers,
+            Err(err) => {
+                debug!("failed to look up providers of {}: {:?}", cid, err);
+                return found;
+            }
+        };
+        tokio::pin!(providers);
+        while found.is_empty() {
+            match providers.try_next().await {
+                Ok(Some(peers)) => {
+                    found.extend(peers.into_iter().filter(|peer| !asked.contains(peer)))
+                }
+                Ok(None) => break,
+                Err(err) => {
+                    debug!("failed to look up providers of {}: {:?}", cid, err);
+                    break;
+                }
+            }
+        }
+        found
+    }
+}
+
+async fn fetch_with_retries<F: BitswapFetcher + Sync>(
+    fetcher: &F,
+    ctx: &LoaderContext,
+    cid: Cid,
+) -> Result<Bytes> {
+    let policy = ctx.fetch_policy();
+    let mut providers = ctx.provider_hints().clone();
+    let mut asked = providers.clone();
+    let mut retries = 0;
+    loop {
+        let fetch = fetcher.fetch(ctx.id(), cid, providers);
+        let res = match policy.per_block_timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, fetch).await {
+                Ok(res) => res,
+                Err(_) => Err(anyhow!("fetching {} timed out after {:?}", cid, timeout)),
+            },
+            None => fetch.await,
+        };
+        let err = match res {
+            Ok(bytes) => return Ok(bytes),
+            Err(err) if retries >= policy.max_provider_retries => {
+                return Err(err.context(BlockNotFound { cid }))
+            }
+            Err(err) => err,
+        };
+        retries += 1;
+        providers = fetcher.find_new_providers(cid, &asked).await;
+        if providers.is_empty() {
+            debug!("{:?} no new providers of {} to retry with", ctx.id(), cid);
+            return Err(err.context(BlockNotFound { cid }));
+        }
+        debug!(
+            "{:?} retrying {} with {} new providers ({}/{}): {:?}",
+            ctx.id(),
+            cid,
+            providers.len(),
+            retries,
+            policy.max_provider_retries,
+            err
+        );
+        asked.extend(providers.iter().copied());
+    }
+}
+
 #[async_trait]
 impl ContentLoader for Client {
     async fn stop_session(&self, ctx: ContextId) -> Result<()> {
@@ -769,35 +1189,22 @@ impl ContentLoader for Client {
         // TODO: better strategy
 
         let cid = *cid;
-        match self.try_store()?.get(cid).await {
-            Ok(Some(data)) => {
-                trace!("{:?} retrieved from store", ctx.id());
-                return Ok(LoadedCid {
-                    data,
-                    source: Source::Store(IROH_STORE),
-                });
-            }
-            Ok(None) => {}
-            Err(err) => {
-                warn!(
-                    "{:?} failed to fetch data from store {}: {:?}",
-                    ctx.id(),
-                    cid,
-                    err
-                );
-            }
+        if let Some(loaded) = load_from_stores(self, cid).await? {
+            trace!("{:?} retrieved from {:?}", ctx.id(), loaded.source);
+            return Ok(loaded);
         }
 
+        // without p2p the stores were the only place to look
+        let p2p = self
+            .try_p2p()
+            .map_err(|err| err.context(BlockNotFound { cid }))?;
+
         // launch fetching using the initial set of cached providers
-        let bytes = self
-            .try_p2p()?
-            .fetch_bitswap(ctx.id().into(), cid, Default::default())
-            .await?;
+        let bytes = fetch_bitswap(&p2p, ctx, cid).await?;
 
         // trigger storage in the background
         let clone = bytes.clone();
         let store = self.try_store();
-        let p2p = self.try_p2p()?;
 
         tokio::spawn(async move {
             let clone2 = clone.clone();
@@ -862,11 +1269,75 @@ impl<T: ContentLoader> Resolver<T> {
         Resolver {
             loader,
             next_id: Arc::new(AtomicU64::new(0)),
+            max_links_per_node: DEFAULT_MAX_LINKS_PER_NODE,
+            max_depth: DEFAULT_MAX_DEPTH,
+            name_resolutions: None,
             _worker: Arc::new(worker),
             session_closer: session_closer_s,
         }
     }
 
+    /// Sets the maximum number of links a single node may have, nodes exceeding it
+    /// fail to resolve with [`LimitExceeded`].
+    pub fn with_max_links_per_node(mut self, max_links_per_node: usize) -> Self {
+        self.max_links_per_node = max_links_per_node;
+        self
+    }
+
+    pub fn max_links_per_node(&self) -> usize {
+        self.max_links_per_node
+    }
+
+    /// Sets how deep recursive resolution may descend below the root, deeper dags fail
+    /// with [`DepthExceeded`].
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    /// Limits how many IPNS and DNSLink names are resolved concurrently. Up to `max_queued`
+    /// further resolutions wait for a free slot, any beyond fail with [`NameResolutionsExceeded`].
+    pub fn with_name_resolution_limit(mut self, limit: usize, max_queued: usize) -> Self {
+        self.name_resolutions = Some(Arc::new(NameResolutionLimiter::new(limit, max_queued)));
+        self
+    }
+
+    /// Errors if following the links of a node at `depth` would exceed the max depth.
+    fn check_depth_limit(&self, depth: usize) -> Result<()> {
+        if depth >= self.max_depth {
+            return Err(DepthExceeded {
+                limit: self.max_depth,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Errors if the encoded dag-pb node `data` has more links than allowed. The links are
+    /// counted without decoding the node, so oversized nodes are rejected before they are
+    /// decoded. Malformed nodes are left to fail decoding.
+    fn check_pb_links_limit(&self, data: &[u8]) -> Result<()> {
+        match count_pb_links(data) {
+            Ok(links) => self.check_links_limit(links),
+            Err(_) => Ok(()),
+        }
+    }
+
+    fn check_links_limit(&self, links: usize) -> Result<()> {
+        if links > self.max_links_per_node {
+            return Err(LimitExceeded {
+                links,
+                limit: self.max_links_per_node,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
     fn next_id(&self) -> ContextId {
         let id = self.next_id.fetch_add(1, Ordering::SeqCst);
         ContextId(id)
@@ -885,9 +1356,9 @@ impl<T: ContentLoader> Resolver<T> {
         let this = self.clone();
         async_stream::try_stream! {
             let output_path = root.clone();
-            blocks.push_back((output_path, this.resolve(root).await));
+            blocks.push_back((0, output_path, this.resolve(root).await));
             loop {
-                if let Some((current_output_path, current_out)) = blocks.pop_front() {
+                if let Some((depth, current_output_path, current_out)) = blocks.pop_front() {
                     let current = current_out?;
                     if !current.is_dir() {
                         yield (current_output_path, current);
@@ -902,6 +1373,9 @@ impl<T: ContentLoader> Resolver<T> {
                         .try_collect()
                         .await;
                     let links = links?;
+                    if !links.is_empty() {
+                        this.check_depth_limit(depth)?;
+                    }
                     // TODO: configurable limit
                     for link_chunk in links.chunks(8) {
                         let next = futures::future::join_all(
@@ -918,8 +1392,8 @@ impl<T: ContentLoader> Resolver<T> {
                                 }
                             })
                         ).await;
-                        for res in next.into_iter() {
-                            blocks.push_back(res);
+                        for (path, res) in next.into_iter() {
+                            blocks.push_back((depth + 1, path, res));
                         }
                     }
                     yield (current_output_path, current);
@@ -946,16 +1420,33 @@ impl<T: ContentLoader> Resolver<T> {
         &self,
         root: Path,
         recursion_limit: Option<usize>,
+    ) -> impl Stream<Item = Result<OutRaw>> {
+        self.resolve_recursive_raw_with_fetch_policy(root, recursion_limit, FetchPolicy::default())
+    }
+
+    /// Like [`Resolver::resolve_recursive_raw`], but fetches blocks from the network as set by
+    /// `policy`.
+    #[tracing::instrument(skip(self))]
+    pub fn resolve_recursive_raw_with_fetch_policy(
+        &self,
+        root: Path,
+        recursion_limit: Option<usize>,
+        policy: FetchPolicy,
     ) -> impl Stream<Item = Result<OutRaw>> {
         let this = self.clone();
-        self.resolve_recursive_mapped(root, recursion_limit, move |cid, mut ctx| {
-            let this = this.clone();
-            async move {
-                this.load_cid(&cid, &mut ctx)
-                    .await
-                    .map(|loaded| OutRaw::from_loaded(cid, loaded))
-            }
-        })
+        self.resolve_recursive_mapped_with_fetch_policy(
+            root,
+            recursion_limit,
+            policy,
+            move |cid, mut ctx| {
+                let this = this.clone();
+                async move {
+                    this.load_cid(&cid, &mut ctx)
+                        .await
+                        .map(|loaded| OutRaw::from_loaded(cid, loaded))
+                }
+            },
+        )
     }
 
     /// Resolve a path recursively and supply a closure to resolve cids to outputs.
@@ -971,20 +1462,52 @@ impl<T: ContentLoader> Resolver<T> {
         M: Fn(Cid, LoaderContext) -> F + Clone,
         F: Future<Output = Result<O>> + Send + 'static,
     {
-        let mut ctx =
-            LoaderContext::from_path(self.next_id(), self.session_closer.clone(), root.clone());
+        self.resolve_recursive_mapped_with_fetch_policy(
+            root,
+            recursion_limit,
+            FetchPolicy::default(),
+            resolve,
+        )
+    }
+
+    /// Like [`Resolver::resolve_recursive_mapped`], the context handed to `resolve` fetches
+    /// blocks from the network as set by `policy`.
+    #[tracing::instrument(skip(self, resolve))]
+    pub fn resolve_recursive_mapped_with_fetch_policy<O, M, F>(
+        &self,
+        root: Path,
+        recursion_limit: Option<usize>,
+        policy: FetchPolicy,
+        resolve: M,
+    ) -> impl Stream<Item = Result<O>>
+    where
+        O: LinksContainer,
+        M: Fn(Cid, LoaderContext) -> F + Clone,
+        F: Future<Output = Result<O>> + Send + 'static,
+    {
+        let ctx =
+            LoaderContext::from_path(self.next_id(), self.session_closer.clone(), root.clone())
+                .with_fetch_policy(policy);
 
         let mut cids = VecDeque::new();
         let this = self.clone();
         let mut counter = 0;
         let chunk_size = 8;
         async_stream::try_stream! {
-            let root_cid = this.resolve_path_to_cid(&root, &mut ctx).await?;
+            let root_cid = this.resolve_name(&root).await?;
             let root_block = resolve(root_cid, ctx.clone()).await?;
-            cids.push_back(root_block);
+            // explicit work queue rather than recursion, so deep dags can't grow the stack
+            cids.push_back((0, root_block));
             loop {
-                if let Some(current) = cids.pop_front() {
+                if let Some((depth, current)) = cids.pop_front() {
+                    if let Some(links) = current.links_count_hint() {
+                        this.check_links_limit(links)?;
+                    }
                     let links = current.links()?;
+                    this.check_links_limit(links.len())?;
+                    if !links.is_empty() {
+                        this.check_depth_limit(depth)?;
+                    }
                     counter += links.len();
                     if let Some(limit) = recursion_limit {
                         if counter > limit {
@@ -1005,7 +1528,7 @@ impl<T: ContentLoader> Resolver<T> {
                         ).await;
                         for res in next.into_iter() {
                             let res = res?;
-                            cids.push_back(res);
+                            cids.push_back((depth + 1, res));
                         }
                     }
                     yield current;
@@ -1027,11 +1550,41 @@ impl<T: ContentLoader> Resolver<T> {
         self.resolve_with_ctx(ctx, path).await
     }
 
+    /// Like [`Resolver::resolve`], but asks the given peers for the blocks first.
+    ///
+    /// Peers that cannot be reached are skipped; if none of them are, the blocks are
+    /// searched for on the network as usual.
+    #[tracing::instrument(skip(self))]
+    pub async fn resolve_with_provider_hints(
+        &self,
+        path: Path,
+        providers: HashSet<PeerId>,
+    ) -> Result<Out> {
+        let ctx =
+            LoaderContext::from_path(self.next_id(), self.session_closer.clone(), path.clone())
+                .with_provider_hints(providers);
+
+        self.resolve_with_ctx(ctx, path).await
+    }
+
+    /// Like [`Resolver::resolve`], but fetches blocks from the network as set by `policy`.
+    ///
+    /// The policy also holds for the blocks loaded through the returned [`Out`], like the
+    /// contents of a file.
+    #[tracing::instrument(skip(self))]
+    pub async fn resolve_with_fetch_policy(&self, path: Path, policy: FetchPolicy) -> Result<Out> {
+        let ctx =
+            LoaderContext::from_path(self.next_id(), self.session_closer.clone(), path.clone())
+                .with_fetch_policy(policy);
+
+        self.resolve_with_ctx(ctx, path).await
+    }
+
     pub async fn resolve_with_ctx(&self, mut ctx: LoaderContext, path: Path) -> Result<Out> {
         // Resolve the root block.
         let (root_cid, loaded_cid) = self.resolve_root(&path, &mut ctx).await?;
         match loaded_cid.source {
-            Source::Store(_) => inc!(ResolverMetrics::CacheHit),
+            Source::Store(_) | Source::FallbackStore(_) => inc!(ResolverMetrics::CacheHit),
             _ => inc!(ResolverMetrics::CacheMiss),
         }
 
@@ -1061,12 +1614,19 @@ impl<T: ContentLoader> Resolver<T> {
     ) -> Result<()> {
         match current {
             UnixfsNode::Directory(_) => {
-                let next_link = current
-                    .get_link_by_name(&part)
-                    .await?
-                    .ok_or_else(|| anyhow!("UnixfsNode::Directory link '{}' not found", part))?;
+                let next_link =
+                    current
+                        .get_link_by_name(&part)
+                        .await?
+                        .ok_or_else(|| LinkNotFound {
+                            name: part.to_string(),
+                        })?;
                 let loaded_cid = self.load_cid(&next_link.cid, ctx).await?;
+                if next_link.cid.codec() == Codec::DagPb as u64 {
+                    self.check_pb_links_limit(&loaded_cid.data)?;
+                }
                 let next_node = UnixfsNode::decode(&next_link.cid, loaded_cid.data)?;
+                self.check_links_limit(next_node.links().len())?;
                 resolved_path.push(next_link.cid);
 
                 *current = next_node;
@@ -1075,14 +1635,16 @@ impl<T: ContentLoader> Resolver<T> {
                 let (next_link, next_node) = hamt
                     .get(ctx.clone(), self, part.as_bytes())
                     .await?
-                    .ok_or_else(|| anyhow!("UnixfsNode::HamtShard link '{}' not found", part))?;
+                    .ok_or_else(|| LinkNotFound {
+                        name: part.to_string(),
+                    })?;
                 // TODO: is this the right way to to resolved path here?
                 resolved_path.push(next_link.cid);
 
                 *current = next_node.clone();
             }
             _ => {
-                bail!("unexpected unixfs type {:?}", current.typ());
+                return Err(UnexpectedUnixfsType { typ: current.typ() }.into());
             }
         }
 
@@ -1099,7 +1661,9 @@ impl<T: ContentLoader> Resolver<T> {
         mut ctx: LoaderContext,
     ) -> Result<Out> {
         trace!("{:?} resolving {} for {}", ctx.id(), cid, root_path);
+        self.check_pb_links_limit(&loaded_cid.data)?;
         if let Ok(node) = UnixfsNode::decode(&cid, loaded_cid.data.clone()) {
+            self.check_links_limit(node.links().len())?;
             let tail = &root_path.tail;
             let mut current = node;
             let mut resolved_path = vec![cid];
@@ -1152,8 +1716,16 @@ impl<T: ContentLoader> Resolver<T> {
             .decode(&loaded_cid.data)
             .map_err(|e| anyhow!("invalid {:?}: {:?}", codec, e))?;
 
+        let mut resolved_path = vec![cid];
         let (codec, out) = self
-            .resolve_ipld_path(cid, codec, ipld, &root_path.tail, &mut ctx)
+            .resolve_ipld_path(
+                cid,
+                codec,
+                ipld,
+                &root_path.tail,
+                &mut resolved_path,
+                &mut ctx,
+            )
             .await?;
 
         // reencode if we only return part of the original
@@ -1179,7 +1751,7 @@ impl<T: ContentLoader> Resolver<T> {
             size: Some(size),
             typ,
             unixfs_type: None,
-            resolved_path: vec![cid],
+            resolved_path,
             source: loaded_cid.source,
         };
         Ok(Out {
@@ -1189,21 +1761,28 @@ impl<T: ContentLoader> Resolver<T> {
         })
     }
 
-    #[tracing::instrument(skip(self, root))]
+    /// Walks `path` through the given node, indexing maps by key and lists by position,
+    /// and following links into other blocks along the way. The cids of all followed
+    /// links are appended to `resolved_path`.
+    #[tracing::instrument(skip(self, root, resolved_path))]
     async fn resolve_ipld_path(
         &self,
-        _cid: Cid,
+        cid: Cid,
         codec: libipld::IpldCodec,
         root: Ipld,
         path: &[String],
+        resolved_path: &mut Vec<Cid>,
         ctx: &mut LoaderContext,
     ) -> Result<(IpldCodec, Ipld)> {
         let mut current = root;
+        let mut current_cid = cid;
         let mut codec = codec;
 
         for part in path.iter().filter(|s| !s.is_empty()) {
             if let libipld::Ipld::Link(c) = current {
                 (codec, current) = self.load_ipld_link(c, ctx).await?;
+                current_cid = c;
+                resolved_path.push(c);
             }
             if codec == IpldCodec::DagPb {
                 current = self.get_dagpb_link(current, part)?;
@@ -1215,15 +1794,18 @@ impl<T: ContentLoader> Resolver<T> {
                 };
                 current = current.take(index).map_err(|_| {
                     anyhow!(
-                        "IPLD resolve error: Couldn't find part {} in path '{}'",
+                        "IPLD resolve error: Couldn't find part {} of path '{}' in {:?} node {}",
                         part,
-                        path.join("/")
+                        path.join("/"),
+                        codec,
+                        current_cid
                     )
                 })?;
             }
         }
         if let libipld::Ipld::Link(c) = current {
             (codec, current) = self.load_ipld_link(c, ctx).await?;
+            resolved_path.push(c);
         }
 
         Ok((codec, current))
@@ -1272,11 +1854,13 @@ impl<T: ContentLoader> Resolver<T> {
                 _ => return Err(anyhow!("expected DagPb link to have a string Name field")),
             }
         }
-        anyhow::bail!("could not find DagPb link '{}'", name);
+        Err(LinkNotFound { name }.into())
     }
 
+    /// Resolves the root of `root` to a [`Cid`], following IPNS and DNSLink names. The tail
+    /// of the path is ignored.
     #[tracing::instrument(skip(self))]
-    async fn resolve_path_to_cid(&self, root: &Path, ctx: &mut LoaderContext) -> Result<Cid> {
+    pub async fn resolve_name(&self, root: &Path) -> Result<Cid> {
         let mut current = root.clone();
 
         // maximum cursion of ipns lookups
@@ -1290,19 +1874,25 @@ impl<T: ContentLoader> Resolver<T> {
                     }
                     CidOrDomain::Domain(_) => bail!("invalid domain encountered"),
                 },
-                PathType::Ipns => match current.root {
-                    CidOrDomain::Cid(ref c) => {
-                        let c = self.load_ipns_record(c).await?;
-                        current = Path::from_cid(c);
-                    }
-                    CidOrDomain::Domain(ref domain) => {
-                        let mut records = resolve_dnslink(domain).await?;
-                        if records.is_empty() {
-                            bail!("no valid dnslink records found for {}", domain);
+                PathType::Ipns => {
+                    let _permit = match self.name_resolutions {
+                        Some(ref limiter) => Some(limiter.acquire().await?),
+                        None => None,
+                    };
+                    match current.root {
+                        CidOrDomain::Cid(ref c) => {
+                            let c = self.load_ipns_record(c).await?;
+                            current = Path::from_cid(c);
+                        }
+                        CidOrDomain::Domain(ref domain) => {
+                            let mut records = resolve_dnslink(domain).await?;
+                            if records.is_empty() {
+                                bail!("no valid dnslink records found for {}", domain);
+                            }
+                            current = records.remove(0);
                         }
-                        current = records.remove(0);
                     }
-                },
+                }
             }
         }
 
@@ -1311,7 +1901,7 @@ impl<T: ContentLoader> Resolver<T> {
 
     #[tracing::instrument(skip(self))]
     async fn resolve_root(&self, root: &Path, ctx: &mut LoaderContext) -> Result<(Cid, LoadedCid)> {
-        let cid = self.resolve_path_to_cid(root, ctx).await?;
+        let cid = self.resolve_name(root).await?;
         let loaded_cid = self.load_cid(&cid, ctx).await?;
         Ok((cid, loaded_cid))
     }
@@ -1321,6 +1911,17 @@ impl<T: ContentLoader> Resolver<T> {
         self.loader.load_cid(cid, ctx).await
     }
 
+    /// Loads the single block `cid` as is, without following any links.
+    #[tracing::instrument(skip(self))]
+    pub async fn load_block(&self, cid: Cid) -> Result<LoadedCid> {
+        let mut ctx = LoaderContext::from_path(
+            self.next_id(),
+            self.session_closer.clone(),
+            Path::from_cid(cid),
+        );
+        self.load_cid(&cid, &mut ctx).await
+    }
+
     #[tracing::instrument(skip(self))]
     pub async fn has_cid(&self, cid: &Cid) -> Result<bool> {
         self.loader.has_cid(cid).await
@@ -1400,7 +2001,7 @@ mod tests {
                     data: b.clone(),
                     source: Source::Bitswap,
                 }),
-                None => bail!("not found"),
+                None => Err(BlockNotFound { cid: *cid }.into()),
             }
         }
 
@@ -1555,6 +2156,149 @@ mod tests {
         }
     }
 
+    /// Records the [`FetchPolicy`] of every load.
+    #[derive(Debug, Clone)]
+    struct PolicyLoader {
+        blocks: Arc<HashMap<Cid, Bytes>>,
+        policies: Arc<std::sync::Mutex<Vec<FetchPolicy>>>,
+    }
+
+    #[async_trait]
+    impl ContentLoader for PolicyLoader {
+        async fn load_cid(&self, cid: &Cid, ctx: &LoaderContext) -> Result<LoadedCid> {
+            self.policies.lock().unwrap().push(ctx.fetch_policy());
+            self.blocks.load_cid(cid, ctx).await
+        }
+
+        async fn stop_session(&self, _ctx: ContextId) -> Result<()> {
+            Ok(())
+        }
+
+        async fn has_cid(&self, cid: &Cid) -> Result<bool> {
+            self.blocks.has_cid(cid).await
+        }
+    }
+
+    /// Serves blocks only when asked with `serving` among the providers, handing out the
+    /// provider sets of `dht` one lookup at a time.
+    #[derive(Debug, Default)]
+    struct FakeFetcher {
+        serving: Option<PeerId>,
+        dht: std::sync::Mutex<VecDeque<HashSet<PeerId>>>,
+        asked: std::sync::Mutex<Vec<HashSet<PeerId>>>,
+    }
+
+    #[async_trait]
+    impl BitswapFetcher for FakeFetcher {
+        async fn fetch(
+            &self,
+            _ctx: ContextId,
+            cid: Cid,
+            providers: HashSet<PeerId>,
+        ) -> Result<Bytes> {
+            let serves = self.serving.map_or(false, |peer| providers.contains(&peer));
+            self.asked.lock().unwrap().push(providers);
+            if serves {
+                Ok(Bytes::from_static(b"hello world"))
+            } else {
+                Err(anyhow!("no provider of {} answered", cid))
+            }
+        }
+
+        async fn find_new_providers(&self, _cid: Cid, asked: &HashSet<PeerId>) -> HashSet<PeerId> {
+            let found = self.dht.lock().unwrap().pop_front().unwrap_or_default();
+            found.difference(asked).copied().collect()
+        }
+    }
+
+    fn retrying_ctx(
+        cid: Cid,
+        hints: HashSet<PeerId>,
+        max_provider_retries: usize,
+    ) -> LoaderContext {
+        let (closer, _) = async_channel::bounded(1);
+        LoaderContext::from_path(ContextId(0), closer, Path::from_cid(cid))
+            .with_provider_hints(hints)
+            .with_fetch_policy(FetchPolicy {
+                max_provider_retries,
+                per_block_timeout: None,
+            })
+    }
+
+    #[tokio::test]
+    async fn test_fetch_retries_with_new_providers() {
+        let cid: Cid = "bafkreifzjut3te2nhyekklss27nh3k72ysco7y32koao5eei66wof36n5e"
+            .parse()
+            .unwrap();
+        let hint = PeerId::random();
+        let provider = PeerId::random();
+        let fetcher = FakeFetcher {
+            serving: Some(provider),
+            dht: std::sync::Mutex::new(VecDeque::from([HashSet::from([hint, provider])])),
+            ..Default::default()
+        };
+        let ctx = retrying_ctx(cid, HashSet::from([hint]), 3);
+
+        let data = fetch_with_retries(&fetcher, &ctx, cid).await.unwrap();
+        assert_eq!(data, Bytes::from_static(b"hello world"));
+        assert_eq!(
+            *fetcher.asked.lock().unwrap(),
+            vec![HashSet::from([hint]), HashSet::from([provider])]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_stops_retrying_without_new_providers() {
+        let cid: Cid = "bafkreifzjut3te2nhyekklss27nh3k72ysco7y32koao5eei66wof36n5e"
+            .parse()
+            .unwrap();
+        let hint = PeerId::random();
+        let fetcher = FakeFetcher::default();
+        let ctx = retrying_ctx(cid, HashSet::from([hint]), 3);
+
+        let err = fetch_with_retries(&fetcher, &ctx, cid).await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<BlockNotFound>(),
+            Some(&BlockNotFound { cid })
+        );
+        assert_eq!(*fetcher.asked.lock().unwrap(), vec![HashSet::from([hint])]);
+
+        // retries are bounded by the policy, even with providers left
+        let fetcher = FakeFetcher {
+            dht: std::sync::Mutex::new((0..5).map(|_| HashSet::from([PeerId::random()])).collect()),
+            ..Default::default()
+        };
+        let ctx = retrying_ctx(cid, HashSet::from([hint]), 2);
+        let err = fetch_with_retries(&fetcher, &ctx, cid).await.unwrap_err();
+        assert!(err.is::<BlockNotFound>());
+        assert_eq!(fetcher.asked.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_with_fetch_policy() {
+        let data = Bytes::from_static(b"hello world");
+        let c = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+        let loader = PolicyLoader {
+            blocks: Arc::new([(c, data)].into_iter().collect()),
+            policies: Default::default(),
+        };
+        let resolver = Resolver::new(loader.clone());
+        let policy = FetchPolicy {
+            max_provider_retries: 3,
+            per_block_timeout: Some(Duration::from_secs(5)),
+        };
+
+        resolver
+            .resolve_with_fetch_policy(Path::from_cid(c), policy)
+            .await
+            .unwrap();
+        resolver.resolve(Path::from_cid(c)).await.unwrap();
+        assert_eq!(
+            *loader.policies.lock().unwrap(),
+            vec![policy, FetchPolicy::default()]
+        );
+    }
+
     #[tokio::test]
     async fn test_resolve_ipld() {
         for codec in [IpldCodec::DagCbor, IpldCodec::DagJson] {
@@ -1636,6 +2380,89 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_resolve_ipld_traverse_links() {
+        fn encode(ipld: &Ipld) -> (Cid, Bytes) {
+            let mut bytes = Vec::new();
+            ipld.encode(IpldCodec::DagCbor, &mut bytes).unwrap();
+            let c = Cid::new_v1(IpldCodec::DagCbor.into(), Code::Sha2_256.digest(&bytes));
+            (c, bytes.into())
+        }
+
+        // leaf: { "title": "bar", "items": [1, 2] }
+        let mut leaf = BTreeMap::new();
+        leaf.insert("title".to_string(), Ipld::String("bar".to_string()));
+        leaf.insert(
+            "items".to_string(),
+            Ipld::List(vec![Ipld::Integer(1), Ipld::Integer(2)]),
+        );
+        let leaf = Ipld::Map(leaf);
+        let (leaf_cid, leaf_bytes) = encode(&leaf);
+
+        // root: { "name": "foo", "child": <leaf>, "list": [<leaf>] }
+        let mut root = BTreeMap::new();
+        root.insert("name".to_string(), Ipld::String("foo".to_string()));
+        root.insert("child".to_string(), Ipld::Link(leaf_cid));
+        root.insert("list".to_string(), Ipld::List(vec![Ipld::Link(leaf_cid)]));
+        let (root_cid, root_bytes) = encode(&Ipld::Map(root));
+
+        let loader: Arc<HashMap<_, _>> = Arc::new(
+            [(root_cid, root_bytes), (leaf_cid, leaf_bytes)]
+                .into_iter()
+                .collect(),
+        );
+        let resolver = Resolver::new(loader);
+
+        let resolve = |path: String| {
+            let resolver = resolver.clone();
+            async move {
+                let out = resolver.resolve(path.parse().unwrap()).await?;
+                let m = out.metadata().clone();
+                let bytes = read_to_vec(
+                    out.pretty(resolver, OutMetrics::default(), ResponseClip::NoClip)
+                        .unwrap(),
+                )
+                .await;
+                let ipld: Ipld = IpldCodec::DagCbor.decode(&bytes).unwrap();
+                Ok::<_, anyhow::Error>((m, ipld))
+            }
+        };
+
+        // a path ending on a link resolves to the linked node
+        let (m, ipld) = resolve(format!("/ipfs/{root_cid}/child")).await.unwrap();
+        assert_eq!(ipld, leaf);
+        assert_eq!(m.typ, OutType::DagCbor);
+        assert_eq!(m.resolved_path, vec![root_cid, leaf_cid]);
+
+        // map keys continue into the linked node
+        let (m, ipld) = resolve(format!("/ipfs/{root_cid}/child/title"))
+            .await
+            .unwrap();
+        assert_eq!(ipld, Ipld::String("bar".to_string()));
+        assert_eq!(m.resolved_path, vec![root_cid, leaf_cid]);
+
+        // as do list indices
+        let (m, ipld) = resolve(format!("/ipfs/{root_cid}/list/0/items/1"))
+            .await
+            .unwrap();
+        assert_eq!(ipld, Ipld::Integer(2));
+        assert_eq!(m.resolved_path, vec![root_cid, leaf_cid]);
+
+        // missing segments name the segment and the node they were looked up in
+        let err = resolve(format!("/ipfs/{root_cid}/child/missing"))
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("missing"), "{}", err);
+        assert!(err.contains(&leaf_cid.to_string()), "{}", err);
+
+        let err = resolve(format!("/ipfs/{root_cid}/list/3"))
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains(&root_cid.to_string()), "{}", err);
+    }
+
     #[tokio::test]
     async fn test_unixfs_basics_cid_v0() {
         // Test content
@@ -2847,4 +3674,273 @@ mod tests {
             format!("/ipfs/{root_cid_str}/bar/bar.txt")
         );
     }
+
+    #[tokio::test]
+    async fn test_max_links_per_node() {
+        // QmdkGfDx42RNdAZFALHn5hjHqUq7L9o6Ef4zLnFEu3Y4Go foo, with the links bar & hello.txt
+        let root_cid_str = "QmdkGfDx42RNdAZFALHn5hjHqUq7L9o6Ef4zLnFEu3Y4Go";
+        let root_cid: Cid = root_cid_str.parse().unwrap();
+        let root_block_bytes = load_fixture(root_cid_str).await;
+        // counted without decoding the node
+        assert_eq!(count_pb_links(&root_block_bytes).unwrap(), 2);
+        assert!(count_pb_links(&root_block_bytes[..root_block_bytes.len() - 1]).is_err());
+
+        let loader: HashMap<Cid, Bytes> = [(root_cid, root_block_bytes)].into_iter().collect();
+        let loader = Arc::new(loader);
+        let path: Path = format!("/ipfs/{root_cid_str}").parse().unwrap();
+
+        let resolver = Resolver::new(loader.clone()).with_max_links_per_node(2);
+        assert!(resolver.resolve(path.clone()).await.is_ok());
+
+        let resolver = Resolver::new(loader).with_max_links_per_node(1);
+        let err = resolver.resolve(path.clone()).await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<LimitExceeded>(),
+            Some(&LimitExceeded { links: 2, limit: 1 })
+        );
+
+        let err = resolver
+            .resolve_recursive_raw(path, None)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<LimitExceeded>(),
+            Some(&LimitExceeded { links: 2, limit: 1 })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resolve_errors() {
+        // QmdkGfDx42RNdAZFALHn5hjHqUq7L9o6Ef4zLnFEu3Y4Go foo, with the links bar & hello.txt
+        let root_cid_str = "QmdkGfDx42RNdAZFALHn5hjHqUq7L9o6Ef4zLnFEu3Y4Go";
+        let root_cid: Cid = root_cid_str.parse().unwrap();
+        let root_block_bytes = load_fixture(root_cid_str).await;
+        let hello_txt_cid_str = "QmZULkCELmmk5XNfCgTnCyFgAVxBRBXyDHGGMVoLFLiXEN";
+        let hello_txt_block_bytes = load_fixture(hello_txt_cid_str).await;
+        // QmcHTZfwWWYG2Gbv9wR6bWZBvAgpFV5BcDoLrC2XMCkggn foo/bar is left out
+        let bar_cid: Cid = "QmcHTZfwWWYG2Gbv9wR6bWZBvAgpFV5BcDoLrC2XMCkggn"
+            .parse()
+            .unwrap();
+
+        let loader: HashMap<Cid, Bytes> = [
+            (root_cid, root_block_bytes),
+            (hello_txt_cid_str.parse().unwrap(), hello_txt_block_bytes),
+        ]
+        .into_iter()
+        .collect();
+        let resolver = Resolver::new(Arc::new(loader));
+
+        let path: Path = format!("/ipfs/{root_cid_str}/missing").parse().unwrap();
+        let err = resolver.resolve(path).await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<LinkNotFound>(),
+            Some(&LinkNotFound {
+                name: "missing".to_string()
+            })
+        );
+
+        let path: Path = format!("/ipfs/{root_cid_str}/hello.txt/more")
+            .parse()
+            .unwrap();
+        let err = resolver.resolve(path).await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<UnexpectedUnixfsType>(),
+            Some(&UnexpectedUnixfsType {
+                typ: Some(DataType::File)
+            })
+        );
+
+        let path: Path = format!("/ipfs/{root_cid_str}/bar").parse().unwrap();
+        let err = resolver.resolve(path).await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<BlockNotFound>(),
+            Some(&BlockNotFound { cid: bar_cid })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_name_resolution_limit() {
+        let loader: Arc<HashMap<Cid, Bytes>> = Default::default();
+        let resolver = Resolver::new(loader).with_name_resolution_limit(2, 1);
+        let limiter = resolver.name_resolutions.clone().unwrap();
+
+        // saturate the limit, as if two names were being looked up
+        let _first = limiter.acquire().await.unwrap();
+        let _second = limiter.acquire().await.unwrap();
+
+        // the next distinct name waits for a free slot
+        let queued = {
+            let resolver = resolver.clone();
+            tokio::task::spawn(async move {
+                resolver
+                    .resolve("/ipns/first.example.com".parse().unwrap())
+                    .await
+            })
+        };
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert!(!queued.is_finished());
+        assert_eq!(limiter.queued.load(Ordering::SeqCst), 1);
+
+        // with the queue full, any further one is rejected right away
+        for name in ["second.example.com", "third.example.com"] {
+            let path: Path = format!("/ipns/{}", name).parse().unwrap();
+            let err = resolver.resolve(path).await.unwrap_err();
+            assert_eq!(
+                err.downcast_ref::<NameResolutionsExceeded>(),
+                Some(&NameResolutionsExceeded { limit: 2 })
+            );
+            assert!(err
+                .to_string()
+                .starts_with("too many concurrent name resolutions"));
+        }
+
+        // giving up on a queued resolution frees its slot
+        queued.abort();
+        assert!(queued.await.unwrap_err().is_cancelled());
+        assert_eq!(limiter.queued.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_max_depth() {
+        // a linear chain of dag-cbor nodes, each linking to the previous one
+        let mut loader: HashMap<Cid, Bytes> = HashMap::new();
+        let mut cids = Vec::new();
+        for i in 0..DEFAULT_MAX_DEPTH * 2 {
+            let mut node = BTreeMap::new();
+            node.insert("depth".to_string(), Ipld::Integer(i as i128));
+            if let Some(prev) = cids.last() {
+                node.insert("next".to_string(), Ipld::Link(*prev));
+            }
+            let mut bytes = Vec::new();
+            Ipld::Map(node)
+                .encode(IpldCodec::DagCbor, &mut bytes)
+                .unwrap();
+            let c = Cid::new_v1(IpldCodec::DagCbor.into(), Code::Sha2_256.digest(&bytes));
+            loader.insert(c, bytes.into());
+            cids.push(c);
+        }
+        let loader = Arc::new(loader);
+        let root: Path = format!("/ipfs/{}", cids.last().unwrap()).parse().unwrap();
+
+        let resolver = Resolver::new(loader.clone());
+        let err = resolver
+            .resolve_recursive_raw(root.clone(), None)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<DepthExceeded>(),
+            Some(&DepthExceeded {
+                limit: DEFAULT_MAX_DEPTH
+            })
+        );
+
+        let err = resolver
+            .resolve_recursive(root.clone())
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap_err();
+        assert!(err.downcast_ref::<DepthExceeded>().is_some());
+
+        // the whole chain resolves once the limit allows for it
+        let resolver = Resolver::new(loader).with_max_depth(cids.len());
+        let blocks = resolver
+            .resolve_recursive_raw(root, None)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(blocks.len(), cids.len());
+    }
+
+    #[cfg(feature = "rpc-mem")]
+    async fn spawn_store() -> (
+        iroh_rpc_types::store::StoreClientAddr,
+        tempfile::TempDir,
+        tokio::task::JoinHandle<()>,
+    ) {
+        let (server_addr, client_addr) = iroh_rpc_types::Addr::new_mem();
+        let dir = tempfile::tempdir().unwrap();
+        let config = iroh_store::Config {
+            path: dir.path().join("db"),
+            rpc_client: Default::default(),
+            metrics: Default::default(),
+            min_free_space: None,
+            write_batch_size: iroh_store::config::DEFAULT_WRITE_BATCH_SIZE,
+            durability: iroh_store::Durability::default(),
+        };
+        let store = iroh_store::Store::create(config).await.unwrap();
+        let task = tokio::task::spawn(async move {
+            iroh_store::rpc::new(server_addr, store, None)
+                .await
+                .unwrap()
+        });
+        (client_addr, dir, task)
+    }
+
+    #[cfg(feature = "rpc-mem")]
+    #[tokio::test]
+    async fn test_fallback_stores() {
+        let (store_addr, _store_dir, store_task) = spawn_store().await;
+        let (fallback_addr, _fallback_dir, fallback_task) = spawn_store().await;
+        let client = Client::new(iroh_rpc_client::Config {
+            store_addr: Some(store_addr),
+            fallback_store_addrs: vec![fallback_addr],
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        // only the slower store has the block
+        let data = Bytes::from_static(b"served from the fallback");
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+        let fallback = client.fallback_stores().pop().unwrap();
+        fallback.put(cid, data.clone(), Vec::new()).await.unwrap();
+
+        let resolver = Resolver::new(client.clone());
+        let path: Path = format!("/ipfs/{}", cid).parse().unwrap();
+        let out = resolver.resolve(path.clone()).await.unwrap();
+        assert_eq!(out.metadata().source, Source::FallbackStore(0));
+
+        // it is promoted into the store in the background
+        let store = client.try_store().unwrap();
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while !store.has(cid).await.unwrap() {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        let out = resolver.resolve(path).await.unwrap();
+        assert_eq!(out.metadata().source, Source::Store(IROH_STORE));
+
+        // blocks in neither store fall through to the network, which isn't configured here
+        let missing = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(b"missing"));
+        let path: Path = format!("/ipfs/{}", missing).parse().unwrap();
+        assert!(resolver.resolve(path).await.is_err());
+
+        store_task.abort();
+        fallback_task.abort();
+    }
+
+    #[test]
+    fn test_out_metrics_streamed() {
+        let mut om = OutMetrics::default();
+        assert!(om.streamed.is_none());
+        om.observe_bytes_read(0, 10);
+        om.observe_bytes_read(10, 5);
+        assert_eq!(om.streamed.map(|(_, bytes)| bytes), Some(15));
+        om.observe_bytes_read(15, 0);
+        assert!(om.finished);
+        // reads after the end change nothing
+        om.observe_bytes_read(15, 0);
+        assert_eq!(om.streamed.map(|(_, bytes)| bytes), Some(15));
+
+        let mut om = OutMetrics::disabled();
+        om.observe_bytes_read(0, 10);
+        om.observe_bytes_read(10, 0);
+        assert!(om.streamed.is_none());
+        assert!(!om.finished);
+    }
 }
@@ -8,7 +8,10 @@ use std::{
 
 use anyhow::{anyhow, bail, ensure, Result};
 use bytes::{Buf, Bytes};
-use cid::{multihash::MultihashDigest, Cid};
+use cid::{
+    multihash::{Code, MultihashDigest},
+    Cid,
+};
 use futures::{future::BoxFuture, stream::BoxStream, FutureExt, Stream, StreamExt};
 use prost::Message;
 use tokio::io::{AsyncRead, AsyncSeek};
@@ -17,9 +20,28 @@ use crate::{
     chunker::DEFAULT_CHUNK_SIZE_LIMIT,
     codecs::Codec,
     hamt::Hamt,
-    resolver::{Block, ContentLoader, LoaderContext, OutMetrics, Resolver, ResponseClip},
+    resolver::{Block, ContentLoader, LoaderContext, OutMetrics, Resolver, ResponseClip, Source},
 };
 
+/// Multihash used for newly created cids, unless a different one is requested.
+pub const DEFAULT_HASH: Code = Code::Sha2_256;
+
+/// Looks up a multihash by its name in the multicodec table, like `sha2-256` or `blake3`.
+pub fn hash_from_name(name: &str) -> Result<Code> {
+    let hash = match name {
+        "sha2-256" => Code::Sha2_256,
+        "sha2-512" => Code::Sha2_512,
+        "sha3-256" => Code::Sha3_256,
+        "sha3-512" => Code::Sha3_512,
+        "blake2b-256" => Code::Blake2b256,
+        "blake2b-512" => Code::Blake2b512,
+        "blake2s-256" => Code::Blake2s256,
+        "blake3" => Code::Blake3_256,
+        _ => bail!("unknown multihash {:?}", name),
+    };
+    Ok(hash)
+}
+
 pub(crate) mod unixfs_pb {
     #![allow(clippy::all)]
     include!(concat!(env!("OUT_DIR"), "/unixfs_pb.rs"));
@@ -213,11 +235,16 @@ impl UnixfsNode {
     }
 
     pub fn encode(&self) -> Result<Block> {
+        self.encode_with_hash(DEFAULT_HASH)
+    }
+
+    /// Encodes the node, deriving its cid with the given multihash.
+    pub fn encode_with_hash(&self, hash: Code) -> Result<Block> {
         let res = match self {
             UnixfsNode::Raw(data) => {
                 let out = data.clone();
                 let links = vec![];
-                let cid = Cid::new_v1(Codec::Raw as _, cid::multihash::Code::Sha2_256.digest(&out));
+                let cid = Cid::new_v1(Codec::Raw as _, hash.digest(&out));
                 Block::new(cid, out, links)
             }
             UnixfsNode::RawNode(node)
@@ -230,10 +257,7 @@ impl UnixfsNode {
                     .links()
                     .map(|x| Ok(x?.cid))
                     .collect::<Result<Vec<_>>>()?;
-                let cid = Cid::new_v1(
-                    Codec::DagPb as _,
-                    cid::multihash::Code::Sha2_256.digest(&out),
-                );
+                let cid = Cid::new_v1(Codec::DagPb as _, hash.digest(&out));
                 Block::new(cid, out, links)
             }
         };
@@ -495,6 +519,7 @@ impl<T: ContentLoader + Unpin + 'static> AsyncRead for UnixfsContentReader<T> {
                         current_links,
                         current_node,
                         ctx.clone(),
+                        out_metrics,
                     ),
                     UnixfsNode::Symlink(node) => {
                         let data = node.inner.data.as_deref().unwrap_or_default();
@@ -628,7 +653,7 @@ pub enum CurrentNodeState {
     Outer,
     None,
     Loaded(usize, UnixfsNode),
-    Loading(BoxFuture<'static, Result<UnixfsNode>>),
+    Loading(BoxFuture<'static, Result<(UnixfsNode, Source)>>),
 }
 
 impl Debug for CurrentNodeState {
@@ -675,7 +700,7 @@ fn load_next_node<T: ContentLoader + 'static>(
         let loaded_cid = loader.loader().load_cid(&link.cid, &ctx).await?;
         let node = UnixfsNode::decode(&link.cid, loaded_cid.data)?;
 
-        Ok(node)
+        Ok((node, loaded_cid.source))
     }
     .boxed();
     *current_node = CurrentNodeState::Loading(fut);
@@ -694,6 +719,7 @@ fn poll_read_file_at<T: ContentLoader + 'static>(
     current_links: &mut Vec<VecDeque<Link>>,
     current_node: &mut CurrentNodeState,
     ctx: std::sync::Arc<tokio::sync::Mutex<LoaderContext>>,
+    out_metrics: &OutMetrics,
 ) -> Poll<std::io::Result<()>> {
     loop {
         match current_node {
@@ -729,7 +755,8 @@ fn poll_read_file_at<T: ContentLoader + 'static>(
                     Poll::Pending => {
                         return Poll::Pending;
                     }
-                    Poll::Ready(Ok(node)) => {
+                    Poll::Ready(Ok((node, source))) => {
+                        out_metrics.observe_block_loaded(&source);
                         match node.links_owned() {
                             Ok(links) => {
                                 current_links.push(links);
@@ -746,10 +773,11 @@ fn poll_read_file_at<T: ContentLoader + 'static>(
                     }
                     Poll::Ready(Err(e)) => {
                         *current_node = CurrentNodeState::None;
-                        return Poll::Ready(Err(std::io::Error::new(
-                            std::io::ErrorKind::InvalidData,
-                            e.to_string(),
-                        )));
+                        // io errors of the loader are passed on, so callers can inspect them
+                        let err = e.downcast::<std::io::Error>().unwrap_or_else(|e| {
+                            std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string())
+                        });
+                        return Poll::Ready(Err(err));
                     }
                 }
             }
@@ -849,6 +877,22 @@ fn poll_read_file_at<T: ContentLoader + 'static>(
     }
 }
 
+/// Counts the links of an encoded dag-pb node, skipping over them without decoding.
+pub(crate) fn count_pb_links(mut buf: &[u8]) -> Result<usize> {
+    // the `Links` field of `PBNode`
+    const LINKS_TAG: u32 = 2;
+
+    let mut links = 0;
+    while !buf.is_empty() {
+        let (tag, wire_type) = prost::encoding::decode_key(&mut buf)?;
+        if tag == LINKS_TAG {
+            links += 1;
+        }
+        prost::encoding::skip_field(wire_type, tag, &mut buf, Default::default())?;
+    }
+    Ok(links)
+}
+
 #[derive(Debug)]
 pub enum Links<'a> {
     Raw,
@@ -897,6 +941,8 @@ impl<'a> Iterator for Links<'a> {
     }
 }
 
+impl<'a> ExactSizeIterator for Links<'a> {}
+
 impl<'a> Iterator for PbLinks<'a> {
     type Item = Result<LinkRef<'a>>;
 
@@ -924,6 +970,7 @@ impl<'a> Iterator for PbLinks<'a> {
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
-        (self.outer.links.len(), Some(self.outer.links.len()))
+        let remaining = self.outer.links.len() - self.i;
+        (remaining, Some(remaining))
     }
 }
@@ -9,7 +9,7 @@ use anyhow::{ensure, Result};
 use async_recursion::async_recursion;
 use async_trait::async_trait;
 use bytes::Bytes;
-use cid::Cid;
+use cid::{multihash::Code, Cid};
 use futures::stream::TryStreamExt;
 use futures::{future, stream::LocalBoxStream, Stream, StreamExt};
 use iroh_rpc_client::Client;
@@ -18,9 +18,9 @@ use tokio::io::AsyncRead;
 
 use crate::{
     balanced_tree::{TreeBuilder, DEFAULT_DEGREE},
-    chunker::{Chunker, DEFAULT_CHUNKS_SIZE, DEFAULT_CHUNK_SIZE_LIMIT},
+    chunker::{Chunker, DEFAULT_CHUNK_SIZE_LIMIT},
     resolver::Block,
-    unixfs::{dag_pb, unixfs_pb, DataType, Node, UnixfsNode},
+    unixfs::{dag_pb, unixfs_pb, DataType, Node, UnixfsNode, DEFAULT_HASH},
 };
 
 // The maximum number of links we allow in a directory
@@ -46,6 +46,7 @@ enum DirectoryType {
 pub struct Directory {
     name: String,
     entries: Vec<Entry>,
+    hash: Code,
 }
 
 impl Directory {
@@ -58,6 +59,7 @@ impl Directory {
     pub fn wrap(self) -> Self {
         Directory {
             name: "".into(),
+            hash: self.hash,
             entries: vec![Entry::Directory(self)],
         }
     }
@@ -75,6 +77,7 @@ impl Directory {
     }
 
     pub fn encode<'a>(self) -> LocalBoxStream<'a, Result<Block>> {
+        let hash = self.hash;
         async_stream::try_stream! {
             let mut links = Vec::new();
             for entry in self.entries {
@@ -128,7 +131,7 @@ impl Directory {
             let outer = encode_unixfs_pb(&inner, links)?;
 
             let node = UnixfsNode::Directory(Node { outer, inner });
-            yield node.encode()?;
+            yield node.encode_with_hash(hash)?;
         }
         .boxed_local()
     }
@@ -165,6 +168,7 @@ pub struct File {
     content: Content,
     tree_builder: TreeBuilder,
     chunker: Chunker,
+    hash: Code,
 }
 
 impl Debug for File {
@@ -174,6 +178,7 @@ impl Debug for File {
             .field("content", &self.content)
             .field("tree_builder", &self.tree_builder)
             .field("chunker", &self.chunker)
+            .field("hash", &self.hash)
             .finish()
     }
 }
@@ -186,6 +191,7 @@ impl File {
     pub fn wrap(self) -> Directory {
         Directory {
             name: "".into(),
+            hash: self.hash,
             entries: vec![Entry::File(self)],
         }
     }
@@ -221,6 +227,7 @@ impl File {
 pub struct Symlink {
     name: String,
     target: PathBuf,
+    hash: Code,
 }
 
 impl Symlink {
@@ -233,12 +240,14 @@ impl Symlink {
                 .unwrap_or_default()
                 .to_string(),
             target: target.into(),
+            hash: DEFAULT_HASH,
         }
     }
 
     pub fn wrap(self) -> Directory {
         Directory {
             name: "".into(),
+            hash: self.hash,
             entries: vec![Entry::Symlink(self)],
         }
     }
@@ -260,7 +269,7 @@ impl Symlink {
         };
         let outer = encode_unixfs_pb(&inner, Vec::new())?;
         let node = UnixfsNode::Symlink(Node { outer, inner });
-        node.encode()
+        node.encode_with_hash(self.hash)
     }
 }
 
@@ -270,8 +279,9 @@ pub struct FileBuilder {
     name: Option<String>,
     path: Option<PathBuf>,
     reader: Option<Pin<Box<dyn AsyncRead>>>,
-    chunk_size: Option<usize>,
+    chunker: Option<Chunker>,
     degree: Option<usize>,
+    hash: Option<Code>,
 }
 
 impl Debug for FileBuilder {
@@ -284,8 +294,9 @@ impl Debug for FileBuilder {
         f.debug_struct("FileBuilder")
             .field("path", &self.path)
             .field("name", &self.name)
-            .field("chunk_size", &self.chunk_size)
+            .field("chunker", &self.chunker)
             .field("degree", &self.degree)
+            .field("hash", &self.hash)
             .field("reader", &reader)
             .finish()
     }
@@ -307,8 +318,15 @@ impl FileBuilder {
         self
     }
 
+    /// Uses fixed size chunks of `chunk_size` bytes, see [`FileBuilder::chunker`].
     pub fn chunk_size(&mut self, chunk_size: usize) -> &mut Self {
-        self.chunk_size = Some(chunk_size);
+        self.chunker = Some(Chunker::fixed_with_size(chunk_size));
+        self
+    }
+
+    /// Sets how the content is split into leaves, defaults to [`Chunker::default`].
+    pub fn chunker(&mut self, chunker: Chunker) -> &mut Self {
+        self.chunker = Some(chunker);
         self
     }
 
@@ -317,6 +335,12 @@ impl FileBuilder {
         self
     }
 
+    /// Sets the multihash used to derive the cids of the file's blocks, defaults to sha2-256.
+    pub fn hash(&mut self, hash: Code) -> &mut Self {
+        self.hash = Some(hash);
+        self
+    }
+
     pub fn content_bytes<B: Into<Bytes>>(&mut self, content: B) -> &mut Self {
         let bytes = content.into();
         self.reader = Some(Box::pin(std::io::Cursor::new(bytes)));
@@ -329,10 +353,10 @@ impl FileBuilder {
     }
 
     pub async fn build(self) -> Result<File> {
-        let chunk_size = self.chunk_size.unwrap_or(DEFAULT_CHUNKS_SIZE);
+        let chunker = self.chunker.unwrap_or_default();
         let degree = self.degree.unwrap_or(DEFAULT_DEGREE);
-        let chunker = Chunker::fixed_with_size(chunk_size);
-        let tree_builder = TreeBuilder::balanced_tree_with_degree(degree);
+        let hash = self.hash.unwrap_or(DEFAULT_HASH);
+        let tree_builder = TreeBuilder::balanced_tree_with_degree_and_hash(degree, hash);
         if let Some(path) = self.path {
             let name = match self.name {
                 Some(n) => n,
@@ -347,6 +371,7 @@ impl FileBuilder {
                 name,
                 chunker,
                 tree_builder,
+                hash,
             });
         }
 
@@ -360,6 +385,7 @@ impl FileBuilder {
                 name,
                 chunker,
                 tree_builder,
+                hash,
             });
         }
         anyhow::bail!("must have a path to the content or a reader for the content");
@@ -381,6 +407,7 @@ pub struct DirectoryBuilder {
     name: Option<String>,
     entries: Vec<Entry>,
     typ: DirectoryType,
+    hash: Code,
 }
 
 impl Default for DirectoryBuilder {
@@ -389,6 +416,7 @@ impl Default for DirectoryBuilder {
             name: None,
             entries: Default::default(),
             typ: DirectoryType::Basic,
+            hash: DEFAULT_HASH,
         }
     }
 }
@@ -408,6 +436,13 @@ impl DirectoryBuilder {
         self
     }
 
+    /// Sets the multihash used to derive the cid of the directory node, defaults to sha2-256.
+    /// Entries keep the hash they were built with.
+    pub fn hash(&mut self, hash: Code) -> &mut Self {
+        self.hash = hash;
+        self
+    }
+
     pub fn add_dir(&mut self, dir: Directory) -> Result<&mut Self> {
         Ok(self.entry(Entry::Directory(dir)))
     }
@@ -430,14 +465,21 @@ impl DirectoryBuilder {
 
     pub fn build(self) -> Result<Directory> {
         let DirectoryBuilder {
-            name, entries, typ, ..
+            name,
+            entries,
+            typ,
+            hash,
         } = self;
 
         ensure!(typ == DirectoryType::Basic, "too many links to fit into one chunk, must be encoded as a HAMT. However, HAMT creation has not yet been implemented.");
 
         let name = name.unwrap_or_default();
 
-        Ok(Directory { name, entries })
+        Ok(Directory {
+            name,
+            entries,
+            hash,
+        })
     }
 }
 
@@ -446,6 +488,7 @@ impl DirectoryBuilder {
 pub struct SymlinkBuilder {
     path: PathBuf,
     target: Option<PathBuf>,
+    hash: Code,
 }
 
 impl SymlinkBuilder {
@@ -453,6 +496,7 @@ impl SymlinkBuilder {
         Self {
             path: path.into(),
             target: None,
+            hash: DEFAULT_HASH,
         }
     }
 
@@ -461,6 +505,12 @@ impl SymlinkBuilder {
         self
     }
 
+    /// Sets the multihash used to derive the cid of the symlink, defaults to sha2-256.
+    pub fn hash(&mut self, hash: Code) -> &mut Self {
+        self.hash = hash;
+        self
+    }
+
     pub async fn build(self) -> Result<Symlink> {
         let name = self
             .path
@@ -472,7 +522,11 @@ impl SymlinkBuilder {
             Some(target) => target,
             None => tokio::fs::read_link(&self.path).await?,
         };
-        Ok(Symlink { name, target })
+        Ok(Symlink {
+            name,
+            target,
+            hash: self.hash,
+        })
     }
 }
 
@@ -565,14 +619,20 @@ impl Store for Arc<tokio::sync::Mutex<std::collections::HashMap<Cid, Bytes>>> {
 /// - storing the content using `rpc.store`
 /// - returns a stream of AddEvent
 /// - optionally wraps into a UnixFs directory to preserve the filename
+/// - splits the content using `chunker`
+/// - derives the cids of all blocks with `hash`
 pub async fn add_file<S: Store>(
     store: Option<S>,
     path: &Path,
     wrap: bool,
+    chunker: Chunker,
+    hash: Code,
 ) -> Result<impl Stream<Item = Result<AddEvent>>> {
     ensure!(path.is_file(), "provided path was not a file");
 
-    let file = FileBuilder::new().path(path).build().await?;
+    let mut file = FileBuilder::new().path(path);
+    file.chunker(chunker).hash(hash);
+    let file = file.build().await?;
 
     let blocks = {
         if wrap {
@@ -589,14 +649,18 @@ pub async fn add_file<S: Store>(
 /// - storing the content using `rpc.store`
 /// - returns a stream of AddEvent
 /// - optionally wraps into a UnixFs directory to preserve the directory name
+/// - splits the content of all files using `chunker`
+/// - derives the cids of all blocks with `hash`
 pub async fn add_dir<S: Store>(
     store: Option<S>,
     path: &Path,
     wrap: bool,
+    chunker: Chunker,
+    hash: Code,
 ) -> Result<impl Stream<Item = Result<AddEvent>>> {
     ensure!(path.is_dir(), "provided path was not a directory");
 
-    let dir = make_dir_from_path(path).await?;
+    let dir = make_dir_from_path(path, &chunker, hash).await?;
 
     // encode and store
     let blocks = {
@@ -611,14 +675,17 @@ pub async fn add_dir<S: Store>(
     Ok(add_blocks_to_store(store, blocks).await)
 }
 
-/// Adds a symlink
+/// Adds a symlink, deriving its cid with `hash`
 pub async fn add_symlink<S: Store>(
     store: Option<S>,
     path: &Path,
     wrap: bool,
+    hash: Code,
 ) -> Result<impl Stream<Item = Result<AddEvent>>> {
     ensure!(path.is_symlink(), "provided path was not a symlink");
-    let symlink = SymlinkBuilder::new(path).build().await?;
+    let mut symlink = SymlinkBuilder::new(path);
+    symlink.hash(hash);
+    let symlink = symlink.build().await?;
     if wrap {
         let dir = symlink.wrap();
         let blocks = dir.encode();
@@ -707,26 +774,33 @@ pub async fn add_blocks_to_store<S: Store>(
 }
 
 #[async_recursion(?Send)]
-async fn make_dir_from_path<P: Into<PathBuf>>(path: P) -> Result<Directory> {
+async fn make_dir_from_path<P: Into<PathBuf>>(
+    path: P,
+    chunker: &Chunker,
+    hash: Code,
+) -> Result<Directory> {
     let path = path.into();
     let mut dir = DirectoryBuilder::new();
     dir.name(
         path.file_name()
             .and_then(|s| s.to_str())
             .unwrap_or_default(),
-    );
+    )
+    .hash(hash);
 
     let mut directory_reader = tokio::fs::read_dir(path.clone()).await?;
     while let Some(entry) = directory_reader.next_entry().await? {
         let path = entry.path();
         if path.is_symlink() {
-            let s = SymlinkBuilder::new(path).build().await?;
-            dir.add_symlink(s);
+            let mut s = SymlinkBuilder::new(path);
+            s.hash(hash);
+            dir.add_symlink(s.build().await?);
         } else if path.is_file() {
-            let f = FileBuilder::new().path(path).build().await?;
-            dir.add_file(f);
+            let mut f = FileBuilder::new().path(path);
+            f.chunker(chunker.clone()).hash(hash);
+            dir.add_file(f.build().await?);
         } else if path.is_dir() {
-            let d = make_dir_from_path(path).await?;
+            let d = make_dir_from_path(path, chunker, hash).await?;
             dir.add_dir(d)?;
         } else {
             anyhow::bail!("directory entry is neither file nor directory")
@@ -740,7 +814,9 @@ mod tests {
     use crate::resolver::{ContentLoader, Out, OutMetrics, Resolver};
 
     use super::*;
+    use crate::chunker::DEFAULT_CHUNKS_SIZE;
     use crate::resolver::ResponseClip;
+    use crate::unixfs::hash_from_name;
     use anyhow::{Context, Result};
     use futures::TryStreamExt;
     use proptest::prelude::*;
@@ -1050,6 +1126,56 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_builder_hash() -> Result<()> {
+        let data = b"hello world".to_vec();
+        let mut cids = Vec::new();
+        for hash in [Code::Sha2_256, Code::Blake3_256] {
+            let file = FileBuilder::new()
+                .name("foo.txt")
+                .content_bytes(data.clone())
+                .hash(hash)
+                .build()
+                .await?;
+            let block = file.encode_root().await?;
+            let cid = *block.cid();
+            assert_eq!(cid.hash().code(), u64::from(hash));
+            assert_eq!(iroh_util::verify_hash(&cid, block.data()), Some(true));
+            cids.push(cid);
+        }
+        assert_ne!(cids[0], cids[1]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_add_dir_hash() -> Result<()> {
+        let dir = std::env::temp_dir().join("test_add_dir_hash");
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .create(dir.join("nested"))?;
+        std::fs::write(dir.join("foo.txt"), b"hello world")?;
+        std::fs::write(dir.join("nested").join("bar.txt"), b"hello world again")?;
+
+        let hash = hash_from_name("blake3")?;
+        let store: Arc<tokio::sync::Mutex<std::collections::HashMap<Cid, Bytes>>> =
+            Default::default();
+        let events: Vec<_> = add_dir(Some(store.clone()), &dir, true, Chunker::default(), hash)
+            .await?
+            .try_collect()
+            .await?;
+        assert!(!events.is_empty());
+
+        let store = store.lock().await;
+        // the wrapping directory, both directories and both files
+        assert_eq!(store.len(), 5);
+        for (cid, data) in store.iter() {
+            assert_eq!(cid.hash().code(), u64::from(Code::Blake3_256));
+            assert_eq!(iroh_util::verify_hash(cid, data), Some(true));
+        }
+        assert!(hash_from_name("md5").is_err());
+        Ok(())
+    }
+
     /// sync version of file_roundtrip_test for use in proptest
     fn file_roundtrip_test_sync(data: Bytes, chunk_size: usize, degree: usize) -> bool {
         let f = file_roundtrip_test(data, chunk_size, degree);
@@ -1103,6 +1229,129 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_default_chunker_cid() -> Result<()> {
+        // `ipfs add --cid-version 1` of the same content
+        let mut file = FileBuilder::new();
+        file.name("hello.txt").content_bytes(&b"hello world"[..]);
+        let root = file.build().await?.encode_root().await?;
+        assert_eq!(
+            root.cid().to_string(),
+            "bafkreifzjut3te2nhyekklss27nh3k72ysco7y32koao5eei66wof36n5e"
+        );
+        Ok(())
+    }
+
+    /// The payloads of the leaves of `root`, read from the resolver fixtures.
+    async fn fixture_chunks(root: &UnixfsNode) -> Result<Vec<Bytes>> {
+        let mut chunks = Vec::new();
+        for link in root.links() {
+            let cid = link?.cid;
+            let bytes = tokio::fs::read(format!("./fixtures/{}", cid)).await?;
+            let chunk = match UnixfsNode::decode(&cid, bytes.into())? {
+                UnixfsNode::Raw(data) => data,
+                UnixfsNode::File(node) | UnixfsNode::RawNode(node) => {
+                    node.data().unwrap_or_default()
+                }
+                node => anyhow::bail!("unexpected leaf {:?}", node.typ()),
+            };
+            chunks.push(chunk);
+        }
+        Ok(chunks)
+    }
+
+    #[tokio::test]
+    async fn test_fixed_size_chunker_matches_go_ipfs() -> Result<()> {
+        // QmUr9cs4mhWxabKqm9PYPSQQ6AQGbHJBtyrNmxtKgxqUx9 README.md, imported with
+        // `go-ipfs add --chunker size-100`. Its leaves are dag-pb nodes, while ours are raw,
+        // so the chunks and sizes are compared instead of the cids.
+        let go_root_cid: Cid = "QmUr9cs4mhWxabKqm9PYPSQQ6AQGbHJBtyrNmxtKgxqUx9".parse()?;
+        let go_root = UnixfsNode::decode(
+            &go_root_cid,
+            tokio::fs::read(format!("./fixtures/{}", go_root_cid))
+                .await?
+                .into(),
+        )?;
+        let go_chunks = fixture_chunks(&go_root).await?;
+        assert!(go_chunks.len() > 1);
+        let content: Vec<u8> = go_chunks.iter().flatten().copied().collect();
+
+        let mut file = FileBuilder::new();
+        file.name("README.md")
+            .chunker("size-100".parse()?)
+            .content_bytes(content);
+        let blocks: Vec<Block> = file.build().await?.encode().await?.try_collect().await?;
+        let (root, leaves) = blocks.split_last().context("no blocks")?;
+
+        let chunks: Vec<_> = leaves.iter().map(|leaf| leaf.data().clone()).collect();
+        assert_eq!(chunks, go_chunks);
+        let root = UnixfsNode::decode(root.cid(), root.data().clone())?;
+        assert_eq!(root.filesize(), go_root.filesize());
+        assert_eq!(root.blocksizes(), go_root.blocksizes());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rabin_chunker_multiple_chunks() -> Result<()> {
+        // there is no go-ipfs fixture for rabin chunking, so only the structure is checked
+        let mut rng = ChaCha8Rng::from_seed([0; 32]);
+        let mut data = vec![0u8; 1024 * 64];
+        rng.fill(data.as_mut_slice());
+
+        let mut file = FileBuilder::new();
+        file.name("data")
+            .chunker("rabin-256-1024-4096".parse()?)
+            .content_bytes(data.clone());
+        let blocks: Vec<Block> = file.build().await?.encode().await?.try_collect().await?;
+        let (root, leaves) = blocks.split_last().context("no blocks")?;
+
+        let sizes: Vec<u64> = leaves.iter().map(|l| l.data().len() as u64).collect();
+        assert!(sizes.len() > 1);
+        let (last, rest) = sizes.split_last().unwrap();
+        assert!(rest.iter().all(|size| (256..=4096).contains(size)));
+        assert!(*last <= 4096);
+        // content defined, so not every chunk is cut at the maximum size
+        assert!(rest.iter().any(|size| *size < 4096));
+        let content: Vec<u8> = leaves.iter().flat_map(|l| l.data().to_vec()).collect();
+        assert_eq!(content, data);
+
+        let root = UnixfsNode::decode(root.cid(), root.data().clone())?;
+        assert_eq!(root.filesize(), Some(data.len() as u64));
+        assert_eq!(root.blocksizes(), sizes);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_chunker_cid_is_deterministic() -> Result<()> {
+        let mut rng = ChaCha8Rng::from_seed([0; 32]);
+        let mut data = vec![0u8; 1024 * 64];
+        rng.fill(data.as_mut_slice());
+        let data = Bytes::from(data);
+
+        for chunker in ["size-1000", "rabin-256-1024-4096"] {
+            let chunker: Chunker = chunker.parse()?;
+
+            let mut file = FileBuilder::new();
+            file.name("data")
+                .chunker(chunker.clone())
+                .content_bytes(data.clone());
+            let root = file.build().await?.encode_root().await?;
+
+            // the same content, delivered in small reads that do not line up with the chunks
+            let pieces: Vec<std::io::Result<Bytes>> = data
+                .chunks(333)
+                .map(|piece| Ok(Bytes::copy_from_slice(piece)))
+                .collect();
+            let reader = tokio_util::io::StreamReader::new(futures::stream::iter(pieces));
+            let mut file = FileBuilder::new();
+            file.name("data").chunker(chunker).content_reader(reader);
+            let root_from_reader = file.build().await?.encode_root().await?;
+
+            assert_eq!(root.cid(), root_from_reader.cid());
+        }
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_builder_roundtrip_128m() -> Result<()> {
         // fill with random data so we get distinct cids for all blocks
@@ -1255,6 +1504,7 @@ mod tests {
                     .unwrap(),
             ),
             entries: vec![Entry::File(nested_file)],
+            hash: DEFAULT_HASH,
         };
 
         let file = FileBuilder::new().path(file_path).build().await?;
@@ -1262,9 +1512,10 @@ mod tests {
         let expected = Directory {
             name: String::from(dir.clone().file_name().and_then(|s| s.to_str()).unwrap()),
             entries: vec![Entry::File(file), Entry::Directory(nested_dir)],
+            hash: DEFAULT_HASH,
         };
 
-        let mut got = make_dir_from_path(dir).await?;
+        let mut got = make_dir_from_path(dir, &Chunker::default(), DEFAULT_HASH).await?;
 
         // Before comparison sort entries to make test deterministic.
         // The readdir_r function is used in the underlying platform which
@@ -1,5 +1,6 @@
 use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::{Context, Result};
 #[cfg(feature = "grpc")]
@@ -8,6 +9,7 @@ use futures::{Stream, StreamExt};
 use crate::config::Config;
 use crate::gateway::GatewayClient;
 use crate::network::P2pClient;
+use crate::retry::RetryPolicy;
 use crate::store::StoreClient;
 
 #[derive(Debug, Clone)]
@@ -15,6 +17,7 @@ pub struct Client {
     pub gateway: Option<GatewayClient>,
     p2p: P2pLBClient,
     store: StoreLBClient,
+    fallback_stores: Vec<StoreLBClient>,
 }
 
 /// Provides a load balanced client for the store service
@@ -77,14 +80,21 @@ impl Client {
             gateway_addr,
             p2p_addr,
             store_addr,
+            fallback_store_addrs,
             channels,
+            max_retries,
+            keepalive_secs,
         } = cfg;
 
+        let retry = RetryPolicy::new(max_retries.unwrap_or_default());
+        let keepalive = keepalive_secs.map(Duration::from_secs);
+
         let gateway = if let Some(addr) = gateway_addr {
             Some(
-                GatewayClient::new(addr)
+                GatewayClient::new_with_keepalive(addr, keepalive)
                     .await
-                    .context("Could not create gateway rpc client")?,
+                    .context("Could not create gateway rpc client")?
+                    .with_retry_policy(retry),
             )
         } else {
             None
@@ -95,9 +105,10 @@ impl Client {
         let mut p2p = P2pLBClient::new();
         if let Some(addr) = p2p_addr {
             for _i in 0..n_channels {
-                let sc = P2pClient::new(addr.clone())
+                let sc = P2pClient::new_with_keepalive(addr.clone(), keepalive)
                     .await
-                    .context("Could not create store rpc client")?;
+                    .context("Could not create store rpc client")?
+                    .with_retry_policy(retry);
                 p2p.clients.push(sc);
             }
         }
@@ -105,17 +116,32 @@ impl Client {
         let mut store = StoreLBClient::new();
         if let Some(addr) = store_addr {
             for _i in 0..n_channels {
-                let sc = StoreClient::new(addr.clone())
+                let sc = StoreClient::new_with_keepalive(addr.clone(), keepalive)
+                    .await
+                    .context("Could not create store rpc client")?
+                    .with_retry_policy(retry);
+                store.clients.push(sc);
+            }
+        }
+
+        let mut fallback_stores = Vec::with_capacity(fallback_store_addrs.len());
+        for addr in fallback_store_addrs {
+            let mut store = StoreLBClient::new();
+            for _i in 0..n_channels {
+                let sc = StoreClient::new_with_keepalive(addr.clone(), keepalive)
                     .await
-                    .context("Could not create store rpc client")?;
+                    .context("Could not create fallback store rpc client")?
+                    .with_retry_policy(retry);
                 store.clients.push(sc);
             }
+            fallback_stores.push(store);
         }
 
         Ok(Client {
             gateway,
             p2p,
             store,
+            fallback_stores,
         })
     }
 
@@ -133,6 +159,14 @@ impl Client {
         self.store.get().context("missing rpc store connnection")
     }
 
+    /// One client for each configured fallback store, in the order they are consulted.
+    pub fn fallback_stores(&self) -> Vec<StoreClient> {
+        self.fallback_stores
+            .iter()
+            .filter_map(|store| store.get())
+            .collect()
+    }
+
     #[cfg(feature = "grpc")]
     pub async fn check(&self) -> crate::status::StatusTable {
         let g = if let Some(ref g) = self.gateway {
@@ -224,6 +258,23 @@ mod tests {
         Ok((reporter, task))
     }
 
+    #[tokio::test]
+    async fn client_keepalive() {
+        let mut cfg = Config::default_grpc();
+        cfg.keepalive_secs = Some(5);
+        let client = Client::new(cfg).await.unwrap();
+
+        let expect = Some(Duration::from_secs(5));
+        assert_eq!(client.try_gateway().unwrap().keepalive(), expect);
+        assert_eq!(client.try_p2p().unwrap().keepalive(), expect);
+        assert_eq!(client.try_store().unwrap().keepalive(), expect);
+
+        let mut cfg = Config::default_grpc();
+        cfg.keepalive_secs = None;
+        let client = Client::new(cfg).await.unwrap();
+        assert_eq!(client.try_store().unwrap().keepalive(), None);
+    }
+
     #[tokio::test]
     async fn client_status() {
         let cfg = Config::default_grpc();
@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use config::{ConfigError, Map, Source, Value};
 use iroh_rpc_types::{gateway::GatewayClientAddr, p2p::P2pClientAddr, store::StoreClientAddr};
 use iroh_util::insert_into_config_map;
@@ -12,8 +14,15 @@ pub struct Config {
     pub p2p_addr: Option<P2pClientAddr>,
     // store rpc address
     pub store_addr: Option<StoreClientAddr>,
+    // slower store rpc addresses, consulted in order when a block is missing from the store
+    #[serde(default)]
+    pub fallback_store_addrs: Vec<StoreClientAddr>,
     // number of concurent channels
     pub channels: Option<usize>,
+    // number of times idempotent calls are retried on transient transport errors
+    pub max_retries: Option<usize>,
+    // interval in seconds of tcp & http2 keepalives on idle rpc connections
+    pub keepalive_secs: Option<u64>,
 }
 
 impl Source for Config {
@@ -32,9 +41,23 @@ impl Source for Config {
         if let Some(addr) = &self.store_addr {
             insert_into_config_map(&mut map, "store_addr", addr.to_string());
         }
+        if !self.fallback_store_addrs.is_empty() {
+            let addrs: Vec<String> = self
+                .fallback_store_addrs
+                .iter()
+                .map(|addr| addr.to_string())
+                .collect();
+            insert_into_config_map(&mut map, "fallback_store_addrs", addrs);
+        }
         if let Some(channels) = &self.channels {
             insert_into_config_map(&mut map, "channels", channels.to_string());
         }
+        if let Some(max_retries) = &self.max_retries {
+            insert_into_config_map(&mut map, "max_retries", max_retries.to_string());
+        }
+        if let Some(keepalive_secs) = &self.keepalive_secs {
+            insert_into_config_map(&mut map, "keepalive_secs", keepalive_secs.to_string());
+        }
         Ok(map)
     }
 }
@@ -45,9 +68,17 @@ impl Config {
             gateway_addr: Some("grpc://0.0.0.0:4400".parse().unwrap()),
             p2p_addr: Some("grpc://0.0.0.0:4401".parse().unwrap()),
             store_addr: Some("grpc://0.0.0.0:4402".parse().unwrap()),
+            fallback_store_addrs: Vec::new(),
             channels: Some(16),
+            max_retries: Some(2),
+            keepalive_secs: Some(30),
         }
     }
+
+    /// Keepalive interval for rpc connections, if enabled.
+    pub fn keepalive(&self) -> Option<Duration> {
+        self.keepalive_secs.map(Duration::from_secs)
+    }
 }
 
 #[cfg(test)]
@@ -75,6 +106,14 @@ mod tests {
             "channels".to_string(),
             Value::new(None, default.channels.unwrap().to_string()),
         );
+        expect.insert(
+            "max_retries".to_string(),
+            Value::new(None, default.max_retries.unwrap().to_string()),
+        );
+        expect.insert(
+            "keepalive_secs".to_string(),
+            Value::new(None, default.keepalive_secs.unwrap().to_string()),
+        );
         let got = Config::default().collect().unwrap();
         for key in got.keys() {
             let left = expect.get(key).unwrap();
@@ -19,7 +19,11 @@ impl_client!(Gateway);
 impl GatewayClient {
     #[tracing::instrument(skip(self))]
     pub async fn version(&self) -> Result<String> {
-        let res = self.backend.version(()).await?;
+        let backend = &self.backend;
+        let res = self
+            .retry
+            .call("gateway.version", move || backend.version(()))
+            .await?;
         Ok(res.version)
     }
 }
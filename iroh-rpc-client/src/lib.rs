@@ -5,13 +5,15 @@ mod client;
 mod config;
 mod gateway;
 mod network;
+mod retry;
 #[cfg(feature = "grpc")]
 mod status;
 mod store;
 
 pub use crate::client::Client;
 pub use crate::config::Config;
-pub use crate::network::{Lookup, P2pClient};
+pub use crate::network::{Lookup, P2pClient, ProtocolStats, Want, Wantlist};
+pub use crate::retry::{is_idempotent, RetryPolicy};
 #[cfg(feature = "grpc")]
 pub use crate::status::{ServiceStatus, StatusRow, StatusTable};
 pub use crate::store::StoreClient;
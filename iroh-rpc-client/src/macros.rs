@@ -14,23 +14,40 @@ macro_rules! impl_client {
             #[derive(Debug, Clone)]
             pub struct [<$label Client>] {
                 backend: [<$label ClientBackend>],
+                retry: $crate::RetryPolicy,
+                keepalive: Option<std::time::Duration>,
             }
 
             impl [<$label Client>] {
                 pub async fn new(addr: [<$label ClientAddr>]) -> Result<Self> {
+                    Self::new_with_keepalive(addr, None).await
+                }
+
+                /// Creates a client whose connection sends tcp and http2 keepalives at the
+                /// given interval, so idle connections are not dropped by intermediaries.
+                pub async fn new_with_keepalive(
+                    addr: [<$label ClientAddr>],
+                    keepalive: Option<std::time::Duration>,
+                ) -> Result<Self> {
                     tracing::info!("connecting to {}", addr);
                     match addr {
                         #[cfg(feature = "grpc")]
                         Addr::GrpcHttp2(addr) => {
-                            let conn = Endpoint::new(format!("http://{}", addr))?
+                            let mut endpoint = Endpoint::new(format!("http://{}", addr))?
                                 .keep_alive_while_idle(true)
-                                .connect_lazy();
+                                .tcp_keepalive(keepalive);
+                            if let Some(interval) = keepalive {
+                                endpoint = endpoint.http2_keep_alive_interval(interval);
+                            }
+                            let conn = endpoint.connect_lazy();
 
                             let client = [<Grpc $label Client>]::new(conn.clone());
                             let health = HealthClient::new(conn);
 
                             Ok([<$label Client>] {
                                 backend: [<$label ClientBackend>]::Grpc { client, health },
+                                retry: Default::default(),
+                                keepalive,
                             })
                         }
                         #[cfg(all(feature = "grpc", unix))]
@@ -40,8 +57,12 @@ macro_rules! impl_client {
 
                             let path = std::sync::Arc::new(path);
                             // dummy addr
-                            let conn = Endpoint::new("http://[..]:50051")?
-                                .keep_alive_while_idle(true)
+                            let mut endpoint = Endpoint::new("http://[..]:50051")?
+                                .keep_alive_while_idle(true);
+                            if let Some(interval) = keepalive {
+                                endpoint = endpoint.http2_keep_alive_interval(interval);
+                            }
+                            let conn = endpoint
                                 .connect_with_connector_lazy(tower::service_fn(move |_: Uri| {
                                     let path = path.clone();
                                     UnixStream::connect(path.as_ref().clone())
@@ -52,15 +73,30 @@ macro_rules! impl_client {
 
                             Ok([<$label Client>] {
                                 backend: [<$label ClientBackend>]::Grpc { client, health },
+                                retry: Default::default(),
+                                keepalive,
                             })
                         }
                         #[cfg(feature = "mem")]
                         Addr::Mem(s) => Ok([<$label Client>] {
                             backend: [<$label ClientBackend>]::Mem(s),
+                            retry: Default::default(),
+                            keepalive,
                         }),
                     }
                 }
 
+                /// Sets how idempotent calls are retried on transient transport errors.
+                pub fn with_retry_policy(mut self, retry: $crate::RetryPolicy) -> Self {
+                    self.retry = retry;
+                    self
+                }
+
+                /// The keepalive interval applied to this client's connection, if any.
+                pub fn keepalive(&self) -> Option<std::time::Duration> {
+                    self.keepalive
+                }
+
                 #[cfg(feature = "grpc")]
                 #[tracing::instrument(skip(self))]
                 pub async fn check(&self) -> StatusRow {
@@ -1,4 +1,5 @@
 use std::collections::{HashMap, HashSet};
+use std::time::Duration;
 
 use anyhow::{Context, Result};
 use bytes::Bytes;
@@ -10,7 +11,7 @@ use iroh_rpc_types::p2p::{
     BitswapBlock, BitswapRequest, ConnectByPeerIdRequest, ConnectRequest, DisconnectRequest,
     GossipsubPeerAndTopics, GossipsubPeerIdMsg, GossipsubPublishRequest, GossipsubTopicHashMsg,
     Key, LookupRequest, NotifyNewBlocksBitswapRequest, P2p, P2pClientAddr, P2pClientBackend,
-    PeerInfo, Providers, StopSessionBitswapRequest,
+    PeerInfo, ProtocolStat, Providers, StopSessionBitswapRequest, WantlistEntry, WantlistRequest,
 };
 use iroh_rpc_types::Addr;
 use libp2p::gossipsub::{MessageId, TopicHash};
@@ -29,20 +30,32 @@ impl_client!(P2p);
 impl P2pClient {
     #[tracing::instrument(skip(self))]
     pub async fn version(&self) -> Result<String> {
-        let res = self.backend.version(()).await?;
+        let backend = &self.backend;
+        let res = self
+            .retry
+            .call("p2p.version", move || backend.version(()))
+            .await?;
         Ok(res.version)
     }
 
     #[tracing::instrument(skip(self))]
     pub async fn local_peer_id(&self) -> Result<PeerId> {
-        let res = self.backend.local_peer_id(()).await?;
+        let backend = &self.backend;
+        let res = self
+            .retry
+            .call("p2p.local_peer_id", move || backend.local_peer_id(()))
+            .await?;
         let peer_id = PeerId::from_bytes(&res.peer_id[..])?;
         Ok(peer_id)
     }
 
     #[tracing::instrument(skip(self))]
     pub async fn external_addresses(&self) -> Result<Vec<Multiaddr>> {
-        let res = self.backend.external_addrs(()).await?;
+        let backend = &self.backend;
+        let res = self
+            .retry
+            .call("p2p.external_addrs", move || backend.external_addrs(()))
+            .await?;
         let addrs = addrs_from_bytes(res.addrs)?;
         Ok(addrs)
     }
@@ -65,7 +78,13 @@ impl P2pClient {
             providers: Some(providers),
             ctx,
         };
-        let res = self.backend.fetch_bitswap(req).await?;
+        let backend = &self.backend;
+        let res = self
+            .retry
+            .call("p2p.fetch_bitswap", move || {
+                backend.fetch_bitswap(req.clone())
+            })
+            .await?;
         Ok(res.data)
     }
 
@@ -76,6 +95,44 @@ impl P2pClient {
         Ok(())
     }
 
+    /// Returns a page of the outstanding bitswap wants, starting at `offset`.
+    #[tracing::instrument(skip(self))]
+    pub async fn wantlist(&self, offset: usize, limit: usize) -> Result<Wantlist> {
+        let req = WantlistRequest {
+            offset: offset as u64,
+            limit: limit as u64,
+        };
+        let backend = &self.backend;
+        let res = self
+            .retry
+            .call("p2p.wantlist", move || backend.wantlist(req.clone()))
+            .await?;
+        let entries = res
+            .entries
+            .into_iter()
+            .map(Want::from_entry)
+            .collect::<Result<_>>()?;
+        Ok(Wantlist {
+            entries,
+            total: res.total as usize,
+        })
+    }
+
+    /// Returns the connected peers advertising each protocol, and the open bitswap substreams.
+    #[tracing::instrument(skip(self))]
+    pub async fn protocol_stats(&self) -> Result<Vec<ProtocolStats>> {
+        let backend = &self.backend;
+        let res = self
+            .retry
+            .call("p2p.protocol_stats", move || backend.protocol_stats(()))
+            .await?;
+        Ok(res
+            .protocols
+            .into_iter()
+            .map(ProtocolStats::from_stat)
+            .collect())
+    }
+
     #[tracing::instrument(skip(self))]
     pub async fn notify_new_blocks_bitswap(&self, blocks: Vec<(Cid, Bytes)>) -> Result<()> {
         let req = NotifyNewBlocksBitswapRequest {
@@ -133,7 +190,13 @@ impl P2pClient {
 
     #[tracing::instrument(skip(self))]
     pub async fn get_listening_addrs(&self) -> Result<(PeerId, Vec<Multiaddr>)> {
-        let res = self.backend.get_listening_addrs(()).await?;
+        let backend = &self.backend;
+        let res = self
+            .retry
+            .call("p2p.get_listening_addrs", move || {
+                backend.get_listening_addrs(())
+            })
+            .await?;
         let peer_id = PeerId::from_bytes(&res.peer_id[..])?;
         let addrs = addrs_from_bytes(res.addrs)?;
         Ok((peer_id, addrs))
@@ -141,7 +204,12 @@ impl P2pClient {
 
     #[tracing::instrument(skip(self))]
     pub async fn get_peers(&self) -> Result<HashMap<PeerId, Vec<Multiaddr>>> {
-        let peers = self.backend.get_peers(()).await?.peers;
+        let backend = &self.backend;
+        let peers = self
+            .retry
+            .call("p2p.get_peers", move || backend.get_peers(()))
+            .await?
+            .peers;
         let mut peers_map = HashMap::new();
         for (peer, addrs) in peers.into_iter() {
             let peer = peer.parse()?;
@@ -176,7 +244,11 @@ impl P2pClient {
             peer_id: peer_id.to_bytes(),
             addr: addr.map(|a| a.to_vec()),
         };
-        let peer_info = self.backend.lookup(req).await?;
+        let backend = &self.backend;
+        let peer_info = self
+            .retry
+            .call("p2p.lookup", move || backend.lookup(req.clone()))
+            .await?;
         Lookup::from_peer_info(peer_info)
     }
 
@@ -207,14 +279,27 @@ impl P2pClient {
 
     #[tracing::instrument(skip(self))]
     pub async fn gossipsub_all_mesh_peers(&self) -> Result<Vec<PeerId>> {
-        let res = self.backend.gossipsub_all_mesh_peers(()).await?;
+        let backend = &self.backend;
+        let res = self
+            .retry
+            .call("p2p.gossipsub_all_mesh_peers", move || {
+                backend.gossipsub_all_mesh_peers(())
+            })
+            .await?;
         let peer_ids = peer_ids_from_bytes(res.peers)?;
         Ok(peer_ids)
     }
 
     #[tracing::instrument(skip(self))]
     pub async fn gossipsub_all_peers(&self) -> Result<Vec<(PeerId, Vec<TopicHash>)>> {
-        let res = self.backend.gossipsub_all_peers(()).await?.all;
+        let backend = &self.backend;
+        let res = self
+            .retry
+            .call("p2p.gossipsub_all_peers", move || {
+                backend.gossipsub_all_peers(())
+            })
+            .await?
+            .all;
         let peers_and_topics = all_peers_from_bytes(res)?;
         Ok(peers_and_topics)
     }
@@ -224,7 +309,13 @@ impl P2pClient {
         let req = GossipsubTopicHashMsg {
             topic_hash: topic.into_string(),
         };
-        let res = self.backend.gossipsub_mesh_peers(req).await?;
+        let backend = &self.backend;
+        let res = self
+            .retry
+            .call("p2p.gossipsub_mesh_peers", move || {
+                backend.gossipsub_mesh_peers(req.clone())
+            })
+            .await?;
         let peer_ids = peer_ids_from_bytes(res.peers)?;
         Ok(peer_ids)
     }
@@ -260,7 +351,11 @@ impl P2pClient {
 
     #[tracing::instrument(skip(self))]
     pub async fn gossipsub_topics(&self) -> Result<Vec<TopicHash>> {
-        let res = self.backend.gossipsub_topics(()).await?;
+        let backend = &self.backend;
+        let res = self
+            .retry
+            .call("p2p.gossipsub_topics", move || backend.gossipsub_topics(()))
+            .await?;
         let topics = res.topics.into_iter().map(TopicHash::from_raw).collect();
         Ok(topics)
     }
@@ -301,6 +396,54 @@ impl Lookup {
     }
 }
 
+#[derive(Debug)]
+pub struct Wantlist {
+    pub entries: Vec<Want>,
+    /// Total number of outstanding wants, of which `entries` is a page.
+    pub total: usize,
+}
+
+#[derive(Debug)]
+pub struct Want {
+    pub cid: Cid,
+    pub peer_id: PeerId,
+    pub priority: i32,
+    pub want_block: bool,
+    /// Time since the want was sent, `None` if it is still queued.
+    pub age: Option<Duration>,
+}
+
+impl Want {
+    fn from_entry(e: WantlistEntry) -> Result<Self> {
+        Ok(Self {
+            cid: Cid::read_bytes(&e.cid[..]).context("invalid cid")?,
+            peer_id: peer_id_from_bytes(e.peer_id)?,
+            priority: e.priority,
+            want_block: e.want_block,
+            age: e.age_ms.map(Duration::from_millis),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProtocolStats {
+    pub protocol: String,
+    /// Connected peers that advertised the protocol via identify.
+    pub peers: usize,
+    /// Open substreams, `None` if they are not tracked for this protocol.
+    pub substreams: Option<usize>,
+}
+
+impl ProtocolStats {
+    fn from_stat(s: ProtocolStat) -> Self {
+        Self {
+            protocol: s.protocol,
+            peers: s.peers as usize,
+            substreams: s.substreams.map(|n| n as usize),
+        }
+    }
+}
+
 fn peers_and_topics_from_bytes(pt: GossipsubPeerAndTopics) -> Result<(PeerId, Vec<TopicHash>)> {
     let peer_id = peer_id_from_bytes(pt.peer_id)?;
     let topics = pt.topics.into_iter().map(TopicHash::from_raw).collect();
@@ -338,7 +481,7 @@ mod tests {
         p2p_server, BitswapResponse, GetListeningAddrsResponse, GetPeersResponse,
         GossipsubAllPeersResponse, GossipsubPeersResponse, GossipsubPublishResponse,
         GossipsubSubscribeResponse, GossipsubTopicsResponse, Multiaddrs, PeerIdResponse,
-        VersionResponse,
+        ProtocolStatsResponse, VersionResponse, WantlistResponse,
     };
     use libp2p::gossipsub::IdentTopic;
     use tokio::net::TcpListener;
@@ -446,6 +589,20 @@ mod tests {
             todo!()
         }
 
+        async fn wantlist(
+            &self,
+            _request: Request<WantlistRequest>,
+        ) -> Result<tonic::Response<WantlistResponse>, tonic::Status> {
+            todo!()
+        }
+
+        async fn protocol_stats(
+            &self,
+            _request: Request<()>,
+        ) -> Result<tonic::Response<ProtocolStatsResponse>, tonic::Status> {
+            todo!()
+        }
+
         async fn notify_new_blocks_bitswap(
             &self,
             _request: Request<NotifyNewBlocksBitswapRequest>,
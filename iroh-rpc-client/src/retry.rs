@@ -0,0 +1,266 @@
+use std::future::Future;
+use std::time::Duration;
+
+use anyhow::Result;
+use iroh_metrics::{core::MRecorder, inc, rpc::RpcMetrics};
+use tracing::debug;
+
+/// Delay before the first retry, doubled for every following one.
+const RETRY_BACKOFF: Duration = Duration::from_millis(50);
+
+/// Methods that only read state, and can therefore be issued again if an attempt
+/// failed somewhere in transport. Anything not listed here is never retried.
+const IDEMPOTENT_METHODS: &[&str] = &[
+    "gateway.version",
+    "p2p.version",
+    "p2p.local_peer_id",
+    "p2p.external_addrs",
+    "p2p.fetch_bitswap",
+    "p2p.get_listening_addrs",
+    "p2p.get_peers",
+    "p2p.lookup",
+    "p2p.gossipsub_all_mesh_peers",
+    "p2p.gossipsub_all_peers",
+    "p2p.gossipsub_mesh_peers",
+    "p2p.gossipsub_topics",
+    "p2p.protocol_stats",
+    "p2p.wantlist",
+    "store.version",
+    "store.get",
+    "store.has",
+    "store.get_links",
+    "store.get_size",
+];
+
+/// Returns `true` if the given rpc method (`<service>.<method>`) is safe to retry.
+pub fn is_idempotent(method: &str) -> bool {
+    IDEMPOTENT_METHODS.contains(&method)
+}
+
+/// How often idempotent rpc calls are retried on transient transport errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RetryPolicy {
+    max_retries: usize,
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: usize) -> Self {
+        RetryPolicy { max_retries }
+    }
+
+    pub fn max_retries(&self) -> usize {
+        self.max_retries
+    }
+
+    /// Runs `f`, retrying it with exponential backoff if `method` is idempotent
+    /// and the attempt failed with a transient error.
+    pub(crate) async fn call<F, Fut, T>(&self, method: &'static str, f: F) -> Result<T>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let retries = if is_idempotent(method) {
+            self.max_retries
+        } else {
+            0
+        };
+
+        let mut backoff = RETRY_BACKOFF;
+        let mut attempt = 0;
+        loop {
+            match f().await {
+                Ok(res) => return Ok(res),
+                Err(err) if is_transient(&err) => {
+                    if attempt >= retries {
+                        if retries > 0 {
+                            inc!(RpcMetrics::RetriesExhausted);
+                        }
+                        return Err(err);
+                    }
+                    attempt += 1;
+                    debug!(
+                        "{}: transient error, retry {}/{}: {:?}",
+                        method, attempt, retries, err
+                    );
+                    inc!(RpcMetrics::Retries);
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+/// Transport level failures, where the request may not have been processed at all.
+fn is_transient(err: &anyhow::Error) -> bool {
+    #[cfg(feature = "grpc")]
+    if let Some(status) = err.downcast_ref::<tonic::Status>() {
+        return matches!(
+            status.code(),
+            tonic::Code::Unavailable | tonic::Code::Unknown | tonic::Code::DataLoss
+        );
+    }
+    #[cfg(not(feature = "grpc"))]
+    let _ = err;
+
+    false
+}
+
+#[cfg(all(test, feature = "grpc"))]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+    use bytes::Bytes;
+    use cid::Cid;
+    use iroh_rpc_types::store::{
+        store_server, GetLinksRequest, GetLinksResponse, GetRequest, GetResponse, GetSizeRequest,
+        GetSizeResponse, HasRequest, HasResponse, PutManyRequest, PutRequest, VersionResponse,
+    };
+    use iroh_rpc_types::Addr;
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::{Request, Response, Status};
+
+    use super::*;
+    use crate::store::StoreClient;
+
+    /// Store server that fails the first attempt of every call with a transport error.
+    #[derive(Debug, Default, Clone)]
+    struct FlakyStore {
+        attempts: Arc<AtomicUsize>,
+    }
+
+    impl FlakyStore {
+        fn attempt(&self) -> Result<(), Status> {
+            if self.attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                Err(Status::unavailable("connection reset"))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    #[async_trait]
+    impl store_server::Store for FlakyStore {
+        async fn version(&self, _: Request<()>) -> Result<Response<VersionResponse>, Status> {
+            self.attempt()?;
+            Ok(Response::new(VersionResponse {
+                version: "test".to_string(),
+            }))
+        }
+
+        async fn put(&self, _: Request<PutRequest>) -> Result<Response<()>, Status> {
+            self.attempt()?;
+            Ok(Response::new(()))
+        }
+
+        async fn put_many(&self, _: Request<PutManyRequest>) -> Result<Response<()>, Status> {
+            self.attempt()?;
+            Ok(Response::new(()))
+        }
+
+        async fn get(&self, _: Request<GetRequest>) -> Result<Response<GetResponse>, Status> {
+            self.attempt()?;
+            Ok(Response::new(GetResponse { data: None }))
+        }
+
+        async fn has(&self, _: Request<HasRequest>) -> Result<Response<HasResponse>, Status> {
+            self.attempt()?;
+            Ok(Response::new(HasResponse { has: true }))
+        }
+
+        async fn get_links(
+            &self,
+            _: Request<GetLinksRequest>,
+        ) -> Result<Response<GetLinksResponse>, Status> {
+            self.attempt()?;
+            Ok(Response::new(GetLinksResponse { links: Vec::new() }))
+        }
+
+        async fn get_size(
+            &self,
+            _: Request<GetSizeRequest>,
+        ) -> Result<Response<GetSizeResponse>, Status> {
+            self.attempt()?;
+            Ok(Response::new(GetSizeResponse { size: None }))
+        }
+    }
+
+    async fn spawn_flaky_store() -> (StoreClient, FlakyStore, tokio::task::JoinHandle<()>) {
+        let store = FlakyStore::default();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let service = store_server::StoreServer::new(store.clone());
+        let task = tokio::spawn(async move {
+            tonic::transport::Server::builder()
+                .add_service(service)
+                .serve_with_incoming(TcpListenerStream::new(listener))
+                .await
+                .unwrap();
+        });
+        let client = StoreClient::new(Addr::GrpcHttp2(addr))
+            .await
+            .unwrap()
+            .with_retry_policy(RetryPolicy::new(2));
+        (client, store, task)
+    }
+
+    #[test]
+    fn test_is_idempotent() {
+        assert!(is_idempotent("store.get"));
+        assert!(is_idempotent("p2p.fetch_bitswap"));
+        assert!(!is_idempotent("store.put"));
+        assert!(!is_idempotent("store.put_many"));
+        assert!(!is_idempotent("p2p.gossipsub_publish"));
+        assert!(!is_idempotent("p2p.shutdown"));
+    }
+
+    #[tokio::test]
+    async fn test_retry_idempotent_call() {
+        let (client, store, task) = spawn_flaky_store().await;
+        let cid: Cid = "bafkreigh2akiscaildcqabsyg3dfr6chu3fgpregiymsck7e7aqa4s52zy"
+            .parse()
+            .unwrap();
+
+        assert!(client.has(cid).await.unwrap());
+        assert_eq!(store.attempts.load(Ordering::SeqCst), 2);
+
+        task.abort();
+    }
+
+    #[tokio::test]
+    async fn test_no_retry_non_idempotent_call() {
+        let (client, store, task) = spawn_flaky_store().await;
+        let cid: Cid = "bafkreigh2akiscaildcqabsyg3dfr6chu3fgpregiymsck7e7aqa4s52zy"
+            .parse()
+            .unwrap();
+
+        let err = client
+            .put(cid, Bytes::from_static(b"hello"), Vec::new())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<Status>().unwrap().code(),
+            tonic::Code::Unavailable
+        );
+        assert_eq!(store.attempts.load(Ordering::SeqCst), 1);
+
+        task.abort();
+    }
+
+    #[tokio::test]
+    async fn test_retry_stops_on_non_transient_error() {
+        let attempts = AtomicUsize::new(0);
+        let res: Result<()> = RetryPolicy::new(3)
+            .call("store.get", || {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                async { Err(Status::internal("not found").into()) }
+            })
+            .await;
+        assert!(res.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+}
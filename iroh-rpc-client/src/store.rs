@@ -25,7 +25,11 @@ impl_client!(Store);
 impl StoreClient {
     #[tracing::instrument(skip(self))]
     pub async fn version(&self) -> Result<String> {
-        let res = self.backend.version(()).await?;
+        let backend = &self.backend;
+        let res = self
+            .retry
+            .call("store.version", move || backend.version(()))
+            .await?;
         Ok(res.version)
     }
 
@@ -59,7 +63,11 @@ impl StoreClient {
         let req = GetRequest {
             cid: cid.to_bytes(),
         };
-        let res = self.backend.get(req).await?;
+        let backend = &self.backend;
+        let res = self
+            .retry
+            .call("store.get", move || backend.get(req.clone()))
+            .await?;
         Ok(res.data)
     }
 
@@ -68,7 +76,11 @@ impl StoreClient {
         let req = HasRequest {
             cid: cid.to_bytes(),
         };
-        let res = self.backend.has(req).await?;
+        let backend = &self.backend;
+        let res = self
+            .retry
+            .call("store.has", move || backend.has(req.clone()))
+            .await?;
         Ok(res.has)
     }
 
@@ -77,7 +89,12 @@ impl StoreClient {
         let req = GetLinksRequest {
             cid: cid.to_bytes(),
         };
-        let links = self.backend.get_links(req).await?.links;
+        let backend = &self.backend;
+        let links = self
+            .retry
+            .call("store.get_links", move || backend.get_links(req.clone()))
+            .await?
+            .links;
         if links.is_empty() {
             Ok(None)
         } else {
@@ -94,7 +111,12 @@ impl StoreClient {
         let req = GetSizeRequest {
             cid: cid.to_bytes(),
         };
-        let size = self.backend.get_size(req).await?.size;
+        let backend = &self.backend;
+        let size = self
+            .retry
+            .call("store.get_size", move || backend.get_size(req.clone()))
+            .await?
+            .size;
         Ok(size)
     }
 }
@@ -0,0 +1,70 @@
+use std::fmt::{self, Display, Formatter};
+
+/// Marks an error caused by a resource running out, like the disk space of the store.
+///
+/// Unlike other errors, which reach the caller as plain messages, it is sent as
+/// `RESOURCE_EXHAUSTED` over grpc and kept as is in memory, so [`is_resource_exhausted`]
+/// recognizes it on the other side.
+#[derive(Debug)]
+pub struct ResourceExhausted(pub anyhow::Error);
+
+impl Display for ResourceExhausted {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ResourceExhausted {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.source()
+    }
+}
+
+/// Whether `err`, returned by an rpc call, was caused by a [`ResourceExhausted`] error.
+pub fn is_resource_exhausted(err: &anyhow::Error) -> bool {
+    err.chain().any(|err| {
+        #[cfg(feature = "grpc")]
+        if let Some(status) = err.downcast_ref::<tonic::Status>() {
+            return status.code() == tonic::Code::ResourceExhausted;
+        }
+        err.is::<ResourceExhausted>()
+    })
+}
+
+/// The grpc status an error of a call is sent as.
+#[cfg(feature = "grpc")]
+pub(crate) fn status(err: anyhow::Error) -> tonic::Status {
+    if is_resource_exhausted(&err) {
+        tonic::Status::resource_exhausted(err.to_string())
+    } else {
+        tonic::Status::internal(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_resource_exhausted() {
+        let err = anyhow::Error::new(ResourceExhausted(anyhow::anyhow!("disk full")));
+        assert!(is_resource_exhausted(&err));
+        assert_eq!(err.to_string(), "disk full");
+        assert!(is_resource_exhausted(&err.context("put failed")));
+        assert!(!is_resource_exhausted(&anyhow::anyhow!("disk full")));
+    }
+
+    #[cfg(feature = "grpc")]
+    #[test]
+    fn test_status() {
+        let err = anyhow::Error::new(ResourceExhausted(anyhow::anyhow!("disk full")));
+        let sent = status(err);
+        assert_eq!(sent.code(), tonic::Code::ResourceExhausted);
+        // as the client gets it back
+        assert!(is_resource_exhausted(&sent.into()));
+        assert_eq!(
+            status(anyhow::anyhow!("oops")).code(),
+            tonic::Code::Internal
+        );
+    }
+}
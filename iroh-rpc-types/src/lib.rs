@@ -13,4 +13,6 @@ pub use tonic::transport::NamedService;
 pub mod test;
 
 mod addr;
+mod error;
 pub use crate::addr::Addr;
+pub use crate::error::{is_resource_exhausted, ResourceExhausted};
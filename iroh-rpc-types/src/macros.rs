@@ -2,6 +2,19 @@ macro_rules! proxy_serve {
     ($label:ident, $($name:ident: $req:ty => $res:ty),+) => {
         paste::paste! {
             pub async fn serve<T: $label>(addr: [<$label ServerAddr>], source: T) -> anyhow::Result<()> {
+                serve_with_keepalive(addr, source, None).await
+            }
+
+            /// Like `serve`, but sends tcp and http2 keepalives at the given interval so idle
+            /// connections are not dropped by intermediaries.
+            pub async fn serve_with_keepalive<T: $label>(
+                addr: [<$label ServerAddr>],
+                source: T,
+                keepalive: Option<std::time::Duration>,
+            ) -> anyhow::Result<()> {
+                #[cfg(not(feature = "grpc"))]
+                let _ = keepalive;
+
                 match addr {
                     #[cfg(feature = "grpc")]
                     $crate::Addr::GrpcHttp2(addr) => {
@@ -11,6 +24,8 @@ macro_rules! proxy_serve {
                             .await;
 
                         tonic::transport::Server::builder()
+                            .tcp_keepalive(keepalive)
+                            .http2_keepalive_interval(keepalive)
                             .add_service(health_service)
                             .add_service([<$label:lower _server>]::[<$label Server>]::new(source))
                             .serve(addr)
@@ -61,6 +76,8 @@ macro_rules! proxy_serve {
                         let uds_stream = UnixListenerStream::new(uds);
 
                         tonic::transport::Server::builder()
+                            .tcp_keepalive(keepalive)
+                            .http2_keepalive_interval(keepalive)
                             .add_service(health_service)
                             .add_service([<$label:lower _server>]::[<$label Server>]::new(source))
                             .serve_with_incoming(uds_stream)
@@ -74,7 +91,7 @@ macro_rules! proxy_serve {
                             match msg {
                                 $(
                                     [<$label Request>]::$name(req) => {
-                                        let res = source.$name(req).await.map_err(|e| e.to_string());
+                                        let res = source.$name(req).await;
                                         sender.send([<$label Response>]::$name(res)).ok();
                                     }
                                 )+
@@ -131,7 +148,7 @@ macro_rules! proxy_serve_types {
             #[allow(non_camel_case_types)]
             pub enum [<$label Response>] {
                 $(
-                    $name(Result<$res, String>),
+                    $name(anyhow::Result<$res>),
                 )+
             }
         }
@@ -182,7 +199,7 @@ macro_rules! proxy_traits {
                                 let res = r_res.await?;
                                 #[allow(irrefutable_let_patterns)]
                                 if let [<$label Response>]::$name(res) = res {
-                                    return res.map_err(|e| anyhow::anyhow!(e))
+                                    return res
                                 } else {
                                     anyhow::bail!("invalid response");
                                 }
@@ -213,7 +230,7 @@ macro_rules! proxy_grpc {
                             req: Request<$req>,
                         ) -> Result<Response<$tonic_res>, Status> {
                             let req = req.into_inner();
-                            let res = $label::$name(self, req).await.map_err(|err| Status::internal(err.to_string()))?;
+                            let res = $label::$name(self, req).await.map_err($crate::error::status)?;
 
                             $(
                                 let res = {
@@ -9,6 +9,8 @@ proxy!(
         std::pin::Pin<Box<dyn futures::Stream<Item = Result<Providers, tonic::Status>> + Send>> =>
         std::pin::Pin<Box<dyn futures::Stream<Item = anyhow::Result<Providers>> + Send>> [FetchProviderDhtStream],
     stop_session_bitswap: StopSessionBitswapRequest => () => (),
+    wantlist: WantlistRequest => WantlistResponse => WantlistResponse,
+    protocol_stats: () => ProtocolStatsResponse => ProtocolStatsResponse,
     notify_new_blocks_bitswap: NotifyNewBlocksBitswapRequest => () => (),
     get_listening_addrs: () => GetListeningAddrsResponse =>  GetListeningAddrsResponse,
     get_peers: () => GetPeersResponse =>  GetPeersResponse,
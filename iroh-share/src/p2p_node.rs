@@ -1,12 +1,15 @@
 use std::{collections::HashSet, path::Path, sync::Arc};
 
-use anyhow::{ensure, Result};
+use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 use cid::Cid;
 use iroh_p2p::{config, Keychain, MemoryStorage, NetworkEvent, Node};
 use iroh_resolver::{
     parse_links,
-    resolver::{ContentLoader, ContextId, LoadedCid, LoaderContext, Resolver, Source, IROH_STORE},
+    resolver::{
+        BlockNotFound, ContentLoader, ContextId, LoadedCid, LoaderContext, Resolver, Source,
+        IROH_STORE,
+    },
 };
 use iroh_rpc_client::Client;
 use iroh_rpc_types::Addr;
@@ -80,7 +83,9 @@ impl ContentLoader for Loader {
             }
         }
 
-        ensure!(!providers.is_empty(), "no providers supplied");
+        if providers.is_empty() {
+            return Err(anyhow!("no providers supplied").context(BlockNotFound { cid }));
+        }
 
         // TODO: track context id
         let res = self
@@ -92,7 +97,7 @@ impl ContentLoader for Loader {
             Ok(bytes) => bytes,
             Err(err) => {
                 error!("Bitswap error: {:#?}", err);
-                return Err(err);
+                return Err(err.context(BlockNotFound { cid }));
             }
         };
 
@@ -135,14 +140,20 @@ impl P2pNode {
         let rpc_store_client_config = iroh_rpc_client::Config {
             p2p_addr: Some(rpc_p2p_addr_client.clone()),
             store_addr: Some(rpc_store_addr_client.clone()),
+            fallback_store_addrs: Vec::new(),
             gateway_addr: None,
             channels: Some(1),
+            max_retries: None,
+            keepalive_secs: None,
         };
         let rpc_p2p_client_config = iroh_rpc_client::Config {
             p2p_addr: Some(rpc_p2p_addr_client.clone()),
             store_addr: Some(rpc_store_addr_client.clone()),
+            fallback_store_addrs: Vec::new(),
             gateway_addr: None,
             channels: Some(1),
+            max_retries: None,
+            keepalive_secs: None,
         };
         let config = config::Config {
             libp2p: config::Libp2pConfig {
@@ -160,6 +171,8 @@ impl P2pNode {
             rpc_client: rpc_p2p_client_config.clone(),
             metrics: Default::default(),
             key_store_path: db_path.parent().unwrap().to_path_buf(),
+            keychain: Default::default(),
+            runtime: Default::default(),
         };
 
         let rpc = Client::new(rpc_p2p_client_config).await?;
@@ -173,6 +186,9 @@ impl P2pNode {
                 tracing: false, // disable tracing by default
                 ..Default::default()
             },
+            min_free_space: None,
+            write_batch_size: iroh_store::config::DEFAULT_WRITE_BATCH_SIZE,
+            durability: iroh_store::Durability::default(),
         };
 
         let store = if store_config.path.exists() {
@@ -192,7 +208,7 @@ impl P2pNode {
         });
 
         let store_task = tokio::spawn(async move {
-            iroh_store::rpc::new(rpc_store_addr_server, store)
+            iroh_store::rpc::new(rpc_store_addr_server, store, None)
                 .await
                 .unwrap()
         });
@@ -5,7 +5,9 @@ use futures::{
 };
 use iroh_p2p::NetworkEvent;
 use iroh_resolver::{
-    resolver::{Out, OutPrettyReader, OutType, Path, Resolver, ResponseClip, UnixfsType},
+    resolver::{
+        Out, OutMetrics, OutPrettyReader, OutType, Path, Resolver, ResponseClip, UnixfsType,
+    },
     unixfs::Link,
 };
 use libp2p::gossipsub::{GossipsubMessage, MessageId, TopicHash};
@@ -234,12 +236,12 @@ impl Data {
 
     pub fn read_dir(&self) -> Result<Option<impl Stream<Item = Result<Link>> + '_>> {
         self.root
-            .unixfs_read_dir(&self.resolver, Default::default())
+            .unixfs_read_dir(&self.resolver, OutMetrics::disabled())
     }
 
     pub fn pretty(self) -> Result<OutPrettyReader<Loader>> {
         self.root
-            .pretty(self.resolver, Default::default(), ResponseClip::NoClip)
+            .pretty(self.resolver, OutMetrics::disabled(), ResponseClip::NoClip)
     }
 
     pub async fn read_file(&self, link: &Link) -> Result<Data> {
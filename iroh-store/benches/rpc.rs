@@ -9,7 +9,7 @@ use iroh_rpc_types::{
     store::{StoreClientAddr, StoreServerAddr},
     Addr,
 };
-use iroh_store::{Config, Store};
+use iroh_store::{config::DEFAULT_WRITE_BATCH_SIZE, Config, Durability, Store};
 use tokio::runtime::Runtime;
 
 const RAW: u64 = 0x55;
@@ -73,11 +73,16 @@ pub fn put_benchmark(c: &mut Criterion) {
                         path: dir.path().join("db"),
                         rpc_client: rpc_client.clone(),
                         metrics: MetricsConfig::default(),
+                        min_free_space: None,
+                        write_batch_size: DEFAULT_WRITE_BATCH_SIZE,
+                        durability: Durability::default(),
                     };
                     let (_task, rpc) = executor.block_on(async {
                         let store = Store::create(config).await.unwrap();
                         let task = executor.spawn(async move {
-                            iroh_store::rpc::new(server_addr, store).await.unwrap()
+                            iroh_store::rpc::new(server_addr, store, None)
+                                .await
+                                .unwrap()
                         });
                         // wait for a moment until the transport is setup
                         // TODO: signal this more clearly
@@ -126,11 +131,16 @@ pub fn get_benchmark(c: &mut Criterion) {
                         path: dir.path().join("db"),
                         rpc_client: rpc_client.clone(),
                         metrics: MetricsConfig::default(),
+                        min_free_space: None,
+                        write_batch_size: DEFAULT_WRITE_BATCH_SIZE,
+                        durability: Durability::default(),
                     };
                     let (_task, rpc) = executor.block_on(async {
                         let store = Store::create(config).await.unwrap();
                         let task = executor.spawn(async move {
-                            iroh_store::rpc::new(server_addr, store).await.unwrap()
+                            iroh_store::rpc::new(server_addr, store, None)
+                                .await
+                                .unwrap()
                         });
                         // wait for a moment until the transport is setup
                         // TODO: signal this more clearly
@@ -1,10 +1,11 @@
 use std::time::Instant;
 
+use bytes::Bytes;
 use cid::multihash::{Code, MultihashDigest};
 use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
 use iroh_metrics::config::Config as MetricsConfig;
 use iroh_rpc_client::Config as RpcClientConfig;
-use iroh_store::{Config, Store};
+use iroh_store::{config::DEFAULT_WRITE_BATCH_SIZE, Config, Durability, Store};
 use tokio::runtime::Runtime;
 
 const RAW: u64 = 0x55;
@@ -28,6 +29,9 @@ pub fn put_benchmark(c: &mut Criterion) {
                     path: dir.path().into(),
                     rpc_client,
                     metrics: MetricsConfig::default(),
+                    min_free_space: None,
+                    write_batch_size: DEFAULT_WRITE_BATCH_SIZE,
+                    durability: Durability::default(),
                 };
                 let store = executor.block_on(async { Store::create(config).await.unwrap() });
                 let store_ref = &store;
@@ -39,6 +43,59 @@ pub fn put_benchmark(c: &mut Criterion) {
     group.finish();
 }
 
+pub fn put_many_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("store_put_many");
+    const BLOCKS: usize = 1000;
+    const VALUE_SIZE: usize = 1024;
+    group.throughput(criterion::Throughput::Bytes((BLOCKS * VALUE_SIZE) as u64));
+    for durability in [Durability::Batch, Durability::Block] {
+        group.bench_with_input(
+            BenchmarkId::new("durability", durability.as_str()),
+            &durability,
+            |b, durability| {
+                let executor = Runtime::new().unwrap();
+                let dir = tempfile::tempdir().unwrap();
+                let rpc_client = RpcClientConfig::default();
+                let config = Config {
+                    path: dir.path().into(),
+                    rpc_client,
+                    metrics: MetricsConfig::default(),
+                    min_free_space: None,
+                    write_batch_size: DEFAULT_WRITE_BATCH_SIZE,
+                    durability: *durability,
+                };
+                let store = executor.block_on(async { Store::create(config).await.unwrap() });
+                let store_ref = &store;
+                let mut next_round = 0u64;
+                b.to_async(&executor).iter_custom(move |iters| {
+                    // every round imports blocks the store has not seen yet
+                    let rounds = next_round..next_round + iters;
+                    next_round += iters;
+                    async move {
+                        let mut elapsed = std::time::Duration::default();
+                        for round in rounds {
+                            let blocks: Vec<_> = (0..BLOCKS)
+                                .map(|i| {
+                                    let mut value = vec![0u8; VALUE_SIZE];
+                                    value[..8].copy_from_slice(&round.to_be_bytes());
+                                    value[8..16].copy_from_slice(&(i as u64).to_be_bytes());
+                                    let hash = Code::Sha2_256.digest(&value);
+                                    (cid::Cid::new_v1(RAW, hash), Bytes::from(value), Vec::new())
+                                })
+                                .collect();
+                            let start = Instant::now();
+                            store_ref.put_many(black_box(blocks)).unwrap();
+                            elapsed += start.elapsed();
+                        }
+                        elapsed
+                    }
+                });
+            },
+        );
+    }
+    group.finish();
+}
+
 pub fn get_benchmark(c: &mut Criterion) {
     let mut group = c.benchmark_group("store_get");
     for value_size in [32, 128, 512, 1024].iter() {
@@ -54,6 +111,9 @@ pub fn get_benchmark(c: &mut Criterion) {
                     path: dir.path().into(),
                     rpc_client,
                     metrics: MetricsConfig::default(),
+                    min_free_space: None,
+                    write_batch_size: DEFAULT_WRITE_BATCH_SIZE,
+                    durability: Durability::default(),
                 };
                 let store = executor.block_on(async { Store::create(config).await.unwrap() });
                 let store_ref = &store;
@@ -87,5 +147,5 @@ pub fn get_benchmark(c: &mut Criterion) {
     group.finish();
 }
 
-criterion_group!(benches, put_benchmark, get_benchmark);
+criterion_group!(benches, put_benchmark, put_many_benchmark, get_benchmark);
 criterion_main!(benches);
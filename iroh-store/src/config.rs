@@ -33,6 +33,40 @@ pub struct Config {
     pub path: PathBuf,
     pub rpc_client: RpcClientConfig,
     pub metrics: MetricsConfig,
+    /// Free disk space in bytes below which new blocks are refused, `None` disables the guard.
+    pub min_free_space: Option<u64>,
+    /// Maximum number of blocks committed together by a single `put_many` write.
+    pub write_batch_size: usize,
+    /// When `put_many` writes are synced to disk, single block puts are never synced.
+    pub durability: Durability,
+}
+
+/// Default for [`Config::write_batch_size`].
+pub const DEFAULT_WRITE_BATCH_SIZE: usize = 1024;
+
+/// When the blocks written by a `put_many` are fsynced.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Durability {
+    /// Sync once per committed batch of blocks.
+    Batch,
+    /// Sync after every single block, trading ingestion throughput for durability.
+    Block,
+}
+
+impl Durability {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Durability::Batch => "batch",
+            Durability::Block => "block",
+        }
+    }
+}
+
+impl Default for Durability {
+    fn default() -> Self {
+        Durability::Batch
+    }
 }
 
 impl Config {
@@ -44,6 +78,9 @@ impl Config {
                 ..Default::default()
             },
             metrics: MetricsConfig::default(),
+            min_free_space: None,
+            write_batch_size: DEFAULT_WRITE_BATCH_SIZE,
+            durability: Durability::default(),
         }
     }
 
@@ -86,6 +123,12 @@ impl Source for Config {
         insert_into_config_map(&mut map, "path", path);
         insert_into_config_map(&mut map, "rpc_client", self.rpc_client.collect()?);
         insert_into_config_map(&mut map, "metrics", self.metrics.collect()?);
+        if let Some(min_free_space) = self.min_free_space {
+            insert_into_config_map(&mut map, "min_free_space", min_free_space.to_string());
+        }
+        // `config` can't deserialize into usize from an unsigned int, cast as a signed int
+        insert_into_config_map(&mut map, "write_batch_size", self.write_batch_size as i64);
+        insert_into_config_map(&mut map, "durability", self.durability.as_str());
 
         Ok(map)
     }
@@ -114,6 +157,14 @@ mod tests {
             "metrics".to_string(),
             Value::new(None, default.metrics.collect().unwrap()),
         );
+        expect.insert(
+            "write_batch_size".to_string(),
+            Value::new(None, default.write_batch_size as i64),
+        );
+        expect.insert(
+            "durability".to_string(),
+            Value::new(None, default.durability.as_str()),
+        );
 
         let got = default.collect().unwrap();
         for key in got.keys() {
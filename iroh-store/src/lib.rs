@@ -5,5 +5,5 @@ pub mod metrics;
 pub mod rpc;
 mod store;
 
-pub use crate::config::Config;
-pub use crate::store::Store;
+pub use crate::config::{Config, Durability};
+pub use crate::store::{InsufficientStorage, Store};
@@ -52,6 +52,7 @@ async fn main() -> anyhow::Result<()> {
     let rpc_addr = config
         .server_rpc_addr()?
         .ok_or_else(|| anyhow!("missing store rpc addr"))?;
+    let keepalive = config.rpc_client.keepalive();
     let store = if config.path.exists() {
         info!("Opening store at {}", config.path.display());
         Store::open(config).await?
@@ -60,7 +61,7 @@ async fn main() -> anyhow::Result<()> {
         Store::create(config).await?
     };
 
-    let rpc_task = tokio::spawn(async move { rpc::new(rpc_addr, store).await.unwrap() });
+    let rpc_task = tokio::spawn(async move { rpc::new(rpc_addr, store, keepalive).await.unwrap() });
 
     block_until_sigint().await;
     rpc_task.abort();
@@ -1,4 +1,5 @@
 use std::io::Cursor;
+use std::time::Duration;
 
 use anyhow::{Context, Result};
 use async_trait::async_trait;
@@ -9,9 +10,10 @@ use iroh_rpc_types::store::{
     HasRequest, HasResponse, PutManyRequest, PutRequest, Store as RpcStore, StoreServerAddr,
     VersionResponse,
 };
+use iroh_rpc_types::ResourceExhausted;
 use tracing::info;
 
-use crate::store::Store;
+use crate::store::{InsufficientStorage, Store};
 
 #[cfg(feature = "rpc-grpc")]
 impl iroh_rpc_types::NamedService for Store {
@@ -30,7 +32,7 @@ impl RpcStore for Store {
     async fn put(&self, req: PutRequest) -> Result<()> {
         let cid = cid_from_bytes(req.cid)?;
         let links = links_from_bytes(req.links)?;
-        let res = self.put(cid, req.blob, links)?;
+        let res = self.put(cid, req.blob, links).map_err(write_error)?;
 
         info!("store rpc call: put cid {}", cid);
         Ok(res)
@@ -47,7 +49,7 @@ impl RpcStore for Store {
                 Ok((cid, req.blob, links))
             })
             .collect::<Result<Vec<_>>>()?;
-        self.put_many(req)
+        self.put_many(req).map_err(write_error)
     }
 
     #[tracing::instrument(skip(self))]
@@ -95,9 +97,9 @@ impl RpcStore for Store {
 }
 
 #[tracing::instrument(skip(store))]
-pub async fn new(addr: StoreServerAddr, store: Store) -> Result<()> {
+pub async fn new(addr: StoreServerAddr, store: Store, keepalive: Option<Duration>) -> Result<()> {
     info!("rpc listening on: {}", addr);
-    iroh_rpc_types::store::serve(addr, store).await
+    iroh_rpc_types::store::serve_with_keepalive(addr, store, keepalive).await
 }
 
 #[tracing::instrument]
@@ -109,3 +111,12 @@ fn cid_from_bytes(b: Vec<u8>) -> Result<Cid> {
 fn links_from_bytes(l: Vec<Vec<u8>>) -> Result<Vec<Cid>> {
     l.into_iter().map(cid_from_bytes).collect()
 }
+
+/// Marks writes refused for lack of disk space, so clients can tell them apart.
+fn write_error(err: anyhow::Error) -> anyhow::Error {
+    if err.is::<InsufficientStorage>() {
+        ResourceExhausted(err).into()
+    } else {
+        err
+    }
+}
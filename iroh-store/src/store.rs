@@ -1,9 +1,13 @@
 use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    path::PathBuf,
     sync::{
         atomic::{AtomicU64, Ordering},
-        Arc,
+        Arc, Mutex,
     },
     thread::available_parallelism,
+    time::{Duration, Instant},
 };
 
 use anyhow::{anyhow, bail, Context, Result};
@@ -15,17 +19,21 @@ use iroh_metrics::{
     store::{StoreHistograms, StoreMetrics},
 };
 use iroh_rpc_client::Client as RpcClient;
-use multihash::Multihash;
+use multihash::{Code, Multihash, MultihashDigest};
 use rocksdb::{
     BlockBasedOptions, Cache, ColumnFamily, DBPinnableSlice, Direction, IteratorMode, Options,
-    WriteBatch, DB as RocksDb,
+    WriteBatch, WriteOptions, DB as RocksDb,
 };
 use smallvec::SmallVec;
 use tokio::task;
 
 use crate::cf::{GraphV0, MetadataV0, CF_BLOBS_V0, CF_GRAPH_V0, CF_ID_V0, CF_METADATA_V0};
+use crate::config::Durability;
 use crate::Config;
 
+/// How long a queried amount of free disk space is reused before it is queried again.
+const FREE_SPACE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
 #[derive(Clone)]
 pub struct Store {
     inner: Arc<InnerStore>,
@@ -34,10 +42,36 @@ pub struct Store {
 struct InnerStore {
     content: RocksDb,
     next_id: AtomicU64,
+    path: PathBuf,
+    min_free_space: Option<u64>,
+    /// The free disk space last queried, with the time it was queried at.
+    free_space: Mutex<Option<(Instant, u64)>>,
+    write_batch_size: usize,
+    durability: Durability,
     _cache: Cache,
     _rpc_client: RpcClient,
 }
 
+/// Returned when a write is refused because the free disk space dropped below
+/// the configured minimum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InsufficientStorage {
+    pub available: u64,
+    pub min_free_space: u64,
+}
+
+impl fmt::Display for InsufficientStorage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "insufficient storage: {} bytes free, below the minimum of {} bytes",
+            self.available, self.min_free_space
+        )
+    }
+}
+
+impl std::error::Error for InsufficientStorage {}
+
 /// Creates the default rocksdb options
 fn default_options() -> (Options, Cache) {
     let mut opts = Options::default();
@@ -132,6 +166,11 @@ impl Store {
             inner: Arc::new(InnerStore {
                 content: db,
                 next_id: 1.into(),
+                path: config.path,
+                min_free_space: config.min_free_space,
+                free_space: Default::default(),
+                write_batch_size: config.write_batch_size.max(1),
+                durability: config.durability,
                 _cache: cache,
                 _rpc_client,
             }),
@@ -184,6 +223,11 @@ impl Store {
             inner: Arc::new(InnerStore {
                 content: db,
                 next_id: next_id.into(),
+                path: config.path,
+                min_free_space: config.min_free_space,
+                free_space: Default::default(),
+                write_batch_size: config.write_batch_size.max(1),
+                durability: config.durability,
                 _cache: cache,
                 _rpc_client,
             }),
@@ -195,14 +239,76 @@ impl Store {
     where
         L: IntoIterator<Item = Cid>,
     {
+        self.ensure_free_space()?;
         self.local_store()?.put(cid, blob, links)
     }
 
+    /// Stores a block after checking that `blob` hashes to the multihash of `cid`.
+    ///
+    /// Storing a block that is already present is a no-op. Returns `true` if the block was newly
+    /// stored and `false` if it was already present.
+    #[tracing::instrument(skip(self, links, blob))]
+    pub fn put_block<T: AsRef<[u8]>, L>(&self, cid: Cid, blob: T, links: L) -> Result<bool>
+    where
+        L: IntoIterator<Item = Cid>,
+    {
+        let code = cid.hash().code();
+        let hash = Code::try_from(code)
+            .map_err(|_| anyhow!("unsupported multihash code {:#x}", code))?
+            .digest(blob.as_ref());
+        if hash.digest() != cid.hash().digest() {
+            bail!("block data does not match cid {}", cid);
+        }
+
+        let store = self.local_store()?;
+        if store.has(&cid)? {
+            return Ok(false);
+        }
+        self.ensure_free_space()?;
+        store.put(cid, blob, links)?;
+        Ok(true)
+    }
+
+    /// Stores many blocks, committing them in batches of at most `write_batch_size` blocks.
+    ///
+    /// Blocks of a batch become visible to reads only once the whole batch is committed.
+    /// Each commit is synced to disk, with [`Durability::Block`] every block is committed on
+    /// its own.
     #[tracing::instrument(skip(self, blocks))]
     pub fn put_many(&self, blocks: impl IntoIterator<Item = (Cid, Bytes, Vec<Cid>)>) -> Result<()> {
+        self.ensure_free_space()?;
         self.local_store()?.put_many(blocks)
     }
 
+    /// Refuses writes with [`InsufficientStorage`] once the free disk space drops below
+    /// the configured minimum.
+    ///
+    /// The free space is queried at most once per [`FREE_SPACE_CHECK_INTERVAL`].
+    fn ensure_free_space(&self) -> Result<()> {
+        if let Some(min_free_space) = self.inner.min_free_space {
+            let mut free_space = self.inner.free_space.lock().unwrap();
+            let available = match *free_space {
+                Some((checked, available)) if checked.elapsed() < FREE_SPACE_CHECK_INTERVAL => {
+                    available
+                }
+                _ => {
+                    let available = iroh_util::available_space(&self.inner.path)
+                        .context("failed to query free disk space")?;
+                    *free_space = Some((Instant::now(), available));
+                    available
+                }
+            };
+            if available < min_free_space {
+                return Err(InsufficientStorage {
+                    available,
+                    min_free_space,
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+
     #[tracing::instrument(skip(self))]
     pub fn get_blob_by_hash(&self, hash: &Multihash) -> Result<Option<DBPinnableSlice<'_>>> {
         self.local_store()?.get_blob_by_hash(hash)
@@ -258,6 +364,8 @@ impl Store {
                 .cf_handle(CF_BLOBS_V0)
                 .context("missing column family: blobs")?,
             next_id: &self.inner.next_id,
+            write_batch_size: self.inner.write_batch_size,
+            durability: self.inner.durability,
         })
     }
 }
@@ -274,6 +382,19 @@ struct LocalStore<'a> {
     graph: &'a ColumnFamily,
     blobs: &'a ColumnFamily,
     next_id: &'a AtomicU64,
+    write_batch_size: usize,
+    durability: Durability,
+}
+
+/// Writes staged for a single atomic commit.
+#[derive(Default)]
+struct PendingBatch {
+    batch: WriteBatch,
+    /// Ids assigned in this batch, not yet visible through the database.
+    ids: HashMap<Cid, u64>,
+    /// Blocks whose blobs are staged in this batch.
+    blocks: HashSet<Cid>,
+    blob_size: u64,
 }
 
 impl<'a> LocalStore<'a> {
@@ -282,41 +403,15 @@ impl<'a> LocalStore<'a> {
         L: IntoIterator<Item = Cid>,
     {
         inc!(StoreMetrics::PutRequests);
-
-        if self.has(&cid)? {
-            return Ok(());
-        }
-        let cf = self;
-
-        let id = self.next_id();
-
         let start = std::time::Instant::now();
 
-        let id_bytes = id.to_be_bytes();
-
-        // guranteed that the key does not exists, so we want to store it
+        let mut pending = PendingBatch::default();
+        self.stage(&mut pending, cid, blob, links)?;
+        let blob_size = pending.blob_size;
+        self.commit(pending, false)?;
 
-        let metadata = MetadataV0 {
-            codec: cid.codec(),
-            multihash: cid.hash().to_bytes(),
-        };
-        let metadata_bytes = rkyv::to_bytes::<_, 1024>(&metadata)?; // TODO: is this the right amount of scratch space?
-        let id_key = id_key(&cid);
-
-        let children = self.ensure_id_many(links.into_iter(), cf)?;
-
-        let graph = GraphV0 { children };
-        let graph_bytes = rkyv::to_bytes::<_, 1024>(&graph)?; // TODO: is this the right amount of scratch space?
-        let blob_size = blob.as_ref().len();
-
-        let mut batch = WriteBatch::default();
-        batch.put_cf(cf.id, id_key, &id_bytes);
-        batch.put_cf(cf.blobs, &id_bytes, blob);
-        batch.put_cf(cf.metadata, &id_bytes, metadata_bytes);
-        batch.put_cf(cf.graph, &id_bytes, graph_bytes);
-        self.db.write(batch)?;
         observe!(StoreHistograms::PutRequests, start.elapsed().as_secs_f64());
-        record!(StoreMetrics::PutBytes, blob_size as u64);
+        record!(StoreMetrics::PutBytes, blob_size);
 
         Ok(())
     }
@@ -325,45 +420,92 @@ impl<'a> LocalStore<'a> {
         inc!(StoreMetrics::PutRequests);
         let start = std::time::Instant::now();
         let mut total_blob_size = 0;
-        let cf = self;
 
-        let mut batch = WriteBatch::default();
+        let mut pending = PendingBatch::default();
         for (cid, blob, links) in blocks.into_iter() {
-            if self.has(&cid)? {
-                return Ok(());
+            self.stage(&mut pending, cid, blob, links)?;
+            if self.durability == Durability::Block || pending.blocks.len() >= self.write_batch_size
+            {
+                total_blob_size += pending.blob_size;
+                self.commit(std::mem::take(&mut pending), true)?;
             }
+        }
+        total_blob_size += pending.blob_size;
+        self.commit(pending, true)?;
 
-            let id = self.next_id();
+        observe!(StoreHistograms::PutRequests, start.elapsed().as_secs_f64());
+        record!(StoreMetrics::PutBytes, total_blob_size);
 
-            let id_bytes = id.to_be_bytes();
+        Ok(())
+    }
 
-            // guranteed that the key does not exists, so we want to store it
+    /// Adds a block to the pending batch, unless it is already stored.
+    fn stage<T: AsRef<[u8]>, L>(
+        &self,
+        pending: &mut PendingBatch,
+        cid: Cid,
+        blob: T,
+        links: L,
+    ) -> Result<()>
+    where
+        L: IntoIterator<Item = Cid>,
+    {
+        if pending.blocks.contains(&cid) || self.has(&cid)? {
+            return Ok(());
+        }
 
-            let metadata = MetadataV0 {
-                codec: cid.codec(),
-                multihash: cid.hash().to_bytes(),
-            };
-            let metadata_bytes = rkyv::to_bytes::<_, 1024>(&metadata)?; // TODO: is this the right amount of scratch space?
-            let id_key = id_key(&cid);
+        let id = self.stage_id(pending, cid)?;
+        let id_bytes = id.to_be_bytes();
+        let children = links
+            .into_iter()
+            .map(|link| self.stage_id(pending, link))
+            .collect::<Result<_>>()?;
 
-            let children = self.ensure_id_many(links.into_iter(), cf)?;
+        let graph = GraphV0 { children };
+        let graph_bytes = rkyv::to_bytes::<_, 1024>(&graph)?; // TODO: is this the right amount of scratch space?
+        let blob = blob.as_ref();
 
-            let graph = GraphV0 { children };
-            let graph_bytes = rkyv::to_bytes::<_, 1024>(&graph)?; // TODO: is this the right amount of scratch space?
+        pending.batch.put_cf(self.blobs, &id_bytes, blob);
+        pending.batch.put_cf(self.graph, &id_bytes, graph_bytes);
+        pending.blob_size += blob.len() as u64;
+        pending.blocks.insert(cid);
 
-            let blob_size = blob.as_ref().len();
-            total_blob_size += blob_size as u64;
+        Ok(())
+    }
 
-            batch.put_cf(cf.id, id_key, &id_bytes);
-            batch.put_cf(cf.blobs, &id_bytes, blob);
-            batch.put_cf(cf.metadata, &id_bytes, metadata_bytes);
-            batch.put_cf(cf.graph, &id_bytes, graph_bytes);
+    /// Returns the id for the cid, assigning and staging a new one if it has none yet.
+    fn stage_id(&self, pending: &mut PendingBatch, cid: Cid) -> Result<u64> {
+        if let Some(id) = pending.ids.get(&cid) {
+            return Ok(*id);
+        }
+        if let Some(id) = self.get_id(&cid)? {
+            return Ok(id);
         }
 
-        self.db.write(batch)?;
-        observe!(StoreHistograms::PutRequests, start.elapsed().as_secs_f64());
-        record!(StoreMetrics::PutBytes, total_blob_size);
+        let id = self.next_id();
+        let id_bytes = id.to_be_bytes();
+        let metadata = MetadataV0 {
+            codec: cid.codec(),
+            multihash: cid.hash().to_bytes(),
+        };
+        let metadata_bytes = rkyv::to_bytes::<_, 1024>(&metadata)?; // TODO: is this the right amount of scratch space?
+        pending.batch.put_cf(self.id, id_key(&cid), &id_bytes);
+        pending
+            .batch
+            .put_cf(self.metadata, &id_bytes, metadata_bytes);
+        pending.ids.insert(cid, id);
+
+        Ok(id)
+    }
 
+    /// Atomically writes the pending batch, syncing it to disk if `sync` is set.
+    fn commit(&self, pending: PendingBatch, sync: bool) -> Result<()> {
+        if pending.batch.is_empty() {
+            return Ok(());
+        }
+        let mut opts = WriteOptions::default();
+        opts.set_sync(sync);
+        self.db.write_opt(pending.batch, &opts)?;
         Ok(())
     }
 
@@ -548,38 +690,6 @@ impl<'a> LocalStore<'a> {
         }
     }
 
-    /// Takes a list of cids and gives them ids, which are boths stored and then returned.
-    #[tracing::instrument(skip(self, cids, cf))]
-    fn ensure_id_many<I>(&self, cids: I, cf: &LocalStore) -> Result<Vec<u64>>
-    where
-        I: IntoIterator<Item = Cid>,
-    {
-        let mut ids = Vec::new();
-        let mut batch = WriteBatch::default();
-        for cid in cids {
-            let id_key = id_key(&cid);
-            let id = if let Some(id) = self.db.get_pinned_cf(cf.id, &id_key)? {
-                u64::from_be_bytes(id.as_ref().try_into()?)
-            } else {
-                let id = self.next_id();
-                let id_bytes = id.to_be_bytes();
-
-                let metadata = MetadataV0 {
-                    codec: cid.codec(),
-                    multihash: cid.hash().to_bytes(),
-                };
-                let metadata_bytes = rkyv::to_bytes::<_, 1024>(&metadata)?; // TODO: is this the right amount of scratch space?
-                batch.put_cf(&cf.id, id_key, &id_bytes);
-                batch.put_cf(&cf.metadata, &id_bytes, metadata_bytes);
-                id
-            };
-            ids.push(id);
-        }
-        self.db.write(batch)?;
-
-        Ok(ids)
-    }
-
     #[tracing::instrument(skip(self))]
     fn next_id(&self) -> u64 {
         let id = self.next_id.fetch_add(1, Ordering::SeqCst);
@@ -595,6 +705,7 @@ mod tests {
 
     use super::*;
 
+    use crate::config::DEFAULT_WRITE_BATCH_SIZE;
     use iroh_metrics::config::Config as MetricsConfig;
     use iroh_rpc_client::Config as RpcClientConfig;
 
@@ -611,6 +722,9 @@ mod tests {
             path: dir.path().into(),
             rpc_client,
             metrics: MetricsConfig::default(),
+            min_free_space: None,
+            write_batch_size: DEFAULT_WRITE_BATCH_SIZE,
+            durability: Durability::default(),
         };
 
         let store = Store::create(config).await.unwrap();
@@ -650,6 +764,9 @@ mod tests {
             path: dir.path().into(),
             rpc_client,
             metrics: MetricsConfig::default(),
+            min_free_space: None,
+            write_batch_size: DEFAULT_WRITE_BATCH_SIZE,
+            durability: Durability::default(),
         };
 
         let store = Store::create(config.clone()).await.unwrap();
@@ -712,6 +829,112 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_min_free_space() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            path: dir.path().into(),
+            rpc_client: RpcClientConfig::default(),
+            metrics: MetricsConfig::default(),
+            min_free_space: None,
+            write_batch_size: DEFAULT_WRITE_BATCH_SIZE,
+            durability: Durability::default(),
+        };
+
+        let data = b"hello world".to_vec();
+        let c = cid::Cid::new_v1(RAW, Code::Sha2_256.digest(&data));
+        let store = Store::create(config.clone()).await.unwrap();
+        store.put(c, &data, []).unwrap();
+        drop(store);
+
+        // no disk has this much space left, simulating a full one
+        let config = Config {
+            min_free_space: Some(u64::MAX),
+            ..config
+        };
+        let store = Store::open(config).await.unwrap();
+
+        let other = b"other".to_vec();
+        let other_cid = cid::Cid::new_v1(RAW, Code::Sha2_256.digest(&other));
+        let err = store.put(other_cid, &other, []).unwrap_err();
+        let err = err.downcast_ref::<InsufficientStorage>().unwrap();
+        assert_eq!(err.min_free_space, u64::MAX);
+        assert!(err.to_string().starts_with("insufficient storage"));
+
+        let err = store
+            .put_many([(other_cid, Bytes::from(other), Vec::new())])
+            .unwrap_err();
+        assert!(err.is::<InsufficientStorage>());
+        assert!(!store.has(&other_cid).unwrap());
+
+        // reads still work
+        assert!(store.has(&c).unwrap());
+        assert_eq!(&store.get(&c).unwrap().unwrap()[..], &data[..]);
+    }
+
+    #[tokio::test]
+    async fn test_put_many_batched() {
+        for durability in [Durability::Batch, Durability::Block] {
+            let dir = tempfile::tempdir().unwrap();
+            let config = Config {
+                path: dir.path().into(),
+                rpc_client: RpcClientConfig::default(),
+                metrics: MetricsConfig::default(),
+                min_free_space: None,
+                write_batch_size: 16,
+                durability,
+            };
+            let store = Store::create(config).await.unwrap();
+
+            let mut blocks = Vec::new();
+            for i in 0..1000u32 {
+                let data = Bytes::from(i.to_be_bytes().repeat(16));
+                let c = cid::Cid::new_v1(RAW, Code::Sha2_256.digest(&data));
+                // link to the previous block, which is staged in the same batch most of the time
+                let links = blocks.last().map(|(c, _, _)| vec![*c]).unwrap_or_default();
+                blocks.push((c, data, links));
+            }
+
+            // an already stored block must not stop the rest of the batch
+            let (c, data, links) = blocks[500].clone();
+            store.put(c, &data, links).unwrap();
+            // neither must a block repeated within a batch
+            let mut import = blocks.clone();
+            import.insert(10, blocks[9].clone());
+            store.put_many(import).unwrap();
+
+            for (c, expected_data, expected_links) in &blocks {
+                let data = store.get(c).unwrap().unwrap();
+                let code = Code::try_from(c.hash().code()).unwrap();
+                assert_eq!(&code.digest(&data), c.hash());
+                assert_eq!(&expected_data[..], &data[..]);
+                assert_eq!(expected_links, &store.get_links(c).unwrap().unwrap());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_put_block() -> anyhow::Result<()> {
+        let (store, _dir) = test_store().await?;
+        let data = b"hello world".to_vec();
+        let c = Cid::new_v1(RAW, Code::Sha2_256.digest(&data));
+        let link = Cid::new_v1(RAW, Code::Sha2_256.digest(b"link"));
+
+        assert!(store.put_block(c, &data, [link])?);
+        assert_eq!(&store.get(&c)?.unwrap()[..], &data[..]);
+        assert_eq!(store.get_links(&c)?.unwrap(), vec![link]);
+
+        // storing it again is a no-op
+        assert!(!store.put_block(c, &data, [])?);
+        assert_eq!(store.get_links(&c)?.unwrap(), vec![link]);
+
+        // data that doesn't hash to the cid is rejected
+        let other = Cid::new_v1(RAW, Code::Sha2_256.digest(b"other"));
+        assert!(store.put_block(other, &data, []).is_err());
+        assert!(!store.has(&other)?);
+        Ok(())
+    }
+
     async fn test_store() -> anyhow::Result<(Store, TempDir)> {
         let dir = tempfile::tempdir()?;
         let rpc_client = RpcClientConfig::default();
@@ -719,6 +942,9 @@ mod tests {
             path: dir.path().into(),
             rpc_client,
             metrics: MetricsConfig::default(),
+            min_free_space: None,
+            write_batch_size: DEFAULT_WRITE_BATCH_SIZE,
+            durability: Durability::default(),
         };
 
         let store = Store::create(config).await?;
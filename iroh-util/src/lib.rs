@@ -1,11 +1,7 @@
 use std::{
-    cell::RefCell,
-    collections::HashMap,
-    path::PathBuf,
-    sync::{
-        atomic::{AtomicUsize, Ordering},
-        Arc,
-    },
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    path::{Path, PathBuf},
 };
 
 use anyhow::{anyhow, Result};
@@ -28,7 +24,40 @@ const DEFAULT_NOFILE_LIMIT: u64 = 65536;
 const MIN_NOFILE_LIMIT: u64 = 2048;
 
 /// Blocks current thread until ctrl-c is received
-pub async fn block_until_sigint() {
+///
+/// On unix SIGTERM is handled the same way, so `kill` and service managers stop a program
+/// gracefully as well. Any further signal exits the program right away.
+///
+/// The handlers are installed when this is called rather than on the first poll, so the
+/// returned future can be created early and watched while a program is still starting up.
+/// This needs to be called from within a tokio runtime.
+#[cfg(unix)]
+pub fn block_until_sigint() -> impl Future<Output = ()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut interrupt = signal(SignalKind::interrupt()).expect("Error setting SIGINT handler");
+    let mut terminate = signal(SignalKind::terminate()).expect("Error setting SIGTERM handler");
+
+    async move {
+        futures::future::select(Box::pin(interrupt.recv()), Box::pin(terminate.recv())).await;
+        println!("Got interrupt, shutting down...");
+        tokio::spawn(async move {
+            futures::future::select(Box::pin(interrupt.recv()), Box::pin(terminate.recv())).await;
+            std::process::exit(0);
+        });
+    }
+}
+
+/// Blocks current thread until ctrl-c is received
+///
+/// The handler is installed when this is called rather than on the first poll, so the
+/// returned future can be created early and watched while a program is still starting up.
+#[cfg(not(unix))]
+pub fn block_until_sigint() -> impl Future<Output = ()> {
+    use std::cell::RefCell;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
     let (ctrlc_send, ctrlc_oneshot) = futures::channel::oneshot::channel();
     let ctrlc_send_c = RefCell::new(Some(ctrlc_send));
 
@@ -47,7 +76,25 @@ pub async fn block_until_sigint() {
     })
     .expect("Error setting Ctrl-C handler");
 
-    ctrlc_oneshot.await.unwrap();
+    async move {
+        ctrlc_oneshot.await.unwrap();
+    }
+}
+
+/// Runs `fut` to completion, unless `interrupt` resolves first.
+///
+/// On interrupt `fut` is dropped, cancelling whatever startup step it was running,
+/// and `None` is returned.
+pub async fn run_until<F, I>(interrupt: &mut I, fut: F) -> Option<F::Output>
+where
+    F: Future,
+    I: Future<Output = ()> + Unpin,
+{
+    futures::pin_mut!(fut);
+    match futures::future::select(interrupt, fut).await {
+        futures::future::Either::Left(_) => None,
+        futures::future::Either::Right((out, _)) => Some(out),
+    }
 }
 
 /// Returns the path to the user's iroh config directory.
@@ -124,6 +171,14 @@ pub fn insert_into_config_map<I: Into<String>, V: Into<ValueKind>>(
     map.insert(field.into(), Value::new(None, val));
 }
 
+/// Reads a duration configured in milliseconds, where `0` means there is none.
+pub fn duration_from_millis(ms: u64) -> Option<std::time::Duration> {
+    match ms {
+        0 => None,
+        ms => Some(std::time::Duration::from_millis(ms)),
+    }
+}
+
 // struct made to shoe-horn in the ability to use the `IROH_METRICS` env var prefix
 #[derive(Debug, Clone)]
 struct MetricsSource {
@@ -162,19 +217,52 @@ where
     S: AsRef<str>,
     V: Into<Value>,
 {
+    make_config_with_origins(default, file_paths, env_prefix, flag_overrides).map(|(cfg, _)| cfg)
+}
+
+/// Like [`make_config`], additionally returning where the final value of each key came from:
+/// `default`, `file <path>`, `env <variable>`, `env` for the metrics variables, or `flag`.
+///
+/// Keys are the dotted paths of the config fields.
+pub fn make_config_with_origins<T, S, V>(
+    default: T,
+    file_paths: Vec<Option<PathBuf>>,
+    env_prefix: &str,
+    flag_overrides: HashMap<S, V>,
+) -> Result<(T, BTreeMap<String, String>)>
+where
+    T: serde::de::DeserializeOwned + Source + Send + Sync + 'static,
+    S: AsRef<str>,
+    V: Into<Value>,
+{
+    let mut origins = BTreeMap::new();
+    let mut record_origins = |source: &dyn Source, origin: &dyn Fn(&str) -> String| {
+        let mut keys = Vec::new();
+        flatten_keys("", &source.collect()?, &mut keys);
+        for key in keys {
+            let origin = origin(&key);
+            origins.insert(key, origin);
+        }
+        Ok::<_, ConfigError>(())
+    };
+
     // create config builder and add default as first source
+    record_origins(&default, &|_| "default".to_string())?;
     let mut builder = Config::builder().add_source(default);
 
     // layer on config options from files
     for path in file_paths.into_iter().flatten() {
         if path.exists() {
             let p = path.to_str().ok_or_else(|| anyhow::anyhow!("empty path"))?;
+            record_origins(&File::with_name(p), &|_| format!("file {}", p))?;
             builder = builder.add_source(File::with_name(p));
         }
     }
 
     // next, add any environment variables
-    builder = builder.add_source(Environment::with_prefix(env_prefix).try_parsing(true));
+    let env = Environment::with_prefix(env_prefix).try_parsing(true);
+    record_origins(&env, &|key: &str| env_var_name(env_prefix, key))?;
+    builder = builder.add_source(env);
 
     // pull metrics config from env variables
     // nesting into this odd `MetricsSource` struct, gives us the option of
@@ -192,17 +280,55 @@ where
     }
     let metrics = metrics.build().unwrap();
 
-    builder = builder.add_source(MetricsSource { metrics });
+    let metrics = MetricsSource { metrics };
+    record_origins(&metrics, &|_| "env".to_string())?;
+    builder = builder.add_source(metrics);
 
     // finally, override any values
     for (flag, val) in flag_overrides.into_iter() {
+        origins.insert(flag.as_ref().to_string(), "flag".to_string());
         builder = builder.set_override(flag, val)?;
     }
 
     let cfg = builder.build()?;
     debug!("make_config:\n{:#?}\n", cfg);
     let cfg: T = cfg.try_deserialize()?;
-    Ok(cfg)
+    Ok((cfg, origins))
+}
+
+/// Lists the dotted key of every field set by `source`, together with the environment
+/// variable overriding it.
+///
+/// Fields that are unset by default, like `None` options, are not included.
+pub fn env_keys<T: Source>(source: &T, env_prefix: &str) -> Result<Vec<(String, String)>> {
+    let mut keys = Vec::new();
+    flatten_keys("", &source.collect()?, &mut keys);
+    keys.sort();
+    Ok(keys
+        .into_iter()
+        .map(|key| {
+            let var = env_var_name(env_prefix, &key);
+            (key, var)
+        })
+        .collect())
+}
+
+fn env_var_name(env_prefix: &str, key: &str) -> String {
+    format!("{}_{}", env_prefix, key.to_uppercase())
+}
+
+fn flatten_keys(prefix: &str, map: &Map<String, Value>, keys: &mut Vec<String>) {
+    for (key, value) in map {
+        let key = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        match &value.kind {
+            ValueKind::Table(table) => flatten_keys(&key, table, keys),
+            _ => keys.push(key),
+        }
+    }
 }
 
 /// Verifies that the provided bytes hash to the given multihash.
@@ -213,13 +339,15 @@ pub fn verify_hash(cid: &Cid, bytes: &[u8]) -> Option<bool> {
     })
 }
 
+/// Returns the number of bytes available to unprivileged users on the filesystem containing `path`.
+pub fn available_space(path: &Path) -> std::io::Result<u64> {
+    fs2::available_space(path)
+}
+
 /// If supported sets a preffered limit for file descriptors.
 #[cfg(unix)]
 pub fn increase_fd_limit() -> std::io::Result<u64> {
-    let (_, hard) = rlimit::Resource::NOFILE.get()?;
-    let target = std::cmp::min(hard, DEFAULT_NOFILE_LIMIT);
-    rlimit::Resource::NOFILE.set(target, hard)?;
-    let (soft, _) = rlimit::Resource::NOFILE.get()?;
+    let soft = increase_fd_limit_to(DEFAULT_NOFILE_LIMIT)?;
     if soft < MIN_NOFILE_LIMIT {
         return Err(std::io::Error::new(
             std::io::ErrorKind::Other,
@@ -229,8 +357,40 @@ pub fn increase_fd_limit() -> std::io::Result<u64> {
     Ok(soft)
 }
 
+/// Raises the soft limit for file descriptors towards `target`, bounded by the hard limit,
+/// and by `OPEN_MAX` on macOS. Returns the soft limit reached, which is below `target` if
+/// those are. A soft limit that is already higher is kept.
+#[cfg(unix)]
+pub fn increase_fd_limit_to(target: u64) -> std::io::Result<u64> {
+    let (soft, hard) = rlimit::Resource::NOFILE.get()?;
+    let target = std::cmp::min(max_fd_limit(hard), target);
+    if soft >= target {
+        return Ok(soft);
+    }
+    rlimit::Resource::NOFILE.set(target, hard)?;
+    let (soft, _) = rlimit::Resource::NOFILE.get()?;
+    Ok(soft)
+}
+
+/// `OPEN_MAX` of `<sys/syslimits.h>`, macOS fails to set higher soft limits with `EINVAL`
+/// even if the hard limit allows them.
+#[cfg(target_os = "macos")]
+const OPEN_MAX: u64 = 10240;
+
+/// The highest soft limit for file descriptors that can be set below `hard`.
+#[cfg(unix)]
+fn max_fd_limit(hard: u64) -> u64 {
+    #[cfg(target_os = "macos")]
+    return std::cmp::min(hard, OPEN_MAX);
+    #[cfg(not(target_os = "macos"))]
+    hard
+}
+
 #[cfg(test)]
 mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
     use super::*;
     #[test]
     fn test_iroh_config_path() {
@@ -238,4 +398,47 @@ mod tests {
         let got = got.to_str().unwrap().to_string();
         assert!(got.ends_with("/iroh/foo.bar"));
     }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_increase_fd_limit_to() {
+        let (soft, hard) = rlimit::Resource::NOFILE.get().unwrap();
+        // never lowers the limit
+        assert_eq!(increase_fd_limit_to(1).unwrap(), soft);
+        // bounded by the hard limit
+        let target = soft.saturating_add(1024);
+        let got = increase_fd_limit_to(target).unwrap();
+        assert!(got >= soft);
+        assert!(got <= target);
+        assert!(got <= hard);
+    }
+
+    #[test]
+    fn test_run_until_interrupted() {
+        struct Cleanup(Arc<AtomicUsize>);
+        impl Drop for Cleanup {
+            fn drop(&mut self) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let cleaned_up = Arc::new(AtomicUsize::new(0));
+        let (started_s, started_r) = futures::channel::oneshot::channel();
+        let cleanup = Cleanup(cleaned_up.clone());
+        // a startup step that never finishes on its own, interrupted once it got going
+        let init = async move {
+            let _cleanup = cleanup;
+            started_s.send(()).unwrap();
+            futures::future::pending::<()>().await;
+        };
+        let mut interrupt = Box::pin(async move { started_r.await.unwrap() });
+
+        let res = futures::executor::block_on(run_until(&mut interrupt, init));
+        assert!(res.is_none());
+        assert_eq!(cleaned_up.load(Ordering::SeqCst), 1);
+
+        let mut interrupt = futures::future::pending::<()>();
+        let res = futures::executor::block_on(run_until(&mut interrupt, async { 42 }));
+        assert_eq!(res, Some(42));
+    }
 }
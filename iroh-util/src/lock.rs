@@ -1,23 +1,29 @@
 use crate::exitcodes;
 use anyhow::{anyhow, Result as AnyhowResult};
-use std::fs::File;
+use std::fs::{File, OpenOptions};
 use std::io::prelude::*;
 use std::io::ErrorKind;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use sysinfo::PidExt;
 use sysinfo::{Pid, ProcessExt, ProcessStatus::*, System, SystemExt};
 use thiserror::Error;
 use tracing::warn;
 
+const ACQUIRE_MIN_BACKOFF: Duration = Duration::from_millis(10);
+const ACQUIRE_MAX_BACKOFF: Duration = Duration::from_millis(500);
+
 /// Manages a lock file used to track if an iroh program is already running.
 /// Aquired locks write a file to iroh's application data path containing the
-/// process identifier (PID) of the process with the lock.
+/// process identifier (PID) of the process with the lock, see [`LockInfo`].
 /// The lock exclusion test requires both a lockfile AND a running process
 /// listed at the PID in the file
-/// An acquired lock is released either when the object is dropped
-/// or when the program stops, which removes the file
+/// An acquired lock is released when the object is dropped, which removes the file.
+/// If the program exits without dropping the lock, e.g. on a panic with `panic = "abort"`
+/// or on [`std::process::exit`], the file is left behind. Such stale locks are detected by
+/// [`ProgramLock::is_stale`] and replaced on acquisition
 /// Invalid or corrupt locks are overwritten on acquisition
 pub struct ProgramLock {
     path: PathBuf,
@@ -30,11 +36,19 @@ impl ProgramLock {
     pub fn new(prog_name: &str) -> Result<Self, LockError> {
         let path = crate::iroh_data_path(&format!("{}.lock", prog_name))
             .map_err(|e| LockError::InvalidPath { source: e })?;
-        Ok(Self {
+        Ok(Self::with_path(path))
+    }
+
+    /// Create a new lock at the given path, e.g. to run several instances of a program with
+    /// separate lock files. This does not yet acquire the lock.
+    ///
+    /// The program name is the file name of `path` up to the first `.`.
+    pub fn with_path(path: PathBuf) -> Self {
+        Self {
             path,
             lock: None,
             system: None,
-        })
+        }
     }
 
     /// Shorthand intended for main functions that need a lock to guard the process
@@ -48,7 +62,12 @@ impl ProgramLock {
                 self
             }
             Ok(true) => {
-                eprintln!("{} is already running", self.program_name());
+                match self.active_pid() {
+                    Ok(pid) => {
+                        eprintln!("{} is already running (pid {})", self.program_name(), pid)
+                    }
+                    Err(_) => eprintln!("{} is already running", self.program_name()),
+                }
                 process::exit(exitcodes::LOCKED);
             }
             Err(err) => {
@@ -79,10 +98,42 @@ impl ProgramLock {
             return Ok(false);
         }
 
-        // path exists, examine lock PID
-        let pid = read_lock(&self.path)?;
-        self.process_is_running(pid)
-            .map_err(|e| LockError::Uncategorized { source: e })
+        Ok(self.is_stale()? == StaleState::Held)
+    }
+
+    /// Check whether the process that wrote the lock file still holds it.
+    ///
+    /// A lock whose PID now belongs to a process started after the lock was acquired is
+    /// considered [`StaleState::PidReused`]. Legacy lock files don't record when they were
+    /// acquired, so for those this can't be detected.
+    pub fn is_stale(&mut self) -> Result<StaleState, LockError> {
+        let info = read_lock_file(&self.path)?;
+        if !self
+            .process_is_running(info.pid)
+            .map_err(|e| LockError::Uncategorized { source: e })?
+        {
+            return Ok(StaleState::DeadProcess);
+        }
+        if info.pid == sysinfo::get_current_pid().unwrap() {
+            return Ok(StaleState::Held);
+        }
+
+        let process_started = self
+            .system
+            .as_ref()
+            .and_then(|system| system.process(info.pid))
+            .map(|process| process.start_time());
+        let lock_started = info
+            .started
+            .and_then(|started| started.duration_since(UNIX_EPOCH).ok())
+            .map(|started| started.as_secs());
+        match (process_started, lock_started) {
+            // both are in whole seconds, allow for rounding
+            (Some(process_started), Some(lock_started)) if process_started > lock_started + 1 => {
+                Ok(StaleState::PidReused)
+            }
+            _ => Ok(StaleState::Held),
+        }
     }
 
     /// returns the PID in the lockfile only if the process is active
@@ -107,13 +158,13 @@ impl ProgramLock {
     pub fn acquire(&mut self) -> Result<(), LockError> {
         match self.is_locked() {
             Ok(false) => self
-                .write()
+                .write(false)
                 .map_err(|e| LockError::Uncategorized { source: anyhow!(e) }),
             Ok(true) => Err(LockError::Locked(self.path.clone())),
             Err(e) => match e {
                 LockError::CorruptLock(_) => {
                     // overwrite corrupt locks
-                    self.write().map_err(|e| LockError::Uncategorized {
+                    self.write(false).map_err(|e| LockError::Uncategorized {
                         source: anyhow!("{}", e),
                     })
                 }
@@ -124,6 +175,50 @@ impl ProgramLock {
         }
     }
 
+    /// Try to acquire a lock for this program, waiting up to `timeout` for a lock file that
+    /// is still being written by another process.
+    ///
+    /// Fails immediately if a running process holds the lock. Stale lock files are removed
+    /// before retrying.
+    ///
+    /// Waiting blocks the calling thread, async callers should run this on a blocking thread,
+    /// e.g. with `tokio::task::spawn_blocking`.
+    pub fn acquire_timeout(&mut self, timeout: Duration) -> AnyhowResult<()> {
+        let deadline = Instant::now() + timeout;
+        let mut backoff = ACQUIRE_MIN_BACKOFF;
+        loop {
+            match self.write(true) {
+                Ok(()) => return Ok(()),
+                // held, stale or still being written by another process
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => {}
+                Err(err) => return Err(err.into()),
+            }
+            match self.is_stale() {
+                Ok(StaleState::Held) => return Err(LockError::Locked(self.path.clone()).into()),
+                Ok(state) => {
+                    warn!("removing stale lock {}: {:?}", self.path.display(), state);
+                    if let Err(err) = std::fs::remove_file(&self.path) {
+                        if err.kind() != ErrorKind::NotFound {
+                            return Err(err.into());
+                        }
+                    }
+                }
+                // removed in the meantime
+                Err(LockError::NoLock(_)) => {}
+                Err(LockError::CorruptLock(_)) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        // overwrite corrupt locks, like `acquire`
+                        return Ok(self.write(false)?);
+                    }
+                    std::thread::sleep(backoff.min(deadline - now));
+                    backoff = (backoff * 2).min(ACQUIRE_MAX_BACKOFF);
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
     fn process_is_running(&mut self, pid: Pid) -> AnyhowResult<bool> {
         // existentialism is sometimes counterproductive
         let this_pid = sysinfo::get_current_pid().unwrap();
@@ -150,12 +245,27 @@ impl ProgramLock {
         }
     }
 
-    fn write(&mut self) -> AnyhowResult<()> {
+    /// Writes the lock file. With `create_new` an existing file is left alone and
+    /// [`ErrorKind::AlreadyExists`] returned, otherwise it is overwritten.
+    fn write(&mut self, create_new: bool) -> std::io::Result<()> {
         // create lock. ensure path to lock exists
-        std::fs::create_dir_all(&crate::iroh_data_root()?)?;
-        let mut file = File::create(&self.path)?;
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .create_new(create_new)
+            .open(&self.path)?;
         let pid = sysinfo::get_current_pid().unwrap();
-        file.write_all(pid.to_string().as_bytes())?;
+        let info = LockInfo {
+            pid,
+            program: Some(self.program_name().to_string()),
+            started: Some(SystemTime::now()),
+            binary: std::env::current_exe().ok(),
+        };
+        file.write_all(info.to_string().as_bytes())?;
         self.lock = Some(pid);
         Ok(())
     }
@@ -166,36 +276,141 @@ impl ProgramLock {
 }
 
 impl Drop for ProgramLock {
+    /// Removes the lock file if the lock is held. Best effort, a file that is already gone
+    /// is fine.
     fn drop(&mut self) {
-        if self.lock.is_some() {
+        if self.lock.take().is_some() {
             if let Err(err) = std::fs::remove_file(&self.path) {
-                warn!("removing lock: {}", err);
+                if err.kind() != ErrorKind::NotFound {
+                    warn!("removing lock: {}", err);
+                }
             }
         }
     }
 }
 
+/// Liveness of a lock file, see [`ProgramLock::is_stale`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StaleState {
+    /// The process that acquired the lock is still running.
+    Held,
+    /// No process is running with the PID of the lock.
+    DeadProcess,
+    /// The PID of the lock belongs to a process started after the lock was acquired.
+    PidReused,
+}
+
+/// Contents of a lock file.
+///
+/// Stored as `key=value` lines, unknown keys are ignored when reading. Lock files written by
+/// older versions only contain the PID, the other fields are `None` for those.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockInfo {
+    pub pid: Pid,
+    pub program: Option<String>,
+    /// When the lock was acquired.
+    pub started: Option<SystemTime>,
+    /// Path of the executable holding the lock.
+    pub binary: Option<PathBuf>,
+}
+
+impl std::fmt::Display for LockInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "pid={}", self.pid)?;
+        if let Some(program) = &self.program {
+            writeln!(f, "program={}", program)?;
+        }
+        if let Some(started) = self.started {
+            let started = started.duration_since(UNIX_EPOCH).unwrap_or_default();
+            writeln!(f, "started={}", started.as_secs())?;
+        }
+        if let Some(binary) = &self.binary {
+            writeln!(f, "binary={}", binary.display())?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for LockInfo {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // legacy lock files contain nothing but the PID
+        if let Ok(pid) = s.trim().parse::<u32>() {
+            return Ok(LockInfo {
+                pid: Pid::from_u32(pid),
+                program: None,
+                started: None,
+                binary: None,
+            });
+        }
+
+        let mut pid = None;
+        let mut info = LockInfo {
+            pid: Pid::from_u32(0),
+            program: None,
+            started: None,
+            binary: None,
+        };
+        for line in s.lines().filter(|line| !line.trim().is_empty()) {
+            let (key, value) = line.split_once('=').ok_or(())?;
+            match key {
+                "pid" => pid = Some(value.parse::<u32>().map_err(|_| ())?),
+                "program" => info.program = Some(value.to_string()),
+                "started" => {
+                    let secs = value.parse::<u64>().map_err(|_| ())?;
+                    info.started = Some(UNIX_EPOCH + Duration::from_secs(secs));
+                }
+                "binary" => info.binary = Some(PathBuf::from(value)),
+                _ => {}
+            }
+        }
+        info.pid = Pid::from_u32(pid.ok_or(())?);
+        Ok(info)
+    }
+}
+
 /// Report Process ID stored in a lock file
 pub fn read_lock_pid(prog_name: &str) -> Result<Pid, LockError> {
-    let path = crate::iroh_data_path(&format!("{}.lock", prog_name))
-        .map_err(|e| LockError::Uncategorized { source: e })?;
-    read_lock(&path)
+    read_lock_pid_in(&lock_root()?, prog_name)
+}
+
+/// Report Process ID stored in a lock file in `dir`
+pub fn read_lock_pid_in(dir: &Path, prog_name: &str) -> Result<Pid, LockError> {
+    read_lock_info_in(dir, prog_name).map(|info| info.pid)
+}
+
+/// Report everything stored in a lock file
+pub fn read_lock_info(prog_name: &str) -> Result<LockInfo, LockError> {
+    read_lock_info_in(&lock_root()?, prog_name)
+}
+
+/// Report everything stored in a lock file in `dir`
+pub fn read_lock_info_in(dir: &Path, prog_name: &str) -> Result<LockInfo, LockError> {
+    read_lock_file(&dir.join(format!("{}.lock", prog_name)))
+}
+
+fn lock_root() -> Result<PathBuf, LockError> {
+    crate::iroh_data_root().map_err(|e| LockError::Uncategorized { source: e })
 }
 
 fn read_lock(path: &PathBuf) -> Result<Pid, LockError> {
+    read_lock_file(path).map(|info| info.pid)
+}
+
+fn read_lock_file(path: &PathBuf) -> Result<LockInfo, LockError> {
     let mut file = File::open(&path).map_err(|e| match e.kind() {
         ErrorKind::NotFound => LockError::NoLock(path.clone()),
         e => LockError::Uncategorized {
             source: anyhow!("{}", e),
         },
     })?;
-    let mut pid = String::new();
-    file.read_to_string(&mut pid)
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)
         .map_err(|_| LockError::CorruptLock(path.clone()))?;
-    let pid = pid
-        .parse::<u32>()
-        .map_err(|_| LockError::CorruptLock(path.clone()))?;
-    Ok(Pid::from_u32(pid))
+    contents
+        .parse()
+        .map_err(|_| LockError::CorruptLock(path.clone()))
 }
 
 /// LockError is the set of known program lock errors
@@ -229,17 +444,14 @@ pub enum LockError {
 mod test {
     use super::*;
 
-    fn create_test_lock(name: &str) -> ProgramLock {
-        ProgramLock {
-            path: PathBuf::from(name),
-            lock: None,
-            system: None,
-        }
+    fn create_test_lock(path: &Path) -> ProgramLock {
+        ProgramLock::with_path(path.to_path_buf())
     }
 
     #[test]
     fn test_corrupt_lock() {
-        let path = PathBuf::from("lock.lock");
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lock.lock");
         let mut f = File::create(&path).unwrap();
         write!(f, "oh noes, not a lock file").unwrap();
         let e = read_lock(&path).err().unwrap();
@@ -251,6 +463,180 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_legacy_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("legacy.lock");
+        std::fs::write(&path, "1234").unwrap();
+        let info = read_lock_file(&path).unwrap();
+        assert_eq!(info.pid, Pid::from_u32(1234));
+        assert_eq!(info.program, None);
+        assert_eq!(info.started, None);
+        assert_eq!(info.binary, None);
+    }
+
+    #[test]
+    fn test_lock_info() {
+        let info = LockInfo {
+            pid: Pid::from_u32(1234),
+            program: Some("iroh-gateway".to_string()),
+            started: Some(UNIX_EPOCH + Duration::from_secs(1_665_000_000)),
+            binary: Some(PathBuf::from("/usr/local/bin/iroh-gateway")),
+        };
+        let contents = info.to_string();
+        assert_eq!(contents.parse::<LockInfo>().unwrap(), info);
+
+        // keys from newer versions are ignored
+        let contents = format!("{}version=2\n", contents);
+        assert_eq!(contents.parse::<LockInfo>().unwrap(), info);
+    }
+
+    #[test]
+    fn test_stale_locks() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stale.lock");
+        let mut lock = create_test_lock(&path);
+        let mut child = std::process::Command::new("sleep")
+            .arg("10")
+            .spawn()
+            .unwrap();
+        let pid = Pid::from_u32(child.id());
+        let write_lock = |started| {
+            let info = LockInfo {
+                pid,
+                program: Some("stale".to_string()),
+                started: Some(started),
+                binary: None,
+            };
+            std::fs::write(&path, info.to_string()).unwrap();
+        };
+
+        write_lock(SystemTime::now());
+        assert_eq!(lock.is_stale().unwrap(), StaleState::Held);
+        assert!(lock.is_locked().unwrap());
+
+        // the lock was acquired long before the process with its PID started
+        write_lock(UNIX_EPOCH + Duration::from_secs(1_000_000));
+        assert_eq!(lock.is_stale().unwrap(), StaleState::PidReused);
+        assert!(!lock.is_locked().unwrap());
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+        write_lock(SystemTime::now());
+        assert_eq!(lock.is_stale().unwrap(), StaleState::DeadProcess);
+        assert!(!lock.is_locked().unwrap());
+    }
+
+    #[test]
+    fn test_lock_across_threads() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("thread.lock");
+
+        let mut lock = create_test_lock(&path);
+        lock.acquire().unwrap();
+        let (release, released) = std::sync::mpsc::channel::<()>();
+        let holder = std::thread::spawn(move || {
+            // hold the lock until told to release it
+            let _lock = lock;
+            released.recv().unwrap();
+        });
+
+        assert!(create_test_lock(&path).is_locked().unwrap());
+        release.send(()).unwrap();
+        holder.join().unwrap();
+        assert!(!create_test_lock(&path).is_locked().unwrap());
+    }
+
+    #[test]
+    fn test_acquire_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("timeout.lock");
+
+        // stale locks are cleaned up
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        child.wait().unwrap();
+        std::fs::write(&path, child.id().to_string()).unwrap();
+        let mut lock = create_test_lock(&path);
+        lock.acquire_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(
+            read_lock(&path).unwrap(),
+            sysinfo::get_current_pid().unwrap()
+        );
+
+        // locks held by a running process fail without waiting
+        let start = Instant::now();
+        let mut other = create_test_lock(&path);
+        assert!(other.acquire_timeout(Duration::from_secs(10)).is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+        drop(lock);
+
+        // a lock that is still being written is waited for
+        std::fs::write(&path, "").unwrap();
+        let writer = std::thread::spawn({
+            let path = path.clone();
+            move || {
+                std::thread::sleep(Duration::from_millis(100));
+                let _ = std::fs::remove_file(path);
+            }
+        });
+        let mut lock = create_test_lock(&path);
+        lock.acquire_timeout(Duration::from_secs(5)).unwrap();
+        writer.join().unwrap();
+        assert!(lock.is_locked().unwrap());
+    }
+
+    #[test]
+    fn test_drop_removes_lock_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("drop.lock");
+
+        let mut lock = create_test_lock(&path);
+        lock.acquire().unwrap();
+        assert!(path.exists());
+        drop(lock);
+        assert!(!path.exists());
+
+        // locks that were never acquired leave other files alone
+        std::fs::write(&path, "1234").unwrap();
+        drop(create_test_lock(&path));
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn test_with_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("iroh-test.lock");
+        let mut lock = ProgramLock::with_path(path.clone());
+        assert_eq!(lock.path(), &path);
+        assert_eq!(lock.program_name(), "iroh-test");
+
+        lock.acquire().unwrap();
+        assert_eq!(
+            read_lock_info_in(dir.path(), "iroh-test")
+                .unwrap()
+                .program
+                .as_deref(),
+            Some("iroh-test")
+        );
+    }
+
+    #[test]
+    fn test_read_lock_in() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(matches!(
+            read_lock_pid_in(dir.path(), "test"),
+            Err(LockError::NoLock(_))
+        ));
+
+        std::fs::write(dir.path().join("test.lock"), "pid=1234\nprogram=test\n").unwrap();
+        assert_eq!(
+            read_lock_pid_in(dir.path(), "test").unwrap(),
+            Pid::from_u32(1234)
+        );
+        let info = read_lock_info_in(dir.path(), "test").unwrap();
+        assert_eq!(info.program.as_deref(), Some("test"));
+    }
+
     #[test]
     fn test_locks() {
         use nix::unistd::{fork, ForkResult::*};
@@ -258,12 +644,14 @@ mod test {
         use std::time::Duration;
 
         // Start with no lock file.
-        // TODO (b5) - use tempfiles for these tests
-        let _ = std::fs::remove_file("test1.lock");
+        let dir = tempfile::tempdir().unwrap();
+        let path1 = dir.path().join("test1.lock");
+        let path2 = dir.path().join("test2.lock");
+        let result_path = dir.path().join("lock_test.result");
 
-        let mut lock = create_test_lock("test1.lock");
+        let mut lock = create_test_lock(&path1);
         assert!(!lock.is_locked().unwrap());
-        assert!(read_lock(&PathBuf::from("test1.lock")).is_err());
+        assert!(read_lock(&path1).is_err());
 
         lock.acquire().unwrap();
 
@@ -271,7 +659,7 @@ mod test {
         // ensure call to is_locked doesn't affect PID reporting
         assert_eq!(
             sysinfo::get_current_pid().unwrap(),
-            read_lock(&PathBuf::from("test1.lock")).unwrap()
+            read_lock(&path1).unwrap()
         );
 
         // Spawn a child process to check we can't get the same lock.
@@ -281,11 +669,9 @@ mod test {
         unsafe {
             match fork() {
                 Ok(Parent { child: _ }) => {
-                    let _ = std::fs::remove_file("lock_test.result");
-
                     std::thread::sleep(Duration::from_secs(1));
 
-                    let mut result = std::fs::File::open("lock_test.result").unwrap();
+                    let mut result = std::fs::File::open(&result_path).unwrap();
                     let mut buf = String::new();
                     let _ = result.read_to_string(&mut buf);
                     assert_eq!(
@@ -295,15 +681,13 @@ mod test {
                             sysinfo::get_current_pid().unwrap()
                         )
                     );
-
-                    let _ = std::fs::remove_file("lock_test.result");
                 }
                 Ok(Child) => {
-                    let mut lock = create_test_lock("test1.lock");
-                    let mut lock2 = create_test_lock("test2.lock");
-                    let pid = read_lock(&PathBuf::from("test1.lock")).unwrap();
+                    let mut lock = create_test_lock(&path1);
+                    let mut lock2 = create_test_lock(&path2);
+                    let pid = read_lock(&path1).unwrap();
                     {
-                        let mut result = std::fs::File::create("lock_test.result").unwrap();
+                        let mut result = std::fs::File::create(&result_path).unwrap();
                         let _ = result.write_all(
                             format!(
                                 "locked1={}, locked2={} lock1pid={}",
@@ -314,6 +698,9 @@ mod test {
                             .as_bytes(),
                         );
                     }
+                    // exit without running destructors, which would remove the directory
+                    // the parent reads the result from
+                    std::process::exit(0);
                 }
                 Err(err) => panic!("Failed to fork: {}", err),
             }
@@ -5,7 +5,7 @@ use std::path::PathBuf;
 use config::{ConfigError, Map, Source, Value};
 use serde::{Deserialize, Serialize};
 
-use iroh_util::{insert_into_config_map, make_config};
+use iroh_util::{env_keys, insert_into_config_map, make_config, make_config_with_origins};
 
 const CONFIG_A: &str = "tests/config.a.toml";
 const CONFIG_B: &str = "tests/config.b.toml";
@@ -193,4 +193,57 @@ fn test_make_config() {
     );
 }
 
+#[test]
+fn test_make_config_origins() {
+    temp_env::with_vars(
+        vec![
+            ("IROH_TEST_CONFIG_PORT", Some("4000")),
+            ("IROH_TEST_CONFIG_METRICS.FOO", Some("true")),
+            ("IROH_METRICS_BAR", Some("10")),
+        ],
+        || {
+            let (_, origins) = make_config_with_origins(
+                TestConfig::new(),
+                vec![Some(PathBuf::from(CONFIG_A)), Some(PathBuf::from(CONFIG_B))],
+                "IROH_TEST_CONFIG",
+                HashMap::from([("enabled", "false")]),
+            )
+            .unwrap();
+
+            let file_a = format!("file {}", CONFIG_A);
+            let file_b = format!("file {}", CONFIG_B);
+            for (key, origin) in [
+                ("port", "env IROH_TEST_CONFIG_PORT"),
+                ("addr", "default"),
+                ("enabled", "flag"),
+                ("list", file_b.as_str()),
+                ("map.one", "default"),
+                ("map.four", file_b.as_str()),
+                ("map.seven", file_a.as_str()),
+                ("metrics.foo", "env IROH_TEST_CONFIG_METRICS.FOO"),
+                ("metrics.bar", "env"),
+                ("metrics.service_env", "default"),
+            ] {
+                assert_eq!(
+                    origins.get(key).map(|o| o.as_str()),
+                    Some(origin),
+                    "{}",
+                    key
+                );
+            }
+        },
+    );
+}
+
+#[test]
+fn test_env_keys() {
+    let keys = env_keys(&TestConfig::new(), "IROH_TEST_CONFIG").unwrap();
+    assert!(keys.contains(&("port".to_string(), "IROH_TEST_CONFIG_PORT".to_string())));
+    assert!(keys.contains(&(
+        "metrics.foo".to_string(),
+        "IROH_TEST_CONFIG_METRICS.FOO".to_string()
+    )));
+    assert_eq!(keys.len(), 11);
+}
+
 // add metrics
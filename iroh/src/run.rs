@@ -1,5 +1,6 @@
 use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
@@ -7,7 +8,9 @@ use console::style;
 use crossterm::style::Stylize;
 use futures::StreamExt;
 use indicatif::{ProgressBar, ProgressStyle};
-use iroh_api::{AddEvent, Api, ApiExt, IpfsPath, Iroh, ServiceStatus};
+use iroh_api::{
+    hash_from_name, AddEvent, Api, ApiExt, Chunker, Code, IpfsPath, Iroh, ServiceStatus,
+};
 use iroh_metrics::config::Config as MetricsConfig;
 use iroh_util::{human, iroh_config_path, make_config};
 
@@ -57,6 +60,13 @@ enum Commands {
         /// Don't provide added content to the network
         #[clap(long)]
         offline: bool,
+        /// How files are split into blocks: size-<size>, rabin, rabin-<avg> or
+        /// rabin-<min>-<avg>-<max>
+        #[clap(long, default_value = "size-262144", value_parser = Chunker::from_str)]
+        chunker: Chunker,
+        /// Multihash used for the cids of the added content, like sha2-256 or blake3
+        #[clap(long, default_value = "sha2-256", value_parser = hash_from_name)]
+        hash: Code,
     },
     #[clap(about = "Fetch IPFS content and write it to disk")]
     #[clap(after_help = doc::GET_LONG_DESCRIPTION )]
@@ -165,8 +175,19 @@ impl Cli {
                 recursive,
                 no_wrap,
                 offline,
+                chunker,
+                hash,
             } => {
-                add(api, path, *no_wrap, *recursive, !*offline).await?;
+                add(
+                    api,
+                    path,
+                    *no_wrap,
+                    *recursive,
+                    !*offline,
+                    chunker.clone(),
+                    *hash,
+                )
+                .await?;
             }
             Commands::Get {
                 ipfs_path: path,
@@ -208,6 +229,8 @@ async fn add(
     no_wrap: bool,
     recursive: bool,
     provide: bool,
+    chunker: Chunker,
+    hash: Code,
 ) -> Result<()> {
     if !path.exists() {
         anyhow::bail!("Path does not exist");
@@ -284,7 +307,7 @@ async fn add(
     // a while before it starts ending progress reports
     pb.inc(0);
 
-    let mut progress = api.add_stream(path, !no_wrap).await?;
+    let mut progress = api.add_stream(path, !no_wrap, chunker, hash).await?;
     let mut cids = Vec::new();
     while let Some(add_event) = progress.next().await {
         match add_event? {
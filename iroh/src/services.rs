@@ -156,7 +156,7 @@ pub async fn stop_services(api: &impl Api, services: HashSet<&str>) -> Result<()
             Ok(pid) => {
                 info!("stopping {} pid: {}", daemon_name, pid);
                 print!("stopping {}... ", &daemon_name);
-                match iroh_localops::process::stop(pid.as_u32()) {
+                match iroh_localops::process::stop(pid.as_u32().into()) {
                     Ok(_) => {
                         let is_down = poll_until_status(
                             api,